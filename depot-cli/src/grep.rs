@@ -0,0 +1,236 @@
+//! `grep` and `scan`, searching the contents of streams without extracting
+//! them. Streams are decompressed by several threads, each reading the depot
+//! on its own, and reported in name order like `grep -r` would list files
+
+use crate::ui;
+use depot_core::depot_handle::{EntryInfo, StreamInfo};
+use depot_core::error::DepotError;
+use depot_core::meta::EntryType;
+use memchr::memmem::Finder;
+use regex::bytes::Regex;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+/// Bytes looked at to tell binary streams from text, like grep a stream
+/// holding a NUL byte there is binary
+const BINARY_PROBE: usize = 8 * 1024;
+
+/// How streams are searched
+pub struct GrepOptions {
+    pub regex: Regex,
+    /// lines printed before and after every match
+    pub context: usize,
+    /// only report whether a stream matches
+    pub names_only: bool,
+}
+
+/// What searching a stream found
+pub enum Found {
+    Nothing,
+    /// the stream matches, it is a binary stream or only names are wanted
+    Match,
+    /// the matching lines and their context, ready to print
+    Lines(String),
+}
+
+/// A byte pattern searched for by `scan`
+pub struct BytePattern {
+    /// how the pattern was given, printed with its matches
+    pub label: String,
+    pub bytes: Vec<u8>,
+}
+
+/// Searches `streams` of the depot at `path` with `search` on `threads`
+/// threads and passes every stream to `report` in the order given, with
+/// what was found in it
+pub fn search<T, S, F>(
+    path: &Path,
+    streams: &[StreamInfo],
+    threads: usize,
+    search: S,
+    mut report: F,
+) where
+    T: Send,
+    S: Fn(&StreamInfo, &mut dyn Read) -> std::io::Result<T> + Sync,
+    F: FnMut(&StreamInfo, Result<T, DepotError>),
+{
+    let mut order: Vec<usize> = (0..streams.len()).collect();
+    // streams are taken in storage order, so reads stay mostly sequential
+    order.sort_by_key(|index| streams[*index].einf.offset);
+    let next = AtomicUsize::new(0);
+    let (done, finished) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..threads.clamp(1, streams.len().max(1)) {
+            let done = done.clone();
+            let (order, next, search) = (&order, &next, &search);
+            scope.spawn(move || {
+                let mut dh = crate::open_read(path);
+                while let Some(index) = order.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let stream = &streams[*index];
+                    let found = dh
+                        .open_stream(stream)
+                        .and_then(|mut reader| Ok(search(stream, &mut reader)?));
+                    if done.send((*index, found)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(done);
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        for (index, found) in finished {
+            pending.insert(index, found);
+            while let Some(found) = pending.remove(&next) {
+                report(&streams[next], found);
+                next += 1;
+            }
+        }
+    });
+}
+
+/// true for the streams worth searching, entries without data are skipped
+pub fn searchable(entry: &EntryInfo) -> bool {
+    entry.entry_type() == EntryType::File && entry.flags & EntryInfo::FLAG_EMPTY == 0
+}
+
+/// Searches the decompressed data of `stream` line by line
+pub fn grep_stream(
+    stream: &StreamInfo,
+    reader: &mut dyn Read,
+    options: &GrepOptions,
+) -> std::io::Result<Found> {
+    let mut reader = BufReader::with_capacity(64 * 1024, reader);
+    let probe = reader.fill_buf()?;
+    let binary = probe[..probe.len().min(BINARY_PROBE)].contains(&0);
+    let (regex, context) = (&options.regex, options.context);
+    let mut out = String::new();
+    let mut line = Vec::new();
+    let mut number = 0;
+    // the lines before the next match and the ones still due after the last
+    let mut before: VecDeque<(u64, Vec<u8>)> = VecDeque::with_capacity(context);
+    let mut after = 0;
+    let mut last_printed = None;
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        number += 1;
+        let text = line.strip_suffix(b"\n").unwrap_or(&line);
+        let text = text.strip_suffix(b"\r").unwrap_or(text);
+        if regex.is_match(text) {
+            if binary || options.names_only {
+                return Ok(Found::Match);
+            }
+            let first = before.front().map_or(number, |(n, _)| *n);
+            if last_printed.is_some_and(|last| last + 1 < first) {
+                out.push_str("--\n");
+            }
+            for (n, text) in before.drain(..) {
+                push_line(&mut out, &stream.name, n, '-', &text, None);
+            }
+            push_line(&mut out, &stream.name, number, ':', text, Some(regex));
+            last_printed = Some(number);
+            after = context;
+        } else if after > 0 {
+            push_line(&mut out, &stream.name, number, '-', text, None);
+            last_printed = Some(number);
+            after -= 1;
+        } else if context > 0 {
+            if before.len() == context {
+                before.pop_front();
+            }
+            before.push_back((number, text.to_vec()));
+        }
+    }
+    Ok(match out.is_empty() {
+        true => Found::Nothing,
+        false => Found::Lines(out),
+    })
+}
+
+/// Appends a line as `name:number:text` for matches, `separator` is `-` for
+/// context lines. The parts matching `highlight` are highlighted
+fn push_line(
+    out: &mut String,
+    name: &str,
+    number: u64,
+    separator: char,
+    text: &[u8],
+    highlight: Option<&Regex>,
+) {
+    let _ = write!(
+        out,
+        "{}{}{}{}",
+        ui::stream_label(name),
+        separator,
+        number,
+        separator
+    );
+    let mut start = 0;
+    for found in highlight.iter().flat_map(|regex| regex.find_iter(text)) {
+        out.push_str(&String::from_utf8_lossy(&text[start..found.start()]));
+        let _ = write!(
+            out,
+            "{}",
+            ui::matched(&String::from_utf8_lossy(found.as_bytes()))
+        );
+        start = found.end();
+    }
+    out.push_str(&String::from_utf8_lossy(&text[start..]));
+    out.push('\n');
+}
+
+/// Bytes of a stream scanned at once
+const SCAN_CHUNK: usize = 1024 * 1024;
+
+/// Finds every occurrence of `patterns` in the decompressed data read from
+/// `reader`, overlapping ones included, as the index of the pattern and
+/// the offset of the match in order of their offsets
+pub fn scan_stream(
+    reader: &mut dyn Read,
+    patterns: &[BytePattern],
+) -> std::io::Result<Vec<(usize, u64)>> {
+    let finders: Vec<_> = patterns.iter().map(|p| Finder::new(&p.bytes)).collect();
+    let longest = patterns.iter().map(|p| p.bytes.len()).max().unwrap_or(0);
+    let mut found = Vec::new();
+    // the window keeps the last bytes of the previous chunk so matches
+    // crossing chunks are found, `base` is the offset of its first byte
+    let mut window = Vec::with_capacity(SCAN_CHUNK + longest);
+    let mut base = 0;
+    let mut kept = 0;
+    let mut chunk = vec![0; SCAN_CHUNK];
+    loop {
+        let n = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        window.extend_from_slice(&chunk[..n]);
+        for (index, finder) in finders.iter().enumerate() {
+            let len = finder.needle().len();
+            let mut start = 0;
+            while let Some(at) = finder.find(&window[start..]) {
+                let at = start + at;
+                // matches within the kept bytes were found in the last round
+                if at + len > kept {
+                    found.push((index, base + at as u64));
+                }
+                start = at + 1;
+            }
+        }
+        let drop = window.len().saturating_sub(longest.saturating_sub(1));
+        window.drain(..drop);
+        base += drop as u64;
+        kept = window.len();
+    }
+    found.sort_by_key(|(index, offset)| (*offset, *index));
+    Ok(found)
+}
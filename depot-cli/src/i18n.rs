@@ -0,0 +1,378 @@
+//! Message catalog for user facing CLI output. Library errors and lint
+//! messages are passed through untranslated so they stay stable for
+//! scripts and bug reports, only the presentation around them is localized
+
+use std::fmt::Display;
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Lang {
+    En,
+    De,
+}
+
+static LANG: OnceLock<Lang> = OnceLock::new();
+
+/// Selects the catalog, `lang` wins over the `LC_ALL`, `LC_MESSAGES` and
+/// `LANG` environment variables, anything unknown falls back to English
+pub fn init(lang: Option<Lang>) {
+    let _ = LANG.set(lang.unwrap_or_else(detect));
+}
+
+fn detect() -> Lang {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .and_then(|value| parse_locale(&value))
+        .unwrap_or(Lang::En)
+}
+
+/// Maps a POSIX locale like `de_AT.UTF-8` to a supported language
+fn parse_locale(locale: &str) -> Option<Lang> {
+    let lang = locale.split(['_', '.', '@']).next()?;
+    match lang.to_ascii_lowercase().as_str() {
+        "en" | "c" | "posix" => Some(Lang::En),
+        "de" => Some(Lang::De),
+        _ => None,
+    }
+}
+
+/// Looks up the message template for `key`, missing translations fall back
+/// to English and unknown keys to the key itself
+pub fn lookup(key: &'static str) -> &'static str {
+    let lang = *LANG.get().unwrap_or(&Lang::En);
+    find(catalog(lang), key)
+        .or_else(|| find(catalog(Lang::En), key))
+        .unwrap_or(key)
+}
+
+/// Substitutes `{0}`, `{1}`, ... in `template` with `args`. Only the
+/// placeholders of the template are replaced, braces in the arguments such
+/// as a file named `a{1}.txt` are kept as they are
+pub fn fill(template: &str, args: &[&dyn Display]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        rest = &rest[open..];
+        let arg = rest
+            .find('}')
+            .and_then(|close| Some((rest[1..close].parse::<usize>().ok()?, close)))
+            .and_then(|(index, close)| Some((args.get(index)?, close)));
+        match arg {
+            Some((arg, close)) => {
+                out.push_str(&arg.to_string());
+                rest = &rest[close + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Translated message, `tr!("key", arg0, arg1)`
+macro_rules! tr {
+    ($key:literal) => {
+        $crate::i18n::lookup($key).to_owned()
+    };
+    ($key:literal, $($arg:expr),+ $(,)?) => {
+        $crate::i18n::fill($crate::i18n::lookup($key), &[$(&$arg),+])
+    };
+}
+pub(crate) use tr;
+
+fn find(catalog: &[(&str, &'static str)], key: &str) -> Option<&'static str> {
+    catalog.iter().find(|(k, _)| *k == key).map(|(_, v)| *v)
+}
+
+fn catalog(lang: Lang) -> &'static [(&'static str, &'static str)] {
+    match lang {
+        Lang::En => EN,
+        Lang::De => DE,
+    }
+}
+
+const EN: &[(&str, &str)] = &[
+    ("banner", "Depot CLI tools {0}"),
+    ("label.warning", "warning:"),
+    ("label.error", "error:"),
+    ("bake.adding", "adding {0} files to {1}"),
+    ("bake.created", "created depot at {0}"),
+    ("bake.parts", "split into {0} parts of {1}"),
+    ("append.updated", "updated depot at {0}"),
+    ("list.listing", "listing contents of {0}\n"),
+    (
+        "list.entry",
+        "{0}, compressed size: {1}, size: {2}, ratio: {3}%, created: {4}, modified: {5}",
+    ),
+    ("list.invalid_ts", "invalid timestamp"),
+    ("extract.extracting", "extracting {0} to {1}"),
+    ("extract.extracted", "extracted {0} to {1}"),
+    ("extract.would_extract", "would extract {0} to {1}"),
+    ("carve.carving", "carving {0} to {1}"),
+    ("carve.carved", "carved {0}"),
+    ("toc.printing", "printing table of contents for {0}"),
+    ("info.format", "format version: {0}"),
+    ("info.fingerprint", "fingerprint: {0}"),
+    ("info.entries", "entries: {0}, size: {1}"),
+    (
+        "info.creator",
+        "written by {0} {1} (library {2}, {3}, format {4})",
+    ),
+    ("info.no_creator", "no creator recorded"),
+    ("info.meta", "attribute {0}: {1}"),
+    ("info.frozen", "frozen, sha256: {0}"),
+    (
+        "du.summary",
+        "{0} of {1} ({2}%) are dead space in {3} regions",
+    ),
+    ("du.region", "{0}..{1}: {2}"),
+    ("oci.exporting", "exporting {0} as an OCI layer to {1}"),
+    ("oci.media_type", "media type: {0}"),
+    ("oci.digest", "digest: {0}"),
+    ("oci.diff_id", "diff id: {0}"),
+    ("oci.size", "size: {0}"),
+    ("install.installed", "installed {0} {1} ({2} files) to {3}"),
+    ("install.manifest", "uninstall manifest: {0}"),
+    ("show.start", "Start of {0}"),
+    ("show.end", "End of {0}"),
+    ("grep.binary_match", "binary stream {0} matches"),
+    ("grep.failed", "could not search {0}: {1}"),
+    ("scan.failed", "could not scan {0}: {1}"),
+    ("lint.summary", "{0} issues, dead space {1} ({2}%)"),
+    ("remove.removed", "removed {0}"),
+    ("freeze.frozen", "froze {0}, sha256: {1}"),
+    ("freeze.unfrozen", "lifted the freeze of {0}"),
+    ("meta.set", "set {0} to {1}"),
+    ("meta.not_set", "{0} is not set"),
+    ("verify.verifying", "verifying {0}"),
+    ("verify.summary", "{0} streams verified, {1} corrupted"),
+    (
+        "verify.frozen",
+        "the depot still matches the hash it was frozen with",
+    ),
+    (
+        "verify.frozen_changed",
+        "the depot changed after it was frozen: {0}",
+    ),
+    ("update.updated", "replaced {0} with {1}"),
+    ("compact.compacted", "compacted {0}, reclaimed {1} bytes"),
+    ("repack.repacked", "repacked {0} into {1}, {2} -> {3} bytes"),
+    ("catalog.building", "cataloguing depots in {0} into {1}"),
+    ("catalog.built", "catalogued {0} depots with {1} entries"),
+    ("catalog.hit", "{0}: {1}, size: {2}, hash: {3}"),
+    (
+        "catalog.not_found",
+        "{0} is in none of the catalogued depots",
+    ),
+    ("catalog.outdated", "{0} is missing or outdated"),
+    (
+        "catalog.up_to_date",
+        "all catalogued depots in {0} are up to date",
+    ),
+    ("error.file_list", "could not read file list {0}: {1}"),
+    ("error.open", "could not open {0}: {1}"),
+    ("error.key_file", "could not read key file {0}: {1}"),
+    ("error.trace", "could not read access trace {0}: {1}"),
+    ("error.policy", "could not read content policy {0}: {1}"),
+    (
+        "error.size",
+        "invalid size {0}, expected e.g. 4096, 512K or 2G",
+    ),
+    (
+        "error.split_append",
+        "--split-size only applies when baking, appending keeps the part size",
+    ),
+    (
+        "error.encrypt_no_key",
+        "--encrypt needs a key given by --key-file",
+    ),
+    (
+        "hint.ignore_version",
+        "use --ignore-version to inspect it anyway",
+    ),
+    (
+        "hint.force_unfreeze",
+        "use --force-unfreeze to lift the freeze and change it anyway",
+    ),
+    ("error.owner_map", "could not read owner map {0}: {1}"),
+    ("error.transform", "invalid --transform {0}: {1}"),
+    ("error.pattern", "invalid pattern {0}: {1}"),
+    (
+        "error.hex",
+        "invalid --hex {0}, expected an even number of hex digits",
+    ),
+    ("error.empty_pattern", "patterns must not be empty"),
+    ("error.script", "could not read script {0}: {1}"),
+    ("error.path_missing", "path {0} does not exist"),
+    ("error.path_outside", "path {0} is outside of the add root"),
+    (
+        "error.path_absolute",
+        "refusing to embed absolute path {0}, pass --allow-absolute to keep it",
+    ),
+    ("error.chdir", "could not change into {0}: {1}"),
+    (
+        "error.dir_without_recurse",
+        "refusing to add directory {0} without --recurse",
+    ),
+];
+
+const DE: &[(&str, &str)] = &[
+    ("banner", "Depot CLI-Werkzeuge {0}"),
+    ("label.warning", "Warnung:"),
+    ("label.error", "Fehler:"),
+    ("bake.adding", "füge {0} Dateien zu {1} hinzu"),
+    ("bake.created", "Depot unter {0} erstellt"),
+    ("bake.parts", "in {0} Teile zu {1} aufgeteilt"),
+    ("append.updated", "Depot unter {0} aktualisiert"),
+    ("list.listing", "Inhalt von {0}\n"),
+    (
+        "list.entry",
+        "{0}, komprimiert: {1}, Größe: {2}, Verhältnis: {3}%, erstellt: {4}, geändert: {5}",
+    ),
+    ("list.invalid_ts", "ungültiger Zeitstempel"),
+    ("extract.extracting", "entpacke {0} nach {1}"),
+    ("extract.extracted", "{0} nach {1} entpackt"),
+    ("extract.would_extract", "würde {0} nach {1} entpacken"),
+    ("carve.carving", "schneide {0} nach {1} aus"),
+    ("carve.carved", "{0} ausgeschnitten"),
+    ("toc.printing", "Inhaltsverzeichnis von {0}"),
+    ("info.format", "Formatversion: {0}"),
+    ("info.fingerprint", "Fingerabdruck: {0}"),
+    ("info.entries", "Einträge: {0}, Größe: {1}"),
+    (
+        "info.creator",
+        "geschrieben von {0} {1} (Bibliothek {2}, {3}, Format {4})",
+    ),
+    ("info.no_creator", "kein Ersteller vermerkt"),
+    ("info.meta", "Attribut {0}: {1}"),
+    ("info.frozen", "eingefroren, sha256: {0}"),
+    (
+        "du.summary",
+        "{0} von {1} ({2}%) sind ungenutzter Platz in {3} Bereichen",
+    ),
+    ("du.region", "{0}..{1}: {2}"),
+    ("oci.exporting", "exportiere {0} als OCI-Layer nach {1}"),
+    ("oci.media_type", "Medientyp: {0}"),
+    ("oci.digest", "Digest: {0}"),
+    ("oci.diff_id", "Diff-ID: {0}"),
+    ("oci.size", "Größe: {0}"),
+    (
+        "install.installed",
+        "{0} {1} ({2} Dateien) nach {3} installiert",
+    ),
+    ("install.manifest", "Deinstallationsliste: {0}"),
+    ("show.start", "Anfang von {0}"),
+    ("show.end", "Ende von {0}"),
+    ("grep.binary_match", "Binärer Stream {0} passt"),
+    ("grep.failed", "{0} konnte nicht durchsucht werden: {1}"),
+    ("scan.failed", "{0} konnte nicht abgesucht werden: {1}"),
+    ("lint.summary", "{0} Probleme, ungenutzter Platz {1} ({2}%)"),
+    ("remove.removed", "{0} entfernt"),
+    ("freeze.frozen", "{0} eingefroren, sha256: {1}"),
+    ("freeze.unfrozen", "{0} wieder aufgetaut"),
+    ("meta.set", "{0} auf {1} gesetzt"),
+    ("meta.not_set", "{0} ist nicht gesetzt"),
+    ("verify.verifying", "prüfe {0}"),
+    ("verify.summary", "{0} Streams geprüft, {1} beschädigt"),
+    (
+        "verify.frozen",
+        "das Depot entspricht noch dem Hash, mit dem es eingefroren wurde",
+    ),
+    (
+        "verify.frozen_changed",
+        "das Depot wurde nach dem Einfrieren verändert: {0}",
+    ),
+    ("update.updated", "{0} durch {1} ersetzt"),
+    ("compact.compacted", "{0} verdichtet, {1} Bytes freigegeben"),
+    (
+        "repack.repacked",
+        "{0} nach {1} umgepackt, {2} -> {3} Bytes",
+    ),
+    ("catalog.building", "katalogisiere Depots in {0} nach {1}"),
+    (
+        "catalog.built",
+        "{0} Depots mit {1} Einträgen katalogisiert",
+    ),
+    ("catalog.hit", "{0}: {1}, Größe: {2}, Hash: {3}"),
+    (
+        "catalog.not_found",
+        "{0} ist in keinem katalogisierten Depot",
+    ),
+    ("catalog.outdated", "{0} fehlt oder ist veraltet"),
+    (
+        "catalog.up_to_date",
+        "alle katalogisierten Depots in {0} sind aktuell",
+    ),
+    (
+        "error.file_list",
+        "Dateiliste {0} konnte nicht gelesen werden: {1}",
+    ),
+    (
+        "error.owner_map",
+        "Besitzerzuordnung {0} konnte nicht gelesen werden: {1}",
+    ),
+    ("error.transform", "ungültiges --transform {0}: {1}"),
+    ("error.pattern", "ungültiges Muster {0}: {1}"),
+    (
+        "error.hex",
+        "ungültiges --hex {0}, erwartet wird eine gerade Anzahl Hexziffern",
+    ),
+    ("error.empty_pattern", "Muster dürfen nicht leer sein"),
+    (
+        "error.script",
+        "Skript {0} konnte nicht gelesen werden: {1}",
+    ),
+    ("error.open", "{0} konnte nicht geöffnet werden: {1}"),
+    (
+        "error.key_file",
+        "Schlüsseldatei {0} konnte nicht gelesen werden: {1}",
+    ),
+    (
+        "error.trace",
+        "Zugriffsprotokoll {0} konnte nicht gelesen werden: {1}",
+    ),
+    (
+        "error.policy",
+        "Inhaltsrichtlinie {0} konnte nicht gelesen werden: {1}",
+    ),
+    (
+        "error.size",
+        "ungültige Größe {0}, erwartet z.B. 4096, 512K oder 2G",
+    ),
+    (
+        "error.split_append",
+        "--split-size gilt nur beim Erstellen, beim Anhängen bleibt die Teilgröße",
+    ),
+    (
+        "error.encrypt_no_key",
+        "--encrypt braucht einen Schlüssel über --key-file",
+    ),
+    (
+        "hint.ignore_version",
+        "mit --ignore-version lässt es sich trotzdem untersuchen",
+    ),
+    (
+        "hint.force_unfreeze",
+        "mit --force-unfreeze lässt es sich auftauen und trotzdem ändern",
+    ),
+    ("error.path_missing", "Pfad {0} existiert nicht"),
+    (
+        "error.path_outside",
+        "Pfad {0} liegt außerhalb des Wurzelverzeichnisses",
+    ),
+    (
+        "error.path_absolute",
+        "absoluter Pfad {0} wird nicht übernommen, --allow-absolute erlaubt ihn",
+    ),
+    ("error.chdir", "Wechsel nach {0} fehlgeschlagen: {1}"),
+    (
+        "error.dir_without_recurse",
+        "Verzeichnis {0} wird ohne --recurse nicht hinzugefügt",
+    ),
+];
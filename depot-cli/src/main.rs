@@ -1,22 +1,96 @@
-use console::Emoji;
 use humansize::BINARY;
 use std::{
-    fs::{self, File, OpenOptions},
-    io::{Read, Seek, Write},
-    path::PathBuf,
+    collections::BTreeMap,
+    fs::{self, File},
+    io::Read,
+    path::{Component, Path, PathBuf},
     process::exit,
+    sync::atomic::{AtomicBool, Ordering},
+    sync::{Arc, OnceLock},
 };
 
 use clap::Parser;
-use depot_core::depot_handle::DepotHandle;
+use depot_core::catalog::Catalog;
+use depot_core::creator::CreatorInfo;
+use depot_core::crypt::EncryptionKey;
+use depot_core::depot_handle::{
+    ChangePolicy, Compression, DepotHandle, EntryInfo, RatioAction, RatioGuard, StreamInfo,
+};
+use depot_core::dict::Dictionary;
+use depot_core::error::DepotError;
+use depot_core::extract::{is_root, strip_components, ExtractOptions, IdMap};
+use depot_core::interop::oci::{self, OciLayerOptions};
+use depot_core::lint::{self, LintReport, Severity};
+use depot_core::package::{self, PackageInfo};
+use depot_core::policy::{ContentPolicy, PolicyAction};
+use depot_core::repack::{RepackOptions, RepackOrder};
+use depot_core::solid::SolidOptions;
+use depot_core::temp::TempPolicy;
+use depot_core::trace::AccessTrace;
+use depot_core::volume::{self, Volumes};
+use depot_core::TsWithTz;
+use i18n::{tr, Lang};
+use progress::{ProgressMode, Reporter};
+use regex::Regex;
+
+mod grep;
+mod i18n;
+mod progress;
+mod ui;
+
+/// Largest stream `show` loads, it is meant for small text streams
+const SHOW_MAX_BYTES: u64 = 16 * 1024 * 1024;
 
-const PACKAGE: Emoji<'_, '_> = Emoji("📦 ", "[||] ");
+/// Set by `--ignore-version`, see [`open_read`]
+static IGNORE_VERSION: AtomicBool = AtomicBool::new(false);
+
+/// Set by `--force-unfreeze` for actions changing the depot, see
+/// [`open_write`]
+static FORCE_UNFREEZE: AtomicBool = AtomicBool::new(false);
+
+/// Read from `--key-file`, see [`encryption_key`]
+static ENCRYPTION_KEY: OnceLock<EncryptionKey> = OnceLock::new();
 
 #[derive(Debug, Parser)]
 #[command(author, version, about)]
 struct Arguments {
     /// depot path
     path: PathBuf,
+    /// only print warnings, errors and requested data
+    #[clap(short, long, global = true)]
+    quiet: bool,
+    /// disable progress reporting, same as `--progress none`
+    #[clap(long, global = true)]
+    no_progress: bool,
+    /// how progress is reported, `json-lines` prints one JSON event per line
+    #[clap(long, value_enum, global = true, default_value = "bar")]
+    progress: ProgressMode,
+    /// disable colored output, also honoured through the `NO_COLOR` variable
+    #[clap(long, global = true)]
+    no_color: bool,
+    /// language of messages, detected from `LC_ALL`/`LC_MESSAGES`/`LANG`
+    /// when not given
+    #[clap(long, value_enum, global = true)]
+    lang: Option<Lang>,
+    /// write intermediates like snapshots and compacted copies only to this
+    /// directory
+    #[clap(long, global = true)]
+    temp_dir: Option<PathBuf>,
+    /// never write intermediates, snapshots fail and compaction moves
+    /// entries within the depot
+    #[clap(long, global = true, conflicts_with = "temp_dir")]
+    no_temp: bool,
+    /// open depots of unsupported format versions for reading anyway, for
+    /// inspecting damaged or newer depots
+    #[clap(long, global = true)]
+    ignore_version: bool,
+    /// key to decrypt encrypted entries with, and to encrypt entries with
+    /// `bake --encrypt` and `update`, 32 raw bytes or 64 hex digits
+    #[clap(long, global = true, value_name = "PATH")]
+    key_file: Option<PathBuf>,
+    /// lift the freeze of a frozen depot before changing it, see `freeze`
+    #[clap(long, global = true)]
+    force_unfreeze: bool,
     /// action
     #[clap(subcommand)]
     action: Action,
@@ -26,6 +100,8 @@ struct Arguments {
 enum Action {
     /// create a new depot
     Bake(CreateArgs),
+    /// add files to an existing depot
+    Append(CreateArgs),
     /// list all streams in a depot
     List(ListArgs),
     /// extraction functionality
@@ -36,8 +112,208 @@ enum Action {
     /// (useful for small text streams)
     /// there is no telling what will happen if you try to show a binary stream
     Show(ExtractArgs),
+    /// search the contents of streams for a regular expression, exits with
+    /// status 1 when nothing matched
+    Grep(GrepArgs),
+    /// search the decompressed streams for byte patterns and print the
+    /// offsets they were found at, exits with status 1 when none was found
+    Scan(ScanArgs),
     /// print the table of contents
     PrintToc,
+    /// print the format version, size and the tool that wrote the depot
+    Info,
+    /// print how much of the file is dead space and where it lies, e.g.
+    /// the data of removed streams
+    Du,
+    /// export the depot as an OCI image layer (tar.gz)
+    ExportOci(ExportOciArgs),
+    /// check the archive for waste, sloppy names and outdated settings
+    Lint(LintArgs),
+    /// decompress every stream and check its size and hash, exits with an
+    /// error status if any is corrupted
+    Verify(VerifyArgs),
+    /// install a package depot, running its scripts and recording
+    /// an uninstall manifest
+    Install(InstallArgs),
+    /// remove streams from a depot, their data is left as dead space
+    /// unless --compact is given
+    Remove(RemoveArgs),
+    /// replace the contents of a stream with a file
+    Update(UpdateArgs),
+    /// write the streams into a new depot, copying their compressed data or
+    /// compressing them again with other settings
+    Repack(RepackArgs),
+    /// index many depots in a catalog stored at the depot path, or find
+    /// which depot holds an asset
+    #[clap(subcommand)]
+    Catalog(CatalogAction),
+    /// read or change the user attributes of the depot or of a stream,
+    /// e.g. a build id or `locale=ja`
+    #[clap(subcommand)]
+    Meta(MetaAction),
+    /// seal the depot, its hash is recorded and it can not be changed
+    /// anymore unless --force-unfreeze is given
+    Freeze,
+}
+
+impl Action {
+    /// true for the actions changing an existing depot
+    fn changes_depot(&self) -> bool {
+        matches!(
+            self,
+            Action::Append(_)
+                | Action::Remove(_)
+                | Action::Update(_)
+                | Action::Meta(MetaAction::Set { .. } | MetaAction::Remove { .. })
+        )
+    }
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum MetaAction {
+    /// print every attribute as `key=value`, or only the value of KEY
+    Get {
+        /// attribute to print
+        key: Option<String>,
+        /// the attributes of this stream instead of those of the depot
+        #[clap(long)]
+        entry: Option<String>,
+    },
+    /// set an attribute
+    Set {
+        key: String,
+        value: String,
+        /// set it on this stream instead of on the depot
+        #[clap(long)]
+        entry: Option<String>,
+    },
+    /// remove an attribute
+    Remove {
+        key: String,
+        /// remove it from this stream instead of from the depot
+        #[clap(long)]
+        entry: Option<String>,
+    },
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum CatalogAction {
+    /// catalog every depot below a directory
+    Build {
+        /// directory holding the depots
+        dir: PathBuf,
+    },
+    /// list the catalogued depots containing a stream
+    Find {
+        /// stream name
+        name: String,
+    },
+    /// list the catalogued depots missing or differing below a directory,
+    /// exits with 1 if there are any
+    Outdated {
+        /// directory holding the installed depots
+        dir: PathBuf,
+    },
+}
+
+#[derive(Debug, Parser)]
+struct RemoveArgs {
+    /// streams to remove
+    #[clap(required = true)]
+    streams: Vec<String>,
+    /// rewrite the depot afterwards to reclaim the space of removed data
+    #[clap(long)]
+    compact: bool,
+}
+
+#[derive(Debug, Parser)]
+struct UpdateArgs {
+    /// stream to replace
+    stream: String,
+    /// file holding the new contents
+    file: PathBuf,
+    /// rewrite the depot afterwards to reclaim the space of the old data
+    #[clap(long)]
+    compact: bool,
+}
+
+#[derive(Debug, Parser)]
+struct RepackArgs {
+    /// path of the new depot
+    output: PathBuf,
+    /// decompress every stream and compress it again with the settings
+    /// below, otherwise the compressed data is copied as is
+    #[clap(long)]
+    recompress: bool,
+    /// compression level
+    #[clap(short, long, default_value = "10", requires = "recompress")]
+    level: i32,
+    /// compression algorithm
+    #[clap(long, value_enum, default_value = "zstd", requires = "recompress")]
+    compression: CompressionArg,
+    /// frame size for compression
+    /// (the default is 8MiB)
+    #[clap(short, long, default_value = "8388608", requires = "recompress")]
+    frame_size: usize,
+    /// compress every this many bytes of a stream into an independent frame
+    #[clap(long, requires = "recompress")]
+    independent_frames: Option<usize>,
+    /// threads zstd compresses large streams with
+    #[clap(short, long, default_value = "4")]
+    threads: usize,
+    /// encrypt the streams with the key given by --key-file
+    #[clap(long, requires = "recompress")]
+    encrypt: bool,
+    /// store streams identical to one already repacked only once
+    #[clap(long)]
+    dedup: bool,
+    /// only repack the streams matching this pattern, `*` stays within a
+    /// directory and `**` crosses them
+    #[clap(long)]
+    glob: Vec<String>,
+    /// order the streams are written in
+    #[clap(long, value_enum, default_value = "stored")]
+    order: OrderArg,
+    /// write the streams read in this access trace first, hottest first
+    #[clap(long, value_name = "PATH", conflicts_with = "order")]
+    trace: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum OrderArg {
+    /// the order they are stored in
+    Stored,
+    /// name order
+    Name,
+}
+
+#[derive(Debug, Parser)]
+struct LintArgs {
+    /// output format
+    #[clap(long, value_enum, default_value = "text")]
+    format: OutputFormat,
+    /// exit with an error status on warnings too
+    #[clap(long)]
+    deny_warnings: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Parser)]
+struct InstallArgs {
+    /// install prefix, defaults to the prefix recorded in the package
+    #[clap(short, long)]
+    prefix: Option<PathBuf>,
+    /// do not run the pre/post install scripts
+    #[clap(long)]
+    no_scripts: bool,
+    /// restore numeric ids as recorded instead of resolving user/group names
+    #[clap(long)]
+    numeric_owner: bool,
 }
 
 #[derive(Debug, Parser)]
@@ -50,17 +326,221 @@ struct CreateArgs {
     /// compression level
     #[clap(short, long, default_value = "10")]
     level: i32,
+    /// compression algorithm
+    #[clap(long, value_enum, default_value = "zstd")]
+    compression: CompressionArg,
+    /// store files with these extensions uncompressed, e.g. `png,mp4`
+    #[clap(long, value_delimiter = ',')]
+    store: Vec<String>,
+    /// compress entries starting with PREFIX with a shared zstd dictionary,
+    /// read from FILE or trained on the files baked under the prefix, e.g.
+    /// `--dict config/ --dict shaders/=shaders.dict`
+    #[clap(long, value_name = "PREFIX[=FILE]")]
+    dict: Vec<String>,
+    /// encrypt the added files with the key given by --key-file
+    #[clap(long)]
+    encrypt: bool,
+    /// store files identical to one already in the depot only once
+    #[clap(long)]
+    dedup: bool,
     /// frame size for compression
-    /// (the default is 8MB)
-    #[clap(short, long, default_value = "8523874304")]
+    /// (the default is 8MiB)
+    #[clap(short, long, default_value = "8388608")]
     frame_size: usize,
-    /// threads to use for compression
+    /// threads to use for compression, small files are compressed several
+    /// at once and large ones by zstd's own threads
+    #[clap(short, long, default_value = "4")]
+    threads: usize,
+    /// read additional paths to add from a file, one per line,
+    /// use `-` to read from stdin
+    #[clap(short = 'T', long)]
+    files_from: Option<PathBuf>,
+    /// paths read by --files-from are NUL delimited instead of newline delimited
+    #[clap(short = '0', long, requires = "files_from")]
+    null: bool,
+    /// change to this directory before adding files, entry names are
+    /// relative to it
+    #[clap(short = 'C', long)]
+    chdir: Option<PathBuf>,
+    /// keep absolute paths outside the add root as entry names instead of
+    /// refusing them
+    #[clap(long)]
+    allow_absolute: bool,
+    /// flag entries whose compressed size exceeds their size times this factor
+    #[clap(long)]
+    max_ratio: Option<f64>,
+    /// what to do with entries exceeding --max-ratio
+    #[clap(long, value_enum, default_value = "store", requires = "max_ratio")]
+    on_expand: ExpandAction,
+    /// hold the added files against the content policy in this TOML file,
+    /// which denies names, caps sizes per extension and scans for secrets
+    #[clap(long, value_name = "PATH")]
+    policy: Option<PathBuf>,
+    /// split the depot into parts of this size, e.g. `2G`, written to
+    /// `<path>.001`, `<path>.002` and so on. Only when baking, appending
+    /// keeps the part size of the depot
+    #[clap(long, value_name = "SIZE", value_parser = parse_size)]
+    split_size: Option<u64>,
+    /// compress files of up to this size together in shared solid blocks,
+    /// e.g. `64K`, which gives far better ratios on many small files
+    #[clap(long, value_name = "SIZE", value_parser = parse_size)]
+    solid_threshold: Option<u64>,
+    /// uncompressed size solid blocks are filled up to
+    #[clap(
+        long,
+        value_name = "SIZE",
+        value_parser = parse_size,
+        default_value = "4M",
+        requires = "solid_threshold"
+    )]
+    solid_block_size: u64,
+    /// keep files that change while they are read, recording the bytes
+    /// actually read, instead of aborting the bake
+    #[clap(long)]
+    tolerate_changes: bool,
+    /// reflink or copy every file to a private snapshot before compressing
+    /// it, for consistent bakes of directories that are in use
+    #[clap(long)]
+    snapshot: bool,
+    /// compress every this many bytes of an entry into an independent frame,
+    /// so large entries can be extracted by several threads
+    #[clap(long)]
+    independent_frames: Option<usize>,
+    /// bake a perfect hash index over the entry names for constant time lookups
+    #[clap(long)]
+    perfect_hash: bool,
+    /// bake a directory index so directories are listed without a scan
+    #[clap(long)]
+    dir_index: bool,
+    /// bake a package, storing a manifest with this package name
+    #[clap(long)]
+    package_name: Option<String>,
+    /// package version recorded in the manifest
+    #[clap(long, default_value = "0.0.0", requires = "package_name")]
+    package_version: String,
+    /// default prefix the package installs to
+    #[clap(long, default_value = "/usr/local", requires = "package_name")]
+    install_prefix: PathBuf,
+    /// shell script run before the package files are installed
+    #[clap(long, requires = "package_name")]
+    pre_install: Option<PathBuf>,
+    /// shell script run after the package files are installed
+    #[clap(long, requires = "package_name")]
+    post_install: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum CompressionArg {
+    /// zstd at the given level
+    Zstd,
+    /// lz4, faster to decompress at a worse ratio
+    Lz4,
+    /// store entries as is
+    None,
+}
+
+impl From<CompressionArg> for Compression {
+    fn from(compression: CompressionArg) -> Self {
+        match compression {
+            CompressionArg::Zstd => Compression::Zstd,
+            CompressionArg::Lz4 => Compression::Lz4,
+            CompressionArg::None => Compression::None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum ExpandAction {
+    /// print a warning and keep the compressed entry
+    Warn,
+    /// abort the bake
+    Error,
+    /// store the entry uncompressed
+    Store,
+}
+
+impl From<ExpandAction> for RatioAction {
+    fn from(action: ExpandAction) -> Self {
+        match action {
+            ExpandAction::Warn => RatioAction::Warn,
+            ExpandAction::Error => RatioAction::Error,
+            ExpandAction::Store => RatioAction::Store,
+        }
+    }
+}
+
+#[derive(Debug, Parser)]
+struct VerifyArgs {
+    /// threads decompressing streams, each reads the depot on its own
+    #[clap(short, long, default_value = "4")]
+    threads: usize,
+}
+
+#[derive(Debug, Parser)]
+struct GrepArgs {
+    /// regular expression searched for in every line
+    pattern: String,
+    /// only search the streams matching this pattern, `*` stays within a
+    /// directory and `**` crosses them, e.g. `config/**/*.toml`
+    #[clap(long)]
+    glob: Vec<String>,
+    /// lines of context printed before and after every match
+    #[clap(short = 'C', long, default_value = "0")]
+    context: usize,
+    /// match regardless of case
+    #[clap(short, long)]
+    ignore_case: bool,
+    /// only print the names of matching streams
+    #[clap(short = 'l', long)]
+    files_with_matches: bool,
+    /// threads decompressing streams, each reads the depot on its own
     #[clap(short, long, default_value = "4")]
     threads: usize,
 }
 
 #[derive(Debug, Parser)]
-struct ListArgs {}
+struct ScanArgs {
+    /// bytes to search for as hex digits, e.g. `DEADBEEF` or `de:ad:be:ef`
+    #[clap(long, required_unless_present = "text")]
+    hex: Vec<String>,
+    /// text to search for, matched as its UTF-8 bytes
+    #[clap(long)]
+    text: Vec<String>,
+    /// only scan the streams matching this pattern, `*` stays within a
+    /// directory and `**` crosses them
+    #[clap(long)]
+    glob: Vec<String>,
+    /// also scan internal streams in the reserved `.depot/` namespace
+    #[clap(short, long, conflicts_with = "glob")]
+    all: bool,
+    /// threads decompressing streams, each reads the depot on its own
+    #[clap(short, long, default_value = "4")]
+    threads: usize,
+}
+
+#[derive(Debug, Parser)]
+struct ListArgs {
+    /// also list internal streams in the reserved `.depot/` namespace
+    #[clap(short, long)]
+    all: bool,
+    /// show timestamps in UTC instead of the zone they were recorded in
+    #[clap(long, conflicts_with = "local")]
+    utc: bool,
+    /// show timestamps in the local time zone
+    #[clap(long)]
+    local: bool,
+    /// print timestamps as ISO 8601
+    #[clap(long)]
+    iso8601: bool,
+    /// only list the direct children of this directory, subdirectories are
+    /// printed with a trailing `/`
+    #[clap(long)]
+    dir: Option<String>,
+    /// only list the streams matching this pattern, `*` stays within a
+    /// directory and `**` crosses them, e.g. `assets/**/*.png`
+    #[clap(long, conflicts_with = "dir")]
+    glob: Vec<String>,
+}
 
 #[derive(Debug, Parser)]
 struct ExtractArgs {
@@ -69,223 +549,1597 @@ struct ExtractArgs {
     output: PathBuf,
     /// streams to extract
     streams: Vec<PathBuf>,
+    /// also take the streams matching this pattern, `*` stays within a
+    /// directory and `**` crosses them, e.g. `assets/textures/**`
+    #[clap(long, conflicts_with = "catalog")]
+    glob: Vec<String>,
+    /// take every stream, recreating the whole tree
+    #[clap(short, long, conflicts_with_all = ["streams", "glob", "catalog"])]
+    all: bool,
+    /// only print where each stream would be extracted to
+    #[clap(short = 'n', long)]
+    dry_run: bool,
+    /// strip N leading path components from stream names,
+    /// streams with fewer components are skipped
+    #[clap(long, default_value = "0")]
+    strip_components: usize,
+    /// rewrite stream names with a sed style expression
+    /// e.g. `s/^assets/content/`, applied after --strip-components
+    #[clap(long)]
+    transform: Option<String>,
+    /// restore recorded file ownership (default when running as root)
+    #[clap(long, overrides_with = "no_same_owner")]
+    same_owner: bool,
+    /// extract files as the current user (default for regular users)
+    #[clap(long)]
+    no_same_owner: bool,
+    /// restore numeric ids as recorded instead of resolving user/group names
+    #[clap(long)]
+    numeric_owner: bool,
+    /// leave extracted files with default permissions instead of the
+    /// recorded ones
+    #[clap(long)]
+    no_same_permissions: bool,
+    /// force the owner of extracted files, e.g. `root:root` or `1000:1000`
+    #[clap(long)]
+    owner: Option<String>,
+    /// file with `uid OLD NEW` / `gid OLD NEW` lines remapping recorded ids
+    #[clap(long)]
+    owner_map: Option<PathBuf>,
+    /// threads writing extracted files, entries are always read in
+    /// archive order
+    #[clap(short, long, default_value = "4")]
+    threads: usize,
+    /// reserve the full size of each output file before writing it
+    #[clap(long)]
+    preallocate: bool,
+    /// resolve the streams through a catalog, the depot path is then the
+    /// directory the catalog was built from
+    #[clap(long)]
+    catalog: Option<PathBuf>,
+}
+
+#[derive(Debug, Parser)]
+struct ExportOciArgs {
+    /// output layer path
+    #[clap(short, long)]
+    output: PathBuf,
+    /// path deleted relative to the lower layers, emitted as a whiteout
+    #[clap(long = "whiteout")]
+    deletions: Vec<String>,
+    /// directory whose lower layer contents are hidden (opaque whiteout)
+    #[clap(long = "opaque-dir")]
+    opaque_dirs: Vec<String>,
+    /// directory inside the layer to place the depot contents under
+    #[clap(long)]
+    prefix: Option<PathBuf>,
 }
 
 fn main() {
     let args = Arguments::parse();
-    println!("Depot CLI tools {}", env!("CARGO_PKG_VERSION"));
-    println!("Copyright (C) 2023, NeonLayer");
+    i18n::init(args.lang);
+    IGNORE_VERSION.store(args.ignore_version, Ordering::Relaxed);
+    if let Some(path) = &args.key_file {
+        let key = fs::read(path)
+            .map_err(DepotError::from)
+            .and_then(|data| EncryptionKey::from_key_file(&data))
+            .unwrap_or_else(|e| {
+                ui::error(tr!("error.key_file", ui::name(path.display()), e));
+                exit(1)
+            });
+        let _ = ENCRYPTION_KEY.set(key);
+    }
+    let mode = if args.no_progress {
+        ProgressMode::None
+    } else {
+        args.progress
+    };
+    let machine_output = matches!(&args.action, Action::Lint(l) if l.format == OutputFormat::Json);
+    let reporter = Reporter::new(mode, args.quiet || machine_output);
+    ui::init(args.no_color, machine_output || reporter.is_json());
+    if !reporter.is_json() {
+        reporter.info(tr!("banner", env!("CARGO_PKG_VERSION")));
+        reporter.info("Copyright (C) 2023, NeonLayer");
+    }
 
+    let temp = match (&args.temp_dir, args.no_temp) {
+        // resolved now, bake may change into another directory
+        (Some(dir), _) => TempPolicy::Dir(std::path::absolute(dir).unwrap()),
+        (None, true) => TempPolicy::Never,
+        (None, false) => TempPolicy::System,
+    };
+    // the freeze is lifted by the change itself, a failing action keeps it
+    let unfreezing = args.force_unfreeze && args.action.changes_depot() && is_frozen(&args.path);
+    FORCE_UNFREEZE.store(unfreezing, Ordering::Relaxed);
     match args.action {
-        Action::Bake(cmd_args) => {
-            let paths = expand_path(cmd_args.files.clone(), cmd_args.recurse);
-            println!(
-                "\n{}adding {} files to `{}`",
-                PACKAGE,
-                paths.len(),
-                args.path.display()
-            );
-            new_depot(
-                &args.path,
-                paths,
-                cmd_args.level,
-                cmd_args.threads,
-                cmd_args.frame_size,
-            )
-            .unwrap();
-            println!("{}created depot at `{}`", PACKAGE, args.path.display());
-        }
-        Action::List(_cmd_args) => {
-            println!("{}listing contents of `{}`\n", PACKAGE, args.path.display());
-            ls_contents(&args.path);
+        Action::Bake(cmd_args) => bake(&args.path, &cmd_args, false, &temp, &reporter),
+        Action::Append(cmd_args) => bake(&args.path, &cmd_args, true, &temp, &reporter),
+        Action::List(cmd_args) => {
+            reporter.info(ui::headline(tr!(
+                "list.listing",
+                ui::name(args.path.display())
+            )));
+            ls_contents(&args.path, &cmd_args);
         }
         Action::Extract(cmd_args) => {
-            println!(
-                "{}extracting `{}` to `{}`",
-                PACKAGE,
-                args.path.display(),
-                cmd_args.output.display()
-            );
-            extract_files(&args.path, &cmd_args.streams, &cmd_args.output);
+            reporter.info(ui::headline(tr!(
+                "extract.extracting",
+                ui::name(args.path.display()),
+                ui::name(cmd_args.output.display())
+            )));
+            let options = extract_options(&cmd_args);
+            if let Some(catalog) = &cmd_args.catalog {
+                extract_from_catalog(catalog, &args.path, &cmd_args, &options, &reporter);
+            } else if cmd_args.all {
+                extract_all(&args.path, &cmd_args.output, &options, &reporter);
+            } else {
+                extract_files(
+                    &args.path,
+                    &stream_names(&args.path, &cmd_args),
+                    &cmd_args.output,
+                    &options,
+                    &reporter,
+                );
+            }
         }
         Action::Carve(cmd_args) => {
+            reporter.info(ui::headline(tr!(
+                "carve.carving",
+                ui::name(args.path.display()),
+                ui::name(cmd_args.output.display())
+            )));
+            let streams = stream_names(&args.path, &cmd_args);
+            carve_files(&args.path, &streams, &cmd_args.output, &reporter);
+        }
+        Action::Info => {
+            let mut dh = open_read(&args.path);
+            println!("{}", tr!("info.format", dh.format_version()));
+            println!("{}", tr!("info.fingerprint", dh.archive_fingerprint()));
             println!(
-                "{}carving `{}` to `{}`",
-                PACKAGE,
-                args.path.display(),
-                cmd_args.output.display()
+                "{}",
+                tr!(
+                    "info.entries",
+                    dh.streams().count(),
+                    humansize::format_size(dh.streams().map(|(_, e)| e.size).sum::<u64>(), BINARY)
+                )
             );
-            carve_files(&args.path, &cmd_args.streams, &cmd_args.output);
+            match dh.creator().unwrap() {
+                Some(creator) => println!(
+                    "{}",
+                    tr!(
+                        "info.creator",
+                        creator.tool,
+                        creator.tool_version,
+                        creator.library_version,
+                        creator.platform,
+                        creator.format_version
+                    )
+                ),
+                None => println!("{}", tr!("info.no_creator")),
+            }
+            for (key, value) in dh.archive_meta().user_attrs() {
+                let value = String::from_utf8_lossy(value);
+                println!("{}", tr!("info.meta", key, value));
+            }
+            if let Some(trailer) = dh.frozen() {
+                println!("{}", tr!("info.frozen", trailer.hash_hex()));
+            }
         }
-        Action::PrintToc => {
+        Action::Du => {
+            let size = depot_size(&args.path).unwrap();
+            let regions = open_read(&args.path).dead_regions().unwrap();
+            let dead: u64 = regions.iter().map(|r| r.end - r.start).sum();
             println!(
-                "{}printing table of contents for `{}`",
-                PACKAGE,
-                args.path.display()
+                "{}",
+                tr!(
+                    "du.summary",
+                    humansize::format_size(dead, BINARY),
+                    humansize::format_size(size, BINARY),
+                    format!("{:.1}", dead as f64 / size.max(1) as f64 * 100.0),
+                    regions.len()
+                )
             );
-            let dh = DepotHandle::open_file(&args.path, depot_core::depot_handle::OpenMode::Read)
-                .unwrap();
+            for region in regions {
+                println!(
+                    "{}",
+                    tr!(
+                        "du.region",
+                        region.start,
+                        region.end,
+                        humansize::format_size(region.end - region.start, BINARY)
+                    )
+                );
+            }
+        }
+        Action::PrintToc => {
+            reporter.info(ui::headline(tr!(
+                "toc.printing",
+                ui::name(args.path.display())
+            )));
+            let dh = open_read(&args.path);
             let toc = dh.get_toc();
             println!("{:#?}", toc);
         }
+        Action::ExportOci(cmd_args) => {
+            reporter.info(ui::headline(tr!(
+                "oci.exporting",
+                ui::name(args.path.display()),
+                ui::name(cmd_args.output.display())
+            )));
+            let mut dh = open_read(&args.path);
+            let options = OciLayerOptions {
+                deletions: cmd_args.deletions,
+                opaque_dirs: cmd_args.opaque_dirs,
+                prefix: cmd_args.prefix,
+                ..Default::default()
+            };
+            let fh = File::create(&cmd_args.output).unwrap();
+            let layer = oci::export_layer(&mut dh, std::io::BufWriter::new(fh), &options).unwrap();
+            reporter.info(tr!("oci.media_type", oci::MEDIA_TYPE));
+            reporter.info(tr!("oci.digest", layer.digest));
+            reporter.info(tr!("oci.diff_id", layer.diff_id));
+            reporter.info(tr!("oci.size", layer.size));
+        }
+        Action::Lint(cmd_args) => {
+            let report = match volume::split_base(&args.path) {
+                Some(base) => Volumes::open(base, false)
+                    .and_then(|volumes| lint::lint(std::io::BufReader::new(volumes))),
+                None => File::open(&args.path)
+                    .map_err(DepotError::from)
+                    .and_then(|fh| lint::lint(std::io::BufReader::new(fh))),
+            }
+            .unwrap();
+            print_lint_report(&report, cmd_args.format);
+            let threshold = if cmd_args.deny_warnings {
+                Severity::Warning
+            } else {
+                Severity::Error
+            };
+            if report.worst().is_some_and(|worst| worst >= threshold) {
+                exit(1)
+            }
+        }
+        Action::Verify(cmd_args) => {
+            reporter.info(ui::headline(tr!(
+                "verify.verifying",
+                ui::name(args.path.display())
+            )));
+            verify(&args.path, cmd_args.threads, &reporter);
+        }
+        Action::Install(cmd_args) => {
+            let mut dh = open_read(&args.path);
+            let options = ExtractOptions {
+                restore_owner: is_root(),
+                numeric_owner: cmd_args.numeric_owner,
+                restore_mode: true,
+                ..Default::default()
+            };
+            let receipt = package::install(
+                &mut dh,
+                cmd_args.prefix.as_deref(),
+                &options,
+                !cmd_args.no_scripts,
+            )
+            .unwrap();
+            reporter.info(ui::headline(tr!(
+                "install.installed",
+                receipt.package.name,
+                receipt.package.version,
+                receipt.files.len(),
+                ui::name(receipt.prefix.display())
+            )));
+            reporter.info(tr!(
+                "install.manifest",
+                ui::name(receipt.manifest.display())
+            ));
+        }
+        Action::Remove(cmd_args) => {
+            let mut dh = open_write(&args.path);
+            dh.set_creator(creator());
+            for name in &cmd_args.streams {
+                dh.remove_stream(name).unwrap_or_else(|e| {
+                    ui::error(e);
+                    exit(1)
+                });
+                reporter.info(tr!("remove.removed", ui::name(name)));
+            }
+            dh.close().unwrap();
+            if cmd_args.compact {
+                compact(&args.path, &temp, &reporter);
+            }
+        }
+        Action::Update(cmd_args) => {
+            let mut dh = open_write(&args.path);
+            dh.set_creator(creator());
+            dh.set_encryption_key(encryption_key());
+            let fh = File::open(&cmd_args.file).unwrap_or_else(|e| {
+                ui::error(tr!("error.open", ui::name(cmd_args.file.display()), e));
+                exit(1)
+            });
+            dh.replace_stream(&cmd_args.stream, std::io::BufReader::new(fh))
+                .unwrap_or_else(|e| {
+                    ui::error(e);
+                    exit(1)
+                });
+            for warning in dh.take_warnings() {
+                reporter.warn(warning);
+            }
+            dh.close().unwrap();
+            reporter.info(tr!(
+                "update.updated",
+                ui::name(&cmd_args.stream),
+                ui::name(cmd_args.file.display())
+            ));
+            if cmd_args.compact {
+                compact(&args.path, &temp, &reporter);
+            }
+        }
+        Action::Repack(cmd_args) => repack(&args.path, &cmd_args, &reporter),
+        Action::Catalog(CatalogAction::Build { dir }) => {
+            reporter.info(ui::headline(tr!(
+                "catalog.building",
+                ui::name(dir.display()),
+                ui::name(args.path.display())
+            )));
+            let catalog = Catalog::build(&dir).unwrap();
+            catalog.save(&args.path).unwrap();
+            let entries: usize = catalog.archives.iter().map(|a| a.entries.len()).sum();
+            reporter.info(tr!("catalog.built", catalog.archives.len(), entries));
+        }
+        Action::Catalog(CatalogAction::Find { name }) => {
+            let catalog = Catalog::open(&args.path).unwrap();
+            let hits = catalog.find(&name);
+            if hits.is_empty() {
+                ui::error(tr!("catalog.not_found", ui::name(&name)));
+                exit(1)
+            }
+            for hit in hits {
+                println!(
+                    "{}",
+                    tr!(
+                        "catalog.hit",
+                        hit.archive.path,
+                        hit.entry.name,
+                        hit.entry.size,
+                        format!("{:016x}", hit.entry.hash)
+                    )
+                );
+            }
+        }
+        Action::Catalog(CatalogAction::Outdated { dir }) => {
+            let catalog = Catalog::open(&args.path).unwrap();
+            let outdated = catalog.outdated(&dir).unwrap_or_else(|e| {
+                ui::error(e);
+                exit(1)
+            });
+            if outdated.is_empty() {
+                reporter.info(tr!("catalog.up_to_date", ui::name(dir.display())));
+            } else {
+                for archive in outdated {
+                    println!("{}", tr!("catalog.outdated", archive.path));
+                }
+                exit(1)
+            }
+        }
+        Action::Meta(action) => meta(&args.path, &action, &reporter),
+        Action::Freeze => {
+            let mut dh = open_write(&args.path);
+            dh.set_creator(creator());
+            let trailer = dh.freeze().unwrap_or_else(|e| {
+                ui::error(e);
+                exit(1)
+            });
+            reporter.info(tr!(
+                "freeze.frozen",
+                ui::name(args.path.display()),
+                trailer.hash_hex()
+            ));
+        }
+        Action::Grep(cmd_args) => grep_streams(&args.path, &cmd_args),
+        Action::Scan(cmd_args) => scan_streams(&args.path, &cmd_args),
         Action::Show(cmd_args) => {
-            let mut dh =
-                DepotHandle::open_file(&args.path, depot_core::depot_handle::OpenMode::Read)
-                    .unwrap();
-            for item in &cmd_args.streams {
+            let mut dh = open_read(&args.path);
+            for item in stream_names(&args.path, &cmd_args) {
                 let stream = dh.get_named_stream(&item.to_string_lossy()).unwrap();
-                let contents = dh.stream_to_memory(&stream).unwrap();
-                println!("Start of {}", stream.name);
+                let contents = dh
+                    .stream_to_memory_limited(&stream, SHOW_MAX_BYTES)
+                    .unwrap_or_else(|e| {
+                        ui::error(e);
+                        exit(1)
+                    });
+                println!("{}", tr!("show.start", stream.name));
                 println!("----------------");
                 println!("{}", String::from_utf8_lossy(&contents));
                 println!("----------------");
-                println!("End of {}", stream.name);
+                println!("{}", tr!("show.end", stream.name));
             }
         }
     }
+    if unfreezing {
+        reporter.info(tr!("freeze.unfrozen", ui::name(args.path.display())));
+    }
 }
 
-fn carve_files(path: &PathBuf, streams: &[PathBuf], output: &PathBuf) {
-    let dh = DepotHandle::open_file(path, depot_core::depot_handle::OpenMode::Read).unwrap();
-    let mut dh_file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .open(path)
-        .unwrap();
-    let mut bufrd = std::io::BufReader::new(&mut dh_file);
+/// true if the depot at `path` is frozen, see `freeze`
+fn is_frozen(path: &Path) -> bool {
+    DepotHandle::open_file(path, depot_core::depot_handle::OpenMode::Read)
+        .is_ok_and(|dh| dh.frozen().is_some_and(|trailer| trailer.is_immutable()))
+}
+
+/// Opens the depot at `path` for reading, any format version is accepted
+/// with `--ignore-version` and encrypted entries are decrypted with the key
+/// of `--key-file`. Exits with the error if it can not be opened
+fn open_read(path: &Path) -> DepotHandle<'static> {
+    let opened = match IGNORE_VERSION.load(Ordering::Relaxed) {
+        true => match volume::split_base(path) {
+            Some(base) => Volumes::open(base, false).and_then(DepotHandle::open_read_any_version),
+            None => File::open(path)
+                .map_err(DepotError::from)
+                .and_then(DepotHandle::open_read_any_version),
+        },
+        false => DepotHandle::open_file(path, depot_core::depot_handle::OpenMode::Read),
+    };
+    let mut dh = opened.unwrap_or_else(|e| {
+        ui::error(&e);
+        if let DepotError::UnsupportedVersion { .. } = e {
+            eprintln!("{}", tr!("hint.ignore_version"));
+        }
+        exit(1)
+    });
+    dh.set_encryption_key(encryption_key());
+    dh
+}
+
+/// Opens the depot at `path` for changing it, a frozen depot only with
+/// `--force-unfreeze`. Exits with the error if it can not be opened
+fn open_write(path: &Path) -> DepotHandle<'static> {
+    let mode = depot_core::depot_handle::OpenMode::ReadWrite;
+    let opened = match FORCE_UNFREEZE.load(Ordering::Relaxed) {
+        true => DepotHandle::open_file_unfreezing(path, mode),
+        false => DepotHandle::open_file(path, mode),
+    };
+    opened.unwrap_or_else(|e| {
+        ui::error(tr!("error.open", ui::name(path.display()), &e));
+        if let DepotError::Frozen = e {
+            eprintln!("{}", tr!("hint.force_unfreeze"));
+        }
+        exit(1)
+    })
+}
+
+/// The key read from `--key-file`, if one was given
+fn encryption_key() -> Option<EncryptionKey> {
+    ENCRYPTION_KEY.get().cloned()
+}
+
+/// The creator recorded in depots this tool writes
+fn creator() -> CreatorInfo {
+    CreatorInfo::for_tool(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+}
+
+/// Size of the depot at `path`, of all its parts when it is split
+fn depot_size(path: &Path) -> Result<u64, DepotError> {
+    match volume::split_base(path) {
+        Some(base) => Volumes::open(base, false)?.size(),
+        None => Ok(fs::metadata(path)?.len()),
+    }
+}
+
+fn compact(path: &Path, temp: &TempPolicy, reporter: &Reporter) {
+    let before = depot_size(path).unwrap();
+    DepotHandle::compact_file(path, creator(), temp).unwrap();
+    let after = depot_size(path).unwrap();
+    reporter.info(tr!(
+        "compact.compacted",
+        ui::name(path.display()),
+        before.saturating_sub(after)
+    ));
+}
+
+fn repack(depot_path: &Path, cmd_args: &RepackArgs, reporter: &Reporter) {
+    if cmd_args.encrypt && encryption_key().is_none() {
+        ui::error(tr!("error.encrypt_no_key"));
+        exit(1)
+    }
+    let order = match (&cmd_args.trace, cmd_args.order) {
+        (Some(path), _) => {
+            let trace = fs::read(path)
+                .map_err(DepotError::from)
+                .and_then(|data| AccessTrace::from_bytes(&data))
+                .unwrap_or_else(|e| {
+                    ui::error(tr!("error.trace", ui::name(path.display()), e));
+                    exit(1)
+                });
+            RepackOrder::Listed(
+                trace
+                    .entries()
+                    .iter()
+                    .map(|(name, _)| name.clone())
+                    .collect(),
+            )
+        }
+        (None, OrderArg::Stored) => RepackOrder::Stored,
+        (None, OrderArg::Name) => RepackOrder::Name,
+    };
+    let options = RepackOptions {
+        recompress: cmd_args.recompress,
+        include: cmd_args.glob.clone(),
+        order,
+    };
+    let mut dh = open_read(depot_path);
+    let mut repacked = DepotHandle::create_file(&cmd_args.output).unwrap_or_else(|e| {
+        ui::error(tr!("error.open", ui::name(cmd_args.output.display()), e));
+        exit(1)
+    });
+    repacked.set_creator(creator());
+    repacked.set_comp_level(cmd_args.level);
+    repacked.set_compression(cmd_args.compression.into());
+    repacked.set_comp_frame_size(cmd_args.frame_size);
+    repacked.set_independent_frames(cmd_args.independent_frames);
+    repacked.set_mt_threads(cmd_args.threads);
+    if cmd_args.encrypt {
+        repacked.set_encryption_key(encryption_key());
+    }
+    repacked.set_dedup(cmd_args.dedup);
+    let total_bytes = dh.streams().map(|(_, e)| e.size).sum();
+    let progress = reporter.begin_bytes("repack", dh.streams().count() as u64, total_bytes);
+    repacked.set_events(progress.events());
+    let done = dh.repack_into(&mut repacked, &options);
+    for warning in repacked.take_warnings() {
+        progress.warn(warning);
+    }
+    progress.finish();
+    if let Err(e) = done.and_then(|_| repacked.close()) {
+        ui::error(e);
+        exit(1)
+    }
+    let before = depot_size(depot_path).unwrap();
+    let after = fs::metadata(&cmd_args.output).unwrap().len();
+    reporter.info(ui::headline(tr!(
+        "repack.repacked",
+        ui::name(depot_path.display()),
+        ui::name(cmd_args.output.display()),
+        before,
+        after
+    )));
+}
+
+fn meta(depot_path: &Path, action: &MetaAction, reporter: &Reporter) {
+    match action {
+        MetaAction::Get { key, entry } => print_meta(depot_path, key.as_deref(), entry.as_deref()),
+        MetaAction::Set { key, value, entry } => {
+            change_meta(depot_path, |dh| match entry {
+                Some(name) => dh.set_entry_meta(name, key, value.as_bytes()),
+                None => dh.set_archive_meta(key, value.as_bytes()),
+            });
+            reporter.info(tr!("meta.set", ui::name(key), value));
+        }
+        MetaAction::Remove { key, entry } => change_meta(depot_path, |dh| {
+            match entry {
+                Some(name) => dh.remove_entry_meta(name, key),
+                None => dh.remove_archive_meta(key),
+            }
+            .map(drop)
+        }),
+    }
+}
+
+/// Prints the user attributes of the depot or of the stream `entry`, only
+/// the value of `key` when given
+fn print_meta(depot_path: &Path, key: Option<&str>, entry: Option<&str>) {
+    let dh = open_read(depot_path);
+    let attrs = match entry {
+        Some(name) => match dh.get_named_stream(name) {
+            Some(stream) => stream.einf.ext.clone(),
+            None => {
+                ui::error(DepotError::EntryNotFound(name.to_owned()));
+                exit(1)
+            }
+        },
+        None => dh.archive_meta().clone(),
+    };
+    let Some(key) = key else {
+        for (key, value) in attrs.user_attrs() {
+            println!("{}={}", key, String::from_utf8_lossy(value));
+        }
+        return;
+    };
+    match attrs.user(key) {
+        Some(value) => println!("{}", String::from_utf8_lossy(value)),
+        None => {
+            ui::error(tr!("meta.not_set", ui::name(key)));
+            exit(1)
+        }
+    }
+}
+
+/// Opens the depot for writing, applies `change` and closes it again
+fn change_meta(depot_path: &Path, change: impl FnOnce(&mut DepotHandle) -> Result<(), DepotError>) {
+    let mut dh = open_write(depot_path);
+    dh.set_creator(creator());
+    if let Err(e) = change(&mut dh).and_then(|_| dh.close()) {
+        ui::error(e);
+        exit(1)
+    }
+}
+
+/// The streams named on the command line followed by those matching the
+/// `--glob` patterns
+fn stream_names(depot_path: &Path, cmd_args: &ExtractArgs) -> Vec<PathBuf> {
+    let mut names = cmd_args.streams.clone();
+    if cmd_args.glob.is_empty() && !cmd_args.all {
+        return names;
+    }
+    let dh = open_read(depot_path);
+    if cmd_args.all {
+        return dh.streams().map(|(name, _)| PathBuf::from(name)).collect();
+    }
+    for pattern in &cmd_args.glob {
+        let found = dh.find_streams(pattern).unwrap_or_else(|e| {
+            ui::error(e);
+            exit(1)
+        });
+        for stream in found {
+            let name = PathBuf::from(&stream.name);
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+    names
+}
+
+/// Prints the lines of the streams matching the `grep` pattern, exits with
+/// status 1 when none matched and 2 when a stream could not be searched
+fn grep_streams(depot_path: &Path, cmd_args: &GrepArgs) {
+    let regex = regex::bytes::RegexBuilder::new(&cmd_args.pattern)
+        .case_insensitive(cmd_args.ignore_case)
+        .build()
+        .unwrap_or_else(|e| {
+            ui::error(tr!("error.pattern", ui::name(&cmd_args.pattern), e));
+            exit(2)
+        });
+    let streams = searched_streams(depot_path, &cmd_args.glob, false);
+    let options = grep::GrepOptions {
+        regex,
+        context: cmd_args.context,
+        names_only: cmd_args.files_with_matches,
+    };
+    let (mut matched, mut failed) = (false, false);
+    grep::search(
+        depot_path,
+        &streams,
+        cmd_args.threads,
+        |stream, reader| grep::grep_stream(stream, reader, &options),
+        |stream, found| match found {
+            Ok(grep::Found::Nothing) => {}
+            Ok(grep::Found::Match) => {
+                matched = true;
+                match cmd_args.files_with_matches {
+                    true => println!("{}", stream.name),
+                    false => println!("{}", tr!("grep.binary_match", ui::name(&stream.name))),
+                }
+            }
+            Ok(grep::Found::Lines(lines)) => {
+                matched = true;
+                print!("{}", lines);
+            }
+            Err(e) => {
+                failed = true;
+                ui::error(tr!("grep.failed", ui::name(&stream.name), e));
+            }
+        },
+    );
+    if failed {
+        exit(2)
+    }
+    if !matched {
+        exit(1)
+    }
+}
+
+/// Prints the offsets of the byte patterns found in the streams, exits with
+/// status 1 when none was found and 2 when a stream could not be scanned
+fn scan_streams(depot_path: &Path, cmd_args: &ScanArgs) {
+    let mut patterns = Vec::new();
+    for hex in &cmd_args.hex {
+        let bytes = parse_hex(hex).unwrap_or_else(|| {
+            ui::error(tr!("error.hex", ui::name(hex)));
+            exit(2)
+        });
+        patterns.push(grep::BytePattern {
+            label: hex.to_lowercase(),
+            bytes,
+        });
+    }
+    for text in &cmd_args.text {
+        if text.is_empty() {
+            ui::error(tr!("error.empty_pattern"));
+            exit(2)
+        }
+        patterns.push(grep::BytePattern {
+            label: format!("{:?}", text),
+            bytes: text.as_bytes().to_vec(),
+        });
+    }
+    let streams = searched_streams(depot_path, &cmd_args.glob, cmd_args.all);
+    let (mut matched, mut failed) = (false, false);
+    grep::search(
+        depot_path,
+        &streams,
+        cmd_args.threads,
+        |_, reader| grep::scan_stream(reader, &patterns),
+        |stream, found| match found {
+            Ok(found) => {
+                matched |= !found.is_empty();
+                for (index, offset) in found {
+                    println!(
+                        "{}:{:#x}:{}",
+                        ui::stream_label(&stream.name),
+                        offset,
+                        ui::matched(&patterns[index].label)
+                    );
+                }
+            }
+            Err(e) => {
+                failed = true;
+                ui::error(tr!("scan.failed", ui::name(&stream.name), e));
+            }
+        },
+    );
+    if failed {
+        exit(2)
+    }
+    if !matched {
+        exit(1)
+    }
+}
+
+/// Parses a size like `4096`, `512K` or `2G`, suffixes are binary
+fn parse_size(size: &str) -> Result<u64, String> {
+    let upper = size.trim().to_ascii_uppercase();
+    let digits = upper.trim_end_matches("IB").trim_end_matches('B');
+    let (digits, shift) = match digits.chars().last() {
+        Some('K') => (&digits[..digits.len() - 1], 10),
+        Some('M') => (&digits[..digits.len() - 1], 20),
+        Some('G') => (&digits[..digits.len() - 1], 30),
+        Some('T') => (&digits[..digits.len() - 1], 40),
+        _ => (digits, 0),
+    };
+    digits
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .filter(|n| *n > 0)
+        .ok_or_else(|| tr!("error.size", size))
+}
+
+/// Parses hex digits into bytes, whitespace and `:` between them are
+/// ignored. `None` unless a non empty, even number of digits is left
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = hex
+        .bytes()
+        .filter(|b| !b.is_ascii_whitespace() && *b != b':')
+        .collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+/// The streams `grep` and `scan` search in name order, all user streams or
+/// those matching `globs`, `all` adds the reserved ones. Entries without
+/// data are left out
+fn searched_streams(depot_path: &Path, globs: &[String], all: bool) -> Vec<StreamInfo> {
+    let dh = open_read(depot_path);
+    let mut streams = BTreeMap::new();
+    if globs.is_empty() {
+        let entries: Vec<_> = match all {
+            true => dh.all_streams().collect(),
+            false => dh.streams().collect(),
+        };
+        streams.extend(
+            entries
+                .into_iter()
+                .map(|(name, entry)| (name.clone(), (name.clone(), entry.clone()).into())),
+        );
+    }
+    for pattern in globs {
+        let found = dh.find_streams(pattern).unwrap_or_else(|e| {
+            ui::error(e);
+            exit(2)
+        });
+        streams.extend(found.map(|stream| (stream.name.clone(), stream)));
+    }
+    streams
+        .into_values()
+        .filter(|stream: &StreamInfo| grep::searchable(&stream.einf))
+        .collect()
+}
+
+fn carve_files(path: &Path, streams: &[PathBuf], output: &PathBuf, reporter: &Reporter) {
+    let mut dh = open_read(path);
     if output.exists() {
         fs::remove_dir_all(output).unwrap();
     }
     fs::create_dir_all(output).unwrap();
+    let mut progress = reporter.begin("carve", streams.len() as u64);
     for item in streams {
         let stream = dh.get_named_stream(&item.to_string_lossy()).unwrap();
-        println!("carving `{:#?}`", stream);
+        progress.item(&stream.name, stream.einf.stream_size);
         let mut outf = output.join(item);
         outf.set_file_name(format!(
             "{}.carved",
             outf.file_name().unwrap().to_string_lossy()
         ));
-        let mut fh = File::create(outf).unwrap();
-        let mut writer = std::io::BufWriter::new(&mut fh);
-        bufrd
-            .seek(std::io::SeekFrom::Start(stream.einf.offset))
-            .unwrap();
-        let mut buf = vec![0; stream.einf.stream_size as usize];
-        println!("reading {} bytes", buf.len());
-        let mut read = 0;
-        while let Ok(n) = bufrd.read(&mut buf) {
-            if read + n > stream.einf.stream_size as usize {
-                writer
-                    .write_all(buf[..stream.einf.stream_size as usize - read].as_ref())
-                    .unwrap();
-                break;
-            }
-            if n == 0 {
-                break;
-            }
+        fs::create_dir_all(outf.parent().unwrap()).unwrap();
+        let fh = File::create(outf).unwrap();
+        dh.carve_stream(&stream, &fh).unwrap_or_else(|e| {
+            ui::error(e);
+            exit(1)
+        });
+
+        progress.info(tr!("carve.carved", ui::name(&stream.name)));
+    }
+    progress.finish();
+}
+
+fn ls_contents(path: &Path, cmd_args: &ListArgs) {
+    let mut dh = open_read(path);
+    if let Some(dir) = &cmd_args.dir {
+        return ls_dir(&mut dh, dir, cmd_args);
+    }
+    if !cmd_args.glob.is_empty() {
+        let mut found = BTreeMap::new();
+        for pattern in &cmd_args.glob {
+            let streams = dh.find_streams(pattern).unwrap_or_else(|e| {
+                ui::error(e);
+                exit(1)
+            });
+            found.extend(streams.map(|stream| (stream.name.clone(), stream)));
+        }
+        for (name, stream) in &found {
+            print_entry(name, &stream.einf, cmd_args);
+        }
+        return;
+    }
+    let streams: Vec<_> = if cmd_args.all {
+        dh.all_streams().collect()
+    } else {
+        dh.streams().collect()
+    };
+    for stream in streams {
+        print_entry(stream.0, stream.1, cmd_args);
+    }
+}
 
-            writer.write_all(&buf[..n]).unwrap();
-            read += n;
+fn print_entry(name: &str, entry: &EntryInfo, cmd_args: &ListArgs) {
+    let ratio = entry.stream_size as f64 / entry.size as f64;
+    println!(
+        "{}",
+        tr!(
+            "list.entry",
+            name,
+            entry.stream_size,
+            entry.size,
+            format!("{:.2}", ratio),
+            format_ts(&entry.create_ts, cmd_args),
+            format_ts(&entry.mod_ts, cmd_args)
+        )
+    );
+}
+
+fn ls_dir(dh: &mut DepotHandle, dir: &str, cmd_args: &ListArgs) {
+    let children = dh.list_dir(dir).unwrap_or_else(|e| {
+        ui::error(e);
+        exit(1)
+    });
+    let dir = dir.trim_start_matches('/').trim_end_matches('/');
+    for child in children {
+        let name = if dir.is_empty() {
+            child.name
+        } else {
+            format!("{}/{}", dir, child.name)
+        };
+        if child.is_dir {
+            println!("{}/", name);
         }
+        // a name can be an entry and a directory at once
+        if let Some(stream) = dh.get_named_stream(&name) {
+            print_entry(&name, &stream.einf, cmd_args);
+        }
+    }
+}
 
-        println!("carved `{}`", stream.name);
+/// Renders a full date in the zone picked by the list options
+fn format_ts(ts: &TsWithTz, cmd_args: &ListArgs) -> String {
+    fn render<Tz: chrono::TimeZone>(dt: chrono::DateTime<Tz>, iso8601: bool) -> String
+    where
+        Tz::Offset: std::fmt::Display,
+    {
+        if iso8601 {
+            dt.to_rfc3339()
+        } else {
+            dt.format("%Y-%m-%d %H:%M:%S %:z").to_string()
+        }
     }
+
+    let rendered = if cmd_args.utc {
+        ts.to_utc().map(|dt| render(dt, cmd_args.iso8601))
+    } else if cmd_args.local {
+        ts.to_local().map(|dt| render(dt, cmd_args.iso8601))
+    } else {
+        ts.to_datetime().map(|dt| render(dt, cmd_args.iso8601))
+    };
+    rendered.unwrap_or_else(|| tr!("list.invalid_ts"))
 }
 
-fn ls_contents(path: &PathBuf) {
-    let dh = DepotHandle::open_file(path, depot_core::depot_handle::OpenMode::Read).unwrap();
-    let streams: Vec<_> = dh.streams().collect();
-    for stream in streams {
-        println!(
-            "{}, compressed size: {}, size: {}, ratio: {:.2}%",
-            stream.0,
-            stream.1.stream_size,
-            stream.1.size,
-            stream.1.stream_size as f64 / stream.1.size as f64
+fn verify(depot_path: &Path, threads: usize, reporter: &Reporter) {
+    let mut dh = open_read(depot_path);
+    let total_bytes = dh.all_streams().map(|(_, e)| e.size).sum();
+    let mut progress = reporter.begin_bytes("verify", dh.all_streams().count() as u64, total_bytes);
+    dh.set_events(progress.events());
+    let mut report = |stream: &StreamInfo, error: Option<&DepotError>| {
+        progress.item(&stream.name, stream.einf.size);
+        match error {
+            None => progress.info(format!("{} {}", ui::verdict(true), ui::name(&stream.name))),
+            Some(e) => progress.warn(format!(
+                "{} {}: {}",
+                ui::verdict(false),
+                ui::name(&stream.name),
+                e
+            )),
+        }
+    };
+    let failed = dh.verify_all_parallel(threads, Some(&mut report));
+    progress.finish();
+    let total = dh.all_streams().count();
+    reporter.info(tr!("verify.summary", total, failed.len()));
+    let mut sealed = true;
+    if dh.frozen().is_some() {
+        match dh.verify_frozen() {
+            Ok(()) => reporter.info(tr!("verify.frozen")),
+            Err(e) => {
+                reporter.warn(tr!("verify.frozen_changed", e));
+                sealed = false;
+            }
+        }
+    }
+    if !failed.is_empty() || !sealed {
+        exit(1)
+    }
+}
+
+fn extract_files(
+    depot_path: &Path,
+    paths: &[PathBuf],
+    output: &Path,
+    options: &ExtractOptions,
+    reporter: &Reporter,
+) {
+    let mut dh = open_read(depot_path);
+    let streams: Vec<_> = paths
+        .iter()
+        .map(|path| dh.get_named_stream(&path.to_string_lossy()).unwrap())
+        .collect();
+    let mut progress = reporter.begin("extract", streams.len() as u64);
+    let mut report = |stream: &StreamInfo, out: &Path| {
+        progress.item(&stream.name, stream.einf.size);
+        progress.info(extracted_message(stream, out, options));
+    };
+    dh.extract_to_dir(&streams, output, options, Some(&mut report))
+        .unwrap();
+    progress.finish();
+}
+
+fn extract_all(depot_path: &Path, output: &Path, options: &ExtractOptions, reporter: &Reporter) {
+    let mut dh = open_read(depot_path);
+    let total_bytes = dh.streams().map(|(_, e)| e.size).sum();
+    let mut progress = reporter.begin_bytes("extract", dh.streams().count() as u64, total_bytes);
+    dh.set_events(progress.events());
+    let mut report = |stream: &StreamInfo, out: &Path| {
+        progress.item(&stream.name, stream.einf.size);
+        progress.info(extracted_message(stream, out, options));
+    };
+    dh.extract_all(output, options, Some(&mut report))
+        .unwrap_or_else(|e| {
+            ui::error(e);
+            exit(1)
+        });
+    progress.finish();
+}
+
+fn extracted_message(stream: &StreamInfo, out: &Path, options: &ExtractOptions) -> String {
+    let (name, out) = (ui::name(&stream.name), ui::name(out.display()));
+    match options.dry_run {
+        true => tr!("extract.would_extract", name, out),
+        false => tr!("extract.extracted", name, out),
+    }
+}
+
+/// Extracts streams from whichever depots of a catalogued release hold them
+fn extract_from_catalog(
+    catalog: &Path,
+    root: &Path,
+    cmd_args: &ExtractArgs,
+    options: &ExtractOptions,
+    reporter: &Reporter,
+) {
+    let catalog = Catalog::open(catalog).unwrap_or_else(|e| {
+        ui::error(e);
+        exit(1)
+    });
+    let names: Vec<String> = cmd_args
+        .streams
+        .iter()
+        .map(|stream| stream.to_string_lossy().into_owned())
+        .collect();
+    let located = catalog.locate(&names).unwrap_or_else(|e| {
+        ui::error(e);
+        exit(1)
+    });
+    for (archive, streams) in located {
+        let streams: Vec<PathBuf> = streams.into_iter().map(PathBuf::from).collect();
+        extract_files(
+            &root.join(&archive.path),
+            &streams,
+            &cmd_args.output,
+            options,
+            reporter,
         );
     }
 }
 
-fn extract_files(depot_path: &PathBuf, paths: &Vec<PathBuf>, output: &PathBuf) {
-    let mut dh =
-        DepotHandle::open_file(depot_path, depot_core::depot_handle::OpenMode::Read).unwrap();
-    for path in paths {
-        let stream = dh.get_named_stream(&path.to_string_lossy()).unwrap();
-        fs::create_dir_all(output.join(path.parent().unwrap())).unwrap();
-        let mut fh = File::create(output.join(path)).unwrap();
-        let mut writer = std::io::BufWriter::new(&mut fh);
-        dh.extract_stream(&stream, &mut writer).unwrap();
-        println!("extracted `{}`", path.display());
+fn extract_options(cmd_args: &ExtractArgs) -> ExtractOptions {
+    let mut options = ExtractOptions {
+        restore_owner: (is_root() || cmd_args.same_owner) && !cmd_args.no_same_owner,
+        numeric_owner: cmd_args.numeric_owner,
+        restore_mode: !cmd_args.no_same_permissions,
+        threads: cmd_args.threads,
+        preallocate: cmd_args.preallocate,
+        dry_run: cmd_args.dry_run,
+        ..Default::default()
+    };
+    if let Some(owner) = &cmd_args.owner {
+        options.owner = Some(owner.parse().unwrap_or_else(|e| {
+            ui::error(e);
+            exit(1)
+        }));
+    }
+    if let Some(map) = &cmd_args.owner_map {
+        let table = fs::read_to_string(map)
+            .map_err(DepotError::from)
+            .and_then(|t| IdMap::from_table(&t));
+        options.id_map = table.unwrap_or_else(|e| {
+            ui::error(tr!("error.owner_map", ui::name(map.display()), e));
+            exit(1)
+        });
     }
+
+    let strip = cmd_args.strip_components;
+    let transform = cmd_args.transform.as_deref().map(|expr| {
+        parse_transform(expr).unwrap_or_else(|e| {
+            ui::error(tr!("error.transform", ui::name(expr), e));
+            exit(1)
+        })
+    });
+    if strip == 0 && transform.is_none() {
+        return options;
+    }
+
+    options.set_path_mapper(move |name| {
+        let path = strip_components(name, strip)?;
+        match &transform {
+            Some((re, replacement, global)) => {
+                let name = path.to_string_lossy();
+                let mapped = if *global {
+                    re.replace_all(&name, replacement.as_str())
+                } else {
+                    re.replace(&name, replacement.as_str())
+                };
+                if mapped.is_empty() {
+                    return None;
+                }
+                Some(PathBuf::from(mapped.into_owned()))
+            }
+            None => Some(path),
+        }
+    });
+    options
 }
 
+/// Largest dictionary trained by `--dict`, about what zstd suggests
+const DICT_MAX_SIZE: usize = 110 * 1024;
+/// Bytes read from every file when training a dictionary
+const DICT_SAMPLE_MAX: u64 = 128 * 1024;
+/// Most files a dictionary is trained on
+const DICT_SAMPLES_MAX: usize = 4096;
+
+/// A content policy file given by `--policy`
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyFile {
+    /// `error` fails the bake, `warn` only prints a warning
+    #[serde(default)]
+    action: PolicyFileAction,
+    /// names refused, patterns without `/` match the file name alone
+    #[serde(default)]
+    deny: Vec<String>,
+    /// largest size in bytes by extension, `*` for every file
+    #[serde(default)]
+    max_size: BTreeMap<String, u64>,
+    /// also scan for private keys and well known API tokens
+    #[serde(default)]
+    default_secrets: bool,
+    /// regular expressions flagging secrets by the name reported for them
+    #[serde(default)]
+    secrets: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum PolicyFileAction {
+    #[default]
+    Error,
+    Warn,
+}
+
+/// Reads the content policy file at `path`, e.g.
+///
+/// ```toml
+/// deny = ["*.pdb", ".env", "*.pem"]
+/// default_secrets = true
+/// [max_size]
+/// png = 8388608
+/// ```
+fn content_policy(path: &Path) -> Result<ContentPolicy, Box<dyn std::error::Error>> {
+    let file: PolicyFile = toml::from_str(&fs::read_to_string(path)?)?;
+    let mut policy = ContentPolicy::new(match file.action {
+        PolicyFileAction::Error => PolicyAction::Error,
+        PolicyFileAction::Warn => PolicyAction::Warn,
+    });
+    for pattern in &file.deny {
+        policy.deny(pattern)?;
+    }
+    for (ext, max) in &file.max_size {
+        policy.max_size(ext, *max);
+    }
+    if file.default_secrets {
+        policy.default_secrets();
+    }
+    for (label, regex) in &file.secrets {
+        policy.secret(label, regex)?;
+    }
+    Ok(policy)
+}
+
+/// The dictionary of a `--dict PREFIX[=FILE]` argument, read from FILE or
+/// trained on the `files` starting with PREFIX. The tag is the prefix with
+/// `/` replaced by `-`
+fn dictionary(spec: &str, files: &[PathBuf]) -> Result<Dictionary, Box<dyn std::error::Error>> {
+    let (prefix, file) = match spec.split_once('=') {
+        Some((prefix, file)) => (prefix, Some(file)),
+        None => (spec, None),
+    };
+    let tag = match prefix.trim_matches('/').replace('/', "-") {
+        tag if tag.is_empty() => "all".to_owned(),
+        tag => tag,
+    };
+    if let Some(file) = file {
+        return Ok(Dictionary::new(&tag, [prefix], fs::read(file)?)?);
+    }
+    let mut samples = Vec::new();
+    for path in files
+        .iter()
+        .filter(|path| path.to_string_lossy().starts_with(prefix))
+        .take(DICT_SAMPLES_MAX)
+    {
+        if !fs::symlink_metadata(path)?.is_file() {
+            continue;
+        }
+        let mut sample = Vec::new();
+        File::open(path)?
+            .take(DICT_SAMPLE_MAX)
+            .read_to_end(&mut sample)?;
+        samples.push(sample);
+    }
+    Ok(Dictionary::train(&tag, [prefix], &samples, DICT_MAX_SIZE)?)
+}
+
+/// parses a sed style `s/regex/replacement/flags` expression, any character
+/// following the `s` is used as the delimiter and `\` followed by it stands
+/// for the delimiter itself, the only supported flag is `g`. The replacement
+/// takes `&` and `\1` to `\9` as sed does, everything else in it is literal
+fn parse_transform(expr: &str) -> Result<(Regex, String, bool), String> {
+    let mut chars = expr.chars();
+    if chars.next() != Some('s') {
+        return Err("expression must start with `s`".to_owned());
+    }
+    let delim = chars.next().ok_or("missing delimiter")?;
+    let mut parts = vec![String::new()];
+    while let Some(c) = chars.next() {
+        if c == delim {
+            parts.push(String::new());
+            continue;
+        }
+        let in_pattern = parts.len() == 1;
+        let in_replacement = parts.len() == 2;
+        let part = parts.last_mut().unwrap();
+        match c {
+            '\\' if in_pattern || in_replacement => {
+                let escaped = chars.next().ok_or("trailing `\\`")?;
+                if in_pattern && escaped == delim {
+                    part.push_str(&regex::escape(&escaped.to_string()));
+                } else if in_pattern {
+                    // other escapes keep their regex meaning
+                    part.push('\\');
+                    part.push(escaped);
+                } else if escaped.is_ascii_digit() {
+                    // sed uses \1 for groups, the regex crate uses ${1}
+                    part.push_str(&format!("${{{}}}", escaped));
+                } else if escaped == '$' {
+                    part.push_str("$$");
+                } else {
+                    part.push(escaped);
+                }
+            }
+            // sed's whole match
+            '&' if in_replacement => part.push_str("${0}"),
+            // a literal `$`, the regex crate would take it for a group
+            '$' if in_replacement => part.push_str("$$"),
+            c => part.push(c),
+        }
+    }
+    if parts.len() != 3 {
+        return Err(format!("expected s{d}regex{d}replacement{d}[g]", d = delim));
+    }
+    let global = match parts[2].as_str() {
+        "" => false,
+        "g" => true,
+        flags => return Err(format!("unsupported flags `{}`", flags)),
+    };
+    let re = Regex::new(&parts[0]).map_err(|e| e.to_string())?;
+    Ok((re, parts.swap_remove(1), global))
+}
+
+/// Bakes the given files into a new depot at `path`, or adds them to the
+/// existing depot there when `append` is set
+fn bake(path: &Path, cmd_args: &CreateArgs, append: bool, temp: &TempPolicy, reporter: &Reporter) {
+    if cmd_args.encrypt && encryption_key().is_none() {
+        ui::error(tr!("error.encrypt_no_key"));
+        exit(1)
+    }
+    if append && cmd_args.split_size.is_some() {
+        ui::error(tr!("error.split_append"));
+        exit(1)
+    }
+    let mut files = cmd_args.files.clone();
+    if let Some(list) = &cmd_args.files_from {
+        files.extend(read_file_list(list, cmd_args.null).unwrap_or_else(|e| {
+            ui::error(tr!("error.file_list", ui::name(list.display()), e));
+            exit(1)
+        }));
+    }
+    // everything given relative to the invocation directory is
+    // resolved before changing into the add root
+    let output = std::path::absolute(path).unwrap();
+    let package = package_info(cmd_args);
+    let policy = cmd_args.policy.as_ref().map(|file| {
+        content_policy(file).unwrap_or_else(|e| {
+            ui::error(tr!("error.policy", ui::name(file.display()), e));
+            exit(1)
+        })
+    });
+    if let Some(dir) = &cmd_args.chdir {
+        std::env::set_current_dir(dir).unwrap_or_else(|e| {
+            ui::error(tr!("error.chdir", ui::name(dir.display()), e));
+            exit(1)
+        });
+    }
+    let root = std::env::current_dir().unwrap();
+    let files = files
+        .into_iter()
+        .map(|path| root_relative(path, &root, cmd_args.allow_absolute))
+        .collect();
+    let paths = expand_path(files, cmd_args.recurse);
+    reporter.info(ui::headline(tr!(
+        "bake.adding",
+        paths.len(),
+        ui::name(path.display())
+    )));
+    let added = new_depot(
+        &output, paths, package, policy, append, temp, cmd_args, reporter,
+    );
+    if let Err(e) = added {
+        ui::error(&e);
+        if let Some(DepotError::Frozen) = e.downcast_ref() {
+            eprintln!("{}", tr!("hint.force_unfreeze"));
+        }
+        exit(1)
+    }
+    let done = if append {
+        tr!("append.updated", ui::name(path.display()))
+    } else {
+        tr!("bake.created", ui::name(path.display()))
+    };
+    reporter.info(ui::headline(done));
+}
+
+#[allow(clippy::too_many_arguments)]
 fn new_depot(
-    path: &PathBuf,
+    path: &Path,
     files: Vec<PathBuf>,
-    level: i32,
-    threads: usize,
-    frame_size: usize,
+    package: Option<PackageInfo>,
+    policy: Option<ContentPolicy>,
+    append: bool,
+    temp: &TempPolicy,
+    cmd_args: &CreateArgs,
+    reporter: &Reporter,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let fh = File::create(path)?;
-    let pb = indicatif::ProgressBar::new(files.len() as u64);
-    pb.set_style(indicatif::ProgressStyle::default_bar().template(
-        "{spinner:.green} [{elapsed_precise}] [{bar:40.red/blue}] {pos:>7}/{len:7} \n {msg}",
-    )?);
-    let mut dh = DepotHandle::create(fh)?;
-    dh.set_comp_level(level);
-    dh.set_mt_threads(threads);
-    dh.set_comp_frame_size(frame_size);
+    let mut dh = match (append, cmd_args.split_size) {
+        (true, _) if FORCE_UNFREEZE.load(Ordering::Relaxed) => {
+            DepotHandle::open_append_unfreezing(path)?
+        }
+        (true, _) => DepotHandle::open_append(path)?,
+        (false, Some(part_size)) => DepotHandle::create_split(path, part_size)?,
+        (false, None) => DepotHandle::create_file(path)?,
+    };
+    dh.set_creator(creator());
+    dh.set_comp_level(cmd_args.level);
+    dh.set_mt_threads(cmd_args.threads);
+    dh.set_comp_frame_size(cmd_args.frame_size);
+    // an index baked before is kept either way
+    if cmd_args.perfect_hash {
+        dh.set_perfect_hash(true);
+    }
+    if cmd_args.dir_index {
+        dh.set_dir_index(true);
+    }
+    dh.set_snapshot(cmd_args.snapshot);
+    dh.set_temp_policy(temp.clone());
+    dh.set_independent_frames(cmd_args.independent_frames);
+    if let Some(threshold) = cmd_args.solid_threshold {
+        dh.set_solid_blocks(Some(SolidOptions {
+            threshold,
+            block_size: cmd_args.solid_block_size,
+        }))?;
+    }
+    if cmd_args.encrypt {
+        dh.set_encryption_key(encryption_key());
+    }
+    dh.set_dedup(cmd_args.dedup);
+    if cmd_args.tolerate_changes {
+        dh.set_change_policy(ChangePolicy::Tolerate);
+    }
+    if let Some(max_ratio) = cmd_args.max_ratio {
+        dh.set_ratio_guard(Some(RatioGuard {
+            max_ratio,
+            action: cmd_args.on_expand.into(),
+        }));
+    }
+    dh.set_content_policy(policy.map(Arc::new));
+    for spec in &cmd_args.dict {
+        dh.add_dictionary(dictionary(spec, &files)?)?;
+    }
     dh.flush()?;
+    let sizes = files
+        .iter()
+        // links and directories are stored without data
+        .map(|path| fs::symlink_metadata(path).map(|meta| meta.is_file() as u64 * meta.len()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut progress = reporter.begin_bytes("bake", files.len() as u64, sizes.iter().sum());
+    let mut sizes = sizes.into_iter();
+    dh.set_compression(cmd_args.compression.into());
+    dh.set_events(progress.events());
+    // several files are compressed at once but added in order
+    let mut bulk = dh.bulk_add().workers(cmd_args.threads).on_added(|path| {
+        let size = sizes.next().unwrap_or(0);
+        progress.item(&path.display().to_string(), size);
+    });
     for path in files {
-        pb.inc(1);
-        let display = path.display().to_string();
-        let size = fs::metadata(&path)?.len();
-        let formatted_size = humansize::format_size(size, BINARY);
-        let msg = format!("{} ({})", &display, formatted_size);
-        pb.set_message(msg);
-        dh.add_file(path, None)?;
+        let stored = path.extension().is_some_and(|ext| {
+            let ext = ext.to_string_lossy();
+            cmd_args.store.iter().any(|s| s.eq_ignore_ascii_case(&ext))
+        });
+        bulk = match stored {
+            true => bulk.path_with(path, Compression::None),
+            false => bulk.path(path),
+        };
+    }
+    let added = bulk.run();
+    for warning in dh.take_warnings() {
+        progress.warn(warning);
+    }
+    added?;
+    progress.finish();
+    if let Some(package) = package {
+        package::write_package_info(&mut dh, &package)?;
     }
+    let volumes = dh.volumes().cloned();
     dh.close()?;
+    if let Some(volumes) = volumes {
+        reporter.info(tr!(
+            "bake.parts",
+            volumes.part_count(),
+            humansize::format_size(volumes.part_size(), BINARY)
+        ));
+    }
     Ok(())
 }
 
+fn print_lint_report(report: &LintReport, format: OutputFormat) {
+    if format == OutputFormat::Json {
+        let issues: Vec<_> = report
+            .issues
+            .iter()
+            .map(|issue| {
+                serde_json::json!({
+                    "code": issue.code,
+                    "severity": issue.severity.as_str(),
+                    "message": issue.message,
+                    "entry": issue.entry,
+                })
+            })
+            .collect();
+        let doc = serde_json::json!({
+            "version": report.version,
+            "file_size": report.file_size,
+            "dead_space": report.dead_space,
+            "dead_regions": report
+                .dead_regions
+                .iter()
+                .map(|r| [r.start, r.end])
+                .collect::<Vec<_>>(),
+            "issues": issues,
+        });
+        println!("{}", doc);
+        return;
+    }
+
+    for issue in &report.issues {
+        match &issue.entry {
+            Some(entry) => println!(
+                "{}[{}] {}: {}",
+                ui::severity(issue.severity),
+                issue.code,
+                entry,
+                issue.message
+            ),
+            None => println!(
+                "{}[{}] {}",
+                ui::severity(issue.severity),
+                issue.code,
+                issue.message
+            ),
+        }
+    }
+    println!(
+        "{}",
+        tr!(
+            "lint.summary",
+            report.issues.len(),
+            humansize::format_size(report.dead_space, BINARY),
+            format!("{:.1}", report.dead_space_ratio() * 100.0)
+        )
+    );
+}
+
+fn package_info(cmd_args: &CreateArgs) -> Option<PackageInfo> {
+    let name = cmd_args.package_name.clone()?;
+    let read_script = |path: &Option<PathBuf>| {
+        path.as_ref().map(|p| {
+            fs::read_to_string(p).unwrap_or_else(|e| {
+                ui::error(tr!("error.script", ui::name(p.display()), e));
+                exit(1)
+            })
+        })
+    };
+    Some(PackageInfo {
+        name,
+        version: cmd_args.package_version.clone(),
+        prefix: cmd_args.install_prefix.clone(),
+        pre_install: read_script(&cmd_args.pre_install),
+        post_install: read_script(&cmd_args.post_install),
+    })
+}
+
+/// reads a list of paths from `list` (or stdin when `list` is `-`),
+/// empty entries are ignored
+fn read_file_list(list: &Path, null: bool) -> std::io::Result<Vec<PathBuf>> {
+    let mut contents = String::new();
+    if list == Path::new("-") {
+        std::io::stdin().read_to_string(&mut contents)?;
+    } else {
+        File::open(list)?.read_to_string(&mut contents)?;
+    }
+
+    let delimiter = if null { '\0' } else { '\n' };
+    Ok(contents
+        .split(delimiter)
+        .map(|line| {
+            if null {
+                line
+            } else {
+                line.trim_end_matches('\r')
+            }
+        })
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from)
+        .collect())
+}
+
+/// Normalizes an input path to the name it is stored under, relative to
+/// the add `root`. Paths escaping the root are refused, absolute paths
+/// outside of it only pass with `allow_absolute`
+fn root_relative(path: PathBuf, root: &Path, allow_absolute: bool) -> PathBuf {
+    let relative = if path.is_absolute() {
+        match path.strip_prefix(root) {
+            Ok(relative) => relative,
+            Err(_) if allow_absolute => return path,
+            Err(_) => {
+                ui::error(tr!("error.path_absolute", ui::name(path.display())));
+                exit(1)
+            }
+        }
+    } else {
+        &path
+    };
+
+    let mut normalized = PathBuf::new();
+    for component in relative.components() {
+        match component {
+            Component::Normal(part) => normalized.push(part),
+            Component::CurDir => {}
+            _ => {
+                ui::error(tr!("error.path_outside", ui::name(path.display())));
+                exit(1)
+            }
+        }
+    }
+    if normalized.as_os_str().is_empty() {
+        normalized.push(".");
+    }
+    normalized
+}
+
 fn expand_path(in_paths: Vec<PathBuf>, recurse: bool) -> Vec<PathBuf> {
     let mut paths = Vec::new();
 
     for path in in_paths {
-        if !path.exists() {
-            eprintln!("path `{}` does not exist", path.display());
-            exit(1)
-        }
-
-        if path.starts_with("..") {
-            eprintln!(
-                "path `{}` is outside of the current directory",
-                path.display()
-            );
+        // a dangling link is still stored as a link
+        if !path.exists() && !path.is_symlink() {
+            ui::error(tr!("error.path_missing", ui::name(path.display())));
             exit(1)
         }
 
-        if path.is_dir() && recurse {
+        if path.is_symlink() {
+            // stored as a link, never followed
+            paths.push(path);
+        } else if path.is_dir() && recurse {
+            // the directory itself is stored for its metadata
+            if path != Path::new(".") {
+                paths.push(path.clone());
+            }
             for entry in path.read_dir().unwrap() {
                 let entry = entry.unwrap();
-                let path = entry.path();
-                if path.is_dir() {
-                    paths.extend(expand_path(vec![path], recurse));
-                } else if path.is_symlink() {
-                    println!("ignoring symlink `{}`", path.display());
+                // entries of the root itself are named without a `./` prefix
+                let child = if path == Path::new(".") {
+                    PathBuf::from(entry.file_name())
                 } else {
-                    paths.push(path);
-                }
+                    entry.path()
+                };
+                paths.extend(expand_path(vec![child], recurse));
             }
-        } else if path.is_file() {
-            paths.push(path.clone());
-        } else {
-            eprintln!(
-                "refusing to add directory `{}` without --recurse",
-                path.display()
-            );
+        } else if path.is_dir() {
+            ui::error(tr!("error.dir_without_recurse", ui::name(path.display())));
             exit(1)
+        } else {
+            // regular files and pipes, the library rejects anything else
+            paths.push(path.clone());
         }
     }
 
@@ -0,0 +1,256 @@
+//! User facing output of the CLI, [`Reporter`] and [`Progress`] route messages
+//! and progress to a bar, to JSON-lines events on stdout or nowhere in quiet
+//! mode
+
+use depot_core::error::DepotError;
+use depot_core::events::{DepotEvents, Operation};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+
+/// How long running operations report their progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ProgressMode {
+    /// interactive progress bar
+    Bar,
+    /// one JSON object per event on stdout, for CI parsing
+    JsonLines,
+    /// no progress reporting
+    None,
+}
+
+/// Routes user facing output according to the quiet and progress settings
+pub struct Reporter {
+    mode: ProgressMode,
+    quiet: bool,
+}
+
+impl Reporter {
+    pub fn new(mode: ProgressMode, quiet: bool) -> Self {
+        let mode = if quiet && mode == ProgressMode::Bar {
+            ProgressMode::None
+        } else {
+            mode
+        };
+        Self { mode, quiet }
+    }
+
+    /// true when stdout is reserved for machine readable events
+    pub fn is_json(&self) -> bool {
+        self.mode == ProgressMode::JsonLines
+    }
+
+    /// Informational message, dropped in quiet mode
+    pub fn info(&self, msg: impl Display) {
+        if self.quiet {
+            return;
+        }
+        if self.is_json() {
+            emit_json(
+                serde_json::json!({"event": "message", "level": "info", "text": msg.to_string()}),
+            );
+        } else {
+            println!("{}", msg);
+        }
+    }
+
+    /// Warnings are always shown
+    pub fn warn(&self, msg: impl Display) {
+        if self.is_json() {
+            emit_json(
+                serde_json::json!({"event": "message", "level": "warning", "text": msg.to_string()}),
+            );
+        } else {
+            eprintln!("{} {}", crate::ui::warning_label(), msg);
+        }
+    }
+
+    /// Starts reporting an operation over `total` items
+    pub fn begin(&self, action: &str, total: u64) -> Progress<'_> {
+        let bar = match self.mode {
+            ProgressMode::Bar => {
+                let bar = ProgressBar::new(total);
+                bar.set_style(
+                    ProgressStyle::default_bar()
+                        .template(
+                            "{spinner:.green} [{elapsed_precise}] [{bar:40.red/blue}] {pos:>7}/{len:7} \n {msg}",
+                        )
+                        .unwrap(),
+                );
+                Some(bar)
+            }
+            ProgressMode::JsonLines => {
+                emit_json(serde_json::json!({"event": "begin", "action": action, "total": total}));
+                None
+            }
+            ProgressMode::None => None,
+        };
+        Progress {
+            reporter: self,
+            bar,
+            action: action.to_owned(),
+            pos: 0,
+            total,
+            bytes: false,
+        }
+    }
+
+    /// Starts reporting an operation over `total` items of `total_bytes`
+    /// bytes together, the bar then advances on bytes through
+    /// [`Progress::bytes`] and shows the speed and ETA
+    pub fn begin_bytes(&self, action: &str, total: u64, total_bytes: u64) -> Progress<'_> {
+        let bar = match self.mode {
+            ProgressMode::Bar => {
+                let bar = ProgressBar::new(total_bytes);
+                bar.set_style(
+                    ProgressStyle::default_bar()
+                        .template(
+                            "{spinner:.green} [{elapsed_precise}] [{bar:40.red/blue}] {binary_bytes}/{binary_total_bytes} {binary_bytes_per_sec} ETA {eta} \n {msg}",
+                        )
+                        .unwrap(),
+                );
+                Some(bar)
+            }
+            ProgressMode::JsonLines => {
+                emit_json(serde_json::json!({
+                    "event": "begin",
+                    "action": action,
+                    "total": total,
+                    "total_bytes": total_bytes,
+                }));
+                None
+            }
+            ProgressMode::None => None,
+        };
+        Progress {
+            reporter: self,
+            bar,
+            action: action.to_owned(),
+            pos: 0,
+            total,
+            bytes: true,
+        }
+    }
+}
+
+/// Progress of a single operation, see [`Reporter::begin`]
+pub struct Progress<'r> {
+    reporter: &'r Reporter,
+    bar: Option<ProgressBar>,
+    action: String,
+    pos: u64,
+    total: u64,
+    /// the bar counts bytes instead of items
+    bytes: bool,
+}
+
+impl Progress<'_> {
+    /// Reports that work on `name` (of `size` bytes) started
+    pub fn item(&mut self, name: &str, size: u64) {
+        self.pos += 1;
+        if let Some(bar) = &self.bar {
+            let size = humansize::format_size(size, humansize::BINARY);
+            if self.bytes {
+                bar.set_message(format!("{}/{} {} ({})", self.pos, self.total, name, size));
+            } else {
+                bar.inc(1);
+                bar.set_message(format!("{} ({})", name, size));
+            }
+        } else if self.reporter.is_json() {
+            emit_json(serde_json::json!({
+                "event": "item",
+                "action": self.action,
+                "name": name,
+                "size": size,
+                "pos": self.pos,
+                "total": self.total,
+            }));
+        }
+    }
+
+    /// Events advancing a bar started by [`Reporter::begin_bytes`] as the
+    /// bytes of every entry are processed, `None` without such a bar
+    pub fn events(&self) -> Option<Arc<dyn DepotEvents>> {
+        let bar = self.bar.as_ref().filter(|_| self.bytes)?;
+        Some(Arc::new(BarEvents {
+            bar: bar.clone(),
+            state: Mutex::default(),
+        }))
+    }
+
+    pub fn info(&self, msg: impl Display) {
+        match &self.bar {
+            Some(bar) => bar.suspend(|| self.reporter.info(msg)),
+            None => self.reporter.info(msg),
+        }
+    }
+
+    pub fn warn(&self, msg: impl Display) {
+        match &self.bar {
+            Some(bar) => bar.suspend(|| self.reporter.warn(msg)),
+            None => self.reporter.warn(msg),
+        }
+    }
+
+    pub fn finish(self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        } else if self.reporter.is_json() {
+            emit_json(serde_json::json!({
+                "event": "finish",
+                "action": self.action,
+                "pos": self.pos,
+                "total": self.total,
+            }));
+        }
+    }
+}
+
+/// Moves a byte counting bar to the bytes processed by all entries, those
+/// still in flight included
+struct BarEvents {
+    bar: ProgressBar,
+    state: Mutex<BarState>,
+}
+
+#[derive(Default)]
+struct BarState {
+    /// bytes of the entries that finished
+    finished: u64,
+    /// size and bytes done of the entries in flight
+    running: HashMap<String, (u64, u64)>,
+}
+
+impl BarState {
+    fn position(&self) -> u64 {
+        self.finished + self.running.values().map(|(_, done)| done).sum::<u64>()
+    }
+}
+
+impl DepotEvents for BarEvents {
+    fn entry_started(&self, _operation: Operation, name: &str, size: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.running.insert(name.to_owned(), (size, 0));
+    }
+
+    fn entry_progress(&self, _operation: Operation, name: &str, done: u64, _total: u64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(running) = state.running.get_mut(name) {
+            running.1 = done;
+        }
+        self.bar.set_position(state.position());
+    }
+
+    fn entry_finished(&self, _operation: Operation, name: &str, _error: Option<&DepotError>) {
+        let mut state = self.state.lock().unwrap();
+        if let Some((size, done)) = state.running.remove(name) {
+            state.finished += size.max(done);
+        }
+        self.bar.set_position(state.position());
+    }
+}
+
+fn emit_json(event: serde_json::Value) {
+    println!("{}", event);
+}
@@ -0,0 +1,89 @@
+//! Terminal rendering shared by all subcommands, colors and emoji are only
+//! used when the output is a terminal that wants them
+
+use crate::i18n::tr;
+use console::{style, Emoji, StyledObject, Term};
+use depot_core::lint::Severity;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const PACKAGE: Emoji<'_, '_> = Emoji("📦 ", "[||] ");
+
+static EMOJI: AtomicBool = AtomicBool::new(false);
+
+/// Decides once whether colors and emoji are used, colors are disabled by
+/// `--no-color`, a non empty `NO_COLOR` or when the stream is not a terminal.
+/// `machine_stdout` keeps stdout plain for machine readable output
+pub fn init(no_color: bool, machine_stdout: bool) {
+    let no_color = no_color || std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    if no_color {
+        console::set_colors_enabled_stderr(false);
+    }
+    if no_color || machine_stdout {
+        console::set_colors_enabled(false);
+    }
+    EMOJI.store(
+        !machine_stdout && Term::stdout().is_term() && Term::stdout().features().wants_emoji(),
+        Ordering::Relaxed,
+    );
+}
+
+/// Marker prefixed to headline messages
+pub fn marker() -> String {
+    if EMOJI.load(Ordering::Relaxed) {
+        PACKAGE.to_string()
+    } else {
+        PACKAGE.1.to_owned()
+    }
+}
+
+/// A headline message prefixed with the package marker
+pub fn headline(msg: impl Display) -> String {
+    format!("{}{}", marker(), msg)
+}
+
+/// A path or stream name quoted in backticks
+pub fn name(name: impl Display) -> StyledObject<String> {
+    style(format!("`{}`", name)).cyan()
+}
+
+/// Name of the stream a `grep` line was found in
+pub fn stream_label(name: &str) -> StyledObject<&str> {
+    style(name).magenta()
+}
+
+/// The part of a line matching a `grep` pattern
+pub fn matched(text: &str) -> StyledObject<&str> {
+    style(text).red().bold()
+}
+
+/// Prefix of warning lines on stderr
+pub fn warning_label() -> StyledObject<String> {
+    style(tr!("label.warning")).yellow().bold().for_stderr()
+}
+
+/// Prints an error to stderr
+pub fn error(msg: impl Display) {
+    eprintln!(
+        "{} {}",
+        style(tr!("label.error")).red().bold().for_stderr(),
+        msg
+    );
+}
+
+/// `OK` or `FAIL` label of a verified stream
+pub fn verdict(ok: bool) -> StyledObject<&'static str> {
+    match ok {
+        true => style("OK").green(),
+        false => style("FAIL").red().bold(),
+    }
+}
+
+pub fn severity(severity: Severity) -> StyledObject<&'static str> {
+    let label = style(severity.as_str());
+    match severity {
+        Severity::Info => label.blue(),
+        Severity::Warning => label.yellow(),
+        Severity::Error => label.red().bold(),
+    }
+}
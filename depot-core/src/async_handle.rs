@@ -0,0 +1,506 @@
+//! Reading depots from async code, e.g. to serve entries over the network
+//! without blocking a runtime thread per request. The toc is loaded with
+//! async reads, and entries are decoded as their stored data arrives
+//! instead of pulling it through a blocking reader. Only available with
+//! the `tokio` feature
+
+use crate::crypt::{ChunkOpener, EncryptionKey};
+use crate::depot_handle::{
+    check_extracted, entry_dict, entry_key, Compression, DepotHeader, DepotToc, EntryInfo,
+    StreamInfo,
+};
+use crate::dict::{Dictionary, DICT_PREFIX};
+use crate::error::DepotError;
+use crate::fingerprint::Fingerprint;
+use crate::hash::EntryHasher;
+use crate::helpers::De;
+use crate::meta::{keys, ExtMeta, Visibility};
+use crate::{is_reserved_name, FORMAT_VERSION, MAX_TOC_SIZE};
+use std::io::{Cursor, Error, ErrorKind, SeekFrom};
+use std::ops::Bound;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{
+    AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt, ReadBuf, Take,
+};
+use zstd::stream::raw::{InBuffer, Operation, OutBuffer};
+
+/// Decoded bytes produced per step, bounds the memory a highly compressed
+/// entry can take while it is served
+const DECODE_CHUNK: usize = 64 * 1024;
+
+/// Read only handle to a depot over an async reader, the counterpart of
+/// [`crate::depot_handle::DepotHandle`] opened in read mode
+pub struct AsyncDepotHandle<R> {
+    reader: R,
+    header: DepotHeader,
+    toc: DepotToc,
+    dictionaries: Vec<Dictionary>,
+    encryption_key: Option<EncryptionKey>,
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> AsyncDepotHandle<R> {
+    /// Opens the depot starting at the current position of `reader`,
+    /// refusing format versions newer than this build supports
+    pub async fn open(mut reader: R) -> Result<Self, DepotError> {
+        let mut header = [0; DepotHeader::SIZE as usize];
+        reader.read_exact(&mut header).await?;
+        let header = DepotHeader::de(Cursor::new(&header[..]))?;
+        if !(1..=FORMAT_VERSION).contains(&header.version) {
+            return Err(DepotError::UnsupportedVersion {
+                found: header.version,
+                supported: FORMAT_VERSION,
+            });
+        }
+        // the toc runs up to the end, it is parsed once it is in memory
+        reader.seek(SeekFrom::Start(header.toc_offset)).await?;
+        let mut toc = Vec::new();
+        (&mut reader)
+            .take(MAX_TOC_SIZE + 1)
+            .read_to_end(&mut toc)
+            .await?;
+        if toc.len() as u64 > MAX_TOC_SIZE {
+            return Err(DepotError::CorruptToc {
+                at_entry: 0,
+                reason: format!("the toc is over {} bytes", MAX_TOC_SIZE),
+            });
+        }
+        let toc = DepotToc::de_versioned(Cursor::new(toc), header.version)?;
+
+        let mut dh = Self {
+            reader,
+            header,
+            toc,
+            dictionaries: Vec::new(),
+            encryption_key: None,
+        };
+        dh.load_dictionaries().await?;
+        Ok(dh)
+    }
+
+    /// Reads back the dictionaries stored in the depot
+    async fn load_dictionaries(&mut self) -> Result<(), DepotError> {
+        let names: Vec<String> = self
+            .toc
+            .entries
+            .range::<str, _>((Bound::Included(DICT_PREFIX), Bound::Unbounded))
+            .map(|(name, _)| name)
+            .take_while(|name| name.starts_with(DICT_PREFIX))
+            .cloned()
+            .collect();
+        for name in names {
+            let data = self.read_bytes(&name).await?;
+            let dict = Dictionary::from_bytes(&name[DICT_PREFIX.len()..], &data)?;
+            self.dictionaries.push(dict);
+        }
+        Ok(())
+    }
+
+    /// Decrypts encrypted entries when they are read, see
+    /// [`crate::depot_handle::DepotHandle::set_encryption_key`]
+    pub fn set_encryption_key(&mut self, key: Option<EncryptionKey>) {
+        self.encryption_key = key;
+    }
+
+    /// Format version the depot was written with
+    pub fn format_version(&self) -> u16 {
+        self.header.version
+    }
+
+    /// Digest identifying what the depot holds, see
+    /// [`crate::depot_handle::DepotHandle::archive_fingerprint`]
+    pub fn archive_fingerprint(&self) -> Fingerprint {
+        Fingerprint::of_toc(self.header.version, &self.toc)
+    }
+
+    /// Metadata of the depot as a whole, see
+    /// [`crate::depot_handle::DepotHandle::archive_meta`]
+    pub fn archive_meta(&self) -> &ExtMeta {
+        &self.toc.meta
+    }
+
+    /// Iterates over the user streams, reserved streams are skipped
+    pub fn streams(&self) -> impl Iterator<Item = (&String, &EntryInfo)> {
+        self.toc
+            .entries
+            .iter()
+            .filter(|(name, _)| !is_reserved_name(name))
+    }
+
+    /// Iterates over the streams that may be served to `audience`, see
+    /// [`crate::depot_handle::DepotHandle::visible_streams`]
+    pub fn visible_streams(
+        &self,
+        audience: Visibility,
+    ) -> impl Iterator<Item = (&String, &EntryInfo)> {
+        self.streams()
+            .filter(move |(_, entry)| entry.visibility() <= audience)
+    }
+
+    pub fn get_named_stream(&self, name: &str) -> Option<StreamInfo> {
+        let entry = self.toc.entries.get(name)?;
+
+        Some((name.to_owned(), entry.clone()).into())
+    }
+
+    /// Opens a stream for reading, the data is decoded as it is read. The
+    /// size and hash are checked once the end is reached, a mismatch
+    /// surfaces as an [`ErrorKind::InvalidData`] error from the final read.
+    /// Solid block members are decoded from the start of their block, which
+    /// is not kept between reads
+    pub async fn open_stream(
+        &mut self,
+        stream: &StreamInfo,
+    ) -> Result<AsyncStreamReader<'_, R>, DepotError> {
+        let entry = &stream.einf;
+        let dict = entry_dict(&self.dictionaries, &stream.name, entry)?;
+        let key = entry_key(self.encryption_key.as_ref(), &stream.name, entry)?;
+        let inflater = Inflater::new(entry, dict.as_deref(), key.as_ref())?;
+        // empty entries have no data and no hash to check
+        let empty = entry.flags & EntryInfo::FLAG_EMPTY != 0;
+        let stored = if empty { 0 } else { entry.stream_size };
+        let skip = entry.ext.solid_block().map_or(0, |(_, at)| at);
+        self.reader.seek(SeekFrom::Start(entry.offset)).await?;
+        Ok(AsyncStreamReader {
+            inner: (&mut self.reader).take(stored),
+            inflater,
+            raw: vec![0; DECODE_CHUNK],
+            out: Vec::new(),
+            pos: 0,
+            skip,
+            name: stream.name.clone(),
+            entry: entry.clone(),
+            read: 0,
+            hasher: EntryHasher::new(),
+            checked: empty,
+        })
+    }
+
+    /// Extracts a stream to any async writer, checking its size and hash
+    pub async fn extract_stream<W: AsyncWrite + Unpin>(
+        &mut self,
+        stream: &StreamInfo,
+        mut writer: W,
+    ) -> Result<(), DepotError> {
+        let mut reader = self.open_stream(stream).await?;
+        tokio::io::copy(&mut reader, &mut writer).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+
+    /// Reads the named stream into memory, failing if it does not exist
+    pub async fn read_bytes(&mut self, name: &str) -> Result<Vec<u8>, DepotError> {
+        let stream = self
+            .get_named_stream(name)
+            .ok_or_else(|| DepotError::EntryNotFound(name.to_owned()))?;
+        let mut buf = Vec::new();
+        self.extract_stream(&stream, &mut buf).await?;
+        Ok(buf)
+    }
+
+    /// Gives back the reader the depot was opened from
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// Lazily decoding async reader over an entry, see
+/// [`AsyncDepotHandle::open_stream`]
+pub struct AsyncStreamReader<'a, R> {
+    inner: Take<&'a mut R>,
+    inflater: Inflater,
+    /// stored data read from `inner`
+    raw: Vec<u8>,
+    /// decoded data and how much of it was read
+    out: Vec<u8>,
+    pos: usize,
+    /// decoded bytes to drop before the entry data, the members before it
+    /// in its solid block
+    skip: u64,
+    name: String,
+    entry: EntryInfo,
+    read: u64,
+    hasher: EntryHasher,
+    checked: bool,
+}
+
+impl<R> AsyncStreamReader<'_, R> {
+    /// Uncompressed size of the entry
+    pub fn size(&self) -> u64 {
+        self.entry.size
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        if !self.checked {
+            self.checked = true;
+            check_extracted(&self.name, &self.entry, self.read, self.hasher.finish())?;
+        }
+        Ok(())
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for AsyncStreamReader<'_, R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let left = this.entry.size - this.read;
+        if left == 0 {
+            return Poll::Ready(this.finish());
+        }
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        loop {
+            if this.skip > 0 && this.pos < this.out.len() {
+                let n = (this.out.len() - this.pos).min(this.skip.min(usize::MAX as u64) as usize);
+                this.pos += n;
+                this.skip -= n as u64;
+                continue;
+            }
+            if this.pos < this.out.len() {
+                // never read past the recorded size of the entry
+                let n = buf
+                    .remaining()
+                    .min(this.out.len() - this.pos)
+                    .min(left.min(usize::MAX as u64) as usize);
+                let data = &this.out[this.pos..this.pos + n];
+                buf.put_slice(data);
+                this.hasher.update(data);
+                this.pos += n;
+                this.read += n as u64;
+                return Poll::Ready(Ok(()));
+            }
+            this.out.clear();
+            this.pos = 0;
+            this.inflater.decode(&mut this.out)?;
+            if !this.out.is_empty() {
+                continue;
+            }
+            if this.inflater.eof {
+                return Poll::Ready(this.finish());
+            }
+            let mut raw = ReadBuf::new(&mut this.raw);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut raw))?;
+            this.inflater.feed(raw.filled())?;
+        }
+    }
+}
+
+/// Decodes the stored data of an entry as it is pushed in, the async
+/// counterpart of the blocking decoders used by the sync handle
+struct Inflater {
+    opener: Option<ChunkOpener>,
+    /// opened stored data not decoded yet, from `consumed` on
+    input: Vec<u8>,
+    consumed: usize,
+    codec: Codec,
+    /// all stored data was fed
+    eof: bool,
+}
+
+enum Codec {
+    Stored,
+    Zstd(Box<zstd::stream::raw::Decoder<'static>>),
+    Lz4(Lz4Frames),
+}
+
+impl Inflater {
+    fn new(
+        entry: &EntryInfo,
+        dict: Option<&[u8]>,
+        key: Option<&EncryptionKey>,
+    ) -> Result<Self, Error> {
+        let opener = match key {
+            Some(key) => {
+                let nonce = entry.ext.get(keys::NONCE).unwrap_or_default();
+                Some(ChunkOpener::new(key, nonce)?)
+            }
+            None => None,
+        };
+        let codec = match entry.compression() {
+            Compression::Zstd => Codec::Zstd(Box::new(
+                zstd::stream::raw::Decoder::with_dictionary(dict.unwrap_or_default())?,
+            )),
+            Compression::Lz4 => Codec::Lz4(Lz4Frames::default()),
+            Compression::None => Codec::Stored,
+        };
+        Ok(Self {
+            opener,
+            input: Vec::new(),
+            consumed: 0,
+            codec,
+            eof: false,
+        })
+    }
+
+    /// Takes the next stored bytes, empty once all were fed
+    fn feed(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.input.drain(..self.consumed);
+        self.consumed = 0;
+        self.eof = data.is_empty();
+        match &mut self.opener {
+            Some(opener) => opener.push(data, &mut self.input, self.eof),
+            None => {
+                self.input.extend_from_slice(data);
+                Ok(())
+            }
+        }
+    }
+
+    /// Appends what the bytes fed so far decode to to `out`, about
+    /// [`DECODE_CHUNK`] bytes at a time. Nothing is appended when more
+    /// input is needed
+    fn decode(&mut self, out: &mut Vec<u8>) -> Result<(), Error> {
+        let input = &self.input[self.consumed..];
+        self.consumed += match &mut self.codec {
+            Codec::Stored => {
+                let n = input.len().min(DECODE_CHUNK);
+                out.extend_from_slice(&input[..n]);
+                n
+            }
+            Codec::Zstd(decoder) => decode_zstd(decoder, input, out)?,
+            Codec::Lz4(frames) => frames.decode(input, out)?,
+        };
+        Ok(())
+    }
+}
+
+/// Feeds `input` to `decoder`, returning how much of it was consumed.
+/// Concatenated frames are decoded one after the other
+fn decode_zstd(
+    decoder: &mut zstd::stream::raw::Decoder<'static>,
+    input: &[u8],
+    out: &mut Vec<u8>,
+) -> Result<usize, Error> {
+    let start = out.len();
+    let mut input = InBuffer::around(input);
+    loop {
+        out.reserve(DECODE_CHUNK);
+        let pos = out.len();
+        let mut output = OutBuffer::around_pos(out, pos);
+        decoder.run(&mut input, &mut output)?;
+        // the decoder may hold back output until there is room for it
+        let full = out.len() == out.capacity();
+        if out.len() - start >= DECODE_CHUNK || (!full && input.pos() == input.src.len()) {
+            return Ok(input.pos());
+        }
+    }
+}
+
+const LZ4_MAGIC: u32 = 0x184D2204;
+/// Linked blocks may refer back this far into the data before them
+const LZ4_WINDOW: usize = 64 * 1024;
+
+/// Decoder for lz4 frames as written by `lz4_flex::frame::FrameEncoder`.
+/// Checksums are skipped, the entry hash covers the decoded data
+#[derive(Default)]
+struct Lz4Frames {
+    frame: Option<Lz4Frame>,
+    /// end of the data decoded so far, for linked blocks
+    window: Vec<u8>,
+}
+
+#[derive(Clone, Copy)]
+struct Lz4Frame {
+    linked: bool,
+    block_checksums: bool,
+    content_checksum: bool,
+    max_block: usize,
+}
+
+impl Lz4Frames {
+    /// Decodes the complete blocks at the start of `input`, returning how
+    /// much of it was consumed
+    fn decode(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<usize, Error> {
+        let invalid = |msg: String| Error::new(ErrorKind::InvalidData, msg);
+        let start = out.len();
+        let mut used = 0;
+        while out.len() - start < DECODE_CHUNK {
+            let rest = &input[used..];
+            let Some(frame) = self.frame else {
+                // magic, flags, block descriptor and header checksum
+                if rest.len() < 7 {
+                    break;
+                }
+                let magic = u32::from_le_bytes(rest[..4].try_into().unwrap());
+                if magic != LZ4_MAGIC {
+                    return Err(invalid("not an lz4 frame".to_owned()));
+                }
+                let (flags, descriptor) = (rest[4], rest[5]);
+                let mut len = 7;
+                if flags & 0x08 != 0 {
+                    len += 8;
+                }
+                if flags & 0x01 != 0 {
+                    len += 4;
+                }
+                if rest.len() < len {
+                    break;
+                }
+                let max_block = match (descriptor >> 4) & 0x07 {
+                    4 => 64 * 1024,
+                    5 => 256 * 1024,
+                    6 => 1024 * 1024,
+                    7 => 4 * 1024 * 1024,
+                    size => return Err(invalid(format!("invalid lz4 block size {}", size))),
+                };
+                self.frame = Some(Lz4Frame {
+                    linked: flags & 0x20 == 0,
+                    block_checksums: flags & 0x10 != 0,
+                    content_checksum: flags & 0x04 != 0,
+                    max_block,
+                });
+                self.window.clear();
+                used += len;
+                continue;
+            };
+            if rest.len() < 4 {
+                break;
+            }
+            let size = u32::from_le_bytes(rest[..4].try_into().unwrap());
+            if size == 0 {
+                // end mark, maybe followed by the content checksum
+                let len = if frame.content_checksum { 8 } else { 4 };
+                if rest.len() < len {
+                    break;
+                }
+                self.frame = None;
+                used += len;
+                continue;
+            }
+            let uncompressed = size & 0x8000_0000 != 0;
+            let size = (size & 0x7fff_ffff) as usize;
+            if size > frame.max_block {
+                return Err(invalid(format!("lz4 block of {} bytes is too large", size)));
+            }
+            let len = 4 + size + if frame.block_checksums { 4 } else { 0 };
+            if rest.len() < len {
+                break;
+            }
+            let block = &rest[4..4 + size];
+            let at = out.len();
+            if uncompressed {
+                out.extend_from_slice(block);
+            } else {
+                out.resize(at + frame.max_block, 0);
+                let n = if frame.linked {
+                    lz4_flex::block::decompress_into_with_dict(block, &mut out[at..], &self.window)
+                } else {
+                    lz4_flex::block::decompress_into(block, &mut out[at..])
+                }
+                .map_err(|e| invalid(e.to_string()))?;
+                out.truncate(at + n);
+            }
+            if frame.linked {
+                self.window.extend_from_slice(&out[at..]);
+                let excess = self.window.len().saturating_sub(LZ4_WINDOW);
+                self.window.drain(..excess);
+            }
+            used += len;
+        }
+        Ok(used)
+    }
+}
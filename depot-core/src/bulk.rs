@@ -0,0 +1,87 @@
+//! Adding many files at once, compressing several of them in parallel while
+//! they are still written to the depot in the order given, so bakes of many
+//! small files use every core rather than just zstd's own threads
+
+use crate::depot_handle::{Compression, DepotHandle};
+use crate::error::DepotError;
+use std::path::{Path, PathBuf};
+
+/// Called after each path of a bulk add was written to the depot, in the
+/// order the paths were given
+pub type BulkAddedFn<'a> = dyn FnMut(&Path) + 'a;
+
+/// A path queued by a [`BulkAdd`]
+#[derive(Debug, Clone)]
+pub(crate) struct BulkItem {
+    pub path: PathBuf,
+    /// replaces the handle's compression for this path
+    pub compression: Option<Compression>,
+}
+
+/// Builder for adding many paths at once, see [`DepotHandle::bulk_add`].
+/// Regular files are compressed by the workers into memory, spilling to a
+/// temp file where the [`crate::temp::TempPolicy`] allows. Large files are
+/// compressed on the calling thread with the handle's zstd threads instead,
+/// as are symlinks, directories and pipes, when their turn comes
+pub struct BulkAdd<'a, 'io> {
+    depot: &'a mut DepotHandle<'io>,
+    items: Vec<BulkItem>,
+    workers: usize,
+    on_added: Option<Box<BulkAddedFn<'a>>>,
+}
+
+impl<'a, 'io> BulkAdd<'a, 'io> {
+    pub(crate) fn new(depot: &'a mut DepotHandle<'io>) -> Self {
+        Self {
+            depot,
+            items: Vec::new(),
+            workers: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            on_added: None,
+        }
+    }
+
+    /// Files compressed at once, 0 or 1 adds every path on the calling
+    /// thread. Defaults to the available parallelism
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    /// Queues a path, added like [`DepotHandle::add_path`]
+    pub fn path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.items.push(BulkItem {
+            path: path.into(),
+            compression: None,
+        });
+        self
+    }
+
+    /// Queues a path compressed with `compression` instead of the handle's
+    pub fn path_with(mut self, path: impl Into<PathBuf>, compression: Compression) -> Self {
+        self.items.push(BulkItem {
+            path: path.into(),
+            compression: Some(compression),
+        });
+        self
+    }
+
+    pub fn paths<I, P>(self, paths: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        paths.into_iter().fold(self, |bulk, path| bulk.path(path))
+    }
+
+    pub fn on_added<F: FnMut(&Path) + 'a>(mut self, on_added: F) -> Self {
+        self.on_added = Some(Box::new(on_added));
+        self
+    }
+
+    /// Adds the queued paths, a failure stops the add with the paths before
+    /// the failing one added
+    pub fn run(mut self) -> Result<(), DepotError> {
+        self.depot
+            .add_bulk(&self.items, self.workers, self.on_added.as_deref_mut())
+    }
+}
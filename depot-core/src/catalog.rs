@@ -0,0 +1,315 @@
+//! Catalogs index many depots at once, e.g. every archive of a release, so
+//! the archive holding an asset can be found without opening them all. A
+//! catalog is itself a depot carrying the index in a reserved stream
+
+use crate::depot_handle::{DepotHandle, OpenMode};
+use crate::error::DepotError;
+use crate::fingerprint::Fingerprint;
+use crate::helpers::{De, Ser};
+use crate::{normalize_id_name, MAGIC};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use neoncore::streams::read::read_lpstr;
+use neoncore::streams::write::write_lpstr;
+use neoncore::streams::{Endianness, LPWidth, SeekRead, SeekWrite};
+use std::fs::{self, File};
+use std::io::{Cursor, Error, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+
+/// Reserved stream holding the catalog index
+pub const CATALOG_STREAM: &str = ".depot/catalog";
+
+const CATALOG_VERSION: u32 = 2;
+
+/// Summary of a single entry of a catalogued depot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CatalogEntry {
+    pub name: String,
+    pub size: u64,
+    pub stream_size: u64,
+    /// Content hash as recorded in the depot's toc
+    pub hash: u64,
+}
+
+/// Toc summary of a catalogued depot
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CatalogArchive {
+    /// Path of the depot relative to the catalogued directory
+    pub path: String,
+    /// Size of the depot file
+    pub file_size: u64,
+    /// Uncompressed size of all entries
+    pub size: u64,
+    /// See [`DepotHandle::archive_fingerprint`], `None` in catalogs built
+    /// before it was recorded
+    pub fingerprint: Option<Fingerprint>,
+    pub entries: Vec<CatalogEntry>,
+}
+
+/// An entry found in a catalog
+#[derive(Debug, Clone, Copy)]
+pub struct CatalogHit<'a> {
+    pub archive: &'a CatalogArchive,
+    pub entry: &'a CatalogEntry,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Catalog {
+    pub archives: Vec<CatalogArchive>,
+}
+
+impl Catalog {
+    /// Catalogs every depot below `root`, other files and catalogs are
+    /// skipped. Archives are ordered by path
+    pub fn build<P: AsRef<Path>>(root: P) -> Result<Self, DepotError> {
+        let root = root.as_ref();
+        let mut catalog = Catalog::default();
+        catalog.scan(root, root)?;
+        catalog.archives.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(catalog)
+    }
+
+    fn scan(&mut self, root: &Path, dir: &Path) -> Result<(), DepotError> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_symlink() {
+                continue;
+            }
+            if path.is_dir() {
+                self.scan(root, &path)?;
+            } else if is_depot(&path)? {
+                let dh = DepotHandle::open_file(&path, OpenMode::Read)
+                    .map_err(|e| not_opened(&path, e))?;
+                if dh.get_named_stream(CATALOG_STREAM).is_some() {
+                    continue;
+                }
+                let relative = path.strip_prefix(root).unwrap_or(&path);
+                self.add_depot(&relative.to_string_lossy(), &dh, fs::metadata(&path)?.len());
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the user streams of an open depot under `path`
+    pub fn add_depot(&mut self, path: &str, depot: &DepotHandle, file_size: u64) {
+        let entries: Vec<CatalogEntry> = depot
+            .streams()
+            .map(|(name, einf)| CatalogEntry {
+                name: name.clone(),
+                size: einf.size,
+                stream_size: einf.stream_size,
+                hash: einf.hash,
+            })
+            .collect();
+        self.archives.push(CatalogArchive {
+            path: normalize_id_name(path),
+            file_size,
+            size: entries.iter().map(|e| e.size).sum(),
+            fingerprint: Some(depot.archive_fingerprint()),
+            entries,
+        });
+    }
+
+    /// The catalogued depots whose copy below `root` is missing or holds
+    /// something else, e.g. for a launcher to find what to download after
+    /// fetching the catalog of a new release. Only the tocs are read, and
+    /// depots catalogued without a fingerprint are always reported
+    pub fn outdated<P: AsRef<Path>>(&self, root: P) -> Result<Vec<&CatalogArchive>, DepotError> {
+        let mut outdated = Vec::new();
+        for archive in &self.archives {
+            let path: PathBuf = root.as_ref().join(&archive.path);
+            let installed = match DepotHandle::open_file(&path, OpenMode::Read) {
+                Ok(dh) => Some(dh.archive_fingerprint()),
+                Err(e) if e.kind() == ErrorKind::NotFound => None,
+                Err(e) => return Err(not_opened(&path, e)),
+            };
+            if archive.fingerprint.is_none() || installed != archive.fingerprint {
+                outdated.push(archive);
+            }
+        }
+        Ok(outdated)
+    }
+
+    /// Entries named `name` in any archive, names are compared the way
+    /// [`crate::entry_id`] normalizes them
+    pub fn find(&self, name: &str) -> Vec<CatalogHit<'_>> {
+        let name = normalize_id_name(name);
+        self.hits(|entry| normalize_id_name(&entry.name) == name)
+    }
+
+    /// Entries with the content hash `hash` in any archive, e.g. to spot an
+    /// asset shipped in several archives
+    pub fn find_hash(&self, hash: u64) -> Vec<CatalogHit<'_>> {
+        self.hits(|entry| entry.hash == hash)
+    }
+
+    /// Groups `names` by the archive holding them, so each archive of a
+    /// sharded release only has to be opened once. A name present in several
+    /// archives is taken from the first by path, the entry names returned are
+    /// spelled as stored in the archive
+    pub fn locate<S: AsRef<str>>(
+        &self,
+        names: &[S],
+    ) -> Result<Vec<(&CatalogArchive, Vec<String>)>, DepotError> {
+        let mut located: Vec<(&CatalogArchive, Vec<String>)> = Vec::new();
+        for name in names {
+            let name = name.as_ref();
+            let hit = self
+                .find(name)
+                .into_iter()
+                .next()
+                .ok_or_else(|| DepotError::EntryNotFound(name.to_owned()))?;
+            match located
+                .iter_mut()
+                .find(|(archive, _)| std::ptr::eq(*archive, hit.archive))
+            {
+                Some((_, entries)) => entries.push(hit.entry.name.clone()),
+                None => located.push((hit.archive, vec![hit.entry.name.clone()])),
+            }
+        }
+        Ok(located)
+    }
+
+    fn hits<F: Fn(&CatalogEntry) -> bool>(&self, matches: F) -> Vec<CatalogHit<'_>> {
+        self.archives
+            .iter()
+            .flat_map(|archive| {
+                archive
+                    .entries
+                    .iter()
+                    .filter(|entry| matches(entry))
+                    .map(move |entry| CatalogHit { archive, entry })
+            })
+            .collect()
+    }
+
+    /// Writes the catalog into a new depot at `path`
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), DepotError> {
+        let mut dh = DepotHandle::create(File::create(path)?)?;
+        dh.add_reserved_stream(CATALOG_STREAM, Cursor::new(self.to_bytes()))?;
+        dh.close()
+    }
+
+    /// Reads the catalog stored in the depot at `path`
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, DepotError> {
+        let path = path.as_ref();
+        let mut dh = DepotHandle::open_file(path, OpenMode::Read)?;
+        if dh.get_named_stream(CATALOG_STREAM).is_none() {
+            return Err(DepotError::InvalidSource {
+                path: path.to_owned(),
+                reason: "is not a catalog".to_owned(),
+            });
+        }
+        Self::from_bytes(&dh.read_bytes(CATALOG_STREAM)?)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, DepotError> {
+        Ok(Self::de(Cursor::new(data))?)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Cursor::new(Vec::new());
+        // writing to memory can not fail
+        self.ser(&mut data).unwrap();
+        data.into_inner()
+    }
+}
+
+/// A depot at `path` that could not be opened, with why
+fn not_opened(path: &Path, e: DepotError) -> DepotError {
+    DepotError::InvalidSource {
+        path: path.to_owned(),
+        reason: format!("could not be opened, {}", e),
+    }
+}
+
+/// true if the file at `path` starts with the depot magic
+fn is_depot(path: &Path) -> Result<bool, Error> {
+    let mut magic = [0; 8];
+    match File::open(path)?.read_exact(&mut magic) {
+        Ok(()) => Ok(u64::from_be_bytes(magic) == MAGIC),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+impl Ser for Catalog {
+    fn ser<S: SeekWrite>(&self, mut output: S) -> Result<u64, Error> {
+        output.write_u32::<BigEndian>(CATALOG_VERSION)?;
+        output.write_u32::<BigEndian>(self.archives.len() as u32)?;
+        let mut written = 8;
+        for archive in &self.archives {
+            written += write_lpstr(
+                &mut output,
+                LPWidth::LP32,
+                Endianness::BigEndian,
+                &archive.path,
+            )?;
+            output.write_u64::<BigEndian>(archive.file_size)?;
+            output.write_u64::<BigEndian>(archive.size)?;
+            // all zeroes when missing, no digest is ever all zeroes
+            let fingerprint = archive.fingerprint.map_or([0; 32], |fp| fp.0);
+            output.write_all(&fingerprint)?;
+            output.write_u32::<BigEndian>(archive.entries.len() as u32)?;
+            written += 52;
+            for entry in &archive.entries {
+                written += write_lpstr(
+                    &mut output,
+                    LPWidth::LP32,
+                    Endianness::BigEndian,
+                    &entry.name,
+                )?;
+                output.write_u64::<BigEndian>(entry.size)?;
+                output.write_u64::<BigEndian>(entry.stream_size)?;
+                output.write_u64::<BigEndian>(entry.hash)?;
+                written += 24;
+            }
+        }
+        Ok(written)
+    }
+}
+
+impl De for Catalog {
+    fn de<D: SeekRead>(mut stream: D) -> Result<Self, Error>
+    where
+        Self: Sized,
+    {
+        let version = stream.read_u32::<BigEndian>()?;
+        if !(1..=CATALOG_VERSION).contains(&version) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported catalog version {}", version),
+            ));
+        }
+        let count = stream.read_u32::<BigEndian>()?;
+        let mut catalog = Catalog::default();
+        for _ in 0..count {
+            let path = read_lpstr(&mut stream, LPWidth::LP32, Endianness::BigEndian)?;
+            let file_size = stream.read_u64::<BigEndian>()?;
+            let size = stream.read_u64::<BigEndian>()?;
+            let mut fingerprint = None;
+            if version >= 2 {
+                let mut digest = [0; 32];
+                stream.read_exact(&mut digest)?;
+                fingerprint = Some(Fingerprint(digest)).filter(|_| digest != [0; 32]);
+            }
+            let entry_count = stream.read_u32::<BigEndian>()?;
+            let mut entries = Vec::new();
+            for _ in 0..entry_count {
+                entries.push(CatalogEntry {
+                    name: read_lpstr(&mut stream, LPWidth::LP32, Endianness::BigEndian)?,
+                    size: stream.read_u64::<BigEndian>()?,
+                    stream_size: stream.read_u64::<BigEndian>()?,
+                    hash: stream.read_u64::<BigEndian>()?,
+                });
+            }
+            catalog.archives.push(CatalogArchive {
+                path,
+                file_size,
+                size,
+                fingerprint,
+                entries,
+            });
+        }
+        Ok(catalog)
+    }
+}
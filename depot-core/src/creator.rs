@@ -0,0 +1,89 @@
+//! Records which build of which tool last wrote a depot, so odd or corrupt
+//! archives can be traced back to their producer
+
+use crate::FORMAT_VERSION;
+use std::io::{Error, ErrorKind};
+
+/// Stream holding the creator record, rewritten whenever a depot is closed
+/// after writing
+pub const CREATOR_STREAM: &str = ".depot/creator";
+
+/// The producer of a depot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CreatorInfo {
+    /// Tool that wrote the depot, the library itself unless the tool set one
+    pub tool: String,
+    pub tool_version: String,
+    /// Version of the depot library the tool was built with
+    pub library_version: String,
+    /// Format version the depot was written in
+    pub format_version: u16,
+    /// Target the writing build was compiled for, e.g. `x86_64-linux`
+    pub platform: String,
+}
+
+impl Default for CreatorInfo {
+    /// The running build of this library
+    fn default() -> Self {
+        Self {
+            tool: env!("CARGO_PKG_NAME").to_owned(),
+            tool_version: env!("CARGO_PKG_VERSION").to_owned(),
+            library_version: env!("CARGO_PKG_VERSION").to_owned(),
+            format_version: FORMAT_VERSION,
+            platform: format!("{}-{}", std::env::consts::ARCH, std::env::consts::OS),
+        }
+    }
+}
+
+impl CreatorInfo {
+    /// The running build of this library used by `tool`
+    pub fn for_tool(tool: &str, version: &str) -> Self {
+        Self {
+            tool: tool.to_owned(),
+            tool_version: version.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn to_record(&self) -> String {
+        format!(
+            "tool={}\ntool_version={}\nlibrary_version={}\nformat_version={}\nplatform={}\n",
+            self.tool, self.tool_version, self.library_version, self.format_version, self.platform
+        )
+    }
+
+    pub(crate) fn parse_record(record: &str) -> Result<Self, Error> {
+        let mut info = Self {
+            tool: String::new(),
+            tool_version: String::new(),
+            library_version: String::new(),
+            format_version: 0,
+            platform: String::new(),
+        };
+        for line in record.lines().filter(|l| !l.trim().is_empty()) {
+            let (key, value) = line.split_once('=').ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("invalid creator record line `{}`", line),
+                )
+            })?;
+            match key {
+                "tool" => info.tool = value.to_owned(),
+                "tool_version" => info.tool_version = value.to_owned(),
+                "library_version" => info.library_version = value.to_owned(),
+                "format_version" => {
+                    info.format_version = value.parse().map_err(|_| {
+                        Error::new(
+                            ErrorKind::InvalidData,
+                            format!("invalid format version `{}` in creator record", value),
+                        )
+                    })?
+                }
+                "platform" => info.platform = value.to_owned(),
+                // unknown keys are ignored for forward compatibility
+                _ => {}
+            }
+        }
+        Ok(info)
+    }
+}
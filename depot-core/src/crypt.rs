@@ -0,0 +1,269 @@
+//! Encryption of entry data, see [`crate::depot_handle::DepotHandle::set_encryption_key`].
+//! The stored stream of an entry, compressed or not, is sealed with
+//! XChaCha20-Poly1305 in the STREAM construction: chunks of
+//! [`CHUNK_SIZE`] bytes are sealed one after the other under a random
+//! nonce prefix kept in the entry's metadata, the last chunk is marked so
+//! truncated or reordered data fails to decrypt. Without the `encryption`
+//! feature keys can still be made, but encrypting or decrypting fails
+
+use crate::error::DepotError;
+use std::fmt::{Debug, Formatter};
+use std::io::{Error, Read, Write};
+
+/// Length of a key in bytes
+pub const KEY_LEN: usize = 32;
+/// Length of the nonce prefix stored with every entry
+pub const NONCE_LEN: usize = 19;
+/// Plain bytes sealed into one chunk, each chunk grows by a 16 byte tag
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A 256 bit XChaCha20-Poly1305 key
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionKey([u8; KEY_LEN]);
+
+impl EncryptionKey {
+    pub fn new(key: [u8; KEY_LEN]) -> Self {
+        Self(key)
+    }
+
+    /// Reads a key file holding either the 32 raw key bytes or 64 hex
+    /// digits, surrounding whitespace is ignored for the latter
+    pub fn from_key_file(data: &[u8]) -> Result<Self, DepotError> {
+        if let Ok(key) = <[u8; KEY_LEN]>::try_from(data) {
+            return Ok(Self(key));
+        }
+        let hex = std::str::from_utf8(data).unwrap_or_default().trim();
+        let mut key = [0; KEY_LEN];
+        if hex.len() != KEY_LEN * 2 || !hex.is_ascii() {
+            return Err(DepotError::InvalidKey);
+        }
+        for (byte, digits) in key.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            let digits = std::str::from_utf8(digits).map_err(|_| DepotError::InvalidKey)?;
+            *byte = u8::from_str_radix(digits, 16).map_err(|_| DepotError::InvalidKey)?;
+        }
+        Ok(Self(key))
+    }
+}
+
+/// Keys never end up in logs
+impl Debug for EncryptionKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+#[cfg(feature = "encryption")]
+mod imp {
+    use super::*;
+    use chacha20poly1305::aead::rand_core::RngCore;
+    use chacha20poly1305::aead::stream::{DecryptorBE32, EncryptorBE32};
+    use chacha20poly1305::aead::OsRng;
+    use chacha20poly1305::{KeyInit, XChaCha20Poly1305};
+
+    const TAG_LEN: usize = 16;
+
+    /// Seals everything written into chunks, [`Encryptor::finish`] seals
+    /// the last one
+    pub(crate) struct Encryptor<W: Write> {
+        inner: W,
+        stream: Option<EncryptorBE32<XChaCha20Poly1305>>,
+        buf: Vec<u8>,
+    }
+
+    impl<W: Write> Encryptor<W> {
+        /// An encryptor under a fresh random nonce, returned to be stored
+        /// with the entry
+        pub(crate) fn new(key: &EncryptionKey, inner: W) -> Result<(Self, Vec<u8>), Error> {
+            let mut nonce = [0; NONCE_LEN];
+            OsRng.fill_bytes(&mut nonce);
+            let aead = XChaCha20Poly1305::new(&key.0.into());
+            let encryptor = Self {
+                inner,
+                stream: Some(EncryptorBE32::from_aead(aead, &nonce.into())),
+                buf: Vec::with_capacity(CHUNK_SIZE + TAG_LEN),
+            };
+            Ok((encryptor, nonce.to_vec()))
+        }
+
+        pub(crate) fn finish(mut self) -> Result<W, Error> {
+            let stream = self.stream.take().expect("finished once");
+            stream
+                .encrypt_last_in_place(b"", &mut self.buf)
+                .map_err(|_| Error::other("encryption failed"))?;
+            self.inner.write_all(&self.buf)?;
+            Ok(self.inner)
+        }
+    }
+
+    impl<W: Write> Write for Encryptor<W> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            // a full chunk is only sealed once more data shows it is not
+            // the last one
+            if self.buf.len() == CHUNK_SIZE {
+                let stream = self.stream.as_mut().expect("not finished");
+                stream
+                    .encrypt_next_in_place(b"", &mut self.buf)
+                    .map_err(|_| Error::other("encryption failed"))?;
+                self.inner.write_all(&self.buf)?;
+                self.buf.clear();
+            }
+            let n = buf.len().min(CHUNK_SIZE - self.buf.len());
+            self.buf.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    /// Opens the chunks sealed by an [`Encryptor`] as their data is
+    /// pushed in, so it also serves readers that can not block
+    pub(crate) struct ChunkOpener {
+        stream: Option<DecryptorBE32<XChaCha20Poly1305>>,
+        /// sealed bytes not opened yet
+        sealed: Vec<u8>,
+    }
+
+    impl ChunkOpener {
+        pub(crate) fn new(key: &EncryptionKey, nonce: &[u8]) -> Result<Self, Error> {
+            let nonce = <[u8; NONCE_LEN]>::try_from(nonce)
+                .map_err(|_| Error::from(DepotError::DecryptFailed))?;
+            let aead = XChaCha20Poly1305::new(&key.0.into());
+            Ok(Self {
+                stream: Some(DecryptorBE32::from_aead(aead, &nonce.into())),
+                sealed: Vec::with_capacity(CHUNK_SIZE + TAG_LEN + 1),
+            })
+        }
+
+        /// Takes the sealed bytes `input` and appends what they open to
+        /// `out`, `eof` once no more input follows
+        pub(crate) fn push(
+            &mut self,
+            input: &[u8],
+            out: &mut Vec<u8>,
+            eof: bool,
+        ) -> Result<(), Error> {
+            let failed = |_| Error::from(DepotError::DecryptFailed);
+            self.sealed.extend_from_slice(input);
+            // a chunk is only known not to be the last once more follows
+            while self.sealed.len() > CHUNK_SIZE + TAG_LEN {
+                let Some(stream) = self.stream.as_mut() else {
+                    break;
+                };
+                let rest = self.sealed.split_off(CHUNK_SIZE + TAG_LEN);
+                let mut chunk = std::mem::replace(&mut self.sealed, rest);
+                stream
+                    .decrypt_next_in_place(b"", &mut chunk)
+                    .map_err(failed)?;
+                out.extend_from_slice(&chunk);
+            }
+            if eof {
+                if let Some(stream) = self.stream.take() {
+                    let mut chunk = std::mem::take(&mut self.sealed);
+                    stream
+                        .decrypt_last_in_place(b"", &mut chunk)
+                        .map_err(failed)?;
+                    out.extend_from_slice(&chunk);
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(feature = "encryption"))]
+mod imp {
+    use super::*;
+    use std::marker::PhantomData;
+
+    fn unsupported() -> Error {
+        DepotError::Unsupported("depot-core was built without the encryption feature".to_owned())
+            .into()
+    }
+
+    pub(crate) struct Encryptor<W: Write>(PhantomData<W>);
+
+    impl<W: Write> Encryptor<W> {
+        pub(crate) fn new(_key: &EncryptionKey, _inner: W) -> Result<(Self, Vec<u8>), Error> {
+            Err(unsupported())
+        }
+
+        pub(crate) fn finish(self) -> Result<W, Error> {
+            Err(unsupported())
+        }
+    }
+
+    impl<W: Write> Write for Encryptor<W> {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(unsupported())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Err(unsupported())
+        }
+    }
+
+    pub(crate) struct ChunkOpener;
+
+    impl ChunkOpener {
+        pub(crate) fn new(_key: &EncryptionKey, _nonce: &[u8]) -> Result<Self, Error> {
+            Err(unsupported())
+        }
+
+        pub(crate) fn push(
+            &mut self,
+            _input: &[u8],
+            _out: &mut Vec<u8>,
+            _eof: bool,
+        ) -> Result<(), Error> {
+            Err(unsupported())
+        }
+    }
+}
+
+pub(crate) use imp::{ChunkOpener, Encryptor};
+
+/// Opens the data sealed by an [`Encryptor`] as it is read
+pub(crate) struct Decryptor<R: Read> {
+    inner: R,
+    opener: ChunkOpener,
+    /// opened data and how much of it was read
+    opened: Vec<u8>,
+    pos: usize,
+    buf: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: Read> Decryptor<R> {
+    pub(crate) fn new(key: &EncryptionKey, nonce: &[u8], inner: R) -> Result<Self, Error> {
+        Ok(Self {
+            inner,
+            opener: ChunkOpener::new(key, nonce)?,
+            opened: Vec::new(),
+            pos: 0,
+            buf: vec![0; CHUNK_SIZE],
+            eof: false,
+        })
+    }
+}
+
+impl<R: Read> Read for Decryptor<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.opened.len() {
+            if self.eof {
+                return Ok(0);
+            }
+            self.opened.clear();
+            self.pos = 0;
+            let n = self.inner.read(&mut self.buf)?;
+            self.eof = n == 0;
+            self.opener
+                .push(&self.buf[..n], &mut self.opened, self.eof)?;
+        }
+        let n = buf.len().min(self.opened.len() - self.pos);
+        buf[..n].copy_from_slice(&self.opened[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
@@ -1,21 +1,50 @@
-use seahash::SeaHasher;
 use std::fmt::Debug;
-use std::fs::OpenOptions;
-use std::hash::Hasher;
-use std::io::{BufReader, Cursor, Error, ErrorKind, Read, Seek, SeekFrom, Write};
-use std::mem::size_of;
-use std::path::Path;
-use std::{fs, vec};
-
-use crate::helpers::{De, Ser, TsWithTz};
-use crate::MAGIC;
+use std::fs::{File, OpenOptions};
+use std::io::{
+    BufRead, BufReader, BufWriter, Cursor, Error, ErrorKind, Read, Seek, SeekFrom, Write,
+};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::{fs, thread, vec};
+
+use crate::bulk::{BulkAdd, BulkAddedFn, BulkItem};
+use crate::creator::{CreatorInfo, CREATOR_STREAM};
+use crate::crypt::{Decryptor, EncryptionKey, Encryptor};
+use crate::dict::{self, Dictionary, DICT_PREFIX};
+use crate::dir_index::{child_of, normalize_dir, DirChild, DirIndex, DIR_INDEX_STREAM};
+use crate::error::DepotError;
+use crate::events::{DepotEvents, Operation, ProgressWriter};
+use crate::extract::{check_contained, DefaultNameMapper, ExtractOptions, ExtractedFn, Owner};
+use crate::fingerprint::Fingerprint;
+use crate::freeze::{self, FreezeTrailer};
+use crate::hash::EntryHasher;
+use crate::helpers::{
+    copy_range, is_pipe, preallocate, read_lpstr_max, remaining, uncovered, De, FileAt, FileId,
+    Mapped, Ser, Snapshot, TsWithTz,
+};
+use crate::ingest::{IngestHooks, IngestSource};
+use crate::meta::keys;
+use crate::meta::{source_meta, EntryType, ExtMeta, Visibility};
+use crate::phf::{PhfIndex, PHF_STREAM};
+use crate::policy::{ContentPolicy, PolicyAction, Scanned, SecretScan};
+use crate::repack::{RepackOptions, RepackOrder};
+use crate::solid::{PendingBlock, SolidOptions};
+use crate::temp::{TempFile, TempPolicy};
+use crate::trace::AccessTrace;
+use crate::volume::{self, Volumes};
+use crate::{
+    entry_id, is_reserved_name, normalize_id_name, FORMAT_VERSION, MAGIC, MAX_NAME_LEN,
+    MAX_TOC_SIZE,
+};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use neoncore::streams::read::{read_lpstr, read_pattern};
+use neoncore::streams::read::read_pattern;
 use neoncore::streams::write::{write_lpstr, write_values};
 use neoncore::streams::{AnyInt, Endianness, LPWidth, SeekRead, SeekReadWrite, SeekWrite};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::{Bound, Range};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 #[readonly::make]
 pub struct DepotToc {
     /// If the toc is compressed and the compression level
@@ -28,6 +57,9 @@ pub struct DepotToc {
     /// a map of resource name to offset, size, and compressed size
     /// if is_compressed is false, the compressed size is set to 0
     pub entries: BTreeMap<String, EntryInfo>,
+    /// Metadata of the depot as a whole, stored after the entries since
+    /// version 7, see [`DepotHandle::set_archive_meta`]
+    pub meta: ExtMeta,
 }
 
 impl Ser for DepotToc {
@@ -38,37 +70,44 @@ impl Ser for DepotToc {
             self.size.into(),
         ];
 
-        let mut written = write_values(
+        let written = write_values(
             &mut output,
             vals.as_slice(),
             neoncore::streams::Endianness::BigEndian,
         )?;
 
+        // the entries are one zstd frame, prefixed with its size
+        let mut entries = Cursor::new(Vec::new());
         for (name, info) in self.entries.iter() {
-            written += write_lpstr(&mut output, LPWidth::LP32, Endianness::BigEndian, name)?;
-            written += info.ser(&mut output)?;
+            write_lpstr(&mut entries, LPWidth::LP32, Endianness::BigEndian, name)?;
+            info.ser(&mut entries)?;
+            info.ext.ser(&mut entries)?;
         }
+        let packed = zstd::bulk::compress(entries.get_ref(), self.compression_level)?;
+        output.write_u64::<BigEndian>(packed.len() as u64)?;
+        output.write_all(&packed)?;
+        let meta = self.meta.ser(&mut output)?;
 
-        Ok(written)
-    }
-}
-
-impl Default for DepotToc {
-    fn default() -> Self {
-        Self {
-            compression_level: 0,
-            entry_count: 0,
-            size: 0,
-            entries: BTreeMap::new(),
-        }
+        Ok(written + 8 + packed.len() as u64 + meta)
     }
 }
 
 impl De for DepotToc {
-    fn de<D: SeekRead>(mut stream: D) -> Result<Self, std::io::Error>
+    fn de<D: SeekRead>(stream: D) -> Result<Self, std::io::Error>
     where
         Self: Sized,
     {
+        Ok(Self::de_versioned(stream, FORMAT_VERSION)?)
+    }
+}
+
+impl DepotToc {
+    /// Reads a toc written by the given format version, the stream is left
+    /// at its end
+    pub(crate) fn de_versioned<D: SeekRead>(
+        mut stream: D,
+        version: u16,
+    ) -> Result<Self, DepotError> {
         let format = "!Wqq";
         let read = read_pattern(&mut stream, format)?;
 
@@ -77,17 +116,95 @@ impl De for DepotToc {
             entry_count: read[1].try_into().unwrap(),
             size: read[2].try_into().unwrap(),
             entries: BTreeMap::new(),
+            meta: ExtMeta::default(),
         };
-        for _ in 0..toc.entry_count {
-            let name = read_lpstr(&mut stream, LPWidth::LP32, Endianness::BigEndian)?;
-            let entry = EntryInfo::de(&mut stream)?;
-            toc.entries.insert(name, entry);
+        // version 6 compressed the entries, earlier ones follow in the clear
+        if version < 6 {
+            toc.read_entries(&mut stream, version)?;
+            return Ok(toc);
+        }
+        let packed_size = stream.read_u64::<BigEndian>()?;
+        let start = stream.stream_position()?;
+        let end = stream.seek(SeekFrom::End(0))?;
+        stream.seek(SeekFrom::Start(start))?;
+        // the size is checked before the buffer for it is allocated
+        if packed_size > end - start {
+            return Err(DepotError::CorruptToc {
+                at_entry: 0,
+                reason: format!(
+                    "the entries are {} bytes packed, {} are left",
+                    packed_size,
+                    end - start
+                ),
+            });
+        }
+        let mut packed = vec![0; packed_size as usize];
+        stream.read_exact(&mut packed)?;
+        let entries = unpack_entries(&packed)?;
+        toc.read_entries(Cursor::new(entries), version)?;
+        // version 7 added the depot metadata
+        if version >= 7 {
+            toc.meta = ExtMeta::de_within(&mut stream, end)?;
         }
-
         Ok(toc)
     }
+
+    /// Reads the entries up to the end of `stream`, lengths are checked
+    /// against what is left of it so a damaged toc fails with
+    /// [`DepotError::CorruptToc`] instead of allocating gigabytes
+    fn read_entries<D: SeekRead>(&mut self, mut stream: D, version: u16) -> Result<(), DepotError> {
+        let start = stream.stream_position()?;
+        let end = stream.seek(SeekFrom::End(0))?;
+        stream.seek(SeekFrom::Start(start))?;
+        for at_entry in 0..self.entry_count {
+            let mut read_entry = || -> Result<(String, EntryInfo), Error> {
+                let max = remaining(&mut stream, end)?.min(MAX_NAME_LEN as u64);
+                let name = read_lpstr_max(&mut stream, max)?;
+                let mut entry = EntryInfo::de(&mut stream)?;
+                if version >= 2 {
+                    entry.ext = ExtMeta::de_within(&mut stream, end)?;
+                }
+                Ok((name, entry))
+            };
+            let (name, entry) = read_entry().map_err(|e| DepotError::CorruptToc {
+                at_entry,
+                reason: e.to_string(),
+            })?;
+            self.entries.insert(name, entry);
+        }
+        Ok(())
+    }
+}
+
+/// Decompresses the packed toc entries, what would unpack to more than
+/// [`MAX_TOC_SIZE`] fails with [`DepotError::CorruptToc`] before it is
+/// allocated
+fn unpack_entries(packed: &[u8]) -> Result<Vec<u8>, DepotError> {
+    let corrupt = |reason| DepotError::CorruptToc {
+        at_entry: 0,
+        reason,
+    };
+    let too_large = || corrupt(format!("the entries unpack to over {} bytes", MAX_TOC_SIZE));
+    // the size recorded in the frame header is checked without decoding
+    if let Ok(Some(size)) = zstd::zstd_safe::get_frame_content_size(packed) {
+        if size > MAX_TOC_SIZE {
+            return Err(too_large());
+        }
+    }
+    let mut entries = Vec::new();
+    zstd::stream::Decoder::new(packed)
+        .and_then(|decoder| decoder.take(MAX_TOC_SIZE + 1).read_to_end(&mut entries))
+        .map_err(|e| corrupt(format!("the entries do not decompress, {}", e)))?;
+    if entries.len() as u64 > MAX_TOC_SIZE {
+        return Err(too_large());
+    }
+    Ok(entries)
 }
 
+/// Called by [`DepotHandle::verify_all`] after checking a stream, with the
+/// error if it is corrupted
+pub type VerifiedFn<'a> = dyn FnMut(&StreamInfo, Option<&DepotError>) + 'a;
+
 #[derive(Debug, Clone)]
 #[readonly::make]
 pub struct StreamInfo {
@@ -107,6 +224,11 @@ pub(crate) struct DepotHeader {
     pub toc_offset: u64,
 }
 
+impl DepotHeader {
+    /// size of the serialized header, magic + version + toc offset
+    pub(crate) const SIZE: u64 = 8 + 2 + 8;
+}
+
 impl Ser for DepotHeader {
     fn ser<S: SeekWrite>(&self, mut output: S) -> Result<u64, Error> {
         output.write_u64::<BigEndian>(MAGIC)?;
@@ -123,10 +245,7 @@ impl De for DepotHeader {
     {
         let magic = stream.read_u64::<BigEndian>()?;
         if magic != MAGIC {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                "invalid magic number in depot header",
-            ));
+            return Err(DepotError::BadMagic.into());
         }
         let version = stream.read_u16::<BigEndian>()?;
         let toc_offset = stream.read_u64::<BigEndian>()?;
@@ -147,6 +266,64 @@ pub struct EntryInfo {
     pub create_ts: TsWithTz,
     pub mod_ts: TsWithTz,
     pub hash: u64,
+    /// Extended metadata, serialized separately by the toc
+    pub ext: ExtMeta,
+}
+
+impl EntryInfo {
+    /// The entry is an empty file with no data
+    pub const FLAG_EMPTY: u64 = 1;
+    /// The entry data is stored uncompressed
+    pub const FLAG_STORED: u64 = 1 << 1;
+    /// The entry data is an lz4 frame
+    pub const FLAG_LZ4: u64 = 1 << 2;
+    /// The stored data is encrypted, see [`crate::crypt`]
+    pub const FLAG_ENCRYPTED: u64 = 1 << 3;
+    /// The stored data is a solid block shared with other small entries,
+    /// see [`crate::solid`]
+    pub const FLAG_SOLID: u64 = 1 << 4;
+
+    /// How the entry data is compressed
+    pub fn compression(&self) -> Compression {
+        if self.flags & Self::FLAG_STORED != 0 {
+            Compression::None
+        } else if self.flags & Self::FLAG_LZ4 != 0 {
+            Compression::Lz4
+        } else {
+            Compression::Zstd
+        }
+    }
+
+    /// When the entry was added to the depot
+    pub fn added_at(&self) -> TsWithTz {
+        self.create_ts
+    }
+
+    /// When the source file was created, if the platform recorded it, see
+    /// [`ExtMeta::birth_time`]
+    pub fn birth_time(&self) -> Option<std::time::SystemTime> {
+        self.ext.birth_time()
+    }
+
+    /// What the entry was added from, see [`ExtMeta::entry_type`]
+    pub fn entry_type(&self) -> EntryType {
+        self.ext.entry_type()
+    }
+
+    /// Target of a symlink entry
+    pub fn link_target(&self) -> Option<PathBuf> {
+        self.ext.link_target()
+    }
+
+    /// Who the entry may be served to, see [`Visibility`]
+    pub fn visibility(&self) -> Visibility {
+        self.ext.visibility()
+    }
+
+    /// Value of the user attribute `key`, see [`DepotHandle::set_entry_meta`]
+    pub fn user_meta(&self, key: &str) -> Option<&[u8]> {
+        self.ext.user(key)
+    }
 }
 
 impl Ser for EntryInfo {
@@ -177,10 +354,21 @@ impl De for EntryInfo {
             create_ts: TsWithTz::from_u64(read[4].try_into().unwrap()),
             mod_ts: TsWithTz::from_u64(read[5].try_into().unwrap()),
             hash: read[6].try_into().unwrap(),
+            ext: ExtMeta::default(),
         })
     }
 }
 
+impl EntryInfo {
+    /// Reads an entry followed by its extended metadata, as the toc holds
+    /// them since version 2. Lengths are checked against `end`
+    pub(crate) fn de_with_ext<D: SeekRead>(mut stream: D, end: u64) -> Result<Self, Error> {
+        let mut entry = Self::de(&mut stream)?;
+        entry.ext = ExtMeta::de_within(&mut stream, end)?;
+        Ok(entry)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct DepotMetadata {
     pub header: DepotHeader,
@@ -195,37 +383,316 @@ pub enum OpenMode {
     ReadWrite,
 }
 
+/// The stream backing a handle, handles opened for reading only ever hold
+/// a stream that cannot be written to
+enum DepotStream<'io> {
+    Read(Box<dyn 'io + SeekRead>),
+    ReadWrite(Box<dyn 'io + SeekReadWrite>),
+}
+
+impl<'io> DepotStream<'io> {
+    fn writer(&mut self) -> Result<&mut (dyn 'io + SeekReadWrite), DepotError> {
+        match self {
+            DepotStream::Read(_) => Err(DepotError::ReadOnly),
+            DepotStream::ReadWrite(stream) => Ok(stream.as_mut()),
+        }
+    }
+}
+
+impl Read for DepotStream<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            DepotStream::Read(stream) => stream.read(buf),
+            DepotStream::ReadWrite(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Seek for DepotStream<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match self {
+            DepotStream::Read(stream) => stream.seek(pos),
+            DepotStream::ReadWrite(stream) => stream.seek(pos),
+        }
+    }
+}
+
+/// Compression applied to added entries
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum Compression {
+    /// zstd at the depot's compression level
+    #[default]
+    Zstd,
+    /// lz4 frames, faster to decompress at a worse ratio, for latency
+    /// sensitive assets
+    Lz4,
+    /// Stored as is, for data that is already compressed like images or video
+    None,
+}
+
+/// What to do with an entry that compressed worse than allowed
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RatioAction {
+    /// Keep the compressed entry and record a warning
+    Warn,
+    /// Fail the add
+    Error,
+    /// Rewrite the entry uncompressed and record a warning
+    Store,
+}
+
+/// Compression ratio guard applied to each added entry
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RatioGuard {
+    /// Largest acceptable `stream_size / size`, 1.0 flags any growth
+    pub max_ratio: f64,
+    pub action: RatioAction,
+}
+
+impl Default for RatioGuard {
+    fn default() -> Self {
+        Self {
+            max_ratio: 1.0,
+            action: RatioAction::Store,
+        }
+    }
+}
+
+/// Serialization format of structured streams
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Format {
+    Json,
+    Toml,
+}
+
+/// Reads of a single entry, see [`DepotHandle::set_access_stats`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AccessStats {
+    /// Times the entry was read
+    pub reads: u64,
+    /// Uncompressed bytes read
+    pub bytes: u64,
+}
+
+/// What to do when a source changes while it is being added
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum ChangePolicy {
+    /// Fail the add, nothing of the entry is kept
+    #[default]
+    Fail,
+    /// Record the bytes actually read and a warning
+    Tolerate,
+}
+
 #[readonly::make]
 pub struct DepotHandle<'io> {
     metadata: DepotMetadata,
     mode: OpenMode,
     header_offset: u64,
+    /// end of the entry data, where the next entry and the toc are written
+    data_end: u64,
     mt_threads: usize,
     compression_frame_size: usize,
-    handle: Box<dyn 'io + SeekReadWrite>,
+    ratio_guard: Option<RatioGuard>,
+    content_policy: Option<Arc<ContentPolicy>>,
+    /// why the secret scan refused the entry being added, see
+    /// [`DepotHandle::add_checked`]
+    secret_found: Option<String>,
+    change_policy: ChangePolicy,
+    snapshot: bool,
+    independent_frames: Option<usize>,
+    compression: Compression,
+    temp_policy: TempPolicy,
+    /// the file the depot is written to, never added to itself
+    output_id: Option<FileId>,
+    /// the file the depot was opened from, used to cut off what is left of
+    /// an older, longer toc and to copy stored data between files without
+    /// a userspace buffer
+    file: Option<File>,
+    warnings: Vec<String>,
+    /// entry ids to names, see [`crate::entry_id`]
+    ids: HashMap<u64, String>,
+    perfect_hash: bool,
+    dir_index: bool,
+    /// the directory index read back or built by [`DepotHandle::list_dir`]
+    dirs: Option<DirIndex>,
+    /// dictionaries read back or added, selected by entry name
+    dictionaries: Vec<Dictionary>,
+    creator: CreatorInfo,
+    access_stats: Option<BTreeMap<String, AccessStats>>,
+    /// decompressed entries kept in memory by
+    /// [`DepotHandle::warm_from_trace`], with the offset they were read from
+    pinned: HashMap<String, (u64, Arc<[u8]>)>,
+    encryption_key: Option<EncryptionKey>,
+    ingest_hooks: Option<Arc<dyn IngestHooks>>,
+    /// content hash and size of the entries added data is shared with,
+    /// `None` unless dedup is on
+    dedup: Option<HashMap<(u64, u64), String>>,
+    events: Option<Arc<dyn DepotEvents>>,
+    /// the trailer the depot was frozen with, see [`DepotHandle::freeze`]
+    frozen: Option<FreezeTrailer>,
+    /// the parts of a split depot, cut to size when closing
+    volumes: Option<Volumes>,
+    solid: Option<SolidOptions>,
+    /// the solid block small entries are being added to
+    block: Option<PendingBlock>,
+    /// id of the next solid block
+    next_block: u64,
+    /// the solid block read last, decompressed, with the offset it was
+    /// read from
+    block_cache: Option<(u64, Arc<[u8]>)>,
+    /// the bytes of a depot held in or mapped into memory, entries are
+    /// decoded from them in place, see [`DepotHandle::stream_slice`]
+    mapped: Option<Mapped<'io>>,
+    handle: DepotStream<'io>,
 }
 
 impl<'io> DepotHandle<'io> {
-    pub fn new<T: SeekReadWrite + 'io>(mut handle: T, mode: OpenMode) -> Result<Self, Error> {
+    /// Opens a depot, when `mode` is [`OpenMode::Read`] the stream is only
+    /// ever used for reading, see [`DepotHandle::open_read`]
+    pub fn new<T: SeekReadWrite + 'io>(handle: T, mode: OpenMode) -> Result<Self, DepotError> {
+        Self::new_with(handle, mode, false)
+    }
+
+    fn new_with<T: SeekReadWrite + 'io>(
+        handle: T,
+        mode: OpenMode,
+        unfreeze: bool,
+    ) -> Result<Self, DepotError> {
+        if mode == OpenMode::Read {
+            return Self::open_read(handle);
+        }
+        Self::from_stream(
+            DepotStream::ReadWrite(Box::new(handle)),
+            mode,
+            true,
+            unfreeze,
+        )
+    }
+
+    /// Opens a depot over a stream that is not writable, the handle is
+    /// read-only and can never modify the stream
+    pub fn open_read<T: SeekRead + 'io>(handle: T) -> Result<Self, DepotError> {
+        Self::from_stream(
+            DepotStream::Read(Box::new(handle)),
+            OpenMode::Read,
+            true,
+            false,
+        )
+    }
+
+    /// Like [`DepotHandle::open_read`], but opens depots of any format
+    /// version instead of failing with [`DepotError::UnsupportedVersion`],
+    /// reading them as the current version. Meant for inspecting damaged
+    /// or newer depots, whatever a newer version added may be misread
+    pub fn open_read_any_version<T: SeekRead + 'io>(handle: T) -> Result<Self, DepotError> {
+        Self::from_stream(
+            DepotStream::Read(Box::new(handle)),
+            OpenMode::Read,
+            false,
+            false,
+        )
+    }
+
+    fn from_stream(
+        mut handle: DepotStream<'io>,
+        mode: OpenMode,
+        check_version: bool,
+        unfreeze: bool,
+    ) -> Result<Self, DepotError> {
         let header_offset = handle.stream_position()?;
         let header = DepotHeader::de(&mut handle)?;
+        if check_version && !(1..=FORMAT_VERSION).contains(&header.version) {
+            return Err(DepotError::UnsupportedVersion {
+                found: header.version,
+                supported: FORMAT_VERSION,
+            });
+        }
         handle.seek(SeekFrom::Start(header.toc_offset))?;
-        let toc = DepotToc::de(&mut handle)?;
+        let toc = DepotToc::de_versioned(&mut handle, header.version)?;
+        let frozen = FreezeTrailer::read_after(&mut handle)?;
+        let immutable = frozen.is_some_and(|trailer| trailer.is_immutable());
+        if mode != OpenMode::Read && immutable && !unfreeze {
+            return Err(DepotError::Frozen);
+        }
+        // archives written before ids were recorded get them derived
+        let ids = toc
+            .entries
+            .iter()
+            .map(|(name, entry)| {
+                (
+                    entry.ext.id().unwrap_or_else(|| entry_id(name)),
+                    name.clone(),
+                )
+            })
+            .collect();
+        // indexes baked before are kept in sync with later changes
+        let perfect_hash = toc.entries.contains_key(PHF_STREAM);
+        let dir_index = toc.entries.contains_key(DIR_INDEX_STREAM);
+        // entries added later overwrite the old toc, and the streams that
+        // are rewritten when closing
+        let data_end = toc
+            .entries
+            .iter()
+            .filter(|(name, _)| !rewritten_on_close(name))
+            .map(|(_, entry)| entry.offset.saturating_add(entry.stream_size))
+            .fold(header_offset + DepotHeader::SIZE, u64::max);
+        let next_block = toc
+            .entries
+            .values()
+            .filter_map(|entry| entry.ext.solid_block())
+            .map(|(id, _)| id + 1)
+            .max()
+            .unwrap_or(0);
 
-        Ok(Self {
+        let mut dh = Self {
             metadata: DepotMetadata { header, toc },
             mode,
             header_offset,
+            data_end,
             mt_threads: 1,
             compression_frame_size: 8192,
-            handle: Box::new(handle),
-        })
+            ratio_guard: None,
+            content_policy: None,
+            secret_found: None,
+            change_policy: ChangePolicy::Fail,
+            snapshot: false,
+            independent_frames: None,
+            compression: Compression::Zstd,
+            temp_policy: TempPolicy::System,
+            output_id: None,
+            file: None,
+            warnings: Vec::new(),
+            ids,
+            perfect_hash,
+            dir_index,
+            dirs: None,
+            dictionaries: Vec::new(),
+            creator: CreatorInfo::default(),
+            access_stats: None,
+            pinned: HashMap::new(),
+            encryption_key: None,
+            ingest_hooks: None,
+            dedup: None,
+            events: None,
+            frozen,
+            volumes: None,
+            solid: None,
+            block: None,
+            next_block,
+            block_cache: None,
+            mapped: None,
+            handle,
+        };
+        dh.load_dictionaries()?;
+        Ok(dh)
     }
 
-    pub fn create<T: SeekReadWrite + 'io>(mut handle: T) -> Result<Self, Error> {
+    pub fn create<T: SeekReadWrite + 'io>(mut handle: T) -> Result<Self, DepotError> {
         let header_offset = handle.stream_position()?;
         let header = DepotHeader {
-            version: 1,
+            version: FORMAT_VERSION,
             toc_offset: !0,
         };
 
@@ -233,36 +700,232 @@ impl<'io> DepotHandle<'io> {
         // write the header with a bogus toc offset
         // of !0(16Eb)
         header.ser(&mut handle)?;
-        handle.seek(SeekFrom::Start(size_of::<DepotHeader>() as u64))?;
+        handle.seek(SeekFrom::Start(header_offset + DepotHeader::SIZE))?;
 
         Ok(Self {
             metadata: DepotMetadata { header, toc },
             mode: OpenMode::ReadWrite,
             header_offset,
+            data_end: header_offset + DepotHeader::SIZE,
             mt_threads: 1,
             compression_frame_size: 8192,
-            handle: Box::new(handle),
+            ratio_guard: None,
+            content_policy: None,
+            secret_found: None,
+            change_policy: ChangePolicy::Fail,
+            snapshot: false,
+            independent_frames: None,
+            compression: Compression::Zstd,
+            temp_policy: TempPolicy::System,
+            output_id: None,
+            file: None,
+            warnings: Vec::new(),
+            ids: HashMap::new(),
+            perfect_hash: false,
+            dir_index: false,
+            dirs: None,
+            dictionaries: Vec::new(),
+            creator: CreatorInfo::default(),
+            access_stats: None,
+            pinned: HashMap::new(),
+            encryption_key: None,
+            ingest_hooks: None,
+            dedup: None,
+            events: None,
+            frozen: None,
+            volumes: None,
+            solid: None,
+            block: None,
+            next_block: 0,
+            block_cache: None,
+            mapped: None,
+            handle: DepotStream::ReadWrite(Box::new(handle)),
         })
     }
 
-    pub fn open_file<P: AsRef<Path>>(file: P, mode: OpenMode) -> Result<Self, Error> {
-        let fh = match mode {
-            OpenMode::Read => fs::OpenOptions::new().read(true).open(file)?,
-            OpenMode::Write => fs::OpenOptions::new().write(true).open(file)?,
-            OpenMode::ReadWrite => fs::OpenOptions::new().read(true).write(true).open(file)?,
+    /// Opens the depot at `file`, split depots are opened by the path of
+    /// their first part or the path they were created at, see
+    /// [`DepotHandle::open_split`]
+    pub fn open_file<P: AsRef<Path>>(file: P, mode: OpenMode) -> Result<Self, DepotError> {
+        Self::open_file_with(file.as_ref(), mode, false)
+    }
+
+    /// Like [`DepotHandle::open_file`], but a frozen depot is opened for
+    /// writing as well. The freeze only ends once the handle writes to the
+    /// depot, so a change failing before that, e.g. on a missing source,
+    /// leaves it frozen. See [`DepotHandle::unfreeze`] to lift it right away
+    pub fn open_file_unfreezing<P: AsRef<Path>>(
+        file: P,
+        mode: OpenMode,
+    ) -> Result<Self, DepotError> {
+        Self::open_file_with(file.as_ref(), mode, true)
+    }
+
+    fn open_file_with(file: &Path, mode: OpenMode, unfreeze: bool) -> Result<Self, DepotError> {
+        if let Some(base) = volume::split_base(file) {
+            return Self::open_split_with(&base, mode, unfreeze);
+        }
+        match mode {
+            OpenMode::Read => {
+                let fh = fs::OpenOptions::new().read(true).open(file)?;
+                let file = fh.try_clone()?;
+                let mut dh = Self::open_read(fh)?;
+                dh.file = Some(file);
+                Ok(dh)
+            }
+            // the toc has to be read back even when only writing
+            OpenMode::Write | OpenMode::ReadWrite => {
+                let fh = fs::OpenOptions::new().read(true).write(true).open(file)?;
+                let output_id = FileId::of(&fh.metadata()?);
+                let file = fh.try_clone()?;
+                let mut dh = Self::new_with(fh, mode, unfreeze)?;
+                dh.output_id = output_id;
+                dh.file = Some(file);
+                Ok(dh)
+            }
+        }
+    }
+
+    /// Creates a new depot at `path`, replacing any existing file. Unlike
+    /// [`DepotHandle::create`] the handle knows its file and refuses to add
+    /// it to itself
+    pub fn create_file<P: AsRef<Path>>(path: P) -> Result<Self, DepotError> {
+        // readable too, so entries can be read back before closing
+        let fh = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let output_id = FileId::of(&fh.metadata()?);
+        let file = fh.try_clone()?;
+        let mut dh = Self::create(fh)?;
+        dh.output_id = output_id;
+        dh.file = Some(file);
+        Ok(dh)
+    }
+
+    /// Creates a new depot at `path` split into parts of `part_size` bytes,
+    /// `path.001`, `path.002` and so on, a new part is started whenever the
+    /// one before is full. Parts of an earlier depot at `path` are replaced
+    pub fn create_split<P: AsRef<Path>>(path: P, part_size: u64) -> Result<Self, DepotError> {
+        let volumes = Volumes::create(path, part_size)?;
+        let mut dh = Self::create(volumes.clone())?;
+        dh.volumes = Some(volumes);
+        Ok(dh)
+    }
+
+    /// Opens the split depot created at `base` by
+    /// [`DepotHandle::create_split`], its parts are chained in order of
+    /// the index in their headers
+    pub fn open_split<P: AsRef<Path>>(base: P, mode: OpenMode) -> Result<Self, DepotError> {
+        Self::open_split_with(base.as_ref(), mode, false)
+    }
+
+    fn open_split_with(base: &Path, mode: OpenMode, unfreeze: bool) -> Result<Self, DepotError> {
+        let volumes = Volumes::open(base, mode != OpenMode::Read)?;
+        let mut dh = match mode {
+            OpenMode::Read => Self::open_read(volumes.clone())?,
+            OpenMode::Write | OpenMode::ReadWrite => {
+                Self::new_with(volumes.clone(), mode, unfreeze)?
+            }
         };
-        Self::new(fh, mode)
+        dh.volumes = Some(volumes);
+        Ok(dh)
+    }
+
+    /// The parts of a split depot, None for depots in a single stream
+    pub fn volumes(&self) -> Option<&Volumes> {
+        self.volumes.as_ref()
+    }
+
+    /// true if the file `meta` describes is the depot being written
+    fn is_output(&self, meta: &fs::Metadata) -> bool {
+        let id = FileId::of(meta);
+        let output = self.output_id.is_some() && id == self.output_id;
+        output || self.volumes.as_ref().is_some_and(|v| v.holds(id))
+    }
+
+    /// Opens the depot at `path` to add entries to it. New entries are
+    /// written after the old toc, which is left as dead space for
+    /// [`DepotHandle::compact_file`] to reclaim, and the toc is written
+    /// again by [`DepotHandle::close`]. The header is only pointed at the new
+    /// toc once that is complete, so the depot stays readable with its old
+    /// entries if the handle is dropped without being closed
+    pub fn open_append<P: AsRef<Path>>(path: P) -> Result<Self, DepotError> {
+        Self::open_append_with(path.as_ref(), false)
+    }
+
+    /// Like [`DepotHandle::open_append`], lifting the freeze of a frozen
+    /// depot once the handle writes to it, see
+    /// [`DepotHandle::open_file_unfreezing`]
+    pub fn open_append_unfreezing<P: AsRef<Path>>(path: P) -> Result<Self, DepotError> {
+        Self::open_append_with(path.as_ref(), true)
+    }
+
+    fn open_append_with(path: &Path, unfreeze: bool) -> Result<Self, DepotError> {
+        let mut dh = Self::open_file_with(path, OpenMode::ReadWrite, unfreeze)?;
+        dh.data_end = dh.handle.seek(SeekFrom::End(0))?;
+        Ok(dh)
+    }
+
+    /// Opens a depot stored in memory for reading, entries are decoded in
+    /// place and stored ones can be borrowed, see
+    /// [`DepotHandle::stream_slice`]
+    pub fn open_memory(data: &'io [u8]) -> Result<Self, DepotError> {
+        let mapped = Mapped(Arc::new(data));
+        let mut dh = Self::open_read(Cursor::new(mapped.clone()))?;
+        dh.mapped = Some(mapped);
+        Ok(dh)
+    }
+
+    /// Opens the depot at `path` for reading with the file mapped into
+    /// memory instead of read through a buffer, like
+    /// [`DepotHandle::open_memory`]. Split depots can not be mapped
+    ///
+    /// # Safety
+    ///
+    /// The handle reads the file through a shared mapping, see
+    /// [`memmap2::Mmap::map`]. The caller must make sure the file is not
+    /// truncated or written to, by this or any other process, until the
+    /// handle and every slice borrowed from it are dropped. A change to the
+    /// file shows through in the borrowed data and a truncation makes
+    /// reading the mapping undefined behavior
+    #[cfg(feature = "mmap")]
+    pub unsafe fn open_mmap<P: AsRef<Path>>(path: P) -> Result<DepotHandle<'static>, DepotError> {
+        let path = path.as_ref();
+        if volume::split_base(path).is_some() {
+            return Err(DepotError::Unsupported(format!(
+                "{} is split into parts, which can not be mapped",
+                path.display()
+            )));
+        }
+        let fh = File::open(path)?;
+        // SAFETY: the mapping is only ever read, the caller guarantees the
+        // file is left as it is while it is mapped
+        let map = unsafe { memmap2::Mmap::map(&fh)? };
+        let mapped = Mapped(Arc::new(map));
+        let mut dh = DepotHandle::open_read(Cursor::new(mapped.clone()))?;
+        dh.mapped = Some(mapped);
+        dh.file = Some(fh);
+        Ok(dh)
     }
 
-    pub fn open_memory(data: &'io mut [u8], mode: OpenMode) -> Result<Self, Error> {
-        let cursor = Cursor::new(data);
-        Self::new(cursor, mode)
+    /// Opens a depot stored in a mutable memory buffer
+    pub fn open_memory_mut(data: &'io mut [u8], mode: OpenMode) -> Result<Self, DepotError> {
+        Self::new(Cursor::new(data), mode)
     }
 
     pub fn set_comp_level(&mut self, level: i32) {
         self.metadata.toc.compression_level = level;
     }
 
+    /// Sets the compression of entries added from now on, it can be changed
+    /// between adds to pick it per entry. Readers detect it from the entry
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
     pub fn set_mt_threads(&mut self, threads: usize) {
         self.mt_threads = threads;
     }
@@ -271,243 +934,4432 @@ impl<'io> DepotHandle<'io> {
         self.compression_frame_size = size;
     }
 
+    /// Compresses every `size` bytes of an entry into its own zstd frame and
+    /// records the frame sizes, so large entries can be decompressed by
+    /// several threads, see [`DepotHandle::extract_stream_parallel`]. Costs
+    /// some ratio as frames share no history, `None` writes single frames.
+    /// [`DepotHandle::read_range`] only decompresses the frames it needs
+    pub fn set_independent_frames(&mut self, size: Option<usize>) {
+        self.independent_frames = size.filter(|size| *size > 0);
+    }
+
+    /// Groups compressed entries added from now on of up to
+    /// `options.threshold` bytes into shared solid blocks, see
+    /// [`crate::solid`], `None` compresses every entry on its own. The
+    /// block being filled is written first
+    pub fn set_solid_blocks(&mut self, options: Option<SolidOptions>) -> Result<(), DepotError> {
+        self.write_block()?;
+        self.solid = options;
+        Ok(())
+    }
+
     pub fn add_file<P: AsRef<Path>>(
         &mut self,
         path: P,
         progress: Option<&mut dyn FnMut(u64, u64)>,
-    ) -> Result<(), Error> {
+    ) -> Result<(), DepotError> {
         let path = path.as_ref();
 
         if self.mode == OpenMode::Read {
-            return Err(Error::new(
-                ErrorKind::PermissionDenied,
-                "cannot add file to depot in read-only mode",
-            ));
+            return Err(DepotError::ReadOnly);
         }
 
         if path.is_dir() {
-            return Err(Error::new(
-                ErrorKind::InvalidInput,
-                format!("{} is a directory", path.display()),
-            ));
+            return Err(DepotError::InvalidSource {
+                path: path.to_owned(),
+                reason: "is a directory".to_owned(),
+            });
         }
 
+        check_user_name(&path.to_string_lossy())?;
+
         // check if the file exists
         if !path.exists() {
-            return Err(Error::new(
-                ErrorKind::NotFound,
-                format!("file {} does not exist", path.display()),
-            ));
-        } else if !path.is_file() {
-            return Err(Error::new(
-                ErrorKind::InvalidInput,
-                format!("{} is not a file", path.display()),
-            ));
+            return Err(DepotError::SourceNotFound(path.to_owned()));
+        }
+
+        // fifos and process substitutions have no size upfront
+        let streaming = is_pipe(path);
+        if !path.is_file() && !streaming {
+            return Err(DepotError::InvalidSource {
+                path: path.to_owned(),
+                reason: "is not a file".to_owned(),
+            });
+        }
+
+        let name = path.to_string_lossy().to_string();
+        // a recursive bake of the directory holding the output would
+        // otherwise read the depot while it grows
+        if self.is_output(&fs::metadata(path)?) {
+            self.warnings
+                .push(format!("skipping {}, it is the depot being written", name));
+            return Ok(());
         }
 
         // open the file for reading
         let mut fh = OpenOptions::new().read(true).open(path)?;
+        if streaming {
+            self.add_named_stream(&name, &mut fh, progress)?;
+            self.set_entry_ext(&name, source_meta(path, true));
+            return Ok(());
+        }
         // get the file size
-        let size = fh.metadata()?.len();
+        let stat = fh.metadata()?;
+        let mut size = stat.len();
+        // with a snapshot the source only has to hold still while it is copied
+        let mut snapshot = None;
+        if self.snapshot {
+            let mut snap = Snapshot::take(&mut fh, path, &self.temp_policy)?;
+            self.check_unchanged(&name, path, &stat)?;
+            size = snap.file().metadata()?.len();
+            snapshot = Some(snap);
+        }
         // create a buffered reader
-        let mut stream = BufReader::new(&mut fh);
+        let source = match &mut snapshot {
+            Some(snap) => snap.file(),
+            None => &mut fh,
+        };
+        let mut stream = BufReader::new(source);
         // get the current position in the depot
-        let before = self.handle.stream_position()?;
+        let before = self.seek_data_end()?;
 
         // zero sized files are just accounted for in the toc
         if size == 0 {
-            let entry_key = path.to_string_lossy().to_string();
+            let entry_key = name;
             // write the entry info
             let entry_info = EntryInfo {
                 offset: before,
                 size: 0,
                 stream_size: 0,
-                flags: 1,
+                flags: EntryInfo::FLAG_EMPTY,
                 create_ts: TsWithTz::now(),
                 mod_ts: TsWithTz::now(),
                 hash: !0,
+                ext: source_meta(path, true),
             };
-            entry_info.ser(&mut self.handle)?;
-            self.metadata.toc.entry_count += 1;
-            self.metadata.toc.entries.insert(entry_key, entry_info);
-            return Ok(());
+            return self.add_with_events(&entry_key, 0, None, |dh, _| {
+                entry_info.ser(dh.handle.writer()?)?;
+                dh.insert_entry(&entry_key, entry_info);
+                Ok(())
+            });
         }
 
-        self.add_named_sized_stream(&path.to_string_lossy(), &mut stream, size, progress)
+        self.add_named_sized_stream(&name, &mut stream, size, progress)?;
+        if snapshot.is_none() {
+            if let Err(e) = self.check_unchanged(&name, path, &stat) {
+                self.remove_last_entry(&name)?;
+                return Err(e);
+            }
+        }
+        self.set_entry_ext(&name, source_meta(path, true));
+        Ok(())
     }
 
-    pub fn add_named_sized_stream<R: SeekRead>(
+    /// Adds `path` as what it is on disk, files with their contents,
+    /// symlinks with their target and directories as an entry of their own
+    /// without descending into them, see [`DepotHandle::add_dir`]
+    pub fn add_path<P: AsRef<Path>>(
         &mut self,
-        name: &str,
-        mut reader: R,
-        size: u64,
-        mut progress: Option<&mut dyn FnMut(u64, u64)>,
-    ) -> Result<(), Error> {
-        let before = self.handle.stream_position()?;
-
-        let mut hasher = SeaHasher::new();
-        let mut compressor = zstd::stream::Encoder::new(
-            self.handle.as_mut(),
-            self.metadata.toc.compression_level as i32,
-        )?;
-
-        compressor.include_checksum(true)?;
-        compressor.multithread(self.mt_threads as u32)?;
-
-        let mut buf = vec![0; self.compression_frame_size];
-        let mut written = 0;
-
-        while let Ok(n) = reader.read(&mut buf) {
-            if n == 0 {
-                break;
-            }
-            compressor.write_all(&buf[..n])?;
-            hasher.write(&buf[..n]);
-            written += n;
-            if let Some(progress) = &mut progress {
-                progress(written as u64, size);
-            }
+        path: P,
+        progress: Option<&mut dyn FnMut(u64, u64)>,
+    ) -> Result<(), DepotError> {
+        let path = path.as_ref();
+        let file_type = fs::symlink_metadata(path)?.file_type();
+        // process substitutions are links like /dev/fd/63 to a pipe
+        if file_type.is_symlink() && !is_pipe(path) {
+            self.add_symlink(path)
+        } else if file_type.is_dir() {
+            self.add_dir_entry(path)
+        } else {
+            self.add_file(path, progress)
         }
+    }
 
-        // finish the compression
-        compressor.flush()?;
-        compressor.finish()?;
-        self.handle.flush()?;
-        let hash = hasher.finish();
+    /// Adds a symlink as an entry recording its target, the target itself
+    /// is not read and need not exist
+    pub fn add_symlink<P: AsRef<Path>>(&mut self, path: P) -> Result<(), DepotError> {
+        let path = path.as_ref();
+        let target = fs::read_link(path)?;
+        let mut ext = source_meta(path, false);
+        ext.set_link_target(&target);
+        self.add_node(path, ext)
+    }
 
-        let compressed_size = self.handle.stream_position()? - before;
+    /// Adds a directory as an entry of its own so its permissions and
+    /// ownership are kept and it is recreated even when empty
+    pub fn add_dir_entry<P: AsRef<Path>>(&mut self, path: P) -> Result<(), DepotError> {
+        let path = path.as_ref();
+        if !fs::symlink_metadata(path)?.is_dir() {
+            return Err(DepotError::InvalidSource {
+                path: path.to_owned(),
+                reason: "is not a directory".to_owned(),
+            });
+        }
+        self.add_node(path, source_meta(path, false))
+    }
 
-        let entry_key = name.to_owned();
+    /// Records an entry without data for a symlink or directory
+    fn add_node(&mut self, path: &Path, ext: ExtMeta) -> Result<(), DepotError> {
+        if self.mode == OpenMode::Read {
+            return Err(DepotError::ReadOnly);
+        }
+        let name = path.to_string_lossy();
+        let name = name.trim_end_matches('/');
+        if name.is_empty() || name == "." {
+            return Err(DepotError::InvalidSource {
+                path: path.to_owned(),
+                reason: "has no name to be stored under".to_owned(),
+            });
+        }
+        check_user_name(name)?;
         let entry = EntryInfo {
-            offset: before,
-            size,
-            stream_size: compressed_size,
-            flags: 0,
+            offset: self.data_end,
+            size: 0,
+            stream_size: 0,
+            flags: EntryInfo::FLAG_EMPTY,
             create_ts: TsWithTz::now(),
             mod_ts: TsWithTz::now(),
-            hash: hash,
+            hash: !0,
+            ext,
         };
-
-        self.metadata.toc.entries.insert(entry_key, entry);
-        self.metadata.toc.entry_count += 1;
-        self.metadata.toc.size += size;
-        Ok(())
+        self.add_with_events(name, 0, None, |dh, _| {
+            dh.insert_entry(name, entry);
+            Ok(())
+        })
     }
 
-    pub fn streams(&self) -> impl Iterator<Item = (&String, &EntryInfo)> {
-        self.metadata.toc.entries.iter()
+    /// Adds everything below `dir` under its path, in name order, with
+    /// directories and symlinks recorded as entries of their own. Symlinks
+    /// are not followed, the depot itself is skipped when it lies in `dir`
+    pub fn add_dir<P: AsRef<Path>>(&mut self, dir: P) -> Result<(), DepotError> {
+        let mut paths = fs::read_dir(dir.as_ref())?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        paths.sort();
+        for path in paths {
+            self.add_path(&path, None)?;
+            if !path.is_symlink() && path.is_dir() {
+                self.add_dir(&path)?;
+            }
+        }
+        Ok(())
     }
 
-    pub fn get_named_stream(&self, name: &str) -> Option<StreamInfo> {
-        let entry = match self.metadata.toc.entries.get(name) {
-            Some(e) => e,
-            None => return None,
-        };
-
-        Some((name.to_owned(), entry.clone()).into())
+    /// Starts adding many paths at once with several files compressed in
+    /// parallel, see [`BulkAdd`]
+    pub fn bulk_add(&mut self) -> BulkAdd<'_, 'io> {
+        BulkAdd::new(self)
     }
 
-    pub fn stream_count(&self) -> u64 {
-        self.metadata.toc.entry_count
+    /// Adds `paths` in order like [`DepotHandle::add_path`], compressing up
+    /// to `workers` files at once
+    pub fn add_files_parallel<P: AsRef<Path>>(
+        &mut self,
+        paths: &[P],
+        workers: usize,
+    ) -> Result<(), DepotError> {
+        self.bulk_add()
+            .workers(workers)
+            .paths(paths.iter().map(|path| path.as_ref()))
+            .run()
     }
 
-    /// Extracts a stream to any SeekWrite implementor
-    pub fn extract_stream<W: SeekWrite>(
+    /// Adds `items` in order, regular files are compressed by `workers`
+    /// threads and written as soon as everything before them is
+    pub(crate) fn add_bulk(
         &mut self,
-        stream: &StreamInfo,
-        mut writer: W,
-    ) -> Result<(), Error> {
-        let name = stream.name.clone();
-        let entry = stream.einf.clone();
-
-        // if the entry is an empty file, just return
-        if entry.flags == 1 {
-            return Ok(());
+        items: &[BulkItem],
+        workers: usize,
+        mut on_added: Option<&mut BulkAddedFn>,
+    ) -> Result<(), DepotError> {
+        self.handle.writer()?;
+        let mut pooled = Vec::with_capacity(items.len());
+        for item in items {
+            check_user_name(&item.path.to_string_lossy())?;
+            let meta = fs::symlink_metadata(&item.path).map_err(|e| {
+                Error::new(
+                    e.kind(),
+                    format!("could not add {}: {}", item.path.display(), e),
+                )
+            })?;
+            let is_output = self.is_output(&meta);
+            // files going into a solid block are only buffered
+            let compression = item.compression.unwrap_or(self.compression);
+            let solid = self
+                .solid
+                .is_some_and(|solid| solid.takes(meta.len(), compression));
+            pooled.push(
+                workers > 1
+                    && meta.is_file()
+                    && (1..=BULK_POOLED_MAX).contains(&meta.len())
+                    && !is_output
+                    && !solid,
+            );
         }
 
-        self.handle.seek(SeekFrom::Start(entry.offset))?;
-        let mut handle_stream = BufReader::new(&mut self.handle);
+        let (jobs, queue) = mpsc::sync_channel::<(usize, Encoding)>(workers);
+        let queue = Arc::new(Mutex::new(queue));
+        let (done, finished) = mpsc::channel::<(usize, Result<Prepared, Error>)>();
+        let snapshot = self.snapshot;
+        let temp_policy = &self.temp_policy.clone();
+        let content_policy = &self.content_policy.clone();
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        thread::scope(|scope| {
+            if pooled.contains(&true) {
+                for _ in 0..workers {
+                    let queue = queue.clone();
+                    let done = done.clone();
+                    scope.spawn(move || loop {
+                        let (index, encoding) = match queue.lock().unwrap().recv() {
+                            Ok(job) => job,
+                            Err(_) => break,
+                        };
+                        let path = &items[index].path;
+                        let result = prepare_file(
+                            path,
+                            &encoding,
+                            snapshot,
+                            temp_policy,
+                            content_policy.as_deref(),
+                        );
+                        let _ = done.send((index, result));
+                    });
+                }
+            }
+            drop(done);
+            let exited = || Error::other("bulk add workers exited");
 
-        let mut hasher = SeaHasher::new();
-        let mut decompressor = zstd::stream::Decoder::new(&mut handle_stream)?;
-        let mut buf = vec![0; 8192];
-        let mut read = 0;
-        while let Ok(n) = decompressor.read(&mut buf) {
-            if read + n > entry.size as usize {
-                writer.write_all(&buf[..entry.size as usize - read])?;
-                break;
+            // workers run at most this far ahead of the last written path,
+            // which bounds the compressed data held back
+            let window = workers * 2;
+            for (index, item) in items.iter().enumerate() {
+                self.write_ready(items, &pooled, &mut next, &mut pending, &mut on_added)?;
+                if !pooled[index] {
+                    continue;
+                }
+                while index - next >= window {
+                    let (done, result) = finished.recv().map_err(|_| exited())?;
+                    pending.insert(done, result);
+                    self.write_ready(items, &pooled, &mut next, &mut pending, &mut on_added)?;
+                }
+                let name = item.path.to_string_lossy();
+                let mut encoding =
+                    self.encoding(&name, item.compression.unwrap_or(self.compression));
+                // the workers already keep every core busy
+                encoding.mt_threads = 0;
+                jobs.send((index, encoding)).map_err(|_| exited())?;
+                pending.extend(finished.try_iter());
             }
-            if n == 0 {
-                break;
+            // closing the queue lets the workers drain it and exit
+            drop(jobs);
+            loop {
+                self.write_ready(items, &pooled, &mut next, &mut pending, &mut on_added)?;
+                if next == items.len() {
+                    return Ok(());
+                }
+                let (done, result) = finished.recv().map_err(|_| exited())?;
+                pending.insert(done, result);
             }
-            writer.write_all(&buf[..n])?;
-            hasher.write(&buf[..n]);
-            read += n;
-        }
+        })
+    }
 
-        // uncompressed size sanity check
-        if writer.stream_position()? != entry.size {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                format!(
-                    "uncompressed size mismatch for {}, expect: {}, actual: {}",
-                    name,
-                    entry.size,
-                    writer.stream_position()?
-                ),
-            ));
+    /// Writes the items of a bulk add from `next` on for as long as they are
+    /// ready, pooled files once their worker finished and the rest right away
+    fn write_ready(
+        &mut self,
+        items: &[BulkItem],
+        pooled: &[bool],
+        next: &mut usize,
+        pending: &mut BTreeMap<usize, Result<Prepared, Error>>,
+        on_added: &mut Option<&mut BulkAddedFn>,
+    ) -> Result<(), DepotError> {
+        while let Some(item) = items.get(*next) {
+            if pooled[*next] {
+                match pending.remove(next) {
+                    Some(prepared) => self.store_prepared(&item.path, prepared?)?,
+                    None => break,
+                }
+            } else {
+                let compression = self.compression;
+                self.compression = item.compression.unwrap_or(compression);
+                let added = self.add_path(&item.path, None);
+                self.compression = compression;
+                added?;
+            }
+            if let Some(cb) = on_added {
+                cb(&item.path);
+            }
+            *next += 1;
         }
+        Ok(())
+    }
 
-        // check the hash
-        let hash = hasher.finish();
-        if hash != entry.hash {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                format!(
-                    "hash mismatch for {}, expect: {}, actual: {}",
-                    name, entry.hash, hash
-                ),
-            ));
+    /// Writes a file compressed by a bulk add worker after the last entry,
+    /// with the checks [`DepotHandle::add_file`] applies
+    fn store_prepared(&mut self, path: &Path, prepared: Prepared) -> Result<(), DepotError> {
+        let name = path.to_string_lossy().to_string();
+        let Prepared {
+            mut entry,
+            size,
+            changed,
+            secret,
+            mut spool,
+            mut source,
+            mut snapshot,
+        } = prepared;
+        self.add_with_events(&name, size, None, |dh, _| {
+            dh.secret_found = secret;
+            if dh.check_ratio(&name, &entry, true)? {
+                // store what was compressed as is, from the snapshot if taken
+                let fh = match &mut snapshot {
+                    Some(snap) => snap.file(),
+                    None => &mut source,
+                };
+                fh.seek(SeekFrom::Start(0))?;
+                entry = dh.write_stream(&name, fh.take(size), size, None, false)?;
+            } else {
+                entry.offset = dh.seek_data_end()?;
+                let shared = dh.dedup_entry(&name, entry.clone());
+                // a duplicate is not written at all
+                if shared.offset == entry.offset {
+                    spool.copy_to(dh.handle.writer()?, dh.file.as_ref())?;
+                }
+                entry = shared;
+            }
+            dh.check_size(&name, &entry, size)?;
+            if changed {
+                dh.report_change(&name)?;
+            }
+            dh.insert_entry(&name, entry);
+            dh.set_entry_ext(&name, source_meta(path, true));
+            Ok(())
+        })
+    }
+
+    /// Compares `path` against the `stat` taken before it was read, a change
+    /// fails or warns according to the [`ChangePolicy`]
+    fn check_unchanged(
+        &mut self,
+        name: &str,
+        path: &Path,
+        stat: &fs::Metadata,
+    ) -> Result<(), DepotError> {
+        if source_changed(path, stat)? {
+            self.report_change(name)?;
         }
+        Ok(())
+    }
 
+    /// Fails or warns about a source modified while it was read, according
+    /// to the [`ChangePolicy`]
+    fn report_change(&mut self, name: &str) -> Result<(), DepotError> {
+        let error = DepotError::SourceModified(name.to_owned());
+        if self.change_policy == ChangePolicy::Fail {
+            return Err(error);
+        }
+        self.warnings.push(error.to_string());
         Ok(())
     }
 
-    /// Extracts a stream to a memory buffer and returns it
-    /// This is a convenience function for extract_stream
-    pub fn stream_to_memory(&mut self, stream: &StreamInfo) -> Result<Vec<u8>, Error> {
-        let mut buf = Vec::new();
-        let mut cursor = Cursor::new(&mut buf);
-        self.extract_stream(stream, &mut cursor)?;
-        Ok(buf)
+    /// Copies every file to a private snapshot before compressing it, using
+    /// a reflink where the filesystem supports it. Sources then only have to
+    /// hold still for the copy, which keeps bakes of live directories like
+    /// saved games or logs consistent
+    pub fn set_snapshot(&mut self, snapshot: bool) {
+        self.snapshot = snapshot;
     }
 
-    fn finalize(&mut self) -> Result<(), Error> {
-        // seek to the end of the file
-        self.handle.seek(SeekFrom::End(0))?;
-        // get the toc offset
-        let toc_offset = self.handle.stream_position()?;
-        // write the toc
-        self.metadata.toc.ser(&mut self.handle)?;
-        // seek to the beginning of the file
-        self.handle.seek(SeekFrom::Start(0))?;
-        // update then write the header
-        self.metadata.header.toc_offset = toc_offset;
-        self.metadata.header.ser(&mut self.handle)?;
-        Ok(())
+    /// Sets where snapshots may be written, see [`TempPolicy`]
+    pub fn set_temp_policy(&mut self, policy: TempPolicy) {
+        self.temp_policy = policy;
     }
 
-    pub fn close(mut self) -> Result<(), Error> {
-        self.finalize()?;
+    /// Drops an entry that was just added and rewinds the data stream so
+    /// the next entry overwrites its data
+    fn remove_last_entry(&mut self, name: &str) -> Result<(), DepotError> {
+        if let Some(entry) = self.metadata.toc.entries.remove(name) {
+            self.metadata.toc.entry_count -= 1;
+            self.metadata.toc.size -= entry.size;
+            self.ids.remove(&entry_id(name));
+            // nothing was written for a member of the block being filled
+            if is_buffered(&entry) {
+                let shared = self.metadata.toc.entries.values().any(|other| {
+                    is_buffered(other) && other.ext.solid_block() == entry.ext.solid_block()
+                });
+                if !shared {
+                    self.unbuffer(&entry);
+                }
+                return Ok(());
+            }
+            // deduplicated data stays for the entries sharing it
+            let shared = self
+                .metadata
+                .toc
+                .entries
+                .values()
+                .any(|other| other.offset == entry.offset && shares_data(other));
+            if !shared {
+                self.data_end = entry.offset;
+                self.block_cache = None;
+                self.handle.seek(SeekFrom::Start(entry.offset))?;
+            }
+        }
         Ok(())
     }
 
-    pub fn flush(&mut self) -> Result<(), Error> {
-        self.handle.flush()?;
-        Ok(())
+    /// Sets how sources growing, shrinking or being rewritten while they are
+    /// read are handled, the default fails the add
+    pub fn set_change_policy(&mut self, policy: ChangePolicy) {
+        self.change_policy = policy;
     }
 
-    pub fn get_toc(&self) -> DepotToc {
-        self.metadata.toc.clone()
+    /// Merges `ext` into the metadata of an entry, keys set by the handle
+    /// itself such as the id are kept
+    fn set_entry_ext(&mut self, name: &str, ext: ExtMeta) {
+        if let Some(entry) = self.metadata.toc.entries.get_mut(name) {
+            for (key, value) in ext.iter() {
+                entry.ext.set(key, value);
+            }
+        }
+    }
+
+    /// Adds `size` bytes read from `reader`, a reader ending early is
+    /// handled according to the [`ChangePolicy`]
+    pub fn add_named_sized_stream<R: SeekRead>(
+        &mut self,
+        name: &str,
+        mut reader: R,
+        size: u64,
+        progress: Option<&mut dyn FnMut(u64, u64)>,
+    ) -> Result<(), DepotError> {
+        check_user_name(name)?;
+        self.add_with_events(name, size, progress, |dh, progress| {
+            let start = reader.stream_position()?;
+            // never read past `size`, a growing source like a log would
+            // otherwise be read forever
+            let mut entry =
+                dh.write_stream(name, (&mut reader).take(size), size, progress, true)?;
+            if dh.check_ratio(name, &entry, true)? {
+                // rewind both sides and store the data as is
+                dh.handle.seek(SeekFrom::Start(entry.offset))?;
+                reader.seek(SeekFrom::Start(start))?;
+                entry = dh.write_stream(name, (&mut reader).take(size), size, None, false)?;
+            }
+            dh.check_size(name, &entry, size)?;
+            let entry = dh.dedup_entry(name, entry);
+            dh.insert_entry(name, entry);
+            Ok(())
+        })
+    }
+
+    /// Runs `add` for the entry `name` of `size` bytes between the add
+    /// events, the progress `add` is handed reports to the events as well
+    fn add_with_events<T>(
+        &mut self,
+        name: &str,
+        size: u64,
+        mut progress: Option<&mut dyn FnMut(u64, u64)>,
+        add: impl FnOnce(&mut Self, Option<&mut dyn FnMut(u64, u64)>) -> Result<T, DepotError>,
+    ) -> Result<T, DepotError> {
+        let Some(events) = self.events.clone() else {
+            return self.add_checked(name, progress, add);
+        };
+        events.entry_started(Operation::Add, name, size);
+        let mut report = |done, total| {
+            if let Some(progress) = &mut progress {
+                progress(done, total);
+            }
+            events.entry_progress(Operation::Add, name, done, total);
+        };
+        let result = self.add_checked(name, Some(&mut report), add);
+        events.entry_finished(Operation::Add, name, result.as_ref().err());
+        result
+    }
+
+    /// Runs `add` for the entry `name` and holds it against the content
+    /// policy, the name before anything is written and the size and secret
+    /// scan of the entry `add` inserted afterwards. A refused entry is
+    /// dropped again
+    fn add_checked<T>(
+        &mut self,
+        name: &str,
+        progress: Option<&mut dyn FnMut(u64, u64)>,
+        add: impl FnOnce(&mut Self, Option<&mut dyn FnMut(u64, u64)>) -> Result<T, DepotError>,
+    ) -> Result<T, DepotError> {
+        let Some(policy) = self.content_policy.clone() else {
+            return add(self, progress);
+        };
+        if let Some(reason) = policy.check_name(name) {
+            self.report_violation(&policy, name, reason)?;
+        }
+        self.secret_found = None;
+        let added = add(self, progress)?;
+        let size = self.metadata.toc.entries.get(name).map(|entry| entry.size);
+        let reasons = self
+            .secret_found
+            .take()
+            .into_iter()
+            .chain(size.and_then(|size| policy.check_size(name, size)));
+        for reason in reasons {
+            if let Err(e) = self.report_violation(&policy, name, reason) {
+                self.remove_last_entry(name)?;
+                return Err(e);
+            }
+        }
+        Ok(added)
+    }
+
+    /// Fails or warns about an entry breaking the content policy
+    fn report_violation(
+        &mut self,
+        policy: &ContentPolicy,
+        name: &str,
+        reason: String,
+    ) -> Result<(), DepotError> {
+        match policy.action {
+            PolicyAction::Warn => {
+                self.warnings
+                    .push(format!("{} breaks the content policy, {}", name, reason));
+                Ok(())
+            }
+            PolicyAction::Error => Err(DepotError::PolicyViolation {
+                name: name.to_owned(),
+                reason,
+            }),
+        }
+    }
+
+    /// Compares the bytes read for an entry to the `size` announced for it,
+    /// a mismatch fails or warns according to the [`ChangePolicy`]
+    fn check_size(&mut self, name: &str, entry: &EntryInfo, size: u64) -> Result<(), DepotError> {
+        // transforms may change the size on their own
+        if entry.size != size && entry.ext.transform().is_none() {
+            let error = DepotError::SourceResized {
+                name: name.to_owned(),
+                expected: size,
+                actual: entry.size,
+            };
+            if self.change_policy == ChangePolicy::Fail {
+                self.handle.seek(SeekFrom::Start(entry.offset))?;
+                return Err(error);
+            }
+            self.warnings.push(error.to_string());
+        }
+        Ok(())
+    }
+
+    /// Adds an entry produced by an [`IngestSource`]. Sources are read once,
+    /// so like [`DepotHandle::add_named_stream`] they can not fall back to
+    /// being stored uncompressed. Sources with a size hint report it as the
+    /// total to `progress`
+    pub fn add_source<S: IngestSource>(
+        &mut self,
+        mut source: S,
+        progress: Option<&mut dyn FnMut(u64, u64)>,
+    ) -> Result<(), DepotError> {
+        let name = source.name().to_owned();
+        check_user_name(&name)?;
+        let size = source.size_hint();
+        self.add_with_events(&name, size.unwrap_or(0), progress, |dh, progress| {
+            let meta = source.metadata();
+            let reader = source.reader()?;
+            let entry = match size {
+                Some(size) => dh.write_stream(&name, reader.take(size), size, progress, true)?,
+                None => dh.write_stream(&name, reader, 0, progress, true)?,
+            };
+            dh.check_ratio(&name, &entry, false)?;
+            if let Some(size) = size {
+                dh.check_size(&name, &entry, size)?;
+            }
+            let entry = dh.dedup_entry(&name, entry);
+            dh.insert_entry(&name, entry);
+            dh.set_entry_ext(&name, meta);
+            Ok(())
+        })
+    }
+
+    /// Adds a stream of unknown size such as a pipe, it is read until EOF and
+    /// the size is recorded after compression, the total passed to `progress`
+    /// is always 0. Unseekable streams can not fall back to being stored
+    /// uncompressed, see [`DepotHandle::set_ratio_guard`]
+    pub fn add_named_stream<R: Read>(
+        &mut self,
+        name: &str,
+        reader: R,
+        progress: Option<&mut dyn FnMut(u64, u64)>,
+    ) -> Result<(), DepotError> {
+        check_user_name(name)?;
+        self.add_with_events(name, 0, progress, |dh, progress| {
+            let entry = dh.write_stream(name, reader, 0, progress, true)?;
+            dh.check_ratio(name, &entry, false)?;
+            let entry = dh.dedup_entry(name, entry);
+            dh.insert_entry(name, entry);
+            Ok(())
+        })
+    }
+
+    /// Adds an in memory buffer as a stream
+    pub fn add_bytes(&mut self, name: &str, data: &[u8]) -> Result<(), DepotError> {
+        self.add_named_sized_stream(name, Cursor::new(data), data.len() as u64, None)
+    }
+
+    /// Serializes `value` as JSON and adds it as a stream
+    #[cfg(feature = "serde")]
+    pub fn add_json<T: serde::Serialize>(
+        &mut self,
+        name: &str,
+        value: &T,
+    ) -> Result<(), DepotError> {
+        self.add_serialized(name, value, Format::Json)
+    }
+
+    /// Serializes `value` in the given format and adds it as a stream
+    #[cfg(feature = "serde")]
+    pub fn add_serialized<T: serde::Serialize>(
+        &mut self,
+        name: &str,
+        value: &T,
+        format: Format,
+    ) -> Result<(), DepotError> {
+        let data = match format {
+            Format::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+            Format::Toml => toml::to_string(value)
+                .map(String::into_bytes)
+                .map_err(|e| e.to_string()),
+        }
+        .map_err(|reason| DepotError::Serialize {
+            name: name.to_owned(),
+            reason,
+        })?;
+        self.add_bytes(name, &data)
+    }
+
+    /// Adds a stream in the reserved namespace, used by library features
+    /// that keep their own data in the depot
+    pub(crate) fn add_reserved_stream<R: Read>(
+        &mut self,
+        name: &str,
+        reader: R,
+    ) -> Result<(), DepotError> {
+        debug_assert!(is_reserved_name(name));
+        let entry = self.write_stream(name, reader, 0, None, true)?;
+        self.insert_entry(name, entry);
+        Ok(())
+    }
+
+    /// Adds a dictionary that zstd entries added afterwards are compressed
+    /// with when their name starts with one of its prefixes. Entries added
+    /// before are left as they are. Tags can not be replaced, entries
+    /// already depend on the dictionary
+    pub fn add_dictionary(&mut self, dict: Dictionary) -> Result<(), DepotError> {
+        if self.mode == OpenMode::Read {
+            return Err(DepotError::ReadOnly);
+        }
+        let name = dict.stream_name();
+        if self.metadata.toc.entries.contains_key(&name) {
+            return Err(DepotError::DictionaryExists(dict.tag().to_owned()));
+        }
+        let data = dict.to_bytes();
+        let entry = self.write_stream(&name, Cursor::new(&data), data.len() as u64, None, false)?;
+        self.insert_entry(&name, entry);
+        self.dictionaries.push(dict);
+        Ok(())
+    }
+
+    /// Dictionaries of the depot in the order they were added or read
+    pub fn dictionaries(&self) -> &[Dictionary] {
+        &self.dictionaries
+    }
+
+    /// Reads back the dictionaries stored in the depot
+    fn load_dictionaries(&mut self) -> Result<(), DepotError> {
+        let names: Vec<String> = self
+            .metadata
+            .toc
+            .entries
+            .range::<str, _>((Bound::Included(DICT_PREFIX), Bound::Unbounded))
+            .map(|(name, _)| name)
+            .take_while(|name| name.starts_with(DICT_PREFIX))
+            .cloned()
+            .collect();
+        for name in names {
+            let Some(stream) = self.get_named_stream(&name) else {
+                continue;
+            };
+            let data = self.stream_to_memory(&stream)?;
+            let dict = Dictionary::from_bytes(&name[DICT_PREFIX.len()..], &data)?;
+            self.dictionaries.push(dict);
+        }
+        Ok(())
+    }
+
+    /// Data of the dictionary `entry` was compressed with, `None` for
+    /// entries compressed without one
+    fn entry_dict(&self, name: &str, entry: &EntryInfo) -> Result<Option<Arc<[u8]>>, DepotError> {
+        entry_dict(&self.dictionaries, name, entry)
+    }
+
+    /// Key to decrypt `entry` with, `None` for entries stored in the clear
+    fn entry_key(
+        &self,
+        name: &str,
+        entry: &EntryInfo,
+    ) -> Result<Option<EncryptionKey>, DepotError> {
+        entry_key(self.encryption_key.as_ref(), name, entry)
+    }
+
+    /// Encrypts the data of entries added or replaced from now on with
+    /// `key`, and decrypts encrypted entries when they are read. Reserved
+    /// streams such as the toc and dictionaries stay in the clear, and
+    /// entries already in the depot are not touched. `None` stops
+    /// encrypting new entries
+    pub fn set_encryption_key(&mut self, key: Option<EncryptionKey>) {
+        self.encryption_key = key;
+    }
+
+    /// Passes the data of entries added or replaced from now on through
+    /// `hooks` before it is compressed, see [`IngestHooks`]. `None` stops
+    /// transforming new entries
+    pub fn set_ingest_hooks(&mut self, hooks: Option<Arc<dyn IngestHooks>>) {
+        self.ingest_hooks = hooks;
+    }
+
+    /// Reports the progress of every entry added, extracted or verified
+    /// from now on to `events`, see [`DepotEvents`]. Reserved streams are
+    /// only reported while verifying
+    pub fn set_events(&mut self, events: Option<Arc<dyn DepotEvents>>) {
+        self.events = events;
+    }
+
+    /// Stores the data of an entry added from now on only once when an
+    /// entry with the same size and content hash already exists, the new
+    /// entry then points at the existing data. Both entries stay separate
+    /// in the toc and can be replaced or removed on their own. Nothing is
+    /// shared while an encryption key is set, see
+    /// [`DepotHandle::set_encryption_key`]
+    pub fn set_dedup(&mut self, dedup: bool) {
+        if !dedup {
+            self.dedup = None;
+            return;
+        }
+        if self.dedup.is_none() {
+            let index = self
+                .streams()
+                .filter(|(_, entry)| shares_data(entry))
+                .map(|(name, entry)| ((entry.hash, entry.size), name.clone()))
+                .collect();
+            self.dedup = Some(index);
+        }
+    }
+
+    /// Points `entry`, just written after the last entry, at the data of
+    /// an earlier entry with the same contents when dedup is on. Its own
+    /// copy is then overwritten by the next entry
+    fn dedup_entry(&mut self, name: &str, mut entry: EntryInfo) -> EntryInfo {
+        let Some(index) = &mut self.dedup else {
+            return entry;
+        };
+        // the key and nonce earlier entries were encrypted with are unknown,
+        // and new entries are encrypted, not pointed at data in the clear
+        if is_reserved_name(name) || !shares_data(&entry) || self.encryption_key.is_some() {
+            return entry;
+        }
+        let key = (entry.hash, entry.size);
+        let encrypted = entry.flags & EntryInfo::FLAG_ENCRYPTED;
+        // the entry found last time may since have been replaced or removed
+        let existing = index
+            .get(&key)
+            .and_then(|other| self.metadata.toc.entries.get(other))
+            .filter(|other| {
+                shares_data(other)
+                    && (other.hash, other.size) == key
+                    && other.flags & EntryInfo::FLAG_ENCRYPTED == encrypted
+            });
+        let Some(existing) = existing else {
+            index.insert(key, name.to_owned());
+            return entry;
+        };
+        let written = entry.clone();
+        entry.offset = existing.offset;
+        entry.stream_size = existing.stream_size;
+        entry.flags = existing.flags;
+        // the keys describing how the shared data is stored
+        for key in [
+            keys::FRAMES,
+            keys::DICT,
+            keys::NONCE,
+            keys::BLOCK,
+            keys::BLOCK_OFFSET,
+        ] {
+            match existing.ext.get(key) {
+                Some(value) => entry.ext.set(key, value),
+                None => {
+                    entry.ext.remove(key);
+                }
+            }
+        }
+        self.unbuffer(&written);
+        entry
+    }
+
+    /// Guards against entries growing when compressed, e.g. when adding
+    /// already compressed or encrypted assets, `None` disables the check
+    pub fn set_ratio_guard(&mut self, guard: Option<RatioGuard>) {
+        self.ratio_guard = guard;
+    }
+
+    /// Holds every entry added afterwards against `policy`, refusing or
+    /// warning about denied names, oversized entries and data that looks
+    /// like a secret, `None` disables the checks. Entries copied as stored
+    /// by [`DepotHandle::repack_into`] are not scanned for secrets
+    pub fn set_content_policy(&mut self, policy: Option<Arc<ContentPolicy>>) {
+        self.content_policy = policy;
+    }
+
+    /// Records per entry read counts and bytes, disabled by default. Readers
+    /// from [`DepotHandle::open_stream`] count the bytes they read once they
+    /// reach the end or are dropped. Disabling it drops the collected stats
+    pub fn set_access_stats(&mut self, enabled: bool) {
+        if !enabled {
+            self.access_stats = None;
+        } else if self.access_stats.is_none() {
+            self.access_stats = Some(BTreeMap::new());
+        }
+    }
+
+    /// Per entry reads since access stats were enabled, `None` when disabled
+    pub fn access_stats(&self) -> Option<&BTreeMap<String, AccessStats>> {
+        self.access_stats.as_ref()
+    }
+
+    /// Entries sorted from the most to the least read bytes, at most `n`
+    pub fn hot_entries(&self, n: usize) -> Vec<(&str, AccessStats)> {
+        let mut hot: Vec<(&str, AccessStats)> = self
+            .access_stats
+            .iter()
+            .flatten()
+            .map(|(name, stats)| (name.as_str(), *stats))
+            .collect();
+        hot.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes).then(b.1.reads.cmp(&a.1.reads)));
+        hot.truncate(n);
+        hot
+    }
+
+    /// The access stats as a trace to save and warm later handles with,
+    /// `None` when access stats are disabled
+    pub fn access_trace(&self) -> Option<AccessTrace> {
+        self.access_stats.as_ref().map(AccessTrace::from_stats)
+    }
+
+    /// Decompresses the hottest entries of `trace` and keeps them in memory
+    /// until the handle is dropped, reads of them are then served without
+    /// touching the stream. Entries are taken from the hottest down while
+    /// they fit in `max_bytes` of decompressed data and read in archive
+    /// order, entries no longer in the depot are skipped. Warming is not
+    /// recorded in the access stats. Returns the bytes pinned in total
+    pub fn warm_from_trace(
+        &mut self,
+        trace: &AccessTrace,
+        max_bytes: u64,
+    ) -> Result<u64, DepotError> {
+        let mut budget = max_bytes.saturating_sub(self.pinned_bytes());
+        let mut hot = Vec::new();
+        for (name, _) in trace.entries() {
+            let Some(stream) = self.get_named_stream(name) else {
+                continue;
+            };
+            if self.pinned_data(&stream).is_some() || stream.einf.size > budget {
+                continue;
+            }
+            budget -= stream.einf.size;
+            hot.push(stream);
+        }
+        hot.sort_by_key(|stream| stream.einf.offset);
+
+        let stats = self.access_stats.take();
+        let mut warmed = Ok(());
+        for stream in hot {
+            match self.stream_to_memory(&stream) {
+                Ok(data) => {
+                    self.pinned
+                        .insert(stream.name, (stream.einf.offset, data.into()));
+                }
+                Err(e) => {
+                    warmed = Err(e);
+                    break;
+                }
+            }
+        }
+        self.access_stats = stats;
+        warmed.map(|_| self.pinned_bytes())
+    }
+
+    /// Decompressed bytes kept in memory by [`DepotHandle::warm_from_trace`]
+    pub fn pinned_bytes(&self) -> u64 {
+        self.pinned
+            .values()
+            .map(|(_, data)| data.len() as u64)
+            .sum()
+    }
+
+    /// Drops every entry pinned by [`DepotHandle::warm_from_trace`]
+    pub fn unpin_all(&mut self) {
+        self.pinned.clear();
+    }
+
+    /// The pinned data of `stream`, unless the entry was replaced since
+    fn pinned_data(&self, stream: &StreamInfo) -> Option<Arc<[u8]>> {
+        match self.pinned.get(&stream.name) {
+            Some((offset, data))
+                if *offset == stream.einf.offset && data.len() as u64 == stream.einf.size =>
+            {
+                Some(data.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Bakes a [`PhfIndex`] over every entry into [`PHF_STREAM`] when the
+    /// depot is finalized, read with [`crate::phf::PhfReader`] without the
+    /// toc. Depots opened for writing rebuild an index baked before,
+    /// disabling it drops the index
+    pub fn set_perfect_hash(&mut self, enabled: bool) {
+        self.perfect_hash = enabled;
+    }
+
+    /// Reads the perfect hash index, `None` if the depot was baked without one
+    pub fn perfect_hash_index(&mut self) -> Result<Option<PhfIndex>, DepotError> {
+        match self.get_named_stream(PHF_STREAM) {
+            Some(stream) => PhfIndex::from_bytes(&self.stream_to_memory(&stream)?).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Writes the perfect hash index at the end of the data, replacing any
+    /// index written before
+    fn write_perfect_hash(&mut self) -> Result<(), DepotError> {
+        self.remove_entry(PHF_STREAM);
+        let index = PhfIndex::build(self.metadata.toc.entries.iter())?.to_bytes();
+        let entry = self.write_stream(
+            PHF_STREAM,
+            Cursor::new(&index),
+            index.len() as u64,
+            None,
+            false,
+        )?;
+        self.insert_entry(PHF_STREAM, entry);
+        Ok(())
+    }
+
+    /// Bakes a [`DirIndex`] over the user entries into [`DIR_INDEX_STREAM`]
+    /// when the depot is finalized, so [`DepotHandle::list_dir`] does not
+    /// have to scan the names. Like the perfect hash index an index baked
+    /// before is rebuilt, disabling it drops the index
+    pub fn set_dir_index(&mut self, enabled: bool) {
+        self.dir_index = enabled;
+    }
+
+    /// Reads the directory index, `None` if the depot was baked without one
+    /// or entries were changed since
+    pub fn dir_index(&mut self) -> Result<Option<DirIndex>, DepotError> {
+        match self.get_named_stream(DIR_INDEX_STREAM) {
+            Some(stream) => DirIndex::from_bytes(&self.stream_to_memory(&stream)?).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Lists the direct children of `dir` in name order, `""` or `"/"` for
+    /// the root. Uses the directory index when the depot has one, otherwise
+    /// every name below `dir` is visited. Empty if nothing lies below `dir`
+    pub fn list_dir(&mut self, dir: &str) -> Result<Vec<DirChild>, DepotError> {
+        let dir = normalize_dir(dir);
+        if self.dirs.is_none() {
+            self.dirs = self.dir_index()?;
+        }
+        if let Some(dirs) = &self.dirs {
+            return Ok(dirs.children(&dir).map(<[_]>::to_vec).unwrap_or_default());
+        }
+        // a name that is also the parent of others is listed as a dir
+        let mut children: BTreeMap<String, bool> = BTreeMap::new();
+        let below = self
+            .metadata
+            .toc
+            .entries
+            .range::<str, _>((Bound::Included(dir.as_str()), Bound::Unbounded))
+            .take_while(|(name, _)| name.starts_with(&dir))
+            .filter(|(name, _)| !is_reserved_name(name));
+        for (name, entry) in below {
+            if let Some(child) = child_of(&dir, name) {
+                let is_dir = child.is_dir || entry.entry_type() == EntryType::Dir;
+                *children.entry(child.name).or_default() |= is_dir;
+            }
+        }
+        Ok(children
+            .into_iter()
+            .map(|(name, is_dir)| DirChild { name, is_dir })
+            .collect())
+    }
+
+    /// Writes the directory index at the end of the data, replacing any
+    /// index written before
+    fn write_dir_index(&mut self) -> Result<(), DepotError> {
+        self.remove_entry(DIR_INDEX_STREAM);
+        let index = DirIndex::build(&self.metadata.toc.entries).to_bytes();
+        let entry = self.write_stream(
+            DIR_INDEX_STREAM,
+            Cursor::new(&index),
+            index.len() as u64,
+            None,
+            true,
+        )?;
+        self.insert_entry(DIR_INDEX_STREAM, entry);
+        Ok(())
+    }
+
+    /// A directory index no longer matches once user entries change, it is
+    /// dropped from the toc and rebuilt when closing if enabled
+    fn invalidate_dir_index(&mut self) {
+        self.dirs = None;
+        self.remove_entry(DIR_INDEX_STREAM);
+    }
+
+    /// Drops an entry from the toc, its data is left as dead space
+    fn remove_entry(&mut self, name: &str) -> Option<EntryInfo> {
+        self.pinned.remove(name);
+        let entry = self.metadata.toc.entries.remove(name)?;
+        self.metadata.toc.entry_count -= 1;
+        self.metadata.toc.size -= entry.size;
+        Some(entry)
+    }
+
+    /// Sets the tool recorded as the creator when the depot is closed, see
+    /// [`CreatorInfo`]. Defaults to this library
+    pub fn set_creator(&mut self, creator: CreatorInfo) {
+        self.creator = creator;
+    }
+
+    /// Reads the record of the tool that last wrote the depot, `None` for
+    /// depots written before creators were recorded. Handles writing to the
+    /// depot only see it once closed and reopened
+    pub fn creator(&mut self) -> Result<Option<CreatorInfo>, DepotError> {
+        match self.get_named_stream(CREATOR_STREAM) {
+            Some(stream) => {
+                let record = self.stream_to_memory(&stream)?;
+                let info = CreatorInfo::parse_record(&String::from_utf8_lossy(&record))?;
+                Ok(Some(info))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Takes the warnings collected while adding streams
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Applies the ratio guard to a freshly written entry, returns true when
+    /// the entry should be rewritten uncompressed
+    fn check_ratio(
+        &mut self,
+        name: &str,
+        entry: &EntryInfo,
+        can_store: bool,
+    ) -> Result<bool, DepotError> {
+        let guard = match self.ratio_guard {
+            Some(guard) => guard,
+            None => return Ok(false),
+        };
+        if entry.size == 0 || entry.stream_size as f64 <= entry.size as f64 * guard.max_ratio {
+            return Ok(false);
+        }
+
+        let error = DepotError::Expanded {
+            name: name.to_owned(),
+            size: entry.size,
+            stored: entry.stream_size,
+        };
+        let msg = error.to_string();
+        match guard.action {
+            RatioAction::Warn => self.warnings.push(msg),
+            RatioAction::Error => return Err(error),
+            RatioAction::Store if can_store => {
+                self.warnings.push(format!("{}, stored uncompressed", msg));
+                return Ok(true);
+            }
+            RatioAction::Store => {
+                self.warnings
+                    .push(format!("{}, stream is not seekable so it was kept", msg));
+            }
+        }
+        Ok(false)
+    }
+
+    fn insert_entry(&mut self, name: &str, mut entry: EntryInfo) {
+        let id = entry_id(name);
+        entry.ext.set_u64(keys::ID, id);
+        if let Some(other) = self.ids.insert(id, name.to_owned()) {
+            if other != name {
+                self.warnings.push(format!(
+                    "{} and {} share the entry id {:#018x}, lookups by id return {}",
+                    other, name, id, name
+                ));
+            }
+        }
+        if !is_reserved_name(name) {
+            self.invalidate_dir_index();
+        }
+        self.data_end = self.data_end.max(entry.offset + entry.stream_size);
+        // blocks copied from other depots keep their ids
+        if let Some((block, _)) = entry.ext.solid_block() {
+            self.next_block = self.next_block.max(block + 1);
+        }
+        self.metadata.toc.size += entry.size;
+        match self.metadata.toc.entries.insert(name.to_owned(), entry) {
+            // adding a name twice replaces the entry, its data is left as
+            // dead space
+            Some(old) => self.metadata.toc.size -= old.size,
+            None => self.metadata.toc.entry_count += 1,
+        }
+    }
+
+    /// Removes an entry from the toc, its data is left in place as dead space
+    /// until the depot is compacted, see [`DepotHandle::compact_into`]
+    pub fn remove_stream(&mut self, name: &str) -> Result<EntryInfo, DepotError> {
+        check_user_name(name)?;
+        self.handle.writer()?;
+        let entry = self
+            .metadata
+            .toc
+            .entries
+            .remove(name)
+            .ok_or_else(|| DepotError::EntryNotFound(name.to_owned()))?;
+        self.metadata.toc.entry_count -= 1;
+        self.metadata.toc.size -= entry.size;
+        self.invalidate_dir_index();
+        let id = entry.ext.id().unwrap_or_else(|| entry_id(name));
+        if self.ids.get(&id).is_some_and(|owner| owner == name) {
+            self.ids.remove(&id);
+        }
+        if let Some(stats) = &mut self.access_stats {
+            stats.remove(name);
+        }
+        Ok(entry)
+    }
+
+    /// Replaces the data of an existing entry with the contents of `reader`.
+    /// The new data is appended, the old data is left as dead space until the
+    /// depot is compacted. The entry keeps its creation time and metadata.
+    /// The new data is checked and reported like an added entry, when it is
+    /// refused the old entry stays
+    pub fn replace_stream<R: Read>(&mut self, name: &str, reader: R) -> Result<(), DepotError> {
+        check_user_name(name)?;
+        let old = self
+            .metadata
+            .toc
+            .entries
+            .get(name)
+            .cloned()
+            .ok_or_else(|| DepotError::EntryNotFound(name.to_owned()))?;
+        self.handle.writer()?;
+        let data_end = self.data_end;
+        let replaced = self.add_with_events(name, 0, None, |dh, progress| {
+            let entry = dh.write_stream(name, reader, 0, progress, true)?;
+            dh.check_ratio(name, &entry, false)?;
+            let mut entry = dh.dedup_entry(name, entry);
+            entry.create_ts = old.create_ts;
+            // the frame table and dictionary describe the old data only
+            let mut ext = old.ext.clone();
+            ext.remove(keys::FRAMES);
+            ext.remove(keys::DICT);
+            ext.remove(keys::NONCE);
+            ext.remove(keys::TRANSFORM);
+            ext.remove(keys::BLOCK);
+            ext.remove(keys::BLOCK_OFFSET);
+            for (key, value) in entry.ext.iter() {
+                ext.set(key, value);
+            }
+            entry.ext = ext;
+            dh.insert_entry(name, entry);
+            Ok(())
+        });
+        // a replacement refused by the content policy was dropped again,
+        // possibly along with the data end when it shared the old data
+        if replaced.is_err() && !self.metadata.toc.entries.contains_key(name) {
+            self.insert_entry(name, old);
+            self.data_end = data_end;
+        }
+        replaced
+    }
+
+    /// Writes a copy of the depot without dead space to `output`, e.g. after
+    /// removing or replacing entries. Entries are copied as stored, nothing
+    /// is decompressed
+    pub fn compact_into<T: SeekReadWrite>(&mut self, output: T) -> Result<(), DepotError> {
+        let compacted = DepotHandle::create(output)?;
+        self.compact_to(compacted)
+    }
+
+    /// Copies the live entries into the new depot `compacted` and closes it
+    fn compact_to(&mut self, mut compacted: DepotHandle) -> Result<(), DepotError> {
+        self.write_block()?;
+        compacted.set_comp_level(self.metadata.toc.compression_level);
+        // the perfect hash index records offsets, it is rebuilt rather than
+        // copied, as are the directory index and the creator record
+        compacted.set_perfect_hash(self.perfect_hash);
+        compacted.set_dir_index(self.dir_index);
+        compacted.set_creator(self.creator.clone());
+        compacted.metadata.toc.meta = self.metadata.toc.meta.clone();
+        let entries: Vec<(String, EntryInfo)> = self
+            .all_streams()
+            .filter(|(name, _)| !rewritten_on_close(name))
+            .map(|(name, entry)| (name.clone(), entry.clone()))
+            .collect();
+        let mut buf = vec![0; 64 * 1024];
+        // where data shared by several entries was copied to
+        let mut copied_to = HashMap::new();
+        for (name, mut entry) in entries {
+            if let Some(offset) = copied_to.get(&(entry.offset, entry.stream_size)) {
+                entry.offset = *offset;
+                compacted.insert_entry(&name, entry);
+                continue;
+            }
+            let copy = self.copy_stored_to(&mut compacted, &name, &entry, &mut buf)?;
+            if shares_data(&entry) {
+                copied_to.insert((entry.offset, entry.stream_size), copy.offset);
+            }
+            compacted.insert_entry(&name, copy);
+        }
+        compacted.close()
+    }
+
+    /// Copies the stored data of the entry `name` after the last entry of
+    /// `target` and returns the entry pointing at the copy, nothing is
+    /// decompressed
+    fn copy_stored_to(
+        &mut self,
+        target: &mut DepotHandle,
+        name: &str,
+        entry: &EntryInfo,
+        buf: &mut [u8],
+    ) -> Result<EntryInfo, DepotError> {
+        let offset = target.seek_data_end()?;
+        let copied = match (&self.file, &target.file) {
+            (Some(src), Some(dst)) => copy_range(src, entry.offset, dst, entry.stream_size)?,
+            _ => {
+                self.handle.seek(SeekFrom::Start(entry.offset))?;
+                copy_hashed(
+                    (&mut self.handle).take(entry.stream_size),
+                    target.handle.writer()?,
+                    buf,
+                    0,
+                    None,
+                )?
+                .0
+            }
+        };
+        if copied != entry.stream_size {
+            return Err(DepotError::Truncated {
+                name: name.to_owned(),
+                expected: entry.stream_size,
+                actual: copied,
+            });
+        }
+        let mut copy = entry.clone();
+        copy.offset = offset;
+        Ok(copy)
+    }
+
+    /// Writes the entries of this depot into `target`, which is left open.
+    /// Entries keep their names, timestamps and metadata, see
+    /// [`RepackOptions`] for what is written how. Copied entries bring the
+    /// dictionaries they were compressed with along, recompressed ones use
+    /// those of `target`. Reserved streams other than the indexes and the
+    /// creator record, which `target` writes on its own, are copied as
+    /// stored, the indexes are baked into `target` when this depot has them.
+    /// The depot metadata is carried over for the keys `target` has not set
+    pub fn repack_into(
+        &mut self,
+        target: &mut DepotHandle,
+        options: &RepackOptions,
+    ) -> Result<(), DepotError> {
+        target.handle.writer()?;
+        self.write_block()?;
+        let mut entries: Vec<(String, EntryInfo)> = match options.include.is_empty() {
+            true => self
+                .streams()
+                .map(|(name, entry)| (name.clone(), entry.clone()))
+                .collect(),
+            false => {
+                let mut found = BTreeMap::new();
+                for pattern in &options.include {
+                    found.extend(self.find_streams(pattern)?.map(|s| (s.name, s.einf)));
+                }
+                found.into_iter().collect()
+            }
+        };
+        match &options.order {
+            RepackOrder::Stored => entries.sort_by_key(|(_, entry)| entry.offset),
+            RepackOrder::Name => entries.sort_by(|a, b| a.0.cmp(&b.0)),
+            RepackOrder::Listed(names) => {
+                let rank: HashMap<&str, usize> = names
+                    .iter()
+                    .enumerate()
+                    .rev()
+                    .map(|(rank, name)| (name.as_str(), rank))
+                    .collect();
+                entries.sort_by_key(|(name, entry)| {
+                    (
+                        rank.get(name.as_str()).copied().unwrap_or(usize::MAX),
+                        entry.offset,
+                    )
+                });
+            }
+        }
+        target.perfect_hash |= self.perfect_hash;
+        target.dir_index |= self.dir_index;
+        // attributes already set on the target win
+        for (key, value) in self.metadata.toc.meta.iter() {
+            if target.metadata.toc.meta.get(key).is_none() {
+                target.metadata.toc.meta.set(key, value);
+            }
+        }
+
+        let mut buf = vec![0; 64 * 1024];
+        let reserved: Vec<(String, EntryInfo)> = self
+            .reserved_streams()
+            .filter(|(name, _)| !rewritten_on_close(name) && !name.starts_with(DICT_PREFIX))
+            .map(|(name, entry)| (name.clone(), entry.clone()))
+            .collect();
+        for (name, entry) in reserved {
+            let copy = self.copy_stored_to(target, &name, &entry, &mut buf)?;
+            target.insert_entry(&name, copy);
+        }
+        if !options.recompress {
+            self.copy_dictionaries_to(target, &entries)?;
+        }
+
+        // the data went through the ingest hooks when it was first added
+        let hooks = target.ingest_hooks.take();
+        let mut copied_to = HashMap::new();
+        let mut repacked = Ok(());
+        for (name, entry) in &entries {
+            let shared = copied_to.get(&(entry.offset, entry.stream_size)).copied();
+            let written = target.add_with_events(name, entry.size, None, |target, progress| {
+                let copy = match shared {
+                    Some(offset) => EntryInfo {
+                        offset,
+                        ..entry.clone()
+                    },
+                    None if !options.recompress || entry.flags & EntryInfo::FLAG_EMPTY != 0 => {
+                        self.copy_stored_to(target, name, entry, &mut buf)?
+                    }
+                    None => self.recompress_to(target, name, entry, progress)?,
+                };
+                let copy = target.dedup_entry(name, copy);
+                let offset = copy.offset;
+                target.insert_entry(name, copy);
+                Ok(offset)
+            });
+            match written {
+                Ok(offset) if shares_data(entry) && !options.recompress => {
+                    copied_to.insert((entry.offset, entry.stream_size), offset);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    repacked = Err(e);
+                    break;
+                }
+            }
+        }
+        target.ingest_hooks = hooks;
+        repacked
+    }
+
+    /// Adds the dictionaries `entries` were compressed with to `target`,
+    /// so their data can be copied as stored
+    fn copy_dictionaries_to(
+        &self,
+        target: &mut DepotHandle,
+        entries: &[(String, EntryInfo)],
+    ) -> Result<(), DepotError> {
+        let mut copied = BTreeSet::new();
+        for (name, entry) in entries {
+            let Some(tag) = entry.ext.get_str(keys::DICT) else {
+                continue;
+            };
+            if !copied.insert(tag) {
+                continue;
+            }
+            let Some(dict) = self.dictionaries.iter().find(|dict| dict.tag() == tag) else {
+                return Err(DepotError::MissingDictionary {
+                    name: name.clone(),
+                    tag: tag.to_owned(),
+                });
+            };
+            match target.dictionaries.iter().find(|other| other.tag() == tag) {
+                Some(other) if other.data() == dict.data() => {}
+                Some(_) => return Err(DepotError::DictionaryExists(tag.to_owned())),
+                None => target.add_dictionary(dict.clone())?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Decompresses the entry `name` and compresses it after the last entry
+    /// of `target` with its settings, returning the entry describing it
+    fn recompress_to(
+        &mut self,
+        target: &mut DepotHandle,
+        name: &str,
+        entry: &EntryInfo,
+        progress: Option<&mut dyn FnMut(u64, u64)>,
+    ) -> Result<EntryInfo, DepotError> {
+        let stream: StreamInfo = (name.to_owned(), entry.clone()).into();
+        let reader = self.open_stream(&stream)?;
+        let mut stored = target.write_stream(name, reader, entry.size, progress, true)?;
+        if target.check_ratio(name, &stored, true)? {
+            let reader = self.open_stream(&stream)?;
+            stored = target.write_stream(name, reader, entry.size, None, false)?;
+        }
+        // everything but how the data is stored is kept
+        let mut ext = entry.ext.clone();
+        for key in [
+            keys::FRAMES,
+            keys::DICT,
+            keys::NONCE,
+            keys::BLOCK,
+            keys::BLOCK_OFFSET,
+        ] {
+            ext.remove(key);
+            if let Some(value) = stored.ext.get(key) {
+                ext.set(key, value);
+            }
+        }
+        Ok(EntryInfo {
+            create_ts: entry.create_ts,
+            mod_ts: entry.mod_ts,
+            ext,
+            ..stored
+        })
+    }
+
+    /// The regions of the stream neither the header, an entry nor the toc
+    /// refers to, in order. That is the data of removed or replaced entries
+    /// until the depot is compacted, which an older toc may still refer to.
+    /// The toc runs to the end of the stream, followed by the freeze
+    /// trailer if there is one, while changes are not written yet the space
+    /// past the entry data is counted as toc
+    pub fn dead_regions(&mut self) -> Result<Vec<Range<u64>>, DepotError> {
+        let end = self.handle.seek(SeekFrom::End(0))?;
+        // members of a pending solid block are not stored yet
+        let mut used: Vec<Range<u64>> = self
+            .metadata
+            .toc
+            .entries
+            .values()
+            .filter(|entry| entry.stream_size > 0)
+            .map(|entry| entry.offset..entry.offset.saturating_add(entry.stream_size))
+            .collect();
+        used.push(self.header_offset..self.header_offset + DepotHeader::SIZE);
+        // a created depot has no toc yet, its offset is !0
+        used.push(self.metadata.header.toc_offset.max(self.data_end)..end);
+        Ok(uncovered(used, self.header_offset..end))
+    }
+
+    /// Compacts the depot file at `path`. The compacted copy is written where
+    /// `temp` allows and moved over the depot once complete, with
+    /// [`TempPolicy::Never`] the entries are moved within the file instead,
+    /// see [`DepotHandle::compact_in_place`]. `creator` is recorded as the
+    /// tool that wrote it. Depots without [`DepotHandle::dead_regions`] are
+    /// left as they are
+    pub fn compact_file<P: AsRef<Path>>(
+        path: P,
+        creator: CreatorInfo,
+        temp: &TempPolicy,
+    ) -> Result<(), DepotError> {
+        let path = path.as_ref();
+        // the parts of split depots are compacted where they are
+        if *temp == TempPolicy::Never || volume::split_base(path).is_some() {
+            let mut dh = DepotHandle::open_file(path, OpenMode::ReadWrite)?;
+            if dh.dead_regions()?.is_empty() {
+                return Ok(());
+            }
+            dh.set_creator(creator);
+            dh.compact_in_place()?;
+            return dh.close();
+        }
+        let mut dh = DepotHandle::open_file(path, OpenMode::Read)?;
+        // the copy replaces the depot, which a freeze forbids as well
+        if dh.frozen().is_some_and(|trailer| trailer.is_immutable()) {
+            return Err(DepotError::Frozen);
+        }
+        if dh.dead_regions()?.is_empty() {
+            return Ok(());
+        }
+        // next to the depot the copy can be renamed over it atomically
+        let sibling = match path.parent() {
+            Some(dir) if temp.allows_sibling() => {
+                let dir = if dir.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    dir
+                };
+                Some(TempFile::create(dir, ".")?)
+            }
+            _ => None,
+        };
+        let mut tmp = match sibling {
+            Some(tmp) => tmp,
+            None => temp.create(&format!("compacting {}", path.display()))?,
+        };
+        dh.set_creator(creator);
+        let file = tmp.file().try_clone()?;
+        let mut compacted = DepotHandle::create(file.try_clone()?)?;
+        compacted.file = Some(file);
+        dh.compact_to(compacted)?;
+        drop(dh);
+        Ok(tmp.persist(path)?)
+    }
+
+    /// Compacts the depot within its own file by moving every entry down over
+    /// the dead space before it, for when there is no room for a copy. The
+    /// depot is unreadable if this is interrupted, the file is cut to size
+    /// when the handle is closed
+    pub fn compact_in_place(&mut self) -> Result<(), DepotError> {
+        self.handle.writer()?;
+        self.write_block()?;
+        self.block_cache = None;
+        // the indexes and the creator record are rewritten when closing
+        let mut entries: Vec<(String, u64, u64)> = self
+            .metadata
+            .toc
+            .entries
+            .iter()
+            .filter(|(name, _)| !rewritten_on_close(name))
+            .map(|(name, entry)| (name.clone(), entry.offset, entry.stream_size))
+            .collect();
+        entries.sort_by_key(|(_, offset, _)| *offset);
+
+        let mut buf = vec![0; 64 * 1024];
+        let mut cursor = self.header_offset + DepotHeader::SIZE;
+        // data shared by several entries is moved once, see set_dedup
+        let mut last_moved: Option<(u64, u64, u64)> = None;
+        for (name, offset, stream_size) in entries {
+            if let Some((_, _, to)) =
+                last_moved.filter(|(from, size, _)| (*from, *size) == (offset, stream_size))
+            {
+                if let Some(entry) = self.metadata.toc.entries.get_mut(&name) {
+                    entry.offset = to;
+                }
+                continue;
+            }
+            if stream_size > 0 {
+                last_moved = Some((offset, stream_size, cursor));
+            }
+            if offset != cursor {
+                // the target always lies before the source, every chunk is
+                // read before anything after it is overwritten
+                let mut moved = 0;
+                while moved < stream_size {
+                    let n = buf.len().min((stream_size - moved) as usize);
+                    self.handle.seek(SeekFrom::Start(offset + moved))?;
+                    self.handle.read_exact(&mut buf[..n])?;
+                    self.handle.seek(SeekFrom::Start(cursor + moved))?;
+                    self.handle.writer()?.write_all(&buf[..n])?;
+                    moved += n as u64;
+                }
+                if let Some(entry) = self.metadata.toc.entries.get_mut(&name) {
+                    entry.offset = cursor;
+                }
+            }
+            cursor += stream_size;
+        }
+        self.data_end = cursor;
+        Ok(())
+    }
+
+    /// Moves to the end of the entry data, reads may have moved the stream
+    fn seek_data_end(&mut self) -> Result<u64, DepotError> {
+        Ok(self.handle.seek(SeekFrom::Start(self.data_end))?)
+    }
+
+    /// Writes the contents of `reader` after the last entry, compressed
+    /// with the handle's [`Compression`] or as is, and returns the entry
+    /// describing them. Small entries are added to the solid block being
+    /// filled instead, see [`DepotHandle::set_solid_blocks`]
+    fn write_stream<R: Read>(
+        &mut self,
+        name: &str,
+        reader: R,
+        size_hint: u64,
+        progress: Option<&mut dyn FnMut(u64, u64)>,
+        compress: bool,
+    ) -> Result<EntryInfo, DepotError> {
+        let solid = self.solid.filter(|solid| {
+            compress && solid.takes(size_hint, self.compression) && !is_reserved_name(name)
+        });
+        if let Some(solid) = solid {
+            return self.add_to_block(name, reader, size_hint, progress, solid);
+        }
+        let before = self.seek_data_end()?;
+        let compression = match compress {
+            true => self.compression,
+            false => Compression::None,
+        };
+        let encoding = self.encoding(name, compression);
+        let policy = self
+            .content_policy
+            .clone()
+            .filter(|policy| policy.scans() && !is_reserved_name(name));
+        let mut scan = policy.as_deref().map(SecretScan::new);
+        let mut entry = encode(
+            &encoding,
+            Scanned::new(reader, scan.as_mut()),
+            self.handle.writer()?,
+            size_hint,
+            progress,
+        )?;
+        if let Some(scan) = scan {
+            self.secret_found = scan.finish();
+        }
+        entry.offset = before;
+        Ok(entry)
+    }
+
+    /// Adds the contents of `reader` to the solid block being filled, which
+    /// is written first when the entry would take it past its size or it
+    /// uses another compression. The entry points at where the block will
+    /// be written, [`DepotHandle::write_block`] completes it
+    fn add_to_block<R: Read>(
+        &mut self,
+        name: &str,
+        reader: R,
+        size_hint: u64,
+        progress: Option<&mut dyn FnMut(u64, u64)>,
+        solid: SolidOptions,
+    ) -> Result<EntryInfo, DepotError> {
+        let compression = self.compression;
+        let full = self.block.as_ref().is_some_and(|block| {
+            block.compression != compression
+                || block.data.len() as u64 + size_hint > solid.block_size
+        });
+        if full {
+            self.write_block()?;
+        }
+        let mut block = match self.block.take() {
+            Some(block) => block,
+            None => {
+                self.next_block += 1;
+                PendingBlock {
+                    id: self.next_block - 1,
+                    compression,
+                    data: Vec::new(),
+                }
+            }
+        };
+        let start = block.data.len();
+        let policy = self.content_policy.clone().filter(|policy| policy.scans());
+        let mut scan = policy.as_deref().map(SecretScan::new);
+        let reader: Box<dyn Read> = Box::new(Scanned::new(reader, scan.as_mut()));
+        let (reader, transform) = match self.ingest_hooks.clone() {
+            Some(hooks) => hooks.transform_reader(name, reader)?,
+            None => (reader, None),
+        };
+        let mut buf = vec![0; self.compression_frame_size];
+        let copied = copy_hashed(reader, &mut block.data, &mut buf, size_hint, progress);
+        if copied.is_err() {
+            block.data.truncate(start);
+        }
+        let id = block.id;
+        self.block = Some(block);
+        let (size, hash) = copied?;
+        if let Some(scan) = scan {
+            self.secret_found = scan.finish();
+        }
+
+        let mut ext = ExtMeta::default();
+        ext.set_u64(keys::BLOCK, id);
+        ext.set_u64(keys::BLOCK_OFFSET, start as u64);
+        if let Some(transform) = transform {
+            ext.set_str(keys::TRANSFORM, &transform);
+        }
+        Ok(EntryInfo {
+            offset: self.data_end,
+            size,
+            stream_size: 0,
+            flags: EntryInfo::FLAG_SOLID,
+            create_ts: TsWithTz::now(),
+            mod_ts: TsWithTz::now(),
+            hash,
+            ext,
+        })
+    }
+
+    /// Compresses the solid block being filled after the last entry and
+    /// points its members at it
+    fn write_block(&mut self) -> Result<(), DepotError> {
+        let Some(block) = self.block.take() else {
+            return Ok(());
+        };
+        let members: Vec<String> = self
+            .metadata
+            .toc
+            .entries
+            .iter()
+            .filter(|(_, entry)| {
+                is_buffered(entry) && entry.ext.solid_block().map(|(id, _)| id) == Some(block.id)
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        // every member may have been removed again
+        let Some(first) = members.first() else {
+            return Ok(());
+        };
+        let before = self.seek_data_end()?;
+        // members are hashed on their own and their transforms applied
+        // when they were added
+        let mut encoding = self.encoding(first, block.compression);
+        encoding.independent_frames = None;
+        encoding.transform = None;
+        let size = block.data.len() as u64;
+        let stored = encode_data(
+            &encoding,
+            block.data.as_slice(),
+            self.handle.writer()?,
+            size,
+            None,
+        )?;
+        for name in &members {
+            let Some(entry) = self.metadata.toc.entries.get_mut(name) else {
+                continue;
+            };
+            entry.offset = before;
+            entry.stream_size = stored.stream_size;
+            entry.flags = stored.flags | EntryInfo::FLAG_SOLID;
+            for key in [keys::DICT, keys::NONCE] {
+                match stored.ext.get(key) {
+                    Some(value) => entry.ext.set(key, value),
+                    None => {
+                        entry.ext.remove(key);
+                    }
+                }
+            }
+        }
+        self.data_end = before + stored.stream_size;
+        Ok(())
+    }
+
+    /// Drops the data of `entry` from the end of the solid block being
+    /// filled, when it is a member added last
+    fn unbuffer(&mut self, entry: &EntryInfo) {
+        let Some((id, at)) = entry.ext.solid_block().filter(|_| is_buffered(entry)) else {
+            return;
+        };
+        if let Some(block) = &mut self.block {
+            if block.id == id && block.data.len() as u64 == at + entry.size {
+                block.data.truncate(at as usize);
+            }
+        }
+    }
+
+    /// The data of the solid block member `stream`, its size and hash
+    /// checked. The block is decompressed unless it was read last or is
+    /// still being filled
+    fn solid_data(&mut self, stream: &StreamInfo) -> Result<Vec<u8>, DepotError> {
+        let entry = &stream.einf;
+        let (id, at) = entry
+            .ext
+            .solid_block()
+            .ok_or_else(|| DepotError::CorruptEntry {
+                name: stream.name.clone(),
+                reason: "is missing the solid block it is stored in".to_owned(),
+            })?;
+        let buffered = self
+            .block
+            .as_ref()
+            .filter(|block| is_buffered(entry) && block.id == id);
+        let data = match buffered {
+            Some(block) => block_member(&stream.name, &block.data, at, entry.size)?.to_vec(),
+            None => {
+                let block = self.solid_block(stream)?;
+                block_member(&stream.name, &block, at, entry.size)?.to_vec()
+            }
+        };
+        let mut hasher = EntryHasher::new();
+        hasher.update(&data);
+        check_extracted(&stream.name, entry, data.len() as u64, hasher.finish())?;
+        Ok(data)
+    }
+
+    /// The decompressed solid block the member `stream` is stored in
+    fn solid_block(&mut self, stream: &StreamInfo) -> Result<Arc<[u8]>, DepotError> {
+        let entry = &stream.einf;
+        if let Some((offset, block)) = &self.block_cache {
+            if *offset == entry.offset {
+                return Ok(block.clone());
+            }
+        }
+        // never decompress past the end of the last member
+        let end = self
+            .metadata
+            .toc
+            .entries
+            .values()
+            .filter(|other| other.offset == entry.offset)
+            .chain([entry])
+            .filter_map(|member| Some(member.ext.solid_block()?.1 + member.size))
+            .max()
+            .unwrap_or(0);
+        let dict = self.entry_dict(&stream.name, entry)?;
+        let key = self.entry_key(&stream.name, entry)?;
+        let mut data = Vec::new();
+        decoder(
+            &stream.name,
+            entry,
+            dict.as_deref(),
+            key.as_ref(),
+            self.stored_data(entry)?,
+        )?
+        .take(end)
+        .read_to_end(&mut data)?;
+        let block: Arc<[u8]> = data.into();
+        self.block_cache = Some((entry.offset, block.clone()));
+        Ok(block)
+    }
+
+    /// The stored data of `entry` for [`decoder`], read in place when the
+    /// depot is held in memory
+    fn stored_data(&mut self, entry: &EntryInfo) -> Result<Box<dyn BufRead + '_>, DepotError> {
+        if let Some(mapped) = &self.mapped {
+            return Ok(Box::new(mapped.range(entry.offset, entry.stream_size)?));
+        }
+        self.handle.seek(SeekFrom::Start(entry.offset))?;
+        Ok(Box::new(BufReader::new(&mut self.handle)))
+    }
+
+    /// How the entry `name` is encoded with `compression` and the current
+    /// settings, zstd entries use the dictionary selected by their name
+    fn encoding(&self, name: &str, compression: Compression) -> Encoding {
+        let dict = match compression {
+            Compression::Zstd if !is_reserved_name(name) => dict::select(&self.dictionaries, name),
+            _ => None,
+        };
+        let encryption = match is_reserved_name(name) {
+            true => None,
+            false => self.encryption_key.clone(),
+        };
+        Encoding {
+            compression,
+            dict: dict.map(|dict| (dict.tag().to_owned(), dict.shared_data())),
+            level: self.metadata.toc.compression_level,
+            mt_threads: self.mt_threads as u32,
+            buf_size: self.compression_frame_size,
+            // frames are decompressed on their own, sealed chunks do not
+            // line up with them
+            independent_frames: self.independent_frames.filter(|_| encryption.is_none()),
+            encryption,
+            transform: self
+                .ingest_hooks
+                .clone()
+                .filter(|_| !is_reserved_name(name))
+                .map(|hooks| (name.to_owned(), hooks)),
+        }
+    }
+
+    /// Iterates over the user streams, reserved streams are skipped
+    pub fn streams(&self) -> impl Iterator<Item = (&String, &EntryInfo)> {
+        self.all_streams()
+            .filter(|(name, _)| !is_reserved_name(name))
+    }
+
+    /// Iterates over the streams that may be served to `audience`, an
+    /// internal audience sees every stream, a public one only public streams
+    pub fn visible_streams(
+        &self,
+        audience: Visibility,
+    ) -> impl Iterator<Item = (&String, &EntryInfo)> {
+        self.streams()
+            .filter(move |(_, entry)| entry.visibility() <= audience)
+    }
+
+    /// Marks a stream as public or internal, see [`Visibility`]
+    pub fn set_visibility(&mut self, name: &str, visibility: Visibility) -> Result<(), DepotError> {
+        if self.mode == OpenMode::Read {
+            return Err(DepotError::ReadOnly);
+        }
+        let entry = self
+            .metadata
+            .toc
+            .entries
+            .get_mut(name)
+            .ok_or_else(|| DepotError::EntryNotFound(name.to_owned()))?;
+        entry.ext.set_visibility(visibility);
+        Ok(())
+    }
+
+    /// Metadata of the depot as a whole, the attributes set with
+    /// [`DepotHandle::set_archive_meta`] are read with [`ExtMeta::user`]
+    pub fn archive_meta(&self) -> &ExtMeta {
+        &self.metadata.toc.meta
+    }
+
+    /// Stamps the depot with the user attribute `key`, e.g. a build id or
+    /// engine version. Written with the toc on close
+    pub fn set_archive_meta(&mut self, key: &str, value: &[u8]) -> Result<(), DepotError> {
+        if self.mode == OpenMode::Read {
+            return Err(DepotError::ReadOnly);
+        }
+        check_meta_key(key)?;
+        self.metadata.toc.meta.set_user(key, value);
+        Ok(())
+    }
+
+    /// Removes the user attribute `key` of the depot, returning its value
+    pub fn remove_archive_meta(&mut self, key: &str) -> Result<Option<Vec<u8>>, DepotError> {
+        if self.mode == OpenMode::Read {
+            return Err(DepotError::ReadOnly);
+        }
+        Ok(self.metadata.toc.meta.remove_user(key))
+    }
+
+    /// Tags the stream `name` with the user attribute `key`, e.g.
+    /// `locale=ja`, read back with [`EntryInfo::user_meta`]
+    pub fn set_entry_meta(
+        &mut self,
+        name: &str,
+        key: &str,
+        value: &[u8],
+    ) -> Result<(), DepotError> {
+        if self.mode == OpenMode::Read {
+            return Err(DepotError::ReadOnly);
+        }
+        check_meta_key(key)?;
+        let entry = self
+            .metadata
+            .toc
+            .entries
+            .get_mut(name)
+            .ok_or_else(|| DepotError::EntryNotFound(name.to_owned()))?;
+        entry.ext.set_user(key, value);
+        Ok(())
+    }
+
+    /// Removes the user attribute `key` of the stream `name`, returning its
+    /// value
+    pub fn remove_entry_meta(
+        &mut self,
+        name: &str,
+        key: &str,
+    ) -> Result<Option<Vec<u8>>, DepotError> {
+        if self.mode == OpenMode::Read {
+            return Err(DepotError::ReadOnly);
+        }
+        let entry = self
+            .metadata
+            .toc
+            .entries
+            .get_mut(name)
+            .ok_or_else(|| DepotError::EntryNotFound(name.to_owned()))?;
+        Ok(entry.ext.remove_user(key))
+    }
+
+    /// Iterates over the streams in the reserved namespace only
+    pub fn reserved_streams(&self) -> impl Iterator<Item = (&String, &EntryInfo)> {
+        self.all_streams()
+            .filter(|(name, _)| is_reserved_name(name))
+    }
+
+    /// Iterates over every stream, reserved ones included
+    pub fn all_streams(&self) -> impl Iterator<Item = (&String, &EntryInfo)> {
+        self.metadata.toc.entries.iter()
+    }
+
+    pub fn get_named_stream(&self, name: &str) -> Option<StreamInfo> {
+        let entry = self.metadata.toc.entries.get(name)?;
+
+        Some((name.to_owned(), entry.clone()).into())
+    }
+
+    /// Streams whose names match `pattern` in name order, reserved streams
+    /// left out. `*` and `?` match within one path component, `**` across
+    /// components and `[...]` a character class. A pattern ending with `/`
+    /// matches everything below that prefix, e.g. `assets/textures/` is the
+    /// same as `assets/textures/**`
+    pub fn find_streams(
+        &self,
+        pattern: &str,
+    ) -> Result<impl Iterator<Item = StreamInfo> + '_, DepotError> {
+        let pattern = match pattern.ends_with('/') {
+            true => format!("{}**", pattern),
+            false => pattern.to_owned(),
+        };
+        let matcher = glob::Pattern::new(&pattern).map_err(|e| DepotError::InvalidPattern {
+            pattern: pattern.clone(),
+            reason: e.to_string(),
+        })?;
+        // only names starting with the literal part can match
+        let literal = match pattern.find(['*', '?', '[']) {
+            Some(end) => pattern[..end].to_owned(),
+            None => pattern.clone(),
+        };
+        let options = glob::MatchOptions {
+            case_sensitive: true,
+            require_literal_separator: true,
+            require_literal_leading_dot: false,
+        };
+        Ok(self
+            .metadata
+            .toc
+            .entries
+            .range::<str, _>((Bound::Included(literal.as_str()), Bound::Unbounded))
+            .take_while(move |(name, _)| name.starts_with(&literal))
+            .filter(move |(name, _)| !is_reserved_name(name) && matcher.matches_with(name, options))
+            .map(|(name, entry)| (name.clone(), entry.clone()).into()))
+    }
+
+    /// Looks a stream up by its numeric id, see [`crate::entry_id`]
+    pub fn get_by_id(&self, id: u64) -> Option<StreamInfo> {
+        self.get_named_stream(self.ids.get(&id)?)
+    }
+
+    pub fn stream_count(&self) -> u64 {
+        self.metadata.toc.entry_count
+    }
+
+    /// Copies the data of a stream as stored, still compressed, to the
+    /// position of `out`. Depots opened by path copy from file to file
+    /// without passing the data through userspace where the platform allows
+    pub fn carve_stream(&mut self, stream: &StreamInfo, mut out: &File) -> Result<(), DepotError> {
+        let entry = &stream.einf;
+        if entry.flags & EntryInfo::FLAG_SOLID != 0 {
+            return Err(DepotError::Unsupported(format!(
+                "{} is stored in a solid block with other entries",
+                stream.name
+            )));
+        }
+        let copied = match &self.file {
+            Some(src) => copy_range(src, entry.offset, out, entry.stream_size)?,
+            None => {
+                self.handle.seek(SeekFrom::Start(entry.offset))?;
+                std::io::copy(&mut (&mut self.handle).take(entry.stream_size), &mut out)?
+            }
+        };
+        if copied != entry.stream_size {
+            return Err(DepotError::Truncated {
+                name: stream.name.clone(),
+                expected: entry.stream_size,
+                actual: copied,
+            });
+        }
+        Ok(())
+    }
+
+    /// The data of a stream as stored, still compressed, borrowed from a
+    /// depot opened with [`DepotHandle::open_memory`] or
+    /// `DepotHandle::open_mmap` without copying it
+    pub fn carve_slice(&self, stream: &StreamInfo) -> Result<&[u8], DepotError> {
+        let entry = &stream.einf;
+        if entry.flags & EntryInfo::FLAG_SOLID != 0 {
+            return Err(DepotError::Unsupported(format!(
+                "{} is stored in a solid block with other entries",
+                stream.name
+            )));
+        }
+        Ok(self
+            .mapped(stream)?
+            .range(entry.offset, entry.stream_size)?)
+    }
+
+    /// The contents of a stored stream borrowed like
+    /// [`DepotHandle::carve_slice`], for assets that are loaded as they are.
+    /// Compressed and encrypted streams have to be decompressed instead.
+    /// Neither the hash is checked nor the read counted in the access stats
+    pub fn stream_slice(&self, stream: &StreamInfo) -> Result<&[u8], DepotError> {
+        let entry = &stream.einf;
+        if entry.flags & EntryInfo::FLAG_EMPTY != 0 {
+            return Ok(&[]);
+        }
+        let stored = entry.compression() == Compression::None
+            && entry.flags & (EntryInfo::FLAG_ENCRYPTED | EntryInfo::FLAG_SOLID) == 0;
+        if !stored {
+            return Err(DepotError::Unsupported(format!(
+                "{} is not stored uncompressed",
+                stream.name
+            )));
+        }
+        let data = self
+            .mapped(stream)?
+            .range(entry.offset, entry.stream_size)?;
+        if data.len() as u64 != entry.size {
+            return Err(DepotError::SizeMismatch {
+                name: stream.name.clone(),
+                expected: entry.size,
+                actual: data.len() as u64,
+            });
+        }
+        Ok(data)
+    }
+
+    fn mapped(&self, stream: &StreamInfo) -> Result<&Mapped<'io>, DepotError> {
+        self.mapped.as_ref().ok_or_else(|| {
+            DepotError::Unsupported(format!(
+                "{} can not be borrowed, the depot is not held in memory",
+                stream.name
+            ))
+        })
+    }
+
+    /// Extracts a stream to any SeekWrite implementor
+    pub fn extract_stream<W: SeekWrite>(
+        &mut self,
+        stream: &StreamInfo,
+        mut writer: W,
+    ) -> Result<(), DepotError> {
+        let name = stream.name.clone();
+        let entry = stream.einf.clone();
+
+        // if the entry is an empty file, just return
+        if entry.flags & EntryInfo::FLAG_EMPTY != 0 {
+            return Ok(());
+        }
+        if let Some(data) = self.pinned_data(stream) {
+            writer.write_all(&data)?;
+            writer.flush()?;
+            self.record_access(&name, entry.size);
+            return Ok(());
+        }
+        if entry.flags & EntryInfo::FLAG_SOLID != 0 {
+            let data = self.solid_data(stream)?;
+            writer.write_all(&data)?;
+            writer.flush()?;
+            self.record_access(&name, entry.size);
+            return Ok(());
+        }
+
+        let dict = self.entry_dict(&name, &entry)?;
+        let key = self.entry_key(&name, &entry)?;
+        let mut hasher = EntryHasher::new();
+        let mut decompressor = decoder(
+            &name,
+            &entry,
+            dict.as_deref(),
+            key.as_ref(),
+            self.stored_data(&entry)?,
+        )?;
+        let mut buf = vec![0; 8192];
+        let mut read = 0;
+        loop {
+            let mut n = decompressor.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            // never write past the recorded size of the entry
+            if read + n > entry.size as usize {
+                n = entry.size as usize - read;
+            }
+            writer.write_all(&buf[..n])?;
+            hasher.update(&buf[..n]);
+            read += n;
+            if read == entry.size as usize {
+                break;
+            }
+        }
+        drop(decompressor);
+        self.record_access(&name, read as u64);
+        check_extracted(&name, &entry, read as u64, hasher.finish())
+    }
+
+    /// Opens a stream for reading, the data is decompressed lazily as it is
+    /// read so large entries can be piped or parsed incrementally. The size
+    /// and hash are checked once the end is reached, a mismatch surfaces as
+    /// an [`ErrorKind::InvalidData`] error from the final read
+    pub fn open_stream(
+        &mut self,
+        stream: &StreamInfo,
+    ) -> Result<DepotStreamReader<'_>, DepotError> {
+        let entry = &stream.einf;
+        let inner: Box<dyn Read> = match self.pinned_data(stream) {
+            Some(data) => Box::new(Cursor::new(data)),
+            None if entry.flags & EntryInfo::FLAG_SOLID != 0 => {
+                Box::new(Cursor::new(self.solid_data(stream)?))
+            }
+            None => {
+                let dict = self.entry_dict(&stream.name, entry)?;
+                let key = self.entry_key(&stream.name, entry)?;
+                // the fields are borrowed one by one, so the reader can
+                // record its access once it is done
+                let data: Box<dyn BufRead> = match &self.mapped {
+                    Some(mapped) => Box::new(mapped.range(entry.offset, entry.stream_size)?),
+                    None => {
+                        self.handle.seek(SeekFrom::Start(entry.offset))?;
+                        Box::new(BufReader::new(&mut self.handle))
+                    }
+                };
+                decoder(&stream.name, entry, dict.as_deref(), key.as_ref(), data)?
+            }
+        };
+        Ok(DepotStreamReader {
+            inner,
+            name: stream.name.clone(),
+            entry: entry.clone(),
+            read: 0,
+            hasher: EntryHasher::new(),
+            checked: false,
+            stats: self.access_stats.as_mut(),
+        })
+    }
+
+    /// Decompresses a stream straight into `out` without any intermediate
+    /// buffer, for engines with custom allocators or frame budgets. `out`
+    /// has to hold at least `entry.size` bytes, returns the bytes written
+    pub fn extract_into(
+        &mut self,
+        stream: &StreamInfo,
+        out: &mut [u8],
+    ) -> Result<usize, DepotError> {
+        let entry = &stream.einf;
+        let size = entry.size as usize;
+        if out.len() < size {
+            return Err(DepotError::BufferTooSmall {
+                name: stream.name.clone(),
+                size: entry.size,
+                capacity: out.len(),
+            });
+        }
+        if entry.flags & EntryInfo::FLAG_EMPTY != 0 {
+            return Ok(0);
+        }
+        if let Some(data) = self.pinned_data(stream) {
+            out[..size].copy_from_slice(&data);
+            self.record_access(&stream.name, entry.size);
+            return Ok(size);
+        }
+        if entry.flags & EntryInfo::FLAG_SOLID != 0 {
+            out[..size].copy_from_slice(&self.solid_data(stream)?);
+            self.record_access(&stream.name, entry.size);
+            return Ok(size);
+        }
+
+        let dict = self.entry_dict(&stream.name, entry)?;
+        let key = self.entry_key(&stream.name, entry)?;
+        let mut decompressor = decoder(
+            &stream.name,
+            entry,
+            dict.as_deref(),
+            key.as_ref(),
+            self.stored_data(entry)?,
+        )?;
+        let out = &mut out[..size];
+        let mut read = 0;
+        while read < size {
+            match decompressor.read(&mut out[read..]) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        drop(decompressor);
+
+        let mut hasher = EntryHasher::new();
+        hasher.update(&out[..read]);
+        self.record_access(&stream.name, read as u64);
+        check_extracted(&stream.name, entry, read as u64, hasher.finish())?;
+        Ok(read)
+    }
+
+    /// Reads `len` bytes starting at `offset` of the uncompressed stream,
+    /// fewer when the stream ends first. Entries written as independent
+    /// frames only have the frames covering the range decompressed, stored
+    /// entries are read in place, others are decompressed from the start,
+    /// solid block members out of their block. Only solid members have
+    /// their hash checked, independent frames are checked against the
+    /// checksum of each frame. Ranges of stored entries are not checked,
+    /// nor those of entries decompressed from the start unless the range
+    /// reaches the checksum at the end of their data
+    pub fn read_range(
+        &mut self,
+        stream: &StreamInfo,
+        offset: u64,
+        len: usize,
+    ) -> Result<Vec<u8>, DepotError> {
+        let entry = &stream.einf;
+        if offset > entry.size {
+            return Err(DepotError::OutOfRange {
+                name: stream.name.clone(),
+                offset,
+                size: entry.size,
+            });
+        }
+        let len = (len as u64).min(entry.size - offset) as usize;
+        if len == 0 || entry.flags & EntryInfo::FLAG_EMPTY != 0 {
+            return Ok(Vec::new());
+        }
+        if let Some(data) = self.pinned_data(stream) {
+            self.record_access(&stream.name, len as u64);
+            return Ok(data[offset as usize..offset as usize + len].to_vec());
+        }
+        if entry.flags & EntryInfo::FLAG_SOLID != 0 {
+            let data = self.solid_data(stream)?;
+            self.record_access(&stream.name, len as u64);
+            return Ok(data[offset as usize..offset as usize + len].to_vec());
+        }
+
+        let encrypted = entry.flags & EntryInfo::FLAG_ENCRYPTED != 0;
+        let frames = self.checked_frames(stream)?;
+        let mut out = vec![0; len];
+        if entry.compression() == Compression::None && !encrypted {
+            match &self.mapped {
+                Some(mapped) => {
+                    out.copy_from_slice(mapped.range(entry.offset + offset, len as u64)?)
+                }
+                None => {
+                    self.handle.seek(SeekFrom::Start(entry.offset + offset))?;
+                    self.handle.read_exact(&mut out)?;
+                }
+            }
+        } else if let Some(frames) = frames {
+            let dict = self.entry_dict(&stream.name, entry)?;
+            let mut decompressor = match &dict {
+                Some(dict) => zstd::bulk::Decompressor::with_dictionary(dict)?,
+                None => zstd::bulk::Decompressor::new()?,
+            };
+            // where each frame starts, compressed and uncompressed
+            let (mut packed_at, mut plain_at) = (entry.offset, 0);
+            let mut filled = 0;
+            for (packed, plain) in frames {
+                let (start, end) = (plain_at, plain_at + plain);
+                if end > offset && start < offset + len as u64 {
+                    let frame = match &self.mapped {
+                        Some(mapped) => decompressor
+                            .decompress(mapped.range(packed_at, packed)?, plain as usize)?,
+                        None => {
+                            let mut data = vec![0; packed as usize];
+                            self.handle.seek(SeekFrom::Start(packed_at))?;
+                            self.handle.read_exact(&mut data)?;
+                            decompressor.decompress(&data, plain as usize)?
+                        }
+                    };
+                    if frame.len() as u64 != plain {
+                        return Err(DepotError::SizeMismatch {
+                            name: stream.name.clone(),
+                            expected: plain,
+                            actual: frame.len() as u64,
+                        });
+                    }
+                    let from = offset.saturating_sub(start) as usize;
+                    let to = frame.len().min((offset + len as u64 - start) as usize);
+                    out[filled..filled + to - from].copy_from_slice(&frame[from..to]);
+                    filled += to - from;
+                }
+                packed_at += packed;
+                plain_at = end;
+            }
+        } else {
+            let dict = self.entry_dict(&stream.name, entry)?;
+            let key = self.entry_key(&stream.name, entry)?;
+            let mut decompressor = decoder(
+                &stream.name,
+                entry,
+                dict.as_deref(),
+                key.as_ref(),
+                self.stored_data(entry)?,
+            )?;
+            std::io::copy(&mut (&mut decompressor).take(offset), &mut std::io::sink())?;
+            decompressor.read_exact(&mut out)?;
+        }
+        self.record_access(&stream.name, len as u64);
+        Ok(out)
+    }
+
+    /// The frame table of a zstd entry stored in the clear, `None` for
+    /// entries written as a single frame. The frames have to add up to the
+    /// entry sizes and end within the depot, so no buffer is sized from a
+    /// damaged table
+    fn checked_frames(
+        &mut self,
+        stream: &StreamInfo,
+    ) -> Result<Option<Vec<(u64, u64)>>, DepotError> {
+        let entry = &stream.einf;
+        let frames = match entry.ext.frames() {
+            Some(frames)
+                if entry.compression() == Compression::Zstd
+                    && entry.flags & EntryInfo::FLAG_ENCRYPTED == 0 =>
+            {
+                frames
+            }
+            _ => return Ok(None),
+        };
+        let corrupt = |reason: &str| DepotError::CorruptEntry {
+            name: stream.name.clone(),
+            reason: reason.to_owned(),
+        };
+        let (mut packed, mut plain) = (0u64, 0u64);
+        for (frame_packed, frame_plain) in &frames {
+            packed = packed
+                .checked_add(*frame_packed)
+                .ok_or_else(|| corrupt("has frames overflowing its size"))?;
+            plain = plain
+                .checked_add(*frame_plain)
+                .ok_or_else(|| corrupt("has frames overflowing its size"))?;
+        }
+        if packed != entry.stream_size || plain != entry.size {
+            return Err(corrupt("has frames not adding up to its size"));
+        }
+        let end = self.handle.seek(SeekFrom::End(0))?;
+        if entry
+            .offset
+            .checked_add(packed)
+            .is_none_or(|data_end| data_end > end)
+        {
+            return Err(corrupt("has frames past the end of the depot"));
+        }
+        Ok(Some(frames))
+    }
+
+    fn record_access(&mut self, name: &str, bytes: u64) {
+        if let Some(stats) = &mut self.access_stats {
+            record_access(stats, name, bytes);
+        }
+    }
+
+    /// Like [`DepotHandle::extract_stream`], but entries written as
+    /// independent frames are decompressed by `threads` threads and written
+    /// in order. Other entries are extracted on the calling thread
+    pub fn extract_stream_parallel<W: Write>(
+        &mut self,
+        stream: &StreamInfo,
+        mut writer: W,
+        threads: usize,
+    ) -> Result<(), DepotError> {
+        let entry = &stream.einf;
+        let frames = match threads > 1 && self.pinned_data(stream).is_none() {
+            true => self.checked_frames(stream)?,
+            false => None,
+        };
+        let Some(frames) = frames else {
+            return self.extract_stream(stream, SeekLess(&mut writer));
+        };
+
+        let dict = self.entry_dict(&stream.name, entry)?;
+        self.handle.seek(SeekFrom::Start(entry.offset))?;
+        let (jobs, queue) = mpsc::sync_channel::<(usize, Vec<u8>, usize)>(threads * 2);
+        let queue = Arc::new(Mutex::new(queue));
+        let (done, finished) = mpsc::channel::<(usize, Result<Vec<u8>, Error>)>();
+        let handle = &mut self.handle;
+        let (read, hash) = thread::scope(|scope| {
+            for _ in 0..threads {
+                let queue = queue.clone();
+                let done = done.clone();
+                let dict = dict.as_deref();
+                scope.spawn(move || loop {
+                    let (index, data, size) = match queue.lock().unwrap().recv() {
+                        Ok(job) => job,
+                        Err(_) => break,
+                    };
+                    let frame = match dict {
+                        Some(dict) => zstd::bulk::Decompressor::with_dictionary(dict)
+                            .and_then(|mut d| d.decompress(&data, size)),
+                        None => zstd::bulk::decompress(&data, size),
+                    };
+                    let _ = done.send((index, frame));
+                });
+            }
+            drop(done);
+
+            // frames come back out of order, they are written as soon as
+            // all frames before them are
+            let mut pending = BTreeMap::new();
+            let mut next = 0;
+            let mut hasher = EntryHasher::new();
+            let mut read = 0u64;
+            let mut write_ready =
+                |pending: &mut BTreeMap<usize, Vec<u8>>, next: &mut usize| -> Result<(), Error> {
+                    while let Some(data) = pending.remove(next) {
+                        writer.write_all(&data)?;
+                        hasher.update(&data);
+                        read += data.len() as u64;
+                        *next += 1;
+                    }
+                    Ok(())
+                };
+            let mut result = Ok(());
+            for (index, (stream_size, size)) in frames.iter().enumerate() {
+                let mut data = vec![0; *stream_size as usize];
+                if let Err(e) = handle.read_exact(&mut data) {
+                    result = Err(e);
+                    break;
+                }
+                if jobs.send((index, data, *size as usize)).is_err() {
+                    result = Err(Error::other("decompression threads exited"));
+                    break;
+                }
+                for (index, data) in finished.try_iter() {
+                    match data {
+                        Ok(data) => {
+                            pending.insert(index, data);
+                        }
+                        Err(e) => result = result.and(Err(e)),
+                    }
+                }
+                result = result.and_then(|_| write_ready(&mut pending, &mut next));
+                if result.is_err() {
+                    break;
+                }
+            }
+            drop(jobs);
+            for (index, data) in finished.iter() {
+                match data {
+                    Ok(data) => {
+                        pending.insert(index, data);
+                    }
+                    Err(e) => result = result.and(Err(e)),
+                }
+            }
+            result = result.and_then(|_| write_ready(&mut pending, &mut next));
+            result.map(|_| (read, hasher.finish()))
+        })?;
+        writer.flush()?;
+        self.record_access(&stream.name, read);
+        check_extracted(&stream.name, entry, read, hash)
+    }
+
+    /// Decompresses a stream without keeping the data, checking its size
+    /// and hash like [`DepotHandle::extract_stream`] does
+    pub fn verify_stream(&mut self, stream: &StreamInfo) -> Result<(), DepotError> {
+        self.extract_stream(stream, Discard)
+    }
+
+    /// Verifies every stream, the reserved ones included, like
+    /// [`DepotHandle::verify_stream`]. `on_verified` is called after each
+    /// stream with its error, if any. Corrupted streams do not stop the
+    /// check, they are returned with their errors in name order
+    pub fn verify_all(
+        &mut self,
+        mut on_verified: Option<&mut VerifiedFn>,
+    ) -> Vec<(String, DepotError)> {
+        let streams: Vec<StreamInfo> = self
+            .all_streams()
+            .map(|(name, entry)| (name.clone(), entry.clone()).into())
+            .collect();
+        let mut failed = Vec::new();
+        for stream in streams {
+            let result = self.verify_with_events(&stream);
+            if let Some(on_verified) = on_verified.as_mut() {
+                on_verified(&stream, result.as_ref().err());
+            }
+            if let Err(e) = result {
+                failed.push((stream.name, e));
+            }
+        }
+        failed
+    }
+
+    /// Verifies a stream between the verify events
+    fn verify_with_events(&mut self, stream: &StreamInfo) -> Result<(), DepotError> {
+        let Some(events) = self.events.clone() else {
+            return self.verify_stream(stream);
+        };
+        let (name, size) = (stream.name.as_str(), stream.einf.size);
+        events.entry_started(Operation::Verify, name, size);
+        let discard = ProgressWriter::new(Discard, &*events, Operation::Verify, name, size);
+        let result = self.extract_stream(stream, discard);
+        events.entry_finished(Operation::Verify, name, result.as_ref().err());
+        result
+    }
+
+    /// Like [`DepotHandle::verify_all`], but the streams are decompressed by
+    /// `threads` threads, each reading the depot file on its own. Depots
+    /// not opened from a file are verified on the calling thread.
+    /// `on_verified` is called on the calling thread as streams finish, in
+    /// no particular order
+    pub fn verify_all_parallel(
+        &mut self,
+        threads: usize,
+        mut on_verified: Option<&mut VerifiedFn>,
+    ) -> Vec<(String, DepotError)> {
+        if threads <= 1 || self.file.is_none() || self.flush().is_err() {
+            return self.verify_all(on_verified);
+        }
+        let (mut streams, solid): (Vec<StreamInfo>, Vec<StreamInfo>) = self
+            .all_streams()
+            .map(|(name, entry)| (name.clone(), entry.clone()).into())
+            .partition(|stream: &StreamInfo| stream.einf.flags & EntryInfo::FLAG_SOLID == 0);
+        // workers take streams in storage order, so reads stay mostly
+        // sequential
+        streams.sort_by_key(|stream| stream.einf.offset);
+        let mut failed = Vec::new();
+        let mut jobs = Vec::with_capacity(streams.len());
+        for stream in &streams {
+            let keys = self
+                .entry_dict(&stream.name, &stream.einf)
+                .and_then(|dict| Ok((dict, self.entry_key(&stream.name, &stream.einf)?)));
+            match keys {
+                Ok((dict, key)) => jobs.push((stream, dict, key)),
+                Err(e) => {
+                    if let Some(events) = &self.events {
+                        events.entry_started(Operation::Verify, &stream.name, stream.einf.size);
+                        events.entry_finished(Operation::Verify, &stream.name, Some(&e));
+                    }
+                    if let Some(on_verified) = on_verified.as_mut() {
+                        on_verified(stream, Some(&e));
+                    }
+                    failed.push((stream.name.clone(), e));
+                }
+            }
+        }
+
+        let file = self.file.as_ref().expect("checked above");
+        let events = self.events.as_deref();
+        let next = AtomicUsize::new(0);
+        let (done, finished) = mpsc::channel::<(&StreamInfo, Result<(), DepotError>)>();
+        thread::scope(|scope| {
+            for _ in 0..threads.min(jobs.len()) {
+                let done = done.clone();
+                let (jobs, next) = (&jobs, &next);
+                scope.spawn(move || {
+                    while let Some((stream, dict, key)) =
+                        jobs.get(next.fetch_add(1, Ordering::Relaxed))
+                    {
+                        let (name, size) = (stream.name.as_str(), stream.einf.size);
+                        let mut report = |done, total| {
+                            if let Some(events) = events {
+                                events.entry_progress(Operation::Verify, name, done, total);
+                            }
+                        };
+                        if let Some(events) = events {
+                            events.entry_started(Operation::Verify, name, size);
+                        }
+                        let mut reader = FileAt::new(file);
+                        let result = reader
+                            .seek(SeekFrom::Start(stream.einf.offset))
+                            .map_err(DepotError::from)
+                            .and_then(|_| {
+                                let reader = BufReader::new(reader);
+                                let (dict, key) = (dict.as_deref(), key.as_ref());
+                                check_stream(stream, dict, key, reader, &mut report)
+                            });
+                        if done.send((stream, result)).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(done);
+            for (stream, result) in finished {
+                if let Some(events) = events {
+                    events.entry_finished(Operation::Verify, &stream.name, result.as_ref().err());
+                }
+                if let Some(on_verified) = on_verified.as_mut() {
+                    on_verified(stream, result.as_ref().err());
+                }
+                if let Err(e) = result {
+                    failed.push((stream.name.clone(), e));
+                }
+            }
+        });
+        // solid block members are verified here, decompressing each block
+        // once rather than once per member
+        for stream in &solid {
+            let result = self.verify_with_events(stream);
+            if let Some(on_verified) = on_verified.as_mut() {
+                on_verified(stream, result.as_ref().err());
+            }
+            if let Err(e) = result {
+                failed.push((stream.name.clone(), e));
+            }
+        }
+        failed.sort_by(|a, b| a.0.cmp(&b.0));
+        failed
+    }
+
+    /// Extracts a stream to a memory buffer and returns it
+    /// This is a convenience function for extract_stream
+    pub fn stream_to_memory(&mut self, stream: &StreamInfo) -> Result<Vec<u8>, DepotError> {
+        let mut buf = Vec::new();
+        let mut cursor = Cursor::new(&mut buf);
+        self.extract_stream(stream, &mut cursor)?;
+        Ok(buf)
+    }
+
+    /// Like [`DepotHandle::stream_to_memory`], but refuses entries claiming
+    /// more than `max_bytes`, so a hostile or corrupted size field can not
+    /// make the host allocate arbitrary amounts of memory
+    pub fn stream_to_memory_limited(
+        &mut self,
+        stream: &StreamInfo,
+        max_bytes: u64,
+    ) -> Result<Vec<u8>, DepotError> {
+        if stream.einf.size > max_bytes {
+            return Err(DepotError::TooLarge {
+                name: stream.name.clone(),
+                size: stream.einf.size,
+                limit: max_bytes,
+            });
+        }
+        // extract_stream never writes past the recorded size
+        self.stream_to_memory(stream)
+    }
+
+    /// Reads the named stream into memory, failing if it does not exist
+    pub fn read_bytes(&mut self, name: &str) -> Result<Vec<u8>, DepotError> {
+        let stream = self
+            .get_named_stream(name)
+            .ok_or_else(|| DepotError::EntryNotFound(name.to_owned()))?;
+        self.stream_to_memory(&stream)
+    }
+
+    /// Reads the named stream as UTF-8 text
+    pub fn read_string(&mut self, name: &str) -> Result<String, DepotError> {
+        String::from_utf8(self.read_bytes(name)?).map_err(|e| DepotError::Deserialize {
+            name: name.to_owned(),
+            reason: format!("UTF-8: {}", e),
+        })
+    }
+
+    /// Reads the named stream and deserializes it from JSON
+    #[cfg(feature = "serde")]
+    pub fn read_json<T: serde::de::DeserializeOwned>(
+        &mut self,
+        name: &str,
+    ) -> Result<T, DepotError> {
+        serde_json::from_slice(&self.read_bytes(name)?).map_err(|e| DepotError::Deserialize {
+            name: name.to_owned(),
+            reason: format!("JSON: {}", e),
+        })
+    }
+
+    /// Reads the named stream and deserializes it from TOML
+    #[cfg(feature = "serde")]
+    pub fn read_toml<T: serde::de::DeserializeOwned>(
+        &mut self,
+        name: &str,
+    ) -> Result<T, DepotError> {
+        toml::from_str(&self.read_string(name)?).map_err(|e| DepotError::Deserialize {
+            name: name.to_owned(),
+            reason: format!("TOML: {}", e),
+        })
+    }
+
+    /// Extracts the given streams into `output`, creating any intermediate
+    /// directories, output paths are resolved through `options`. Entries
+    /// are read in the order they are stored in so extraction stays
+    /// sequential on spinning disks, `on_extracted` may be called out of order
+    pub fn extract_to_dir<P: AsRef<Path>>(
+        &mut self,
+        streams: &[StreamInfo],
+        output: P,
+        options: &ExtractOptions,
+        on_extracted: Option<&mut ExtractedFn>,
+    ) -> Result<(), DepotError> {
+        let mut mapped = Vec::with_capacity(streams.len());
+        for stream in streams {
+            if let Some(rel_path) = options.map_path(&stream.name)? {
+                mapped.push((stream, rel_path));
+            }
+        }
+        self.extract_mapped(&mapped, output.as_ref(), options, on_extracted)
+    }
+
+    /// Extracts each stream to its path relative to `output`, see
+    /// [`DepotHandle::extract_to_dir`]
+    fn extract_mapped(
+        &mut self,
+        mapped: &[(&StreamInfo, PathBuf)],
+        output: &Path,
+        options: &ExtractOptions,
+        mut on_extracted: Option<&mut ExtractedFn>,
+    ) -> Result<(), DepotError> {
+        let mut ordered: Vec<&(&StreamInfo, PathBuf)> = mapped.iter().collect();
+        ordered.sort_by_key(|(stream, _)| stream.einf.offset);
+        if options.dry_run {
+            for (stream, rel_path) in ordered {
+                if let Some(cb) = &mut on_extracted {
+                    cb(stream, &output.join(rel_path));
+                }
+            }
+            return Ok(());
+        }
+
+        // symlinks and directories are only created once every file is out
+        let mut nodes = Vec::new();
+        if options.threads <= 1 {
+            for (stream, rel_path) in ordered {
+                let out_path = output_path(output, &stream.name, rel_path)?;
+                if stream.einf.entry_type() != EntryType::File {
+                    nodes.push((*stream, out_path));
+                    continue;
+                }
+                self.extract_to_file(stream, &out_path, options)?;
+                if let Some(cb) = &mut on_extracted {
+                    cb(stream, &out_path);
+                }
+            }
+            return create_nodes(&nodes, options, self.events.as_deref(), on_extracted);
+        }
+
+        // small entries are decompressed here and handed to the writers,
+        // the queue bounds the memory held by in flight entries
+        let (jobs, queue) = mpsc::sync_channel::<WriteJob>(options.threads * 2);
+        let queue = Arc::new(Mutex::new(queue));
+        let (done, finished) = mpsc::channel::<(usize, PathBuf, Result<(), DepotError>)>();
+        let events = self.events.clone();
+        thread::scope(|scope| {
+            for _ in 0..options.threads {
+                let queue = queue.clone();
+                let done = done.clone();
+                let events = events.as_deref();
+                scope.spawn(move || loop {
+                    let job = match queue.lock().unwrap().recv() {
+                        Ok(job) => job,
+                        Err(_) => break,
+                    };
+                    let result = job.write(options).map_err(DepotError::from);
+                    if let Some(events) = events {
+                        let error = result.as_ref().err();
+                        events.entry_finished(Operation::Extract, &job.stream.name, error);
+                    }
+                    let _ = done.send((job.index, job.path, result));
+                });
+            }
+            drop(done);
+
+            let mut report = |index: usize, path: &Path| {
+                if let Some(cb) = &mut on_extracted {
+                    cb(ordered[index].0, path);
+                }
+            };
+            let mut first_error = None;
+            for (index, (stream, rel_path)) in ordered.iter().enumerate() {
+                for (index, path, result) in finished.try_iter() {
+                    match result {
+                        Ok(()) => report(index, &path),
+                        Err(e) => first_error = first_error.or(Some(e)),
+                    }
+                }
+                if first_error.is_some() {
+                    break;
+                }
+                let result = output_path(output, &stream.name, rel_path).and_then(|out_path| {
+                    if stream.einf.entry_type() != EntryType::File {
+                        nodes.push((*stream, out_path));
+                        return Ok(());
+                    }
+                    if stream.einf.size > POOLED_ENTRY_MAX {
+                        self.extract_to_file(stream, &out_path, options)?;
+                        report(index, &out_path);
+                        return Ok(());
+                    }
+                    if let Some(events) = &events {
+                        events.entry_started(Operation::Extract, &stream.name, stream.einf.size);
+                    }
+                    let data = self.stream_to_memory(stream).inspect_err(|e| {
+                        if let Some(events) = &events {
+                            events.entry_finished(Operation::Extract, &stream.name, Some(e));
+                        }
+                    })?;
+                    let job = WriteJob {
+                        index,
+                        stream: (*stream).clone(),
+                        data,
+                        owner: options.resolve_owner(&stream.einf.ext),
+                        mode: options.resolve_mode(&stream.einf.ext),
+                        path: out_path,
+                    };
+                    jobs.send(job)
+                        .map_err(|_| Error::other("extraction writers exited").into())
+                });
+                if let Err(e) = result {
+                    first_error = Some(e);
+                    break;
+                }
+            }
+            // closing the queue lets the writers drain it and exit
+            drop(jobs);
+            for (index, path, result) in finished.iter() {
+                match result {
+                    Ok(()) => report(index, &path),
+                    Err(e) => first_error = first_error.or(Some(e)),
+                }
+            }
+            first_error.map_or(Ok(()), Err)
+        })?;
+        create_nodes(&nodes, options, events.as_deref(), on_extracted)
+    }
+
+    /// Extracts every stream but the reserved ones into `output`, recreating
+    /// the tree formed by their names, see [`DepotHandle::extract_to_dir`].
+    /// The paths are passed through the name mapper of `options` so names
+    /// the target can not hold are rewritten instead of failing
+    pub fn extract_all<P: AsRef<Path>>(
+        &mut self,
+        output: P,
+        options: &ExtractOptions,
+        on_extracted: Option<&mut ExtractedFn>,
+    ) -> Result<(), DepotError> {
+        let streams: Vec<StreamInfo> = self
+            .streams()
+            .map(|(name, entry)| (name.clone(), entry.clone()).into())
+            .collect();
+        let mut mapped = Vec::with_capacity(streams.len());
+        for stream in &streams {
+            if let Some(rel_path) = options.map_path(&stream.name)? {
+                mapped.push((stream, rel_path));
+            }
+        }
+
+        let default_mapper = DefaultNameMapper::default();
+        let mapper = options.name_mapper.as_deref().unwrap_or(&default_mapper);
+        let paths = mapper.map_names(&mapped);
+        if paths.len() != mapped.len() {
+            return Err(DepotError::InvalidInput(format!(
+                "name mapper returned {} paths for {} streams",
+                paths.len(),
+                mapped.len()
+            )));
+        }
+        for ((stream, rel_path), path) in mapped.iter_mut().zip(paths) {
+            check_contained(&stream.name, &path)?;
+            *rel_path = path;
+        }
+        self.extract_mapped(&mapped, output.as_ref(), options, on_extracted)
+    }
+
+    /// Extracts a stream into a new file at `path` between the extract
+    /// events
+    fn extract_to_file(
+        &mut self,
+        stream: &StreamInfo,
+        path: &Path,
+        options: &ExtractOptions,
+    ) -> Result<(), DepotError> {
+        let Some(events) = self.events.clone() else {
+            return self.write_file(stream, path, options, None);
+        };
+        events.entry_started(Operation::Extract, &stream.name, stream.einf.size);
+        let result = self.write_file(stream, path, options, Some(&*events));
+        events.entry_finished(Operation::Extract, &stream.name, result.as_ref().err());
+        result
+    }
+
+    /// Writes a stream into a new file at `path`, reporting its progress to
+    /// `events`
+    fn write_file(
+        &mut self,
+        stream: &StreamInfo,
+        path: &Path,
+        options: &ExtractOptions,
+        events: Option<&dyn DepotEvents>,
+    ) -> Result<(), DepotError> {
+        let mut fh = File::create(path)?;
+        if options.preallocate {
+            preallocate(&fh, stream.einf.size).map_err(|e| {
+                Error::new(
+                    e.kind(),
+                    format!(
+                        "could not reserve {} bytes for {}: {}",
+                        stream.einf.size,
+                        path.display(),
+                        e
+                    ),
+                )
+            })?;
+        }
+        let extracted =
+            options.write_hooked(stream, path, Box::new(BufWriter::new(&mut fh)), |writer| {
+                let (name, size) = (stream.name.as_str(), stream.einf.size);
+                match events {
+                    Some(events) => {
+                        let writer =
+                            ProgressWriter::new(writer, events, Operation::Extract, name, size);
+                        self.extract_stream_parallel(stream, writer, options.threads)
+                    }
+                    None => self.extract_stream_parallel(stream, writer, options.threads),
+                }
+            });
+        if options.preallocate {
+            // a stream ending early must not leave the reserved tail behind
+            let written = fh.stream_position()?;
+            fh.set_len(written)?;
+        }
+        extracted?;
+        restore_owner(path, options.resolve_owner(&stream.einf.ext))?;
+        restore_mode(path, options.resolve_mode(&stream.einf.ext))?;
+        Ok(())
+    }
+
+    /// Writes the indexes, toc and header, returns where the toc ends
+    fn finalize(&mut self) -> Result<u64, DepotError> {
+        self.write_block()?;
+        let record = self.creator.to_record();
+        self.add_reserved_stream(CREATOR_STREAM, Cursor::new(record))?;
+        if self.dir_index {
+            self.write_dir_index()?;
+        } else {
+            self.remove_entry(DIR_INDEX_STREAM);
+        }
+        // last, so it covers the other reserved streams and is found in
+        // front of the toc by [`crate::phf::PhfReader`]
+        if self.perfect_hash {
+            self.write_perfect_hash()?;
+        } else {
+            self.remove_entry(PHF_STREAM);
+        }
+        // the toc follows the last entry
+        let toc_offset = self.seek_data_end()?;
+        // write the toc
+        self.metadata.toc.ser(self.handle.writer()?)?;
+        let end = self.handle.stream_position()?;
+        if let Some(events) = &self.events {
+            events.toc_written(self.metadata.toc.entry_count, end - toc_offset);
+        }
+        // the toc is complete before the header points at it
+        self.flush()?;
+        // seek to the beginning of the depot
+        self.handle.seek(SeekFrom::Start(self.header_offset))?;
+        // update then write the header, the toc is always written
+        // in the current format
+        self.metadata.header.toc_offset = toc_offset;
+        self.metadata.header.version = FORMAT_VERSION;
+        self.metadata.header.ser(self.handle.writer()?)?;
+        // an older toc may have been longer than what replaced it
+        self.flush()?;
+        if let Some(file) = &self.file {
+            if file.metadata()?.len() > end {
+                file.set_len(end)?;
+            }
+        }
+        if let Some(volumes) = &self.volumes {
+            volumes.truncate(end)?;
+        }
+        Ok(end)
+    }
+
+    /// Writes the toc and header and closes the depot, closing a read-only
+    /// handle does not touch the stream
+    pub fn close(mut self) -> Result<(), DepotError> {
+        if self.mode == OpenMode::Read {
+            return Ok(());
+        }
+        self.finalize()?;
+        Ok(())
+    }
+
+    /// Closes the depot like [`DepotHandle::close`] and seals it, the SHA-256
+    /// of the whole depot is written with the immutable flag in a trailer
+    /// after the toc. Handles then refuse to open it for writing with
+    /// [`DepotError::Frozen`] until it is unfrozen with
+    /// [`DepotHandle::unfreeze`], readers are not affected
+    pub fn freeze(mut self) -> Result<FreezeTrailer, DepotError> {
+        if self.mode == OpenMode::Read {
+            return Err(DepotError::ReadOnly);
+        }
+        let end = self.finalize()?;
+        let hash = freeze::hash_range(&mut self.handle, self.header_offset, end)?;
+        let trailer = FreezeTrailer {
+            flags: FreezeTrailer::FLAG_IMMUTABLE,
+            hash,
+            offset: end,
+        };
+        self.handle.seek(SeekFrom::Start(end))?;
+        trailer.ser(self.handle.writer()?)?;
+        self.flush()?;
+        Ok(trailer)
+    }
+
+    /// The trailer the depot was frozen with, None unless it was frozen
+    pub fn frozen(&self) -> Option<&FreezeTrailer> {
+        self.frozen.as_ref()
+    }
+
+    /// Hashes the depot again and checks it still matches the hash it was
+    /// frozen with, fails with [`DepotError::FreezeMismatch`] if it changed
+    /// since. Depots that were not frozen pass
+    pub fn verify_frozen(&mut self) -> Result<(), DepotError> {
+        let Some(trailer) = self.frozen else {
+            return Ok(());
+        };
+        let hash = freeze::hash_range(&mut self.handle, self.header_offset, trailer.offset)?;
+        if hash != trailer.hash {
+            let actual = FreezeTrailer { hash, ..trailer };
+            return Err(DepotError::FreezeMismatch {
+                expected: trailer.hash_hex(),
+                actual: actual.hash_hex(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Lifts the freeze of the depot starting at the current position of
+    /// `handle` by wiping its trailer, so it can be opened for writing
+    /// again. Returns false if it was not frozen
+    pub fn unfreeze<T: SeekReadWrite>(mut handle: T) -> Result<bool, DepotError> {
+        let header_offset = handle.stream_position()?;
+        let header = DepotHeader::de(&mut handle)?;
+        handle.seek(SeekFrom::Start(header.toc_offset))?;
+        DepotToc::de_versioned(&mut handle, header.version)?;
+        let Some(trailer) = FreezeTrailer::read_after(&mut handle)? else {
+            return Ok(false);
+        };
+        handle.seek(SeekFrom::Start(trailer.offset))?;
+        handle.write_all(&[0; FreezeTrailer::SIZE as usize])?;
+        handle.flush()?;
+        handle.seek(SeekFrom::Start(header_offset))?;
+        Ok(true)
+    }
+
+    /// Like [`DepotHandle::unfreeze`] for the depot at `path`, the trailer
+    /// is cut off the file
+    pub fn unfreeze_file<P: AsRef<Path>>(path: P) -> Result<bool, DepotError> {
+        if let Some(base) = volume::split_base(path.as_ref()) {
+            let mut volumes = Volumes::open(base, true)?;
+            if !Self::unfreeze(&mut volumes)? {
+                return Ok(false);
+            }
+            volumes.truncate(volumes.size()? - FreezeTrailer::SIZE)?;
+            return Ok(true);
+        }
+        let mut fh = fs::OpenOptions::new().read(true).write(true).open(path)?;
+        if !Self::unfreeze(&mut fh)? {
+            return Ok(false);
+        }
+        let len = fh.metadata()?.len();
+        fh.set_len(len - FreezeTrailer::SIZE)?;
+        Ok(true)
+    }
+
+    pub fn flush(&mut self) -> Result<(), DepotError> {
+        if let DepotStream::ReadWrite(stream) = &mut self.handle {
+            stream.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Format version the depot was read in, depots written by this
+    /// handle are always closed in [`FORMAT_VERSION`]
+    pub fn format_version(&self) -> u16 {
+        self.metadata.header.version
+    }
+
+    /// Digest identifying what the depot holds, computed from the toc
+    /// without reading any entry data, see [`crate::fingerprint`]. Changes
+    /// not closed yet are included, along with the version they will be
+    /// written in
+    pub fn archive_fingerprint(&self) -> Fingerprint {
+        let version = match self.mode {
+            OpenMode::Read => self.metadata.header.version,
+            OpenMode::Write | OpenMode::ReadWrite => FORMAT_VERSION,
+        };
+        Fingerprint::of_toc(version, &self.metadata.toc)
+    }
+
+    pub fn get_toc(&self) -> DepotToc {
+        self.metadata.toc.clone()
+    }
+}
+
+/// Refuses empty metadata keys, which could not be told apart from the
+/// prefix alone
+fn check_meta_key(key: &str) -> Result<(), DepotError> {
+    if key.is_empty() {
+        return Err(DepotError::InvalidInput(
+            "metadata keys can not be empty".to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+/// Largest entry extracted through the writer pool, bigger ones are
+/// streamed to disk on the reading thread instead of being held in memory
+const POOLED_ENTRY_MAX: u64 = 16 * 1024 * 1024;
+
+/// A decompressed entry waiting to be written by the extraction pool
+struct WriteJob {
+    index: usize,
+    stream: StreamInfo,
+    path: PathBuf,
+    data: Vec<u8>,
+    owner: Option<Owner>,
+    mode: Option<u32>,
+}
+
+impl WriteJob {
+    fn write(&self, options: &ExtractOptions) -> Result<(), Error> {
+        let mut fh = File::create(&self.path)?;
+        if options.preallocate {
+            preallocate(&fh, self.data.len() as u64).map_err(|e| {
+                Error::new(
+                    e.kind(),
+                    format!(
+                        "could not reserve {} bytes for {}: {}",
+                        self.data.len(),
+                        self.path.display(),
+                        e
+                    ),
+                )
+            })?;
+        }
+        options.write_hooked(&self.stream, &self.path, Box::new(&mut fh), |writer| {
+            Ok(writer.write_all(&self.data)?)
+        })?;
+        restore_owner(&self.path, self.owner)?;
+        restore_mode(&self.path, self.mode)
+    }
+}
+
+/// Creates the symlink and directory entries of an extraction after its
+/// files, so no file is written through an extracted link and directories
+/// are only made read-only once they are filled
+fn create_nodes(
+    nodes: &[(&StreamInfo, PathBuf)],
+    options: &ExtractOptions,
+    events: Option<&dyn DepotEvents>,
+    mut on_extracted: Option<&mut ExtractedFn>,
+) -> Result<(), DepotError> {
+    let (links, dirs): (Vec<_>, Vec<_>) = nodes
+        .iter()
+        .partition(|(stream, _)| stream.einf.entry_type() == EntryType::Symlink);
+    for (_, path) in &dirs {
+        fs::create_dir_all(path)?;
+    }
+    for (stream, path) in links {
+        node_with_events(events, stream, || {
+            let target = stream
+                .einf
+                .link_target()
+                .ok_or_else(|| DepotError::CorruptEntry {
+                    name: stream.name.clone(),
+                    reason: "is a symlink without a target".to_owned(),
+                })?;
+            create_symlink(&target, path)?;
+            Ok(restore_owner(
+                path,
+                options.resolve_owner(&stream.einf.ext),
+            )?)
+        })?;
+        if let Some(cb) = &mut on_extracted {
+            cb(stream, path);
+        }
+    }
+    for (stream, path) in dirs {
+        node_with_events(events, stream, || {
+            restore_owner(path, options.resolve_owner(&stream.einf.ext))?;
+            Ok(restore_mode(path, options.resolve_mode(&stream.einf.ext))?)
+        })?;
+        if let Some(cb) = &mut on_extracted {
+            cb(stream, path);
+        }
+    }
+    Ok(())
+}
+
+/// Runs `create` for the symlink or directory `stream` between the extract
+/// events
+fn node_with_events(
+    events: Option<&dyn DepotEvents>,
+    stream: &StreamInfo,
+    create: impl FnOnce() -> Result<(), DepotError>,
+) -> Result<(), DepotError> {
+    let Some(events) = events else {
+        return create();
+    };
+    events.entry_started(Operation::Extract, &stream.name, 0);
+    let result = create();
+    events.entry_finished(Operation::Extract, &stream.name, result.as_ref().err());
+    result
+}
+
+/// Joins the path a stream is extracted to and creates its parent
+/// directories, refusing a parent below `output` that is a symlink so an
+/// entry can not be written through a link extracted earlier
+fn output_path(output: &Path, name: &str, rel_path: &Path) -> Result<PathBuf, DepotError> {
+    let out_path = output.join(rel_path);
+    let mut parent = output.to_owned();
+    if let Some(dirs) = rel_path.parent() {
+        for component in dirs.components() {
+            parent.push(component);
+            match fs::symlink_metadata(&parent) {
+                Ok(meta) if meta.file_type().is_symlink() => {
+                    return Err(DepotError::UnsafePath {
+                        name: name.to_owned(),
+                        path: rel_path.to_owned(),
+                    })
+                }
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+    if let Some(parent) = out_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(out_path)
+}
+
+/// Sets the owner of `path` itself, a symlink is not followed
+#[cfg(unix)]
+fn restore_owner(path: &Path, owner: Option<Owner>) -> Result<(), Error> {
+    if let Some(owner) = owner {
+        std::os::unix::fs::lchown(path, owner.uid, owner.gid)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn restore_owner(_path: &Path, _owner: Option<Owner>) -> Result<(), Error> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn restore_mode(path: &Path, mode: Option<u32>) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+    if let Some(mode) = mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn restore_mode(_path: &Path, _mode: Option<u32>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Creates a symlink at `path`, replacing a file or link already there
+#[cfg(unix)]
+fn create_symlink(target: &Path, path: &Path) -> Result<(), Error> {
+    if fs::symlink_metadata(path).is_ok_and(|meta| !meta.is_dir()) {
+        fs::remove_file(path)?;
+    }
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(not(unix))]
+fn create_symlink(_target: &Path, path: &Path) -> Result<(), Error> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        format!("can not create symlink {} on this platform", path.display()),
+    ))
+}
+
+/// Writer dropping everything written to it
+struct Discard;
+
+impl Write for Discard {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for Discard {
+    fn seek(&mut self, _: SeekFrom) -> std::io::Result<u64> {
+        Ok(0)
+    }
+}
+
+/// Adapts a plain writer to [`DepotHandle::extract_stream`], which only
+/// ever writes sequentially
+struct SeekLess<W>(W);
+
+impl<W: Write> Write for SeekLess<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<W> Seek for SeekLess<W> {
+    fn seek(&mut self, _: SeekFrom) -> std::io::Result<u64> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "extraction target is not seekable",
+        ))
+    }
+}
+
+/// Data of the dictionary in `dictionaries` that `entry` was compressed
+/// with, `None` for entries compressed without one
+pub(crate) fn entry_dict(
+    dictionaries: &[Dictionary],
+    name: &str,
+    entry: &EntryInfo,
+) -> Result<Option<Arc<[u8]>>, DepotError> {
+    let Some(tag) = entry.ext.get_str(keys::DICT) else {
+        return Ok(None);
+    };
+    match dictionaries.iter().find(|dict| dict.tag() == tag) {
+        Some(dict) => Ok(Some(dict.shared_data())),
+        None => Err(DepotError::MissingDictionary {
+            name: name.to_owned(),
+            tag: tag.to_owned(),
+        }),
+    }
+}
+
+/// `key` when `entry` is encrypted, `None` for entries stored in the clear
+pub(crate) fn entry_key(
+    key: Option<&EncryptionKey>,
+    name: &str,
+    entry: &EntryInfo,
+) -> Result<Option<EncryptionKey>, DepotError> {
+    if entry.flags & EntryInfo::FLAG_ENCRYPTED == 0 {
+        return Ok(None);
+    }
+    match key {
+        Some(key) => Ok(Some(key.clone())),
+        None => Err(DepotError::NoKey(name.to_owned())),
+    }
+}
+
+/// Decoder for the data of `entry`, `reader` has to be positioned at its
+/// offset. Reads never go past the stored data, `key` is required for
+/// encrypted entries
+pub(crate) fn decoder<'a, R: BufRead + 'a>(
+    name: &str,
+    entry: &EntryInfo,
+    dict: Option<&[u8]>,
+    key: Option<&EncryptionKey>,
+    reader: R,
+) -> Result<Box<dyn Read + 'a>, DepotError> {
+    if entry.flags & EntryInfo::FLAG_EMPTY != 0 {
+        return Ok(Box::new(std::io::empty()));
+    }
+    let data = reader.take(entry.stream_size);
+    let data: Box<dyn BufRead + 'a> = match (entry.flags & EntryInfo::FLAG_ENCRYPTED != 0, key) {
+        (false, _) => Box::new(data),
+        (true, Some(key)) => {
+            let nonce = entry.ext.get(keys::NONCE).unwrap_or_default();
+            Box::new(BufReader::new(Decryptor::new(key, nonce, data)?))
+        }
+        (true, None) => return Err(DepotError::NoKey(name.to_owned())),
+    };
+    Ok(match (entry.compression(), dict) {
+        (Compression::Zstd, Some(dict)) => {
+            Box::new(zstd::stream::Decoder::with_dictionary(data, dict)?)
+        }
+        (Compression::Zstd, None) => Box::new(zstd::stream::Decoder::with_buffer(data)?),
+        (Compression::Lz4, _) => Box::new(lz4_flex::frame::FrameDecoder::new(data)),
+        (Compression::None, _) => Box::new(data),
+    })
+}
+
+/// Largest file compressed by the bulk add workers, bigger ones are
+/// compressed on the calling thread using the handle's zstd threads
+const BULK_POOLED_MAX: u64 = 64 * 1024 * 1024;
+
+/// Compressed data a bulk add worker holds in memory before spilling it to
+/// a temp file
+const SPOOL_MEMORY_MAX: usize = 16 * 1024 * 1024;
+
+/// A file compressed by a bulk add worker, waiting for its turn to be written
+struct Prepared {
+    /// describes the spooled data, its offset is set once it is written
+    entry: EntryInfo,
+    /// size of the source when it was opened
+    size: u64,
+    /// the source changed while it was read
+    changed: bool,
+    /// why the secret scan refused the file
+    secret: Option<String>,
+    spool: Spool,
+    /// the open source, read again when the data is stored as is
+    source: File,
+    snapshot: Option<Snapshot>,
+}
+
+/// Compresses a file for a bulk add on a worker thread
+fn prepare_file(
+    path: &Path,
+    encoding: &Encoding,
+    snapshot: bool,
+    temp: &TempPolicy,
+    policy: Option<&ContentPolicy>,
+) -> Result<Prepared, Error> {
+    let mut fh = File::open(path)?;
+    let stat = fh.metadata()?;
+    let mut size = stat.len();
+    let mut changed = false;
+    let mut snap = None;
+    if snapshot {
+        let mut taken = Snapshot::take(&mut fh, path, temp)?;
+        changed = source_changed(path, &stat)?;
+        size = taken.file().metadata()?.len();
+        snap = Some(taken);
+    }
+    let reader = match &mut snap {
+        Some(snap) => snap.file(),
+        None => &mut fh,
+    };
+    let mut spool = Spool::new(temp.clone());
+    let mut scan = policy.filter(|policy| policy.scans()).map(SecretScan::new);
+    let entry = encode(
+        encoding,
+        Scanned::new(BufReader::new(reader).take(size), scan.as_mut()),
+        &mut spool,
+        size,
+        None,
+    )?;
+    if snap.is_none() {
+        changed = source_changed(path, &stat)?;
+    }
+    Ok(Prepared {
+        entry,
+        size,
+        changed,
+        secret: scan.and_then(SecretScan::finish),
+        spool,
+        source: fh,
+        snapshot: snap,
+    })
+}
+
+/// Encoded data held in memory, moved to a temp file once it outgrows
+/// [`SPOOL_MEMORY_MAX`] and the temp policy allows one
+struct Spool {
+    memory: Vec<u8>,
+    file: Option<TempFile>,
+    temp: TempPolicy,
+}
+
+impl Spool {
+    fn new(temp: TempPolicy) -> Self {
+        Self {
+            memory: Vec::new(),
+            file: None,
+            temp,
+        }
+    }
+
+    /// Copies the spooled data to `writer`, or straight from file to file
+    /// when `dst` is the file behind `writer`
+    fn copy_to<W: Write>(&mut self, mut writer: W, dst: Option<&File>) -> Result<(), Error> {
+        match (&mut self.file, dst) {
+            (Some(file), Some(dst)) => {
+                let file = file.file();
+                let len = file.metadata()?.len();
+                copy_range(file, 0, dst, len)?;
+            }
+            (Some(file), None) => {
+                let file = file.file();
+                file.seek(SeekFrom::Start(0))?;
+                std::io::copy(file, &mut writer)?;
+            }
+            (None, _) => writer.write_all(&self.memory)?,
+        }
+        writer.flush()
+    }
+}
+
+impl Write for Spool {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.file.is_none()
+            && self.memory.len() + buf.len() > SPOOL_MEMORY_MAX
+            && self.temp.dir().is_some()
+        {
+            let mut file = self.temp.create("spooling a compressed file")?;
+            file.file().write_all(&self.memory)?;
+            self.memory = Vec::new();
+            self.file = Some(file);
+        }
+        match &mut self.file {
+            Some(file) => file.file().write(buf),
+            None => {
+                self.memory.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.file().flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// true if `path` no longer matches the `stat` taken before it was read
+fn source_changed(path: &Path, stat: &fs::Metadata) -> Result<bool, Error> {
+    // a rewrite of the same size is only visible in the modification time
+    let after = fs::metadata(path)?;
+    Ok(after.len() != stat.len() || after.modified().ok() != stat.modified().ok())
+}
+
+/// Settings entry data is encoded with, taken from the handle so bulk add
+/// workers can encode without it
+#[derive(Clone)]
+struct Encoding {
+    compression: Compression,
+    /// tag and data of the zstd dictionary
+    dict: Option<(String, Arc<[u8]>)>,
+    level: i32,
+    /// zstd worker threads per entry, 0 compresses on the calling thread
+    mt_threads: u32,
+    buf_size: usize,
+    independent_frames: Option<usize>,
+    /// key the encoded data is sealed with
+    encryption: Option<EncryptionKey>,
+    /// name of the entry and the hooks transforming its data
+    transform: Option<(String, Arc<dyn IngestHooks>)>,
+}
+
+/// Encodes the contents of `reader` into `writer`, returning the entry
+/// describing them at offset 0. The contents pass the ingest hooks first
+fn encode<'r, R: Read + 'r, W: Write>(
+    encoding: &Encoding,
+    reader: R,
+    writer: W,
+    size_hint: u64,
+    progress: Option<&mut dyn FnMut(u64, u64)>,
+) -> Result<EntryInfo, Error> {
+    let Some((name, hooks)) = &encoding.transform else {
+        return encode_data(encoding, reader, writer, size_hint, progress);
+    };
+    let (reader, transform) = hooks.transform_reader(name, Box::new(reader))?;
+    let mut entry = encode_data(encoding, reader, writer, size_hint, progress)?;
+    if let Some(transform) = transform {
+        entry.ext.set_str(keys::TRANSFORM, &transform);
+    }
+    Ok(entry)
+}
+
+/// Encodes the contents of `reader` into `writer` as they are
+fn encode_data<R: Read, W: Write>(
+    encoding: &Encoding,
+    reader: R,
+    writer: W,
+    size_hint: u64,
+    progress: Option<&mut dyn FnMut(u64, u64)>,
+) -> Result<EntryInfo, Error> {
+    if let (Compression::Zstd, Some(frame_size)) =
+        (encoding.compression, encoding.independent_frames)
+    {
+        return encode_frames(encoding, reader, writer, frame_size, size_hint, progress);
+    }
+    let mut buf = vec![0; encoding.buf_size];
+    let mut writer = Counted::new(writer);
+    let mut ext = ExtMeta::default();
+
+    let (written, hash) = match &encoding.encryption {
+        Some(key) => {
+            let (mut encryptor, nonce) = Encryptor::new(key, &mut writer)?;
+            let copied = compress(
+                encoding,
+                reader,
+                &mut encryptor,
+                &mut buf,
+                size_hint,
+                progress,
+            )?;
+            encryptor.finish()?;
+            ext.set(keys::NONCE, &nonce);
+            copied
+        }
+        None => compress(encoding, reader, &mut writer, &mut buf, size_hint, progress)?,
+    };
+    writer.flush()?;
+
+    if let Some((tag, _)) = &encoding.dict {
+        ext.set_str(keys::DICT, tag);
+    }
+    let mut flags = match encoding.compression {
+        Compression::Zstd => 0,
+        Compression::Lz4 => EntryInfo::FLAG_LZ4,
+        Compression::None => EntryInfo::FLAG_STORED,
+    };
+    if encoding.encryption.is_some() {
+        flags |= EntryInfo::FLAG_ENCRYPTED;
+    }
+    Ok(EntryInfo {
+        offset: 0,
+        size: written,
+        stream_size: writer.count,
+        flags,
+        create_ts: TsWithTz::now(),
+        mod_ts: TsWithTz::now(),
+        hash,
+        ext,
+    })
+}
+
+/// Compresses `reader` into `writer` as `encoding` says, returning the
+/// uncompressed size and hash
+fn compress<R: Read, W: Write>(
+    encoding: &Encoding,
+    reader: R,
+    mut writer: W,
+    buf: &mut [u8],
+    size_hint: u64,
+    progress: Option<&mut dyn FnMut(u64, u64)>,
+) -> Result<(u64, u64), Error> {
+    Ok(match encoding.compression {
+        Compression::Zstd => {
+            let mut compressor = zstd_encoder(encoding, &mut writer)?;
+            let copied = copy_hashed(reader, &mut compressor, buf, size_hint, progress)?;
+            // finish the compression
+            compressor.finish()?;
+            copied
+        }
+        Compression::Lz4 => {
+            let info = lz4_flex::frame::FrameInfo::new().content_checksum(true);
+            let mut compressor = lz4_flex::frame::FrameEncoder::with_frame_info(info, &mut writer);
+            let copied = copy_hashed(reader, &mut compressor, buf, size_hint, progress)?;
+            compressor.finish().map_err(Error::from)?;
+            copied
+        }
+        Compression::None => copy_hashed(reader, &mut writer, buf, size_hint, progress)?,
+    })
+}
+
+/// Compresses `reader` into independent frames of `frame_size`
+/// uncompressed bytes, recording them in the entry's metadata
+fn encode_frames<R: Read, W: Write>(
+    encoding: &Encoding,
+    mut reader: R,
+    writer: W,
+    frame_size: usize,
+    size_hint: u64,
+    mut progress: Option<&mut dyn FnMut(u64, u64)>,
+) -> Result<EntryInfo, Error> {
+    let mut writer = Counted::new(writer);
+    let mut buf = vec![0; encoding.buf_size.min(frame_size)];
+    let mut hasher = EntryHasher::new();
+    let mut frames = Vec::new();
+    let mut written = 0;
+    loop {
+        // a frame is only started once there is data for it
+        let mut n = read_some(&mut reader, &mut buf)?;
+        if n == 0 {
+            break;
+        }
+        let frame_start = writer.count;
+        let mut compressor = zstd_encoder(encoding, &mut writer)?;
+        let mut frame_read = 0;
+        while n > 0 {
+            compressor.write_all(&buf[..n])?;
+            hasher.update(&buf[..n]);
+            frame_read += n;
+            written += n as u64;
+            if let Some(progress) = &mut progress {
+                progress(written, size_hint);
+            }
+            let want = buf.len().min(frame_size - frame_read);
+            if want == 0 {
+                break;
+            }
+            n = read_some(&mut reader, &mut buf[..want])?;
+        }
+        compressor.finish()?;
+        frames.push((writer.count - frame_start, frame_read as u64));
+    }
+    writer.flush()?;
+
+    let mut ext = ExtMeta::default();
+    if frames.len() > 1 {
+        ext.set_frames(&frames);
+    }
+    if let Some((tag, _)) = &encoding.dict {
+        ext.set_str(keys::DICT, tag);
+    }
+    Ok(EntryInfo {
+        offset: 0,
+        size: written,
+        stream_size: writer.count,
+        flags: 0,
+        create_ts: TsWithTz::now(),
+        mod_ts: TsWithTz::now(),
+        hash: hasher.finish(),
+        ext,
+    })
+}
+
+/// A zstd encoder with the level, threads and dictionary of `encoding`
+fn zstd_encoder<'a, W: Write>(
+    encoding: &Encoding,
+    writer: W,
+) -> Result<zstd::stream::Encoder<'a, W>, Error> {
+    let mut compressor = match &encoding.dict {
+        Some((_, dict)) => zstd::stream::Encoder::with_dictionary(writer, encoding.level, dict)?,
+        None => zstd::stream::Encoder::new(writer, encoding.level)?,
+    };
+    compressor.include_checksum(true)?;
+    compressor.multithread(encoding.mt_threads)?;
+    Ok(compressor)
+}
+
+/// Passes writes through while counting them
+struct Counted<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Counted<W> {
+    fn new(inner: W) -> Self {
+        Self { inner, count: 0 }
+    }
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reads into `buf` until it is full or the reader is exhausted
+fn read_some<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize, Error> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Lazily decompressing reader over an entry, see
+/// [`DepotHandle::open_stream`]
+pub struct DepotStreamReader<'a> {
+    inner: Box<dyn Read + 'a>,
+    name: String,
+    entry: EntryInfo,
+    read: u64,
+    hasher: EntryHasher,
+    checked: bool,
+    /// access stats of the handle, taken once the read is recorded
+    stats: Option<&'a mut BTreeMap<String, AccessStats>>,
+}
+
+impl DepotStreamReader<'_> {
+    /// Uncompressed size of the entry
+    pub fn size(&self) -> u64 {
+        self.entry.size
+    }
+
+    fn finish(&mut self) -> std::io::Result<usize> {
+        self.record();
+        if !self.checked {
+            self.checked = true;
+            check_extracted(&self.name, &self.entry, self.read, self.hasher.finish())?;
+        }
+        Ok(0)
+    }
+
+    /// Records the bytes read so far as one access, once
+    fn record(&mut self) {
+        if let Some(stats) = self.stats.take() {
+            record_access(stats, &self.name, self.read);
+        }
+    }
+}
+
+impl Drop for DepotStreamReader<'_> {
+    fn drop(&mut self) {
+        self.record();
+    }
+}
+
+impl Read for DepotStreamReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let left = self.entry.size - self.read;
+        if left == 0 || buf.is_empty() {
+            return if left == 0 { self.finish() } else { Ok(0) };
+        }
+        // never read past the recorded size of the entry
+        let len = buf.len().min(left.min(usize::MAX as u64) as usize);
+        let n = self.inner.read(&mut buf[..len])?;
+        if n == 0 {
+            return self.finish();
+        }
+        self.hasher.update(&buf[..n]);
+        self.read += n as u64;
+        Ok(n)
+    }
+}
+
+/// Counts one read of `bytes` bytes of the entry `name`
+fn record_access(stats: &mut BTreeMap<String, AccessStats>, name: &str, bytes: u64) {
+    let stats = stats.entry(name.to_owned()).or_default();
+    stats.reads += 1;
+    stats.bytes += bytes;
+}
+
+/// Decompresses a stream from `reader`, positioned at its offset, and
+/// checks its size and hash, reporting the bytes decompressed to `progress`
+fn check_stream<R: BufRead>(
+    stream: &StreamInfo,
+    dict: Option<&[u8]>,
+    key: Option<&EncryptionKey>,
+    reader: R,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<(), DepotError> {
+    let entry = &stream.einf;
+    if entry.flags & EntryInfo::FLAG_EMPTY != 0 {
+        return Ok(());
+    }
+    let mut decompressor = decoder(&stream.name, entry, dict, key, reader)?;
+    let mut hasher = EntryHasher::new();
+    let mut buf = vec![0; 64 * 1024];
+    let mut read = 0;
+    // never read past the recorded size of the entry
+    while read < entry.size {
+        let want = buf.len().min((entry.size - read) as usize);
+        let n = match decompressor.read(&mut buf[..want]) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        hasher.update(&buf[..n]);
+        read += n as u64;
+        progress(read, entry.size);
+    }
+    check_extracted(&stream.name, entry, read, hasher.finish())
+}
+
+/// Checks the size and hash of extracted entry data against its toc entry
+pub(crate) fn check_extracted(
+    name: &str,
+    entry: &EntryInfo,
+    read: u64,
+    hash: u64,
+) -> Result<(), DepotError> {
+    // uncompressed size sanity check
+    if read != entry.size {
+        return Err(DepotError::SizeMismatch {
+            name: name.to_owned(),
+            expected: entry.size,
+            actual: read,
+        });
+    }
+
+    // check the hash
+    if hash != entry.hash {
+        return Err(DepotError::HashMismatch {
+            name: name.to_owned(),
+            expected: entry.hash,
+            actual: hash,
+        });
+    }
+    Ok(())
+}
+
+/// Copies `reader` into `writer` through `buf`, returning the number of bytes
+/// copied and their hash
+fn copy_hashed<R: Read, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    buf: &mut [u8],
+    size_hint: u64,
+    mut progress: Option<&mut dyn FnMut(u64, u64)>,
+) -> Result<(u64, u64), Error> {
+    let mut hasher = EntryHasher::new();
+    let mut written = 0;
+    loop {
+        let n = match reader.read(buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buf[..n])?;
+        hasher.update(&buf[..n]);
+        written += n as u64;
+        if let Some(progress) = &mut progress {
+            progress(written, size_hint);
+        }
+    }
+    writer.flush()?;
+    Ok((written, hasher.finish()))
+}
+
+/// true if the data of `entry` can be shared with entries of the same
+/// contents, see [`DepotHandle::set_dedup`]
+fn shares_data(entry: &EntryInfo) -> bool {
+    entry.size > 0 && entry.flags & EntryInfo::FLAG_EMPTY == 0
+}
+
+/// true if `entry` is a member of a solid block that was not written yet
+fn is_buffered(entry: &EntryInfo) -> bool {
+    entry.flags & EntryInfo::FLAG_SOLID != 0 && entry.stream_size == 0
+}
+
+/// The data of a solid block member starting `at` in `block`
+pub(crate) fn block_member<'b>(
+    name: &str,
+    block: &'b [u8],
+    at: u64,
+    size: u64,
+) -> Result<&'b [u8], DepotError> {
+    match at.checked_add(size) {
+        Some(end) if end <= block.len() as u64 => Ok(&block[at as usize..end as usize]),
+        _ => Err(DepotError::CorruptEntry {
+            name: name.to_owned(),
+            reason: "lies past the end of its solid block".to_owned(),
+        }),
+    }
+}
+
+/// Reserved streams that are written anew whenever the depot is closed
+fn rewritten_on_close(name: &str) -> bool {
+    name == PHF_STREAM || name == CREATOR_STREAM || name == DIR_INDEX_STREAM
+}
+
+fn check_user_name(name: &str) -> Result<(), DepotError> {
+    // `./.depot/x` or `.depot\x` name the same entry as `.depot/x`
+    if is_reserved_name(&normalize_id_name(name)) {
+        return Err(DepotError::ReservedName(name.to_owned()));
+    }
+    if name.len() > MAX_NAME_LEN as usize {
+        return Err(DepotError::InvalidName {
+            name: name.to_owned(),
+            reason: format!(
+                "is {} bytes, entry names are at most {}",
+                name.len(),
+                MAX_NAME_LEN
+            ),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A toc prefix of `count` entries followed by `entries` in the clear,
+    /// as written before version 6
+    fn toc_v5(count: u64, entries: &[u8]) -> Vec<u8> {
+        let mut toc = Vec::new();
+        toc.write_i32::<BigEndian>(3).unwrap();
+        toc.write_u64::<BigEndian>(count).unwrap();
+        toc.write_u64::<BigEndian>(0).unwrap();
+        toc.extend_from_slice(entries);
+        toc
+    }
+
+    /// Like [`toc_v5`] with the entries compressed and no depot metadata
+    fn toc_packed(count: u64, entries: &[u8]) -> Vec<u8> {
+        let packed = zstd::bulk::compress(entries, 3).unwrap();
+        let mut toc = toc_v5(count, &[]);
+        toc.write_u64::<BigEndian>(packed.len() as u64).unwrap();
+        toc.extend_from_slice(&packed);
+        toc.write_u32::<BigEndian>(0).unwrap();
+        toc
+    }
+
+    /// An entry named `name` without metadata attributes
+    fn entry(name: &str) -> Vec<u8> {
+        let mut entry = Vec::new();
+        entry.write_u32::<BigEndian>(name.len() as u32).unwrap();
+        entry.extend_from_slice(name.as_bytes());
+        entry.extend_from_slice(&[0; 7 * 8]);
+        entry.write_u32::<BigEndian>(0).unwrap();
+        entry
+    }
+
+    /// Reads `entries` in both an uncompressed and a compressed toc of
+    /// `count` entries, each must fail at `entry` for the given reason
+    fn check_corrupt(count: u64, entries: &[u8], entry: u64, reason: &str) {
+        for (toc, version) in [(toc_v5(count, entries), 5), (toc_packed(count, entries), 8)] {
+            check_corrupt_at(toc, version, entry, reason);
+        }
+    }
+
+    /// Reads `toc` written by `version`, it must fail at `entry` for the
+    /// given reason
+    fn check_corrupt_at(toc: Vec<u8>, version: u16, entry: u64, reason: &str) {
+        let err = DepotToc::de_versioned(Cursor::new(toc), version).unwrap_err();
+        assert!(err.is_corruption(), "version {}: {}", version, err);
+        match err {
+            DepotError::CorruptToc {
+                at_entry,
+                reason: r,
+            } => {
+                assert_eq!(at_entry, entry, "version {}: {}", version, r);
+                assert!(r.contains(reason), "version {}: {}", version, r);
+            }
+            e => panic!("version {}: expected a corrupt toc, got {}", version, e),
+        }
+    }
+
+    #[test]
+    fn reads_entries() {
+        let entries = [entry("a"), entry("b")].concat();
+        for (toc, version) in [(toc_v5(2, &entries), 5), (toc_packed(2, &entries), 8)] {
+            let toc = DepotToc::de_versioned(Cursor::new(toc), version).unwrap();
+            assert_eq!(toc.entries.keys().collect::<Vec<_>>(), ["a", "b"]);
+        }
+    }
+
+    #[test]
+    fn huge_name_length() {
+        // the length is checked against the bytes left before the name
+        // buffer is allocated, not after 4 GiB of zeroes
+        let mut entries = entry("a");
+        entries.write_u32::<BigEndian>(u32::MAX).unwrap();
+        entries.extend_from_slice(b"b");
+        check_corrupt(2, &entries, 1, "over the limit of 5 bytes");
+    }
+
+    #[test]
+    fn damaged_packed_entries() {
+        let mut toc = toc_packed(1, &entry("a"));
+        // the packed size is at the end of the fixed fields
+        toc[20..28].copy_from_slice(&u64::MAX.to_be_bytes());
+        check_corrupt_at(toc, 8, 0, "bytes packed");
+
+        let mut toc = toc_packed(1, &entry("a"));
+        toc[28..32].copy_from_slice(b"junk");
+        check_corrupt_at(toc, 8, 0, "do not decompress");
+    }
+
+    #[test]
+    fn huge_unpacked_size() {
+        // a few kilobytes of zeroes that would unpack to over the limit are
+        // refused by the size in the frame header, before decoding
+        let mut encoder = zstd::stream::Encoder::new(Vec::new(), 1).unwrap();
+        encoder
+            .set_pledged_src_size(Some(MAX_TOC_SIZE + 1))
+            .unwrap();
+        let zeroes = vec![0; 1024 * 1024];
+        for _ in 0..MAX_TOC_SIZE / zeroes.len() as u64 {
+            encoder.write_all(&zeroes).unwrap();
+        }
+        encoder.write_all(&[0]).unwrap();
+        let packed = encoder.finish().unwrap();
+        let mut toc = toc_v5(1, &[]);
+        toc.write_u64::<BigEndian>(packed.len() as u64).unwrap();
+        toc.extend_from_slice(&packed);
+        check_corrupt_at(toc, 8, 0, "unpack to over");
+    }
+
+    #[test]
+    fn truncated_entry() {
+        let mut entries = entry("a");
+        entries.extend_from_slice(&entry("b")[..20]);
+        check_corrupt(2, &entries, 1, "failed to fill whole buffer");
+        // more entries counted than there are
+        check_corrupt(
+            3,
+            &[entry("a"), entry("b")].concat(),
+            2,
+            "failed to fill whole buffer",
+        );
+    }
+
+    #[test]
+    fn oversized_metadata_length() {
+        let mut attr = entry("a");
+        attr.truncate(attr.len() - 4);
+        attr.write_u32::<BigEndian>(1).unwrap();
+        attr.write_u32::<BigEndian>(1).unwrap();
+        attr.extend_from_slice(b"k");
+        attr.write_u32::<BigEndian>(u32::MAX).unwrap();
+        attr.extend_from_slice(b"value");
+        check_corrupt(1, &attr, 0, "over the limit of 9 bytes");
+
+        let mut count = entry("a");
+        count.truncate(count.len() - 4);
+        count.write_u32::<BigEndian>(u32::MAX).unwrap();
+        check_corrupt(1, &count, 0, "metadata attributes do not fit the toc");
+    }
+
+    #[test]
+    fn damaged_frame_tables() {
+        let data: Vec<u8> = (0..64 * 1024u32).map(|i| (i % 251) as u8).collect();
+        let mut depot = Cursor::new(Vec::new());
+        let mut dh = DepotHandle::create(&mut depot).unwrap();
+        dh.set_independent_frames(Some(16 * 1024));
+        dh.add_bytes("a.bin", &data).unwrap();
+        dh.close().unwrap();
+
+        let mut dh = DepotHandle::open_read(Cursor::new(depot.into_inner())).unwrap();
+        let stream = dh.get_named_stream("a.bin").unwrap();
+        let frames = stream.einf.ext.frames().unwrap();
+        assert_eq!(
+            dh.read_range(&stream, 20_000, 10).unwrap(),
+            data[20_000..20_010]
+        );
+
+        let (packed, plain) = frames[0];
+        let damaged = [
+            (
+                vec![(u64::MAX, plain), (packed, plain)],
+                stream.einf.stream_size,
+                "overflowing",
+            ),
+            (frames.clone(), stream.einf.stream_size + 1, "not adding up"),
+        ];
+        for (table, stream_size, reason) in damaged {
+            let mut stream = stream.clone();
+            stream.einf.ext.set_frames(&table);
+            stream.einf.stream_size = stream_size;
+            for err in [
+                dh.read_range(&stream, 0, 10).unwrap_err(),
+                dh.extract_stream_parallel(&stream, std::io::sink(), 4)
+                    .unwrap_err(),
+            ] {
+                assert!(err.to_string().contains(reason), "{}", err);
+            }
+        }
+
+        // a table matching its sizes, but for more data than the depot holds
+        let mut stream = stream.clone();
+        let table = vec![(packed, plain); frames.len() * 1000];
+        stream.einf.ext.set_frames(&table);
+        stream.einf.size = plain * table.len() as u64;
+        stream.einf.stream_size = packed * table.len() as u64;
+        let err = dh.read_range(&stream, 0, 10).unwrap_err();
+        assert!(err.to_string().contains("past the end"), "{}", err);
+    }
+
+    #[test]
+    fn stream_reader_records_bytes_read() {
+        let data = vec![5; 100 * 1024];
+        let mut depot = Cursor::new(Vec::new());
+        let mut dh = DepotHandle::create(&mut depot).unwrap();
+        dh.add_bytes("a.bin", &data).unwrap();
+        dh.close().unwrap();
+
+        let mut dh = DepotHandle::open_read(Cursor::new(depot.into_inner())).unwrap();
+        dh.set_access_stats(true);
+        let stream = dh.get_named_stream("a.bin").unwrap();
+        let mut reader = dh.open_stream(&stream).unwrap();
+        reader.read_exact(&mut [0; 10]).unwrap();
+        drop(reader);
+        let stats = dh.access_stats().unwrap()["a.bin"];
+        assert_eq!((stats.reads, stats.bytes), (1, 10));
+
+        let mut read = Vec::new();
+        let mut reader = dh.open_stream(&stream).unwrap();
+        reader.read_to_end(&mut read).unwrap();
+        drop(reader);
+        let stats = dh.access_stats().unwrap()["a.bin"];
+        assert_eq!((stats.reads, stats.bytes), (2, 10 + data.len() as u64));
+    }
+
+    /// An entry below a symlink extracted earlier, in the same or in an
+    /// earlier call, is refused instead of written where the link points
+    #[cfg(unix)]
+    #[test]
+    fn extracts_nothing_through_links() {
+        let dir = std::env::temp_dir().join(format!("depot-links-{}", std::process::id()));
+        let outside = dir.join("outside");
+        let output = dir.join("output");
+        fs::create_dir_all(&outside).unwrap();
+        fs::create_dir_all(&output).unwrap();
+
+        let mut depot = Cursor::new(Vec::new());
+        let mut dh = DepotHandle::create(&mut depot).unwrap();
+        let mut ext = ExtMeta::default();
+        ext.set_entry_type(EntryType::Symlink);
+        ext.set_link_target(&outside);
+        dh.add_node(Path::new("a"), ext).unwrap();
+        dh.add_bytes("a/pwned", b"data").unwrap();
+        dh.close().unwrap();
+
+        let mut dh = DepotHandle::open_read(Cursor::new(depot.into_inner())).unwrap();
+        let link = dh.get_named_stream("a").unwrap();
+        let file = dh.get_named_stream("a/pwned").unwrap();
+        let options = ExtractOptions::default();
+        // one batch writes the file first, the link then finds its path taken
+        let batch = [link.clone(), file.clone()];
+        assert!(dh.extract_to_dir(&batch, &output, &options, None).is_err());
+        assert!(fs::symlink_metadata(output.join("a")).unwrap().is_dir());
+        fs::remove_dir_all(&output).unwrap();
+        // an entry at a time, the link is already there for the file
+        dh.extract_to_dir(&[link], &output, &options, None).unwrap();
+        let err = dh
+            .extract_to_dir(&[file], &output, &options, None)
+            .unwrap_err();
+        assert!(matches!(err, DepotError::UnsafePath { .. }), "{}", err);
+        let escaped = outside.join("pwned").exists();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(!escaped);
+    }
+
+    /// A depot in memory holding `data` as `a.bin` stored as is, so it
+    /// spans several chunks, and encrypted with `key`
+    #[cfg(feature = "encryption")]
+    fn encrypted_depot(key: &EncryptionKey, data: &[u8]) -> Vec<u8> {
+        let mut depot = Cursor::new(Vec::new());
+        let mut dh = DepotHandle::create(&mut depot).unwrap();
+        dh.set_compression(Compression::None);
+        dh.set_encryption_key(Some(key.clone()));
+        dh.add_bytes("a.bin", data).unwrap();
+        dh.close().unwrap();
+        depot.into_inner()
+    }
+
+    #[cfg(feature = "encryption")]
+    fn read_encrypted(depot: &[u8], key: Option<EncryptionKey>) -> Result<Vec<u8>, DepotError> {
+        let mut dh = DepotHandle::open_read(Cursor::new(depot)).unwrap();
+        dh.set_encryption_key(key);
+        let stream = dh.get_named_stream("a.bin").unwrap();
+        dh.stream_to_memory(&stream)
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_round_trip() {
+        let key = EncryptionKey::new([7; 32]);
+        let data: Vec<u8> = (0..crate::crypt::CHUNK_SIZE * 2 + 100)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let depot = encrypted_depot(&key, &data);
+        // nothing of the data is left in the clear
+        assert!(!depot.windows(251).any(|w| w == &data[..251]));
+        assert_eq!(read_encrypted(&depot, Some(key)).unwrap(), data);
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn wrong_key() {
+        let depot = encrypted_depot(&EncryptionKey::new([7; 32]), b"secret");
+        let err = read_encrypted(&depot, Some(EncryptionKey::new([8; 32]))).unwrap_err();
+        assert!(matches!(err, DepotError::DecryptFailed), "{}", err);
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn tampered_chunk() {
+        let key = EncryptionKey::new([7; 32]);
+        let mut depot = encrypted_depot(&key, &[1; 3 * 1024]);
+        let dh = DepotHandle::open_read(Cursor::new(&depot)).unwrap();
+        let offset = dh.get_named_stream("a.bin").unwrap().einf.offset as usize;
+        drop(dh);
+        depot[offset + 10] ^= 1;
+        let err = read_encrypted(&depot, Some(key)).unwrap_err();
+        assert!(matches!(err, DepotError::DecryptFailed), "{}", err);
+        assert!(err.is_corruption());
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn missing_key() {
+        let depot = encrypted_depot(&EncryptionKey::new([7; 32]), b"secret");
+        let err = read_encrypted(&depot, None).unwrap_err();
+        assert!(
+            matches!(&err, DepotError::NoKey(name) if name == "a.bin"),
+            "{}",
+            err
+        );
     }
 }
@@ -0,0 +1,158 @@
+//! zstd dictionaries shared by groups of entries, e.g. one for JSON configs
+//! and one for shaders, which compress small similar entries far better than
+//! they compress on their own. Each dictionary is kept in a reserved stream
+//! and covers the names starting with one of its prefixes
+//! (the longest matching prefix wins)
+
+use crate::error::DepotError;
+use crate::helpers::{De, Ser};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use neoncore::streams::read::read_lpstr;
+use neoncore::streams::write::write_lpstr;
+use neoncore::streams::{Endianness, LPWidth, SeekRead, SeekWrite};
+use std::io::{Cursor, Error, ErrorKind};
+use std::sync::Arc;
+
+/// Prefix of the reserved streams holding dictionaries, followed by the tag
+pub const DICT_PREFIX: &str = ".depot/dict/";
+
+const DICT_VERSION: u32 = 1;
+
+/// A dictionary and the entry names it is used for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dictionary {
+    tag: String,
+    prefixes: Vec<String>,
+    data: Arc<[u8]>,
+}
+
+impl Dictionary {
+    /// A dictionary used for names starting with any of `prefixes`, `tag`
+    /// names it within the depot and may not contain `/`
+    pub fn new<S: Into<String>>(
+        tag: &str,
+        prefixes: impl IntoIterator<Item = S>,
+        data: Vec<u8>,
+    ) -> Result<Self, DepotError> {
+        if tag.is_empty() || tag.contains('/') {
+            return Err(DepotError::InvalidName {
+                name: tag.to_owned(),
+                reason: "is not a valid dictionary tag".to_owned(),
+            });
+        }
+        Ok(Self {
+            tag: tag.to_owned(),
+            prefixes: prefixes.into_iter().map(Into::into).collect(),
+            data: data.into(),
+        })
+    }
+
+    /// Trains a dictionary of at most `max_size` bytes on `samples`, zstd
+    /// needs a few dozen samples at least and suggests around 100 KiB
+    pub fn train<S: Into<String>, D: AsRef<[u8]>>(
+        tag: &str,
+        prefixes: impl IntoIterator<Item = S>,
+        samples: &[D],
+        max_size: usize,
+    ) -> Result<Self, DepotError> {
+        let data = zstd::dict::from_samples(samples, max_size).map_err(|e| {
+            Error::new(
+                e.kind(),
+                format!("could not train dictionary {}: {}", tag, e),
+            )
+        })?;
+        Self::new(tag, prefixes, data)
+    }
+
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    pub fn prefixes(&self) -> &[String] {
+        &self.prefixes
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub(crate) fn shared_data(&self) -> Arc<[u8]> {
+        self.data.clone()
+    }
+
+    /// Reserved stream the dictionary is kept in
+    pub fn stream_name(&self) -> String {
+        format!("{}{}", DICT_PREFIX, self.tag)
+    }
+
+    /// Length of the longest prefix `name` starts with
+    fn matches(&self, name: &str) -> Option<usize> {
+        self.prefixes
+            .iter()
+            .filter(|prefix| name.starts_with(prefix.as_str()))
+            .map(String::len)
+            .max()
+    }
+
+    /// Parses a dictionary read from its reserved stream
+    pub fn from_bytes(tag: &str, data: &[u8]) -> Result<Self, DepotError> {
+        let mut dict = Self::de(Cursor::new(data))?;
+        dict.tag = tag.to_owned();
+        Ok(dict)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Cursor::new(Vec::new());
+        // writing to memory can not fail
+        self.ser(&mut data).unwrap();
+        data.into_inner()
+    }
+}
+
+/// The dictionary with the longest prefix matching `name`
+pub(crate) fn select<'a>(dicts: &'a [Dictionary], name: &str) -> Option<&'a Dictionary> {
+    dicts
+        .iter()
+        .filter_map(|dict| Some((dict.matches(name)?, dict)))
+        .max_by_key(|(len, _)| *len)
+        .map(|(_, dict)| dict)
+}
+
+impl Ser for Dictionary {
+    fn ser<S: SeekWrite>(&self, mut output: S) -> Result<u64, Error> {
+        output.write_u32::<BigEndian>(DICT_VERSION)?;
+        output.write_u32::<BigEndian>(self.prefixes.len() as u32)?;
+        let mut written = 8;
+        for prefix in &self.prefixes {
+            written += write_lpstr(&mut output, LPWidth::LP32, Endianness::BigEndian, prefix)?;
+        }
+        output.write_all(&self.data)?;
+        Ok(written + self.data.len() as u64)
+    }
+}
+
+impl De for Dictionary {
+    fn de<D: SeekRead>(mut stream: D) -> Result<Self, Error>
+    where
+        Self: Sized,
+    {
+        let version = stream.read_u32::<BigEndian>()?;
+        if version != DICT_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported dictionary version {}", version),
+            ));
+        }
+        let count = stream.read_u32::<BigEndian>()?;
+        let prefixes = (0..count)
+            .map(|_| read_lpstr(&mut stream, LPWidth::LP32, Endianness::BigEndian))
+            .collect::<Result<_, Error>>()?;
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data)?;
+        Ok(Self {
+            tag: String::new(),
+            prefixes,
+            data: data.into(),
+        })
+    }
+}
@@ -0,0 +1,195 @@
+//! Directory index over entry names, baked into a reserved stream at
+//! finalize so listing a directory costs as much as it has children instead
+//! of a scan over every name below it, e.g. for readdir in file system layers
+
+use crate::depot_handle::EntryInfo;
+use crate::error::DepotError;
+use crate::helpers::{De, Ser};
+use crate::is_reserved_name;
+use crate::meta::EntryType;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use neoncore::streams::read::read_lpstr;
+use neoncore::streams::write::write_lpstr;
+use neoncore::streams::{Endianness, LPWidth, SeekRead, SeekWrite};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Cursor, Error, ErrorKind};
+
+/// Reserved stream holding the index
+pub const DIR_INDEX_STREAM: &str = ".depot/index/dirs";
+
+const DIR_INDEX_VERSION: u32 = 1;
+
+/// A direct child of a directory
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DirChild {
+    /// Last component of the child's path, without a trailing `/`
+    pub name: String,
+    /// true if the child is a directory entry or other entries lie below
+    /// it, which may also be an entry itself
+    pub is_dir: bool,
+}
+
+/// Children of every directory, keyed by the directory with a trailing `/`,
+/// the root is the empty string
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DirIndex {
+    dirs: HashMap<String, Vec<DirChild>>,
+}
+
+/// `dir` with a trailing `/` and no leading one, the root is the empty string
+pub(crate) fn normalize_dir(dir: &str) -> String {
+    let dir = dir.trim_start_matches('/');
+    if dir.is_empty() || dir.ends_with('/') {
+        dir.to_owned()
+    } else {
+        format!("{}/", dir)
+    }
+}
+
+/// The child of `dir` that `name` lies in, `None` unless `name` is below it
+pub(crate) fn child_of(dir: &str, name: &str) -> Option<DirChild> {
+    let rest = name.strip_prefix(dir)?;
+    match rest.split_once('/') {
+        Some((child, _)) if !child.is_empty() => Some(DirChild {
+            name: child.to_owned(),
+            is_dir: true,
+        }),
+        Some(_) => None,
+        None if !rest.is_empty() => Some(DirChild {
+            name: rest.to_owned(),
+            is_dir: false,
+        }),
+        None => None,
+    }
+}
+
+impl DirIndex {
+    /// Builds the index over `entries`, names in the reserved namespace are
+    /// left out
+    pub fn build<'a, I>(entries: I) -> Self
+    where
+        I: IntoIterator<Item = (&'a String, &'a EntryInfo)>,
+    {
+        let mut dirs: BTreeMap<String, BTreeMap<String, bool>> = BTreeMap::new();
+        dirs.insert(String::new(), BTreeMap::new());
+        for (name, entry) in entries
+            .into_iter()
+            .filter(|(name, _)| !is_reserved_name(name))
+        {
+            let mut dir = String::new();
+            let mut rest = name.as_str();
+            while let Some((child, tail)) = rest.split_once('/') {
+                dirs.entry(dir.clone())
+                    .or_default()
+                    .insert(child.to_owned(), true);
+                dir.push_str(child);
+                dir.push('/');
+                rest = tail;
+            }
+            // a name that is also the parent of others is listed as a dir
+            if !rest.is_empty() {
+                *dirs
+                    .entry(dir)
+                    .or_default()
+                    .entry(rest.to_owned())
+                    .or_insert(false) |= entry.entry_type() == EntryType::Dir;
+            }
+        }
+        Self {
+            dirs: dirs
+                .into_iter()
+                .map(|(dir, children)| {
+                    let children = children
+                        .into_iter()
+                        .map(|(name, is_dir)| DirChild { name, is_dir })
+                        .collect();
+                    (dir, children)
+                })
+                .collect(),
+        }
+    }
+
+    /// Children of `dir` in name order, `None` if no entry lies below it.
+    /// `dir` may be given with or without a trailing `/`
+    pub fn children(&self, dir: &str) -> Option<&[DirChild]> {
+        self.dirs.get(&normalize_dir(dir)).map(Vec::as_slice)
+    }
+
+    /// Number of directories, the root included
+    pub fn len(&self) -> usize {
+        self.dirs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dirs.is_empty()
+    }
+
+    /// Parses an index read from [`DIR_INDEX_STREAM`]
+    pub fn from_bytes(data: &[u8]) -> Result<Self, DepotError> {
+        Ok(Self::de(Cursor::new(data))?)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Cursor::new(Vec::new());
+        // writing to memory can not fail
+        self.ser(&mut data).unwrap();
+        data.into_inner()
+    }
+}
+
+impl Ser for DirIndex {
+    fn ser<S: SeekWrite>(&self, mut output: S) -> Result<u64, Error> {
+        output.write_u32::<BigEndian>(DIR_INDEX_VERSION)?;
+        output.write_u32::<BigEndian>(self.dirs.len() as u32)?;
+        let mut written = 8;
+        // sorted so the same names always bake the same bytes
+        let mut dirs: Vec<_> = self.dirs.iter().collect();
+        dirs.sort_by_key(|(dir, _)| *dir);
+        for (dir, children) in dirs {
+            written += write_lpstr(&mut output, LPWidth::LP32, Endianness::BigEndian, dir)?;
+            output.write_u32::<BigEndian>(children.len() as u32)?;
+            written += 4;
+            for child in children {
+                output.write_u8(child.is_dir as u8)?;
+                written += 1;
+                written += write_lpstr(
+                    &mut output,
+                    LPWidth::LP32,
+                    Endianness::BigEndian,
+                    &child.name,
+                )?;
+            }
+        }
+        Ok(written)
+    }
+}
+
+impl De for DirIndex {
+    fn de<D: SeekRead>(mut stream: D) -> Result<Self, Error>
+    where
+        Self: Sized,
+    {
+        let version = stream.read_u32::<BigEndian>()?;
+        if version != DIR_INDEX_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported directory index version {}", version),
+            ));
+        }
+        let count = stream.read_u32::<BigEndian>()?;
+        let mut dirs = HashMap::with_capacity(count as usize);
+        for _ in 0..count {
+            let dir = read_lpstr(&mut stream, LPWidth::LP32, Endianness::BigEndian)?;
+            let len = stream.read_u32::<BigEndian>()?;
+            let children = (0..len)
+                .map(|_| {
+                    let is_dir = stream.read_u8()? != 0;
+                    let name = read_lpstr(&mut stream, LPWidth::LP32, Endianness::BigEndian)?;
+                    Ok(DirChild { name, is_dir })
+                })
+                .collect::<Result<_, Error>>()?;
+            dirs.insert(dir, children);
+        }
+        Ok(Self { dirs })
+    }
+}
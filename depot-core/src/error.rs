@@ -0,0 +1,387 @@
+//! The error type of the library, failures callers commonly react to have
+//! their own variant, everything else is carried as an [`std::io::Error`]
+
+use std::fmt::{Display, Formatter};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum DepotError {
+    /// Reading or writing the underlying stream or a file failed
+    Io(std::io::Error),
+    /// The stream does not start with the depot magic
+    BadMagic,
+    /// No entry of that name exists
+    EntryNotFound(String),
+    /// An entry decompressed to a different size than recorded
+    SizeMismatch {
+        name: String,
+        expected: u64,
+        actual: u64,
+    },
+    /// An entry decompressed to data with a different hash than recorded
+    HashMismatch {
+        name: String,
+        expected: u64,
+        actual: u64,
+    },
+    /// The depot was opened for reading only
+    ReadOnly,
+    /// The name lies in the namespace reserved for the library
+    ReservedName(String),
+    /// The depot was written in a format version this library can not read,
+    /// versions 1 to `supported` are readable
+    UnsupportedVersion { found: u16, supported: u16 },
+    /// The toc could not be read from the entry at index `at_entry` on,
+    /// e.g. because a length field was damaged
+    CorruptToc { at_entry: u64, reason: String },
+    /// The entry was refused by the content policy, see [`crate::policy`]
+    PolicyViolation { name: String, reason: String },
+    /// The depot was frozen and can not be opened for writing, see
+    /// [`crate::freeze`]
+    Frozen,
+    /// The depot no longer matches the hash it was frozen with, both as hex
+    FreezeMismatch { expected: String, actual: String },
+    /// The file to add does not exist
+    SourceNotFound(PathBuf),
+    /// The path can not be added as asked, e.g. a directory given as a file
+    InvalidSource { path: PathBuf, reason: String },
+    /// A source was modified while it was being added, see
+    /// [`crate::depot_handle::ChangePolicy`]
+    SourceModified(String),
+    /// A source read to a different size than announced for it, see
+    /// [`crate::depot_handle::ChangePolicy`]
+    SourceResized {
+        name: String,
+        expected: u64,
+        actual: u64,
+    },
+    /// An entry grew when compressed and the ratio guard refused it, see
+    /// [`crate::depot_handle::RatioGuard`]
+    Expanded {
+        name: String,
+        size: u64,
+        stored: u64,
+    },
+    /// A dictionary with the tag already exists, see [`crate::dict`]
+    DictionaryExists(String),
+    /// The entry was compressed with a dictionary the depot lacks
+    MissingDictionary { name: String, tag: String },
+    /// Less stored data was found for an entry than recorded
+    Truncated {
+        name: String,
+        expected: u64,
+        actual: u64,
+    },
+    /// The recorded layout of an entry is damaged, e.g. it lies past the
+    /// end of its solid block
+    CorruptEntry { name: String, reason: String },
+    /// The entry is encrypted and the handle has no key, see [`crate::crypt`]
+    NoKey(String),
+    /// The key is not in one of the accepted formats, see
+    /// [`crate::crypt::EncryptionKey::from_key_file`]
+    InvalidKey,
+    /// Encrypted data did not open, the key is wrong or the data damaged
+    DecryptFailed,
+    /// A name pattern does not parse
+    InvalidPattern { pattern: String, reason: String },
+    /// The name can not be stored, e.g. it is too long
+    InvalidName { name: String, reason: String },
+    /// A caller provided buffer can not hold the entry
+    BufferTooSmall {
+        name: String,
+        size: u64,
+        capacity: usize,
+    },
+    /// An offset lies past the end of the entry
+    OutOfRange {
+        name: String,
+        offset: u64,
+        size: u64,
+    },
+    /// The entry is larger than the caller allowed
+    TooLarge { name: String, size: u64, limit: u64 },
+    /// A value could not be serialized into an entry
+    Serialize { name: String, reason: String },
+    /// The contents of an entry do not parse as the requested format
+    Deserialize { name: String, reason: String },
+    /// A part of a split depot does not follow the one before, it belongs
+    /// to another depot or was damaged, `index` counts from 0. See
+    /// [`crate::volume`]
+    BadPart { path: PathBuf, index: usize },
+    /// A stored or remapped name would be extracted outside of the output
+    /// directory, see [`crate::extract`]
+    UnsafePath { name: String, path: PathBuf },
+    /// The stream lies outside of the scoped view, see [`crate::scope`]
+    OutOfScope { name: String, scope: String },
+    /// The temp policy forbids the scratch file the operation needs, see
+    /// [`crate::temp::TempPolicy`]
+    TempForbidden(String),
+    /// The operation is not supported for this depot or entry, e.g. carving
+    /// a member of a solid block
+    Unsupported(String),
+    /// An argument is invalid in a way no other variant describes
+    InvalidInput(String),
+    /// The operation was cancelled by its caller before it finished
+    Cancelled,
+}
+
+impl DepotError {
+    /// The closest [`ErrorKind`], for callers treating all errors alike
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            DepotError::Io(e) => e.kind(),
+            DepotError::BadMagic
+            | DepotError::SizeMismatch { .. }
+            | DepotError::HashMismatch { .. }
+            | DepotError::CorruptToc { .. }
+            | DepotError::FreezeMismatch { .. }
+            | DepotError::SourceModified(_)
+            | DepotError::SourceResized { .. }
+            | DepotError::Expanded { .. }
+            | DepotError::MissingDictionary { .. }
+            | DepotError::CorruptEntry { .. }
+            | DepotError::DecryptFailed
+            | DepotError::TooLarge { .. }
+            | DepotError::Deserialize { .. }
+            | DepotError::BadPart { .. } => ErrorKind::InvalidData,
+            DepotError::Truncated { .. } => ErrorKind::UnexpectedEof,
+            DepotError::EntryNotFound(_) | DepotError::SourceNotFound(_) => ErrorKind::NotFound,
+            DepotError::ReadOnly
+            | DepotError::Frozen
+            | DepotError::NoKey(_)
+            | DepotError::OutOfScope { .. }
+            | DepotError::TempForbidden(_) => ErrorKind::PermissionDenied,
+            DepotError::ReservedName(_)
+            | DepotError::PolicyViolation { .. }
+            | DepotError::InvalidSource { .. }
+            | DepotError::InvalidKey
+            | DepotError::InvalidPattern { .. }
+            | DepotError::InvalidName { .. }
+            | DepotError::BufferTooSmall { .. }
+            | DepotError::OutOfRange { .. }
+            | DepotError::Serialize { .. }
+            | DepotError::UnsafePath { .. }
+            | DepotError::InvalidInput(_) => ErrorKind::InvalidInput,
+            DepotError::DictionaryExists(_) => ErrorKind::AlreadyExists,
+            DepotError::UnsupportedVersion { .. } | DepotError::Unsupported(_) => {
+                ErrorKind::Unsupported
+            }
+            DepotError::Cancelled => ErrorKind::Other,
+        }
+    }
+
+    /// true for errors caused by damaged data rather than the environment
+    /// or the caller, other io errors of [`ErrorKind::InvalidData`] count
+    /// as damage too, e.g. a zstd frame that does not decode
+    pub fn is_corruption(&self) -> bool {
+        match self {
+            DepotError::Io(e) => e.kind() == ErrorKind::InvalidData,
+            DepotError::BadMagic
+            | DepotError::SizeMismatch { .. }
+            | DepotError::HashMismatch { .. }
+            | DepotError::CorruptToc { .. }
+            | DepotError::FreezeMismatch { .. }
+            | DepotError::MissingDictionary { .. }
+            | DepotError::Truncated { .. }
+            | DepotError::CorruptEntry { .. }
+            | DepotError::DecryptFailed
+            | DepotError::BadPart { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+impl Display for DepotError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DepotError::Io(e) => write!(f, "{}", e),
+            DepotError::BadMagic => write!(f, "invalid magic number in depot header"),
+            DepotError::EntryNotFound(name) => write!(f, "{} is not in the depot", name),
+            DepotError::SizeMismatch {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "uncompressed size mismatch for {}, expect: {}, actual: {}",
+                name, expected, actual
+            ),
+            DepotError::HashMismatch {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "hash mismatch for {}, expect: {}, actual: {}",
+                name, expected, actual
+            ),
+            DepotError::ReadOnly => write!(f, "depot was opened read-only"),
+            DepotError::ReservedName(name) => write!(
+                f,
+                "{} lies in the reserved {} namespace",
+                name,
+                crate::RESERVED_PREFIX
+            ),
+            DepotError::UnsupportedVersion { found, supported } if found > supported => write!(
+                f,
+                "depot format version {} is newer than the supported versions 1 to {}, \
+                 it was written by a newer release, upgrade to read it",
+                found, supported
+            ),
+            DepotError::UnsupportedVersion { found, supported } => write!(
+                f,
+                "depot format version {} is not one of the supported versions 1 to {}, \
+                 the header is likely damaged",
+                found, supported
+            ),
+            DepotError::CorruptToc { at_entry, reason } => {
+                write!(f, "the toc is corrupt at entry {}: {}", at_entry, reason)
+            }
+            DepotError::PolicyViolation { name, reason } => {
+                write!(f, "{} breaks the content policy, {}", name, reason)
+            }
+            DepotError::Frozen => write!(f, "depot is frozen and can not be changed"),
+            DepotError::FreezeMismatch { expected, actual } => write!(
+                f,
+                "hash mismatch for the frozen depot, expect: {}, actual: {}",
+                expected, actual
+            ),
+            DepotError::SourceNotFound(path) => {
+                write!(f, "file {} does not exist", path.display())
+            }
+            DepotError::InvalidSource { path, reason } => {
+                write!(f, "{} {}", path.display(), reason)
+            }
+            DepotError::SourceModified(name) => {
+                write!(f, "{} was modified while it was being added", name)
+            }
+            DepotError::SourceResized {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} changed size while it was being added, expected {} bytes but read {}",
+                name, expected, actual
+            ),
+            DepotError::Expanded { name, size, stored } => write!(
+                f,
+                "{} grew from {} to {} bytes when compressed",
+                name, size, stored
+            ),
+            DepotError::DictionaryExists(tag) => {
+                write!(f, "the depot already has a dictionary {}", tag)
+            }
+            DepotError::MissingDictionary { name, tag } => {
+                write!(f, "{} needs the missing dictionary {}", name, tag)
+            }
+            DepotError::Truncated {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} is truncated, expected {} stored bytes but found {}",
+                name, expected, actual
+            ),
+            DepotError::CorruptEntry { name, reason } => write!(f, "{} {}", name, reason),
+            DepotError::NoKey(name) => write!(f, "{} is encrypted and no key is set", name),
+            DepotError::InvalidKey => write!(
+                f,
+                "a key is {} raw bytes or {} hex digits",
+                crate::crypt::KEY_LEN,
+                crate::crypt::KEY_LEN * 2
+            ),
+            DepotError::DecryptFailed => write!(f, "could not decrypt, wrong key or damaged data"),
+            DepotError::InvalidPattern { pattern, reason } => {
+                write!(f, "invalid pattern {}: {}", pattern, reason)
+            }
+            DepotError::InvalidName { name, reason } => write!(f, "{} {}", name, reason),
+            DepotError::BufferTooSmall {
+                name,
+                size,
+                capacity,
+            } => write!(
+                f,
+                "buffer of {} bytes is too small for {} of {} bytes",
+                capacity, name, size
+            ),
+            DepotError::OutOfRange { name, offset, size } => write!(
+                f,
+                "offset {} is past the end of {} of {} bytes",
+                offset, name, size
+            ),
+            DepotError::TooLarge { name, size, limit } => write!(
+                f,
+                "{} is {} bytes, more than the limit of {} bytes",
+                name, size, limit
+            ),
+            DepotError::Serialize { name, reason } => {
+                write!(f, "could not serialize {}: {}", name, reason)
+            }
+            DepotError::Deserialize { name, reason } => {
+                write!(f, "stream {} is not valid {}", name, reason)
+            }
+            DepotError::BadPart { path, index } => {
+                write!(
+                    f,
+                    "{} is not part {} of the split depot",
+                    path.display(),
+                    index + 1
+                )
+            }
+            DepotError::UnsafePath { name, path } => write!(
+                f,
+                "refusing to extract {} to {}, path escapes the output directory",
+                name,
+                path.display()
+            ),
+            DepotError::OutOfScope { name, scope } => {
+                write!(f, "{} is outside of the scope {}", name, scope)
+            }
+            DepotError::TempForbidden(what) => write!(
+                f,
+                "{} needs scratch space but the temp policy forbids it",
+                what
+            ),
+            DepotError::Unsupported(what) | DepotError::InvalidInput(what) => {
+                write!(f, "{}", what)
+            }
+            DepotError::Cancelled => write!(f, "operation cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for DepotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DepotError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Errors that passed through an [`std::io::Error`], e.g. from a reader,
+/// are unwrapped to their original variant
+impl From<std::io::Error> for DepotError {
+    fn from(e: std::io::Error) -> Self {
+        if !e.get_ref().is_some_and(|inner| inner.is::<DepotError>()) {
+            return DepotError::Io(e);
+        }
+        match e.into_inner().map(|inner| inner.downcast::<DepotError>()) {
+            Some(Ok(inner)) => *inner,
+            _ => unreachable!("checked to wrap a DepotError"),
+        }
+    }
+}
+
+impl From<DepotError> for std::io::Error {
+    fn from(e: DepotError) -> Self {
+        match e {
+            DepotError::Io(e) => e,
+            e => std::io::Error::new(e.kind(), e),
+        }
+    }
+}
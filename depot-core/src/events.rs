@@ -0,0 +1,83 @@
+//! Progress events reported by a handle for every entry it adds, extracts or
+//! verifies, so frontends can render progress without wiring a callback into
+//! each call, see [`crate::depot_handle::DepotHandle::set_events`]
+
+use crate::error::DepotError;
+use std::io::{Seek, SeekFrom, Write};
+
+/// What a handle was doing with the entry an event is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Add,
+    Extract,
+    Verify,
+}
+
+/// Receives the progress of a handle. Entries worked on in parallel report
+/// from worker threads and their events interleave, but every entry starts
+/// before it progresses and finishes last. Every method defaults to doing
+/// nothing
+pub trait DepotEvents: Send + Sync {
+    /// Work on `name` started, `size` is its uncompressed size or 0 when it
+    /// is not known upfront
+    fn entry_started(&self, _operation: Operation, _name: &str, _size: u64) {}
+
+    /// `done` uncompressed bytes of `name` were processed out of `total`,
+    /// 0 when unknown. Small entries may finish without reporting progress
+    fn entry_progress(&self, _operation: Operation, _name: &str, _done: u64, _total: u64) {}
+
+    /// Work on `name` ended, with the error that stopped it if any
+    fn entry_finished(&self, _operation: Operation, _name: &str, _error: Option<&DepotError>) {}
+
+    /// The toc of `entries` entries was written, `size` bytes long
+    fn toc_written(&self, _entries: u64, _size: u64) {}
+}
+
+/// Reports the bytes written through it as progress of an entry
+pub(crate) struct ProgressWriter<'a, W> {
+    inner: W,
+    events: &'a dyn DepotEvents,
+    operation: Operation,
+    name: &'a str,
+    total: u64,
+    done: u64,
+}
+
+impl<'a, W> ProgressWriter<'a, W> {
+    pub(crate) fn new(
+        inner: W,
+        events: &'a dyn DepotEvents,
+        operation: Operation,
+        name: &'a str,
+        total: u64,
+    ) -> Self {
+        Self {
+            inner,
+            events,
+            operation,
+            name,
+            total,
+            done: 0,
+        }
+    }
+}
+
+impl<W: Write> Write for ProgressWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.done += n as u64;
+        self.events
+            .entry_progress(self.operation, self.name, self.done, self.total);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Seek> Seek for ProgressWriter<'_, W> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
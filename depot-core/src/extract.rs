@@ -0,0 +1,442 @@
+use crate::depot_handle::StreamInfo;
+use crate::error::DepotError;
+use crate::meta::{EntryType, ExtMeta};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::hash::Hasher;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+/// Maps a stream name to the path it is extracted to, relative to the output
+/// directory, returning `None` skips the stream
+pub type PathMapper = dyn Fn(&str) -> Option<PathBuf> + Send + Sync;
+
+/// Called after each stream is written with the path it was written to
+pub type ExtractedFn<'a> = dyn FnMut(&StreamInfo, &Path) + 'a;
+
+/// Options for extracting streams to a directory
+#[derive(Default)]
+pub struct ExtractOptions {
+    /// Remaps stream names to output paths, when unset streams are written
+    /// to their stored name
+    pub path_mapper: Option<Box<PathMapper>>,
+    /// Restore the owner recorded for each entry, tar does this by default
+    /// when running as root
+    pub restore_owner: bool,
+    /// Ignore recorded user and group names, restoring the numeric ids as stored
+    pub numeric_owner: bool,
+    /// Restore the permission bits recorded for each entry, see
+    /// [`ExtractOptions::resolve_mode`]
+    pub restore_mode: bool,
+    /// Owner forced onto every extracted file, takes precedence over the
+    /// recorded owner and is applied even when `restore_owner` is unset
+    pub owner: Option<Owner>,
+    /// Remaps the recorded numeric ids, a recorded name known on this host
+    /// still takes precedence unless `numeric_owner` is set
+    pub id_map: IdMap,
+    /// Threads writing extracted files, entries are still read one after
+    /// the other in archive order. 0 or 1 writes on the calling thread
+    pub threads: usize,
+    /// Reserve the full size of each output file before writing it
+    pub preallocate: bool,
+    /// Only resolve the output paths and report them as extracted, nothing
+    /// is read or written
+    pub dry_run: bool,
+    /// Rewrites the output paths of
+    /// [`crate::depot_handle::DepotHandle::extract_all`] to fit the target
+    /// filesystem, [`DefaultNameMapper`] when unset
+    pub name_mapper: Option<Box<dyn EntryNameMapper>>,
+    /// Called around every file written, see [`ExtractHooks`]
+    pub hooks: Option<Box<dyn ExtractHooks>>,
+}
+
+impl ExtractOptions {
+    /// Writes the file of `stream` at `path` through `write`, calling the
+    /// hooks around it. `writer` is flushed and dropped before
+    /// [`ExtractHooks::on_entry_done`]
+    pub(crate) fn write_hooked<'w, F>(
+        &self,
+        stream: &StreamInfo,
+        path: &Path,
+        writer: Box<dyn Write + 'w>,
+        write: F,
+    ) -> Result<(), DepotError>
+    where
+        F: FnOnce(&mut dyn Write) -> Result<(), DepotError>,
+    {
+        let write_all = |mut writer: Box<dyn Write + 'w>| {
+            write(&mut writer)?;
+            Ok(writer.flush()?)
+        };
+        let Some(hooks) = &self.hooks else {
+            return write_all(writer);
+        };
+        hooks.on_entry_start(stream, path)?;
+        let written = hooks
+            .transform_writer(stream, path, writer)
+            .and_then(write_all);
+        let done = hooks.on_entry_done(stream, path, written.as_ref().err());
+        written.and(done)
+    }
+
+    pub fn set_path_mapper<F>(&mut self, mapper: F)
+    where
+        F: Fn(&str) -> Option<PathBuf> + Send + Sync + 'static,
+    {
+        self.path_mapper = Some(Box::new(mapper));
+    }
+
+    /// Resolves the relative output path for a stream name, `Ok(None)` means
+    /// the mapper asked for the stream to be skipped
+    pub fn map_path(&self, name: &str) -> Result<Option<PathBuf>, DepotError> {
+        let path = match &self.path_mapper {
+            Some(mapper) => match mapper(name) {
+                Some(p) => p,
+                None => return Ok(None),
+            },
+            None => PathBuf::from(name),
+        };
+
+        check_contained(name, &path)?;
+        if path.as_os_str().is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(path))
+    }
+}
+
+/// Never lets a stored or remapped name escape the output directory
+pub(crate) fn check_contained(name: &str, path: &Path) -> Result<(), DepotError> {
+    for component in path.components() {
+        match component {
+            Component::Normal(_) | Component::CurDir => {}
+            _ => {
+                return Err(DepotError::UnsafePath {
+                    name: name.to_owned(),
+                    path: path.to_owned(),
+                })
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Rewrites the output paths of a whole extraction so none of them fails
+/// on the target filesystem, e.g. because a name is too long or two names
+/// only differ in case
+pub trait EntryNameMapper: Send + Sync {
+    /// Gets every stream with the relative path it would be extracted to,
+    /// in name order, and returns the path to use for each of them. The
+    /// same input has to give the same paths
+    fn map_names(&self, streams: &[(&StreamInfo, PathBuf)]) -> Vec<PathBuf>;
+}
+
+/// Plugin points around every file an extraction writes, e.g. to unpack
+/// nested archives on the fly, scan files or collect telemetry. Symlinks
+/// and directories are created without calling them. With
+/// [`ExtractOptions::threads`] above 1 the hooks may be called from the
+/// writer threads, but for one file always from the same thread
+pub trait ExtractHooks: Send + Sync {
+    /// Called before the file of `stream` is written to `path`, an error
+    /// aborts the extraction
+    fn on_entry_start(&self, _stream: &StreamInfo, _path: &Path) -> Result<(), DepotError> {
+        Ok(())
+    }
+
+    /// Wraps the writer the decompressed data is written through, the
+    /// returned writer is flushed and dropped once all data went in, so
+    /// writers that have to finish e.g. a trailer do so when dropped
+    fn transform_writer<'w>(
+        &self,
+        _stream: &StreamInfo,
+        _path: &Path,
+        writer: Box<dyn Write + 'w>,
+    ) -> Result<Box<dyn Write + 'w>, DepotError> {
+        Ok(writer)
+    }
+
+    /// Called once the file was written or failed with `error`, an error
+    /// returned for a written file aborts the extraction, the file is left
+    /// in place
+    fn on_entry_done(
+        &self,
+        _stream: &StreamInfo,
+        _path: &Path,
+        _error: Option<&DepotError>,
+    ) -> Result<(), DepotError> {
+        Ok(())
+    }
+}
+
+/// Longest file name most filesystems accept, in bytes
+pub const NAME_MAX: usize = 255;
+
+/// Cuts path components longer than `max_component_len`, ending them in a
+/// hash of the full component instead, and renames paths taken by an
+/// earlier stream to `name~1.ext`, `name~2.ext` and so on. A directory
+/// renamed this way takes the streams below it along
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DefaultNameMapper {
+    /// Longest path component in bytes
+    pub max_component_len: usize,
+    /// Paths only differing in case collide, as on Windows and macOS
+    pub case_insensitive: bool,
+}
+
+impl Default for DefaultNameMapper {
+    fn default() -> Self {
+        Self {
+            max_component_len: NAME_MAX,
+            case_insensitive: cfg!(any(windows, target_os = "macos")),
+        }
+    }
+}
+
+impl DefaultNameMapper {
+    /// The key two paths colliding on the target share
+    fn key(&self, path: &Path) -> String {
+        let path = path.to_string_lossy();
+        match self.case_insensitive {
+            true => path.to_lowercase(),
+            false => path.into_owned(),
+        }
+    }
+
+    /// `component` as is, or cut and ended in a hash of it when too long
+    fn shorten(&self, component: &OsStr) -> OsString {
+        match component.len() <= self.max_component_len {
+            true => component.to_owned(),
+            false => self.fit(component, &format!("~{:016x}", hash(component))),
+        }
+    }
+
+    /// `component` with `tag` inserted before its extension, cut to fit
+    /// the maximum length
+    fn fit(&self, component: &OsStr, tag: &str) -> OsString {
+        let component = component.to_string_lossy();
+        let (stem, ext) = match component.rfind('.') {
+            // only short extensions are worth keeping
+            Some(dot) if dot > 0 && component.len() - dot <= 16 => component.split_at(dot),
+            _ => (component.as_ref(), ""),
+        };
+        let ext = match tag.len() + ext.len() <= self.max_component_len {
+            true => ext,
+            false => "",
+        };
+        let mut keep = self
+            .max_component_len
+            .saturating_sub(tag.len() + ext.len())
+            .min(stem.len());
+        while !stem.is_char_boundary(keep) {
+            keep -= 1;
+        }
+        format!("{}{}{}", &stem[..keep], tag, ext).into()
+    }
+
+    /// Renames the last component of `path` until it is free in `taken`
+    fn unique(&self, path: &Path, taken: &HashMap<String, (PathBuf, bool)>) -> PathBuf {
+        let name = path.file_name().unwrap_or_default();
+        (1..)
+            .map(|n| path.with_file_name(self.fit(name, &format!("~{}", n))))
+            .find(|candidate| !taken.contains_key(&self.key(candidate)))
+            .expect("some suffix is free")
+    }
+}
+
+impl EntryNameMapper for DefaultNameMapper {
+    fn map_names(&self, streams: &[(&StreamInfo, PathBuf)]) -> Vec<PathBuf> {
+        // every path handed out so far, and whether it is a directory
+        let mut taken: HashMap<String, (PathBuf, bool)> = HashMap::new();
+        // directories that had to be renamed, so later streams follow them
+        let mut moved: HashMap<String, PathBuf> = HashMap::new();
+        let mut paths = Vec::with_capacity(streams.len());
+        for (stream, path) in streams {
+            let components: Vec<_> = path.components().collect();
+            let mut out = PathBuf::new();
+            for (i, component) in components.iter().enumerate() {
+                let is_dir = i + 1 < components.len() || stream.einf.entry_type() == EntryType::Dir;
+                out.push(self.shorten(component.as_os_str()));
+                let key = self.key(&out);
+                if let Some(dir) = moved.get(&key).filter(|_| is_dir) {
+                    out = dir.clone();
+                    continue;
+                }
+                match taken.get(&key) {
+                    None => {
+                        taken.insert(key, (out.clone(), is_dir));
+                    }
+                    // the directory exists already, in its first spelling
+                    Some((existing, true)) if is_dir => out = existing.clone(),
+                    Some(_) => {
+                        let renamed = self.unique(&out, &taken);
+                        taken.insert(self.key(&renamed), (renamed.clone(), is_dir));
+                        if is_dir {
+                            moved.insert(key, renamed.clone());
+                        }
+                        out = renamed;
+                    }
+                }
+            }
+            paths.push(out);
+        }
+        paths
+    }
+}
+
+fn hash(data: &OsStr) -> u64 {
+    let mut hasher = seahash::SeaHasher::new();
+    hasher.write(data.as_encoded_bytes());
+    hasher.finish()
+}
+
+impl ExtractOptions {
+    /// Resolves the owner an extracted entry should be given, `None` leaves
+    /// the owner of the created file untouched
+    pub fn resolve_owner(&self, meta: &ExtMeta) -> Option<Owner> {
+        if let Some(owner) = self.owner {
+            return Some(owner);
+        }
+        if !self.restore_owner {
+            return None;
+        }
+
+        let mut uid = meta
+            .uid()
+            .map(|uid| *self.id_map.uids.get(&uid).unwrap_or(&uid));
+        let mut gid = meta
+            .gid()
+            .map(|gid| *self.id_map.gids.get(&gid).unwrap_or(&gid));
+        #[cfg(unix)]
+        if !self.numeric_owner {
+            // names take precedence so archives move between hosts with
+            // different id assignments, like tar
+            uid = meta.uname().and_then(crate::helpers::lookup_uid).or(uid);
+            gid = meta.gname().and_then(crate::helpers::lookup_gid).or(gid);
+        }
+
+        if uid.is_none() && gid.is_none() {
+            return None;
+        }
+        Some(Owner { uid, gid })
+    }
+
+    /// Resolves the permission bits an extracted entry should be given,
+    /// `None` leaves those of the created file. setuid is only kept when
+    /// the user is restored as well and setgid when the group is, like tar
+    pub fn resolve_mode(&self, meta: &ExtMeta) -> Option<u32> {
+        if !self.restore_mode {
+            return None;
+        }
+        let mut mode = meta.mode()?;
+        let owner = self.resolve_owner(meta);
+        if owner.is_none_or(|owner| owner.uid.is_none()) {
+            mode &= !0o4000;
+        }
+        if owner.is_none_or(|owner| owner.gid.is_none()) {
+            mode &= !0o2000;
+        }
+        Some(mode)
+    }
+}
+
+/// A numeric owner and group, `None` leaves that side of the file as is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Owner {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl FromStr for Owner {
+    type Err = DepotError;
+
+    /// Parses `user:group`, each side either a numeric id or a name
+    /// resolved through the system databases
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            |what: &str| DepotError::InvalidInput(format!("invalid owner `{}`: {}", s, what));
+        let (user, group) = s
+            .split_once(':')
+            .ok_or_else(|| invalid("expected user:group"))?;
+
+        let uid = match user.parse::<u32>() {
+            Ok(id) => id,
+            #[cfg(unix)]
+            Err(_) => crate::helpers::lookup_uid(user).ok_or_else(|| invalid("unknown user"))?,
+            #[cfg(not(unix))]
+            Err(_) => return Err(invalid("user names are only supported on unix")),
+        };
+        let gid = match group.parse::<u32>() {
+            Ok(id) => id,
+            #[cfg(unix)]
+            Err(_) => crate::helpers::lookup_gid(group).ok_or_else(|| invalid("unknown group"))?,
+            #[cfg(not(unix))]
+            Err(_) => return Err(invalid("group names are only supported on unix")),
+        };
+
+        Ok(Owner {
+            uid: Some(uid),
+            gid: Some(gid),
+        })
+    }
+}
+
+/// Tables remapping recorded user and group ids on extraction
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IdMap {
+    pub uids: BTreeMap<u32, u32>,
+    pub gids: BTreeMap<u32, u32>,
+}
+
+impl IdMap {
+    /// Parses a remapping table, one `uid OLD NEW` or `gid OLD NEW` mapping
+    /// per line, blank lines and lines starting with `#` are ignored
+    pub fn from_table(table: &str) -> Result<Self, DepotError> {
+        let mut map = IdMap::default();
+        for (lineno, line) in table.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || {
+                DepotError::InvalidInput(format!(
+                    "invalid id mapping on line {}: `{}`",
+                    lineno + 1,
+                    line
+                ))
+            };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 3 {
+                return Err(invalid());
+            }
+            let old = fields[1].parse::<u32>().map_err(|_| invalid())?;
+            let new = fields[2].parse::<u32>().map_err(|_| invalid())?;
+            match fields[0] {
+                "uid" => map.uids.insert(old, new),
+                "gid" => map.gids.insert(old, new),
+                _ => return Err(invalid()),
+            };
+        }
+        Ok(map)
+    }
+}
+
+/// true when the process runs with an effective uid of 0, used to pick
+/// tar's default of restoring ownership only as root
+pub fn is_root() -> bool {
+    #[cfg(unix)]
+    return unsafe { libc::geteuid() } == 0;
+    #[cfg(not(unix))]
+    return false;
+}
+
+/// Removes the first `n` components of a stream name, like tar's
+/// `--strip-components`, names with `n` or fewer components yield `None`
+pub fn strip_components(name: &str, n: usize) -> Option<PathBuf> {
+    let stripped: PathBuf = Path::new(name).components().skip(n).collect();
+    if stripped.as_os_str().is_empty() {
+        return None;
+    }
+    Some(stripped)
+}
@@ -0,0 +1,94 @@
+//! Fingerprints telling depots apart by what they hold, e.g. for a launcher
+//! to check whether the installed depot is the one a release ships. The
+//! fingerprint is a SHA-256 over the format version, the depot metadata and
+//! the names, sizes, content hashes and metadata of the entries, all taken
+//! from the toc, so no entry data is read. How the data is stored does not
+//! enter it, a depot compacted or repacked into the same format keeps its
+//! fingerprint, see [`crate::depot_handle::DepotHandle::archive_fingerprint`]
+
+use crate::creator::CREATOR_STREAM;
+use crate::depot_handle::DepotToc;
+use crate::dict::DICT_PREFIX;
+use crate::dir_index::DIR_INDEX_STREAM;
+use crate::meta::{keys, ExtMeta};
+use crate::phf::PHF_STREAM;
+use sha2::{Digest, Sha256};
+use std::fmt::{Display, Formatter};
+
+/// Entry metadata keys left out, they describe how the data is stored or
+/// when the source was created rather than what the entry holds
+const SKIPPED_KEYS: &[&str] = &[
+    keys::ID,
+    keys::BTIME,
+    keys::FRAMES,
+    keys::DICT,
+    keys::NONCE,
+    keys::BLOCK,
+    keys::BLOCK_OFFSET,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fingerprint(pub [u8; 32]);
+
+impl Fingerprint {
+    /// The digest as lower case hex digits
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Parses a fingerprint written by [`Fingerprint::to_hex`], case is
+    /// ignored
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        if hex.len() != 64 || !hex.is_ascii() {
+            return None;
+        }
+        let mut digest = [0; 32];
+        for (i, byte) in digest.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+        }
+        Some(Self(digest))
+    }
+
+    /// Fingerprint of a depot of format `version` with the toc `toc`. The
+    /// streams rewritten whenever a depot is closed and the dictionaries
+    /// are left out
+    pub(crate) fn of_toc(version: u16, toc: &DepotToc) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(version.to_be_bytes());
+        hash_meta(&mut hasher, &toc.meta, &[]);
+        let entries = toc.entries.iter().filter(|(name, _)| {
+            ![PHF_STREAM, CREATOR_STREAM, DIR_INDEX_STREAM].contains(&name.as_str())
+                && !name.starts_with(DICT_PREFIX)
+        });
+        for (name, entry) in entries {
+            hash_bytes(&mut hasher, name.as_bytes());
+            hasher.update(entry.size.to_be_bytes());
+            hasher.update(entry.hash.to_be_bytes());
+            hash_meta(&mut hasher, &entry.ext, SKIPPED_KEYS);
+        }
+        Self(hasher.finalize().into())
+    }
+}
+
+impl Display for Fingerprint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+fn hash_bytes(hasher: &mut Sha256, bytes: &[u8]) {
+    hasher.update((bytes.len() as u32).to_be_bytes());
+    hasher.update(bytes);
+}
+
+fn hash_meta(hasher: &mut Sha256, meta: &ExtMeta, skipped: &[&str]) {
+    let attrs: Vec<_> = meta
+        .iter()
+        .filter(|(key, _)| !skipped.contains(&key.as_str()))
+        .collect();
+    hasher.update((attrs.len() as u32).to_be_bytes());
+    for (key, value) in attrs {
+        hash_bytes(hasher, key.as_bytes());
+        hash_bytes(hasher, value);
+    }
+}
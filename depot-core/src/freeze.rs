@@ -0,0 +1,86 @@
+//! Freezing, sealing a finished depot so it is not changed by accident. A
+//! frozen depot ends in a trailer after its toc holding the SHA-256 of
+//! everything before it and the immutable flag, handles refuse to open it
+//! for writing until it was unfrozen, see
+//! [`crate::depot_handle::DepotHandle::freeze`]
+
+use crate::SeekRead;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use neoncore::const_fn::ascii_to_u64_be;
+use sha2::{Digest, Sha256};
+use std::io::{SeekFrom, Write};
+
+/// cbindgen:ignore
+pub const FREEZE_MAGIC: u64 = ascii_to_u64_be(b"DEPOTFRZ");
+
+/// The trailer of a frozen depot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FreezeTrailer {
+    pub flags: u64,
+    /// SHA-256 of the depot from its header up to the trailer
+    pub hash: [u8; 32],
+    /// where the trailer starts, the end of the sealed bytes. Not stored
+    pub offset: u64,
+}
+
+impl FreezeTrailer {
+    pub const SIZE: u64 = 48;
+    /// handles refuse to open the depot for writing
+    pub const FLAG_IMMUTABLE: u64 = 1;
+
+    pub fn is_immutable(&self) -> bool {
+        self.flags & Self::FLAG_IMMUTABLE != 0
+    }
+
+    /// The hash as lower case hex digits
+    pub fn hash_hex(&self) -> String {
+        self.hash.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub(crate) fn ser<W: Write>(&self, mut stream: W) -> std::io::Result<()> {
+        stream.write_u64::<BigEndian>(FREEZE_MAGIC)?;
+        stream.write_u64::<BigEndian>(self.flags)?;
+        stream.write_all(&self.hash)
+    }
+
+    /// Reads the trailer following the toc that ends at the current
+    /// position, depots that were not frozen end with their toc
+    pub(crate) fn read_after<S: SeekRead>(mut stream: S) -> std::io::Result<Option<Self>> {
+        let offset = stream.stream_position()?;
+        let end = stream.seek(SeekFrom::End(0))?;
+        if end - offset != Self::SIZE {
+            return Ok(None);
+        }
+        stream.seek(SeekFrom::Start(offset))?;
+        if stream.read_u64::<BigEndian>()? != FREEZE_MAGIC {
+            return Ok(None);
+        }
+        let flags = stream.read_u64::<BigEndian>()?;
+        let mut hash = [0; 32];
+        stream.read_exact(&mut hash)?;
+        Ok(Some(Self {
+            flags,
+            hash,
+            offset,
+        }))
+    }
+}
+
+/// SHA-256 of the bytes of `stream` from `start` to `end`
+pub(crate) fn hash_range<S: SeekRead>(
+    mut stream: S,
+    start: u64,
+    end: u64,
+) -> std::io::Result<[u8; 32]> {
+    stream.seek(SeekFrom::Start(start))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    let mut left = end - start;
+    while left > 0 {
+        let n = buf.len().min(left as usize);
+        stream.read_exact(&mut buf[..n])?;
+        hasher.update(&buf[..n]);
+        left -= n as u64;
+    }
+    Ok(hasher.finalize().into())
+}
@@ -0,0 +1,87 @@
+//! The content hash recorded for every entry, exposed so data streamed
+//! elsewhere, e.g. straight from the network to disk, can be hashed on the
+//! fly and later checked against a toc without reading it again
+
+use crate::depot_handle::{check_extracted, EntryInfo};
+use crate::error::DepotError;
+use seahash::SeaHasher;
+use std::fmt::{Debug, Formatter};
+use std::hash::Hasher;
+use std::io::{ErrorKind, Read, Write};
+
+/// Incremental hasher producing the same hash as [`EntryInfo::hash`] for the
+/// same bytes, regardless of how they are split across updates
+#[derive(Clone, Default)]
+pub struct EntryHasher {
+    hasher: SeaHasher,
+    len: u64,
+}
+
+impl EntryHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.hasher.write(data);
+        self.len += data.len() as u64;
+    }
+
+    /// Number of bytes hashed so far
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Hash of the bytes so far, more can still be added afterwards
+    pub fn finish(&self) -> u64 {
+        self.hasher.finish()
+    }
+
+    /// Checks the bytes hashed so far against the size and hash of `entry`,
+    /// `name` is only used in the error
+    pub fn verify(&self, name: &str, entry: &EntryInfo) -> Result<(), DepotError> {
+        check_extracted(name, entry, self.len, self.finish())
+    }
+
+    /// Hashes everything `reader` yields, returning the hash and the number
+    /// of bytes read
+    pub fn hash_reader<R: Read>(mut reader: R) -> Result<(u64, u64), DepotError> {
+        let mut hasher = Self::new();
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => hasher.update(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok((hasher.finish(), hasher.len()))
+    }
+}
+
+impl Debug for EntryHasher {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EntryHasher")
+            .field("len", &self.len)
+            .field("hash", &self.finish())
+            .finish()
+    }
+}
+
+/// Hashes everything written, so the hasher can sit behind a tee or be the
+/// target of [`std::io::copy`]
+impl Write for EntryHasher {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
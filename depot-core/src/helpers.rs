@@ -1,6 +1,276 @@
-use chrono::{NaiveTime, TimeZone};
+use crate::temp::{TempFile, TempPolicy};
+use byteorder::{BigEndian, ReadBytesExt};
+use chrono::{DateTime, FixedOffset, Local, NaiveTime, TimeZone, Utc};
 use neoncore::streams::{SeekRead, SeekWrite};
 use std::fmt::{Debug, Formatter};
+use std::io::{Error, ErrorKind, Read, Seek};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+/// true if `path` is a fifo, e.g. a named pipe or a shell process
+/// substitution like `<(cmd)`. Device nodes like `/dev/zero` are not, they
+/// may never end
+#[cfg(unix)]
+pub(crate) fn is_pipe(path: &Path) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    match std::fs::metadata(path) {
+        Ok(meta) => meta.file_type().is_fifo(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn is_pipe(_path: &Path) -> bool {
+    false
+}
+
+/// Reserves `len` bytes for `file` up front so the data is laid out
+/// contiguously and a full disk is reported before anything is written.
+/// Where the filesystem can not reserve space the file is only extended to
+/// `len`, which still saves growing it while writing
+#[cfg(target_os = "linux")]
+pub(crate) fn preallocate(file: &std::fs::File, len: u64) -> Result<(), std::io::Error> {
+    use std::os::unix::io::AsRawFd;
+    if len == 0 {
+        return Ok(());
+    }
+    match unsafe { libc::posix_fallocate(file.as_raw_fd(), 0, len as libc::off_t) } {
+        0 => Ok(()),
+        libc::EOPNOTSUPP | libc::EINVAL => file.set_len(len),
+        errno => Err(std::io::Error::from_raw_os_error(errno)),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn preallocate(file: &std::fs::File, len: u64) -> Result<(), std::io::Error> {
+    file.set_len(len)
+}
+
+/// Identity of a file on disk, two paths with the same id are the same file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FileId {
+    dev: u64,
+    ino: u64,
+}
+
+impl FileId {
+    #[cfg(unix)]
+    pub(crate) fn of(meta: &std::fs::Metadata) -> Option<Self> {
+        use std::os::unix::fs::MetadataExt;
+        Some(Self {
+            dev: meta.dev(),
+            ino: meta.ino(),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn of(_meta: &std::fs::Metadata) -> Option<Self> {
+        None
+    }
+}
+
+/// A private copy of a source file taken before it is compressed, the copy
+/// is deleted when dropped
+pub(crate) struct Snapshot(TempFile);
+
+impl Snapshot {
+    /// Snapshots the open `source` at `path` where `temp` allows it. A
+    /// reflink is tried first as it is instant and takes no space, otherwise
+    /// the contents are copied
+    pub(crate) fn take(
+        source: &mut std::fs::File,
+        path: &Path,
+        temp: &TempPolicy,
+    ) -> Result<Self, std::io::Error> {
+        use std::io::{Seek, SeekFrom};
+
+        // next to the source a reflink is most likely to succeed
+        if temp.allows_sibling() {
+            if let Some(dir) = path.parent() {
+                let dir = if dir.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    dir
+                };
+                if let Ok(mut tmp) = TempFile::create(dir, ".") {
+                    if reflink(source, tmp.file()) {
+                        return Ok(Self(tmp));
+                    }
+                }
+            }
+        }
+
+        let mut tmp = temp.create(&format!("snapshotting {}", path.display()))?;
+        if reflink(source, tmp.file()) {
+            return Ok(Self(tmp));
+        }
+        source.seek(SeekFrom::Start(0))?;
+        let file = tmp.file();
+        std::io::copy(source, file)?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(Self(tmp))
+    }
+
+    pub(crate) fn file(&mut self) -> &mut std::fs::File {
+        self.0.file()
+    }
+}
+
+/// Copies `len` bytes at `offset` of `src` to the position of `dst`,
+/// returns the bytes copied. std hands copies between files to
+/// copy_file_range or sendfile where the platform has them and falls back
+/// to a buffer loop elsewhere
+pub(crate) fn copy_range(
+    mut src: &std::fs::File,
+    offset: u64,
+    mut dst: &std::fs::File,
+    len: u64,
+) -> Result<u64, std::io::Error> {
+    use std::io::{Read, Seek, SeekFrom};
+    src.seek(SeekFrom::Start(offset))?;
+    std::io::copy(&mut src.take(len), &mut dst)
+}
+
+/// Reads a shared file through positional reads with a cursor of its own,
+/// so several threads can read one file at once
+pub(crate) struct FileAt<'a> {
+    file: &'a std::fs::File,
+    pos: u64,
+}
+
+impl<'a> FileAt<'a> {
+    pub(crate) fn new(file: &'a std::fs::File) -> Self {
+        Self { file, pos: 0 }
+    }
+}
+
+impl Read for FileAt<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        #[cfg(unix)]
+        let n = std::os::unix::fs::FileExt::read_at(self.file, buf, self.pos)?;
+        #[cfg(windows)]
+        let n = std::os::windows::fs::FileExt::seek_read(self.file, buf, self.pos)?;
+        #[cfg(not(any(unix, windows)))]
+        let n = {
+            let _ = buf;
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "positional reads are not supported on this platform",
+            ));
+        };
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for FileAt<'_> {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        let (base, offset) = match pos {
+            std::io::SeekFrom::Start(offset) => {
+                self.pos = offset;
+                return Ok(offset);
+            }
+            std::io::SeekFrom::Current(offset) => (self.pos, offset),
+            std::io::SeekFrom::End(offset) => (self.file.metadata()?.len(), offset),
+        };
+        self.pos = base
+            .checked_add_signed(offset)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "seek to a negative position"))?;
+        Ok(self.pos)
+    }
+}
+
+/// Clones the contents of `source` into the empty `dst` without copying
+/// them, true on success
+#[cfg(target_os = "linux")]
+fn reflink(source: &std::fs::File, dst: &std::fs::File) -> bool {
+    use std::os::unix::io::AsRawFd;
+    unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONE, source.as_raw_fd()) == 0 }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_source: &std::fs::File, _dst: &std::fs::File) -> bool {
+    false
+}
+
+/// Reads a big endian LP32 buffer of at most `max` bytes, a larger length
+/// fails before anything is allocated
+pub(crate) fn read_lpbuf_max<D: Read>(mut stream: D, max: u64) -> Result<Vec<u8>, Error> {
+    let len = stream.read_u32::<BigEndian>()? as u64;
+    if len > max {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("length {} is over the limit of {} bytes", len, max),
+        ));
+    }
+    let mut buf = vec![0; len as usize];
+    stream.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Like [`read_lpbuf_max`] for UTF-8 strings
+pub(crate) fn read_lpstr_max<D: Read>(stream: D, max: u64) -> Result<String, Error> {
+    String::from_utf8(read_lpbuf_max(stream, max)?)
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "string is not valid UTF-8"))
+}
+
+/// Bytes left between the position of `stream` and `end`
+pub(crate) fn remaining<D: Seek>(mut stream: D, end: u64) -> Result<u64, Error> {
+    Ok(end.saturating_sub(stream.stream_position()?))
+}
+
+/// The bytes of a depot held in or mapped into memory, shared by the handle
+/// and the stream it reads the toc through
+#[derive(Clone)]
+pub(crate) struct Mapped<'a>(pub(crate) Arc<dyn AsRef<[u8]> + Send + Sync + 'a>);
+
+impl Mapped<'_> {
+    /// The `len` bytes at `offset`, failing when they run past the end
+    pub(crate) fn range(&self, offset: u64, len: u64) -> Result<&[u8], Error> {
+        let data = self.as_ref();
+        offset
+            .checked_add(len)
+            .filter(|end| *end <= data.len() as u64)
+            .map(|end| &data[offset as usize..end as usize])
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::UnexpectedEof,
+                    format!(
+                        "{} bytes at {} lie past the end of the mapping ({} bytes)",
+                        len,
+                        offset,
+                        data.len()
+                    ),
+                )
+            })
+    }
+}
+
+impl AsRef<[u8]> for Mapped<'_> {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_ref().as_ref()
+    }
+}
+
+/// The parts of `extent` none of the `used` ranges cover, in order. The
+/// used ranges may overlap, e.g. for data shared by several entries
+pub(crate) fn uncovered(mut used: Vec<Range<u64>>, extent: Range<u64>) -> Vec<Range<u64>> {
+    used.sort_unstable_by_key(|range| range.start);
+    let mut gaps = Vec::new();
+    let mut covered_to = extent.start;
+    for range in used {
+        let start = range.start.min(extent.end);
+        if start > covered_to {
+            gaps.push(covered_to..start);
+        }
+        covered_to = covered_to.max(range.end);
+    }
+    if covered_to < extent.end {
+        gaps.push(covered_to..extent.end);
+    }
+    gaps
+}
 
 pub(crate) trait Ser {
     fn ser<S: SeekWrite>(&self, stream: S) -> Result<u64, std::io::Error>;
@@ -26,17 +296,40 @@ impl TsWithTz {
         TsWithTz { ts, tz: tz_offset }
     }
 
+    /// Seconds since the unix epoch
+    pub fn timestamp(&self) -> i64 {
+        self.ts as i64
+    }
+
+    /// Offset of the recording time zone from UTC in seconds
+    pub fn tz_offset(&self) -> i32 {
+        self.tz
+    }
+
+    /// Date and time in the time zone it was recorded in, `None` when the
+    /// stored offset is out of range
+    pub fn to_datetime(&self) -> Option<DateTime<FixedOffset>> {
+        let tz = FixedOffset::east_opt(self.tz)?;
+        Some(self.to_utc()?.with_timezone(&tz))
+    }
+
+    pub fn to_utc(&self) -> Option<DateTime<Utc>> {
+        Utc.timestamp_opt(self.ts as i64, 0).single()
+    }
+
+    /// Date and time in the local time zone of this machine
+    pub fn to_local(&self) -> Option<DateTime<Local>> {
+        Some(self.to_utc()?.with_timezone(&Local))
+    }
+
     pub(crate) fn as_naive_time(&self) -> Option<NaiveTime> {
-        let tz = chrono::FixedOffset::east_opt(self.tz);
-        let ndt = chrono::NaiveDateTime::from_timestamp_opt(self.ts as i64, 0);
-        if let (Some(tz), Some(ndt)) = (tz, ndt) {
-            return Some(tz.from_utc_datetime(&ndt).time());
-        }
-        None
+        self.to_datetime().map(|dt| dt.time())
     }
 
-    pub(crate) fn to_u64(&self) -> u64 {
-        (self.ts as u64) << 32 | (self.tz as u64)
+    pub(crate) fn to_u64(self) -> u64 {
+        // the offset is masked, sign extending a negative one would clobber
+        // the timestamp in the upper half
+        (self.ts as u64) << 32 | (self.tz as u32 as u64)
     }
 
     pub(crate) fn from_u64(ts: u64) -> Self {
@@ -57,3 +350,75 @@ impl Debug for TsWithTz {
         write!(f, "Invalid timestamp")
     }
 }
+
+/// resolves a uid to a user name through the system user database
+#[cfg(unix)]
+pub(crate) fn user_name(uid: u32) -> Option<String> {
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let rc = unsafe { libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if rc != 0 || result.is_null() {
+        return None;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(pwd.pw_name) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+/// resolves a gid to a group name through the system group database
+#[cfg(unix)]
+pub(crate) fn group_name(gid: u32) -> Option<String> {
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut grp: libc::group = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let rc = unsafe { libc::getgrgid_r(gid, &mut grp, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if rc != 0 || result.is_null() {
+        return None;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(grp.gr_name) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+/// resolves a user name to its uid through the system user database
+#[cfg(unix)]
+pub(crate) fn lookup_uid(name: &str) -> Option<u32> {
+    let cname = std::ffi::CString::new(name).ok()?;
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let rc = unsafe {
+        libc::getpwnam_r(
+            cname.as_ptr(),
+            &mut pwd,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if rc != 0 || result.is_null() {
+        return None;
+    }
+    Some(pwd.pw_uid)
+}
+
+/// resolves a group name to its gid through the system group database
+#[cfg(unix)]
+pub(crate) fn lookup_gid(name: &str) -> Option<u32> {
+    let cname = std::ffi::CString::new(name).ok()?;
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut grp: libc::group = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let rc = unsafe {
+        libc::getgrnam_r(
+            cname.as_ptr(),
+            &mut grp,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if rc != 0 || result.is_null() {
+        return None;
+    }
+    Some(grp.gr_gid)
+}
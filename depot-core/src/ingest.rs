@@ -0,0 +1,95 @@
+//! Sources of entry data other than files on disk, e.g. generated data,
+//! database exports or network fetches, added through
+//! [`crate::depot_handle::DepotHandle::add_source`] with the same
+//! compression, ratio guard and progress handling as files
+
+use crate::error::DepotError;
+use crate::meta::ExtMeta;
+use std::io::{Error, Read};
+
+/// A producer of a single entry
+pub trait IngestSource {
+    /// Name the entry is stored under
+    fn name(&self) -> &str;
+
+    /// Exact size of the data when known up front, a source producing a
+    /// different amount is handled according to the change policy
+    fn size_hint(&self) -> Option<u64> {
+        None
+    }
+
+    /// The data, called once
+    fn reader(&mut self) -> Result<Box<dyn Read + '_>, Error>;
+
+    /// Extended metadata stored with the entry
+    fn metadata(&self) -> ExtMeta {
+        ExtMeta::default()
+    }
+}
+
+/// An [`IngestSource`] over any reader
+pub struct ReaderSource<R> {
+    name: String,
+    reader: R,
+    size: Option<u64>,
+    meta: ExtMeta,
+}
+
+impl<R: Read> ReaderSource<R> {
+    pub fn new(name: impl Into<String>, reader: R) -> Self {
+        Self {
+            name: name.into(),
+            reader,
+            size: None,
+            meta: ExtMeta::default(),
+        }
+    }
+
+    pub fn with_size(mut self, size: u64) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    pub fn with_metadata(mut self, meta: ExtMeta) -> Self {
+        self.meta = meta;
+        self
+    }
+}
+
+impl<R: Read> IngestSource for ReaderSource<R> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn size_hint(&self) -> Option<u64> {
+        self.size
+    }
+
+    fn reader(&mut self) -> Result<Box<dyn Read + '_>, Error> {
+        Ok(Box::new(&mut self.reader))
+    }
+
+    fn metadata(&self) -> ExtMeta {
+        self.meta.clone()
+    }
+}
+
+/// Transforms the data of entries before it is compressed, e.g. to strip
+/// debug symbols or normalize line endings, set with
+/// [`crate::depot_handle::DepotHandle::set_ingest_hooks`]. Applies to every
+/// entry with data, however it is added, reserved streams excepted. The
+/// size and hash of an entry describe the transformed data, and the
+/// identity of the transform is recorded in its metadata, see
+/// [`ExtMeta::transform`]. Bulk adds call the hooks from their workers
+pub trait IngestHooks: Send + Sync {
+    /// Wraps the reader of the data added as `name`, returning it along
+    /// with a name and version identifying the transform, e.g.
+    /// `strip-debug@1`, or the reader as is and `None` to leave the entry
+    /// untouched. The same data may be read twice when it is stored
+    /// uncompressed after all, the transform has to give the same output
+    fn transform_reader<'r>(
+        &self,
+        name: &str,
+        reader: Box<dyn Read + 'r>,
+    ) -> Result<(Box<dyn Read + 'r>, Option<String>), DepotError>;
+}
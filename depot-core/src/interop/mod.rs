@@ -0,0 +1,4 @@
+//! Conversions between depots and other archive and packaging formats
+
+#[cfg(feature = "oci")]
+pub mod oci;
@@ -0,0 +1,241 @@
+//! Export of depots as OCI image layers, so packaged content can be shipped
+//! through container registries
+
+use crate::depot_handle::{DepotHandle, StreamInfo};
+use crate::error::DepotError;
+use crate::meta::EntryType;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::io::{Error, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// Media type of the layers produced by [`export_layer`]
+pub const MEDIA_TYPE: &str = "application/vnd.oci.image.layer.v1.tar+gzip";
+
+const WHITEOUT_PREFIX: &str = ".wh.";
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+
+/// Options for exporting a depot as an OCI image layer
+#[derive(Debug, Clone)]
+pub struct OciLayerOptions {
+    /// Image paths deleted relative to the lower layers, exported as `.wh.`
+    /// whiteout files, not affected by `prefix`
+    pub deletions: Vec<String>,
+    /// Image directories whose lower layer contents are hidden, exported as
+    /// opaque whiteouts, not affected by `prefix`
+    pub opaque_dirs: Vec<String>,
+    /// Prefix prepended to every entry path inside the layer, e.g. `opt/app`
+    pub prefix: Option<PathBuf>,
+    /// gzip compression level, 0-9
+    pub gzip_level: u32,
+}
+
+impl Default for OciLayerOptions {
+    fn default() -> Self {
+        Self {
+            deletions: Vec::new(),
+            opaque_dirs: Vec::new(),
+            prefix: None,
+            gzip_level: 6,
+        }
+    }
+}
+
+/// Descriptor data of an exported layer, as needed by an image manifest and config
+#[derive(Debug, Clone)]
+pub struct OciLayer {
+    /// `sha256:` digest of the compressed layer, referenced by the manifest
+    pub digest: String,
+    /// `sha256:` digest of the uncompressed tar, the config `rootfs.diff_ids` entry
+    pub diff_id: String,
+    /// size in bytes of the compressed layer
+    pub size: u64,
+}
+
+/// Passes writes through while hashing and counting them
+struct DigestWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+    written: u64,
+}
+
+impl<W: Write> DigestWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            written: 0,
+        }
+    }
+
+    fn digest(&self) -> String {
+        format!("sha256:{:x}", self.hasher.clone().finalize())
+    }
+}
+
+impl<W: Write> Write for DigestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writes every stream of `depot` to `output` as a gzip compressed OCI layer
+/// tarball, along with the whiteouts described by `options`
+pub fn export_layer<W: Write>(
+    depot: &mut DepotHandle,
+    output: W,
+    options: &OciLayerOptions,
+) -> Result<OciLayer, DepotError> {
+    let compressed = DigestWriter::new(output);
+    let gz = GzEncoder::new(compressed, Compression::new(options.gzip_level));
+    let mut builder = tar::Builder::new(DigestWriter::new(gz));
+    let mut dirs = BTreeSet::new();
+
+    for dir in &options.opaque_dirs {
+        let path = layer_path(None, dir)?.join(OPAQUE_WHITEOUT);
+        append_parents(&mut builder, &mut dirs, &path)?;
+        append_empty(&mut builder, &path)?;
+    }
+
+    for deleted in &options.deletions {
+        let path = layer_path(None, deleted)?;
+        let name = path.file_name().ok_or_else(|| DepotError::InvalidName {
+            name: deleted.clone(),
+            reason: "can not be whited out".to_owned(),
+        })?;
+        let path = path.with_file_name(format!("{}{}", WHITEOUT_PREFIX, name.to_string_lossy()));
+        append_parents(&mut builder, &mut dirs, &path)?;
+        append_empty(&mut builder, &path)?;
+    }
+
+    let streams: Vec<StreamInfo> = depot
+        .streams()
+        .map(|(name, einf)| (name.clone(), einf.clone()).into())
+        .collect();
+    for stream in streams {
+        let path = layer_path(options.prefix.as_deref(), &stream.name)?;
+        if let Some(name) = path.file_name() {
+            if name.to_string_lossy().starts_with(WHITEOUT_PREFIX) {
+                return Err(DepotError::InvalidName {
+                    name: stream.name.clone(),
+                    reason: "uses the reserved whiteout prefix".to_owned(),
+                });
+            }
+        }
+        append_parents(&mut builder, &mut dirs, &path)?;
+
+        let kind = stream.einf.entry_type();
+        // names sort before what lies below them, so a directory entry is
+        // only already written if it was a whiteout parent
+        if kind == EntryType::Dir && !dirs.insert(path.clone()) {
+            continue;
+        }
+        let ext = &stream.einf.ext;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(stream.einf.size);
+        match kind {
+            EntryType::File => {
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_mode(ext.mode().unwrap_or(0o644));
+            }
+            EntryType::Symlink => {
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_mode(0o777);
+            }
+            EntryType::Dir => {
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_mode(ext.mode().unwrap_or(0o755));
+            }
+        }
+        header.set_mtime(stream.einf.mod_ts.timestamp().max(0) as u64);
+        header.set_uid(ext.uid().unwrap_or(0) as u64);
+        header.set_gid(ext.gid().unwrap_or(0) as u64);
+        if let Some(name) = ext.uname() {
+            header.set_username(name)?;
+        }
+        if let Some(name) = ext.gname() {
+            header.set_groupname(name)?;
+        }
+        match stream.einf.link_target() {
+            Some(target) if kind == EntryType::Symlink => {
+                header.set_size(0);
+                builder.append_link(&mut header, &path, target)?
+            }
+            // the size is known upfront, so the data is streamed into the
+            // layer instead of loaded whole
+            _ => {
+                let data = depot.open_stream(&stream)?;
+                builder.append_data(&mut header, &path, data)?
+            }
+        }
+    }
+
+    let uncompressed = builder.into_inner()?;
+    let diff_id = uncompressed.digest();
+    let mut compressed = uncompressed.inner.finish()?;
+    compressed.flush()?;
+
+    Ok(OciLayer {
+        digest: compressed.digest(),
+        diff_id,
+        size: compressed.written,
+    })
+}
+
+/// Maps a stream name to a relative path inside the layer
+fn layer_path(prefix: Option<&Path>, name: &str) -> Result<PathBuf, DepotError> {
+    let mut path = prefix.map(Path::to_path_buf).unwrap_or_default();
+    for component in Path::new(name).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::RootDir | Component::CurDir => {}
+            _ => {
+                return Err(DepotError::InvalidName {
+                    name: name.to_owned(),
+                    reason: "can not be represented in a layer".to_owned(),
+                })
+            }
+        }
+    }
+    Ok(path)
+}
+
+fn append_parents<W: Write>(
+    builder: &mut tar::Builder<W>,
+    dirs: &mut BTreeSet<PathBuf>,
+    path: &Path,
+) -> Result<(), Error> {
+    let parents: Vec<&Path> = path
+        .ancestors()
+        .skip(1)
+        .filter(|p| !p.as_os_str().is_empty())
+        .collect();
+    for dir in parents.into_iter().rev() {
+        if !dirs.insert(dir.to_path_buf()) {
+            continue;
+        }
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_size(0);
+        header.set_mode(0o755);
+        builder.append_data(&mut header, dir, std::io::empty())?;
+    }
+    Ok(())
+}
+
+fn append_empty<W: Write>(builder: &mut tar::Builder<W>, path: &Path) -> Result<(), Error> {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_size(0);
+    header.set_mode(0o644);
+    builder.append_data(&mut header, path, std::io::empty())
+}
@@ -1,9 +1,85 @@
+pub use helpers::TsWithTz;
 use neoncore::const_fn::ascii_to_u64_be;
 pub use neoncore::streams::{SeekRead, SeekWrite};
 
+#[cfg(feature = "tokio")]
+pub mod async_handle;
+pub mod bulk;
+pub mod catalog;
+pub mod creator;
+pub mod crypt;
 pub mod depot_handle;
+pub mod dict;
+pub mod dir_index;
+pub mod error;
+pub mod events;
+pub mod extract;
+pub mod fingerprint;
+pub mod freeze;
+pub mod hash;
 mod helpers;
+pub mod ingest;
+pub mod interop;
+pub mod lint;
+pub mod meta;
+pub mod package;
+pub mod phf;
+pub mod policy;
+pub mod priority;
+pub mod read;
+pub mod repack;
+pub mod scope;
+pub mod scrub;
+pub mod solid;
+pub mod temp;
+pub mod trace;
 // mod types;
+pub mod volume;
 
 /// cbindgen:ignore
 pub const MAGIC: u64 = ascii_to_u64_be(b"DEPOTARC");
+
+/// Format version written by this library, version 1 archives are still
+/// readable but lack extended entry metadata. Version 3 added symlink and
+/// directory entries, which older readers would take for empty files,
+/// version 4 entries compressed with a dictionary and version 5 encrypted
+/// entries. Version 6 compresses the toc entries and version 7 adds depot
+/// metadata after them. Version 8 groups small entries into solid blocks,
+/// see [`solid`]
+pub const FORMAT_VERSION: u16 = 8;
+
+/// Prefix of the names reserved for streams managed by the library itself,
+/// such as package manifests, user adds under it are rejected
+pub const RESERVED_PREFIX: &str = ".depot/";
+
+/// Longest entry name in bytes, longer lengths read from a toc are taken
+/// as corruption rather than allocated
+pub const MAX_NAME_LEN: u32 = 64 * 1024;
+
+/// Most bytes the compressed toc entries may unpack to, and the stored toc
+/// may take when read whole, a toc claiming more is taken as corruption
+/// rather than decompressed or read
+pub const MAX_TOC_SIZE: u64 = 256 * 1024 * 1024;
+
+/// true if `name` lies in the [`RESERVED_PREFIX`] namespace
+pub fn is_reserved_name(name: &str) -> bool {
+    name.starts_with(RESERVED_PREFIX)
+}
+
+/// Stable numeric id of an entry, the SeaHash of its name with `\`
+/// separators and leading `./` or `/` normalized away. Engines can bake
+/// these into their own tables and look entries up with
+/// [`depot_handle::DepotHandle::get_by_id`]
+pub fn entry_id(name: &str) -> u64 {
+    seahash::hash(normalize_id_name(name).as_bytes())
+}
+
+/// The name [`entry_id`] hashes
+pub(crate) fn normalize_id_name(name: &str) -> String {
+    let name = name.replace('\\', "/");
+    let mut rest = name.as_str();
+    while let Some(r) = rest.strip_prefix("./").or_else(|| rest.strip_prefix('/')) {
+        rest = r;
+    }
+    rest.to_owned()
+}
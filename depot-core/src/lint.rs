@@ -0,0 +1,323 @@
+//! Health checks over an archive, flagging issues that do not prevent
+//! reading it but indicate waste, sloppy producers or outdated files. Frame
+//! settings are judged by the independent frames recorded for an entry,
+//! the zstd frame size a writer used otherwise is not stored in the file
+
+use crate::depot_handle::{DepotHeader, DepotToc, EntryInfo};
+use crate::error::DepotError;
+use crate::freeze::FreezeTrailer;
+use crate::helpers::{self, De};
+use crate::meta::keys;
+use crate::FORMAT_VERSION;
+use neoncore::streams::SeekRead;
+use std::io::SeekFrom;
+use std::ops::Range;
+use std::path::{Component, Path};
+
+/// Share of the file not referenced by the header, entries or toc above
+/// which the archive is flagged for compaction
+pub const DEAD_SPACE_THRESHOLD: f64 = 0.10;
+
+/// Independent frames of fewer uncompressed bytes lose more ratio than
+/// decompressing them in parallel gains
+pub const MIN_FRAME_SIZE: u64 = 64 * 1024;
+
+/// Independent frames of more uncompressed bytes leave little to split
+/// between threads or to skip when reading a range
+pub const MAX_FRAME_SIZE: u64 = 1024 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LintIssue {
+    /// Stable identifier of the check, e.g. `dead-space`
+    pub code: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    /// Entry the issue applies to, `None` for archive wide issues
+    pub entry: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LintReport {
+    pub issues: Vec<LintIssue>,
+    /// Format version found in the header
+    pub version: u16,
+    pub file_size: u64,
+    /// Bytes not referenced by the header, any entry or the toc
+    pub dead_space: u64,
+    /// Where the dead space lies, see [`crate::depot_handle::DepotHandle::dead_regions`]
+    pub dead_regions: Vec<Range<u64>>,
+}
+
+impl LintReport {
+    /// Share of the file that is dead space, 0.0 to 1.0
+    pub fn dead_space_ratio(&self) -> f64 {
+        if self.file_size == 0 {
+            return 0.0;
+        }
+        self.dead_space as f64 / self.file_size as f64
+    }
+
+    /// The most severe issue found, `None` for a clean archive
+    pub fn worst(&self) -> Option<Severity> {
+        self.issues.iter().map(|i| i.severity).max()
+    }
+
+    fn push(
+        &mut self,
+        code: &'static str,
+        severity: Severity,
+        message: String,
+        entry: Option<&str>,
+    ) {
+        self.issues.push(LintIssue {
+            code,
+            severity,
+            message,
+            entry: entry.map(str::to_owned),
+        });
+    }
+}
+
+/// Lints the depot starting at the current position of `stream`, only
+/// failing when the stream can not be read at all or has no depot header
+pub fn lint<S: SeekRead>(mut stream: S) -> Result<LintReport, DepotError> {
+    let header_offset = stream.stream_position()?;
+    let header = DepotHeader::de(&mut stream)?;
+    let file_size = stream.seek(SeekFrom::End(0))?;
+    let mut report = LintReport {
+        version: header.version,
+        file_size,
+        ..Default::default()
+    };
+
+    if header.toc_offset == !0 {
+        report.push(
+            "not-finalized",
+            Severity::Error,
+            "the archive was never finalized, its writer did not close it".to_owned(),
+            None,
+        );
+        return Ok(report);
+    }
+    if header.version > FORMAT_VERSION {
+        report.push(
+            "unsupported-version",
+            Severity::Error,
+            format!(
+                "format version {} is newer than the supported {}",
+                header.version, FORMAT_VERSION
+            ),
+            None,
+        );
+        return Ok(report);
+    }
+    if header.version < FORMAT_VERSION {
+        report.push(
+            "outdated-version",
+            Severity::Warning,
+            format!(
+                "format version {} is outdated, current is {}",
+                header.version, FORMAT_VERSION
+            ),
+            None,
+        );
+    }
+    if header.toc_offset >= file_size {
+        report.push(
+            "truncated",
+            Severity::Error,
+            format!(
+                "toc offset {} is past the end of the file ({} bytes)",
+                header.toc_offset, file_size
+            ),
+            None,
+        );
+        return Ok(report);
+    }
+
+    stream.seek(SeekFrom::Start(header.toc_offset))?;
+    let toc = match DepotToc::de_versioned(&mut stream, header.version) {
+        Ok(toc) => toc,
+        Err(e) => {
+            report.push(
+                "corrupt-toc",
+                Severity::Error,
+                format!("the toc could not be read: {}", e),
+                None,
+            );
+            return Ok(report);
+        }
+    };
+    let toc_end = stream.stream_position()?;
+    // the trailer of a frozen depot follows the toc
+    let trailer = FreezeTrailer::read_after(&mut stream)?;
+
+    if toc.entry_count != toc.entries.len() as u64 {
+        report.push(
+            "entry-count-mismatch",
+            Severity::Error,
+            format!(
+                "toc claims {} entries but holds {}",
+                toc.entry_count,
+                toc.entries.len()
+            ),
+            None,
+        );
+    }
+    if !(-7..=22).contains(&toc.compression_level) {
+        report.push(
+            "absurd-compression-level",
+            Severity::Warning,
+            format!(
+                "compression level {} is outside of zstd's -7..=22 range",
+                toc.compression_level
+            ),
+            None,
+        );
+    }
+
+    for (name, entry) in toc.entries.iter() {
+        lint_entry(&mut report, name, entry, file_size);
+    }
+
+    // everything referenced, shared ranges are counted once
+    let mut ranges: Vec<Range<u64>> = toc
+        .entries
+        .values()
+        .filter(|e| e.stream_size > 0)
+        .map(|e| e.offset..e.offset.saturating_add(e.stream_size))
+        .collect();
+    ranges.push(header_offset..header_offset + DepotHeader::SIZE);
+    ranges.push(header.toc_offset..toc_end);
+    if let Some(trailer) = trailer {
+        ranges.push(trailer.offset..trailer.offset + FreezeTrailer::SIZE);
+    }
+    report.dead_regions = helpers::uncovered(ranges, header_offset..file_size);
+    report.dead_space = report.dead_regions.iter().map(|r| r.end - r.start).sum();
+    if report.dead_space_ratio() > DEAD_SPACE_THRESHOLD {
+        report.push(
+            "dead-space",
+            Severity::Warning,
+            format!(
+                "{} bytes ({:.1}%) of the file are unreferenced",
+                report.dead_space,
+                report.dead_space_ratio() * 100.0
+            ),
+            None,
+        );
+    }
+
+    Ok(report)
+}
+
+fn lint_entry(report: &mut LintReport, name: &str, entry: &EntryInfo, file_size: u64) {
+    if entry.offset.saturating_add(entry.stream_size) > file_size {
+        report.push(
+            "entry-out-of-bounds",
+            Severity::Error,
+            format!(
+                "data at {}..{} lies past the end of the file",
+                entry.offset,
+                entry.offset.saturating_add(entry.stream_size)
+            ),
+            Some(name),
+        );
+    }
+    // members of a solid block share its stored size
+    let solid = entry.flags & EntryInfo::FLAG_SOLID != 0;
+    if entry.size > 0 && entry.stream_size > entry.size && !solid {
+        report.push(
+            "expanded-entry",
+            Severity::Warning,
+            format!(
+                "compressed to {} bytes from {}, storing it uncompressed would be smaller",
+                entry.stream_size, entry.size
+            ),
+            Some(name),
+        );
+    }
+    lint_frames(report, name, entry);
+    if !is_normalized_name(name) {
+        report.push(
+            "non-normalized-name",
+            Severity::Warning,
+            "name is absolute, contains `.`/`..`, backslashes or empty components".to_owned(),
+            Some(name),
+        );
+    }
+}
+
+/// Checks the independent frames recorded for an entry, see
+/// [`crate::depot_handle::DepotHandle::set_independent_frames`]
+fn lint_frames(report: &mut LintReport, name: &str, entry: &EntryInfo) {
+    if entry.ext.get(keys::FRAMES).is_none() {
+        return;
+    }
+    let frames = entry.ext.frames().filter(|frames| {
+        frames.iter().map(|f| f.0).sum::<u64>() == entry.stream_size
+            && frames.iter().map(|f| f.1).sum::<u64>() == entry.size
+    });
+    let Some(frames) = frames else {
+        report.push(
+            "corrupt-frames",
+            Severity::Warning,
+            "the recorded frames do not add up to the entry, readers ignore them".to_owned(),
+            Some(name),
+        );
+        return;
+    };
+    // the last frame holds whatever is left
+    let size = frames.first().map_or(0, |f| f.1);
+    if frames.len() > 1 && size < MIN_FRAME_SIZE {
+        report.push(
+            "absurd-frame-size",
+            Severity::Warning,
+            format!(
+                "{} independent frames of {} bytes, below {} they cost more ratio than they gain",
+                frames.len(),
+                size,
+                MIN_FRAME_SIZE
+            ),
+            Some(name),
+        );
+    } else if size > MAX_FRAME_SIZE {
+        report.push(
+            "absurd-frame-size",
+            Severity::Warning,
+            format!(
+                "independent frames of {} bytes, above {} they barely split the entry",
+                size, MAX_FRAME_SIZE
+            ),
+            Some(name),
+        );
+    }
+}
+
+/// true if `name` is a relative, `/` separated path without `.`, `..` or
+/// empty components
+pub fn is_normalized_name(name: &str) -> bool {
+    if name.is_empty() || name.contains('\\') || name.contains("//") || name.ends_with('/') {
+        return false;
+    }
+    Path::new(name)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+        && !name.split('/').any(|part| part == ".")
+}
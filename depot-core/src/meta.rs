@@ -0,0 +1,400 @@
+use crate::helpers::{read_lpbuf_max, read_lpstr_max, remaining, De, Ser};
+use crate::MAX_NAME_LEN;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use neoncore::streams::write::{write_lpbuf, write_lpstr};
+use neoncore::streams::{Endianness, LPWidth, SeekRead, SeekWrite};
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Well known extended metadata keys
+pub mod keys {
+    /// numeric owner id of the source file
+    pub const UID: &str = "uid";
+    /// numeric group id of the source file
+    pub const GID: &str = "gid";
+    /// owner name of the source file, when it could be resolved
+    pub const UNAME: &str = "uname";
+    /// group name of the source file, when it could be resolved
+    pub const GNAME: &str = "gname";
+    /// stable numeric id of the entry, see [`crate::entry_id`]
+    pub const ID: &str = "id";
+    /// creation (birth) time of the source file in seconds since the unix
+    /// epoch, only recorded where the platform and filesystem report it
+    pub const BTIME: &str = "btime";
+    /// compressed and uncompressed size of every zstd frame of an entry
+    /// written as independent frames, big endian u64 pairs
+    pub const FRAMES: &str = "frames";
+    /// permission bits of the source, `st_mode & 0o7777`
+    pub const MODE: &str = "mode";
+    /// what the entry was added from, see [`super::EntryType`], missing
+    /// for regular files
+    pub const TYPE: &str = "type";
+    /// target of a symlink entry as the raw bytes of the path
+    pub const LINK: &str = "link";
+    /// tag of the zstd dictionary the entry was compressed with, see
+    /// [`crate::dict`]
+    pub const DICT: &str = "dict";
+    /// who may be served the entry, see [`super::Visibility`], missing for
+    /// public entries
+    pub const VISIBILITY: &str = "visibility";
+    /// nonce prefix an encrypted entry was sealed under, see
+    /// [`crate::crypt`]
+    pub const NONCE: &str = "nonce";
+    /// what the source data was transformed with before it was compressed,
+    /// see [`crate::ingest::IngestHooks`]
+    pub const TRANSFORM: &str = "transform";
+    /// id of the solid block holding the entry data, see [`crate::solid`]
+    pub const BLOCK: &str = "block";
+    /// where the entry data starts in its decompressed solid block
+    pub const BLOCK_OFFSET: &str = "block_offset";
+    /// prefix of the keys set by users, e.g. `user.locale`, the library
+    /// never interprets them, see [`super::ExtMeta::user`]
+    pub const USER_PREFIX: &str = "user.";
+}
+
+/// What an entry was added from, entries other than regular files carry no
+/// data
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EntryType {
+    #[default]
+    File,
+    /// A symbolic link, its target is recorded in the metadata
+    Symlink,
+    /// A directory, recorded so empty directories and directory metadata
+    /// survive a round trip
+    Dir,
+}
+
+/// Who an entry may be served to, so one depot can back public downloads
+/// while also carrying internal assets like debug symbols. Ordered from the
+/// widest audience to the narrowest
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Visibility {
+    #[default]
+    Public,
+    /// Only served to internal clients
+    Internal,
+}
+
+impl EntryType {
+    fn from_u64(value: u64) -> Option<Self> {
+        match value {
+            0 => Some(EntryType::File),
+            1 => Some(EntryType::Symlink),
+            2 => Some(EntryType::Dir),
+            _ => None,
+        }
+    }
+
+    fn to_u64(self) -> u64 {
+        match self {
+            EntryType::File => 0,
+            EntryType::Symlink => 1,
+            EntryType::Dir => 2,
+        }
+    }
+}
+
+/// Extended metadata attached to an entry, a map of keys to raw values.
+/// Stored right after the entry info in version 2+ tables of contents
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExtMeta {
+    attrs: BTreeMap<String, Vec<u8>>,
+}
+
+impl ExtMeta {
+    pub fn get(&self, key: &str) -> Option<&[u8]> {
+        self.attrs.get(key).map(|v| v.as_slice())
+    }
+
+    pub fn set(&mut self, key: &str, value: &[u8]) {
+        self.attrs.insert(key.to_owned(), value.to_vec());
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Vec<u8>> {
+        self.attrs.remove(key)
+    }
+
+    /// Reads a value stored with [`ExtMeta::set_u64`]
+    pub fn get_u64(&self, key: &str) -> Option<u64> {
+        let value: [u8; 8] = self.get(key)?.try_into().ok()?;
+        Some(u64::from_be_bytes(value))
+    }
+
+    pub fn set_u64(&mut self, key: &str, value: u64) {
+        self.set(key, &value.to_be_bytes());
+    }
+
+    /// Reads a value stored with [`ExtMeta::set_str`], `None` if missing or
+    /// not valid UTF-8
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        std::str::from_utf8(self.get(key)?).ok()
+    }
+
+    pub fn set_str(&mut self, key: &str, value: &str) {
+        self.set(key, value.as_bytes());
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Vec<u8>)> {
+        self.attrs.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.attrs.is_empty()
+    }
+
+    pub fn id(&self) -> Option<u64> {
+        self.get_u64(keys::ID)
+    }
+
+    pub fn uid(&self) -> Option<u32> {
+        self.get_u64(keys::UID).map(|v| v as u32)
+    }
+
+    pub fn gid(&self) -> Option<u32> {
+        self.get_u64(keys::GID).map(|v| v as u32)
+    }
+
+    pub fn uname(&self) -> Option<&str> {
+        self.get_str(keys::UNAME)
+    }
+
+    pub fn gname(&self) -> Option<&str> {
+        self.get_str(keys::GNAME)
+    }
+
+    /// Permission bits of the source, `None` where the platform has none
+    pub fn mode(&self) -> Option<u32> {
+        self.get_u64(keys::MODE).map(|v| v as u32 & 0o7777)
+    }
+
+    /// What the entry was added from, unknown types are treated as files
+    pub fn entry_type(&self) -> EntryType {
+        self.get_u64(keys::TYPE)
+            .and_then(EntryType::from_u64)
+            .unwrap_or_default()
+    }
+
+    pub fn set_entry_type(&mut self, kind: EntryType) {
+        match kind {
+            EntryType::File => {
+                self.remove(keys::TYPE);
+            }
+            kind => self.set_u64(keys::TYPE, kind.to_u64()),
+        }
+    }
+
+    /// Who the entry may be served to, unknown values are treated as
+    /// internal so a newer writer's restriction is never widened
+    pub fn visibility(&self) -> Visibility {
+        match self.get_u64(keys::VISIBILITY) {
+            None | Some(0) => Visibility::Public,
+            Some(_) => Visibility::Internal,
+        }
+    }
+
+    pub fn set_visibility(&mut self, visibility: Visibility) {
+        match visibility {
+            Visibility::Public => {
+                self.remove(keys::VISIBILITY);
+            }
+            Visibility::Internal => self.set_u64(keys::VISIBILITY, 1),
+        }
+    }
+
+    /// What the source data was transformed with before it was stored,
+    /// see [`crate::ingest::IngestHooks`]
+    pub fn transform(&self) -> Option<&str> {
+        self.get_str(keys::TRANSFORM)
+    }
+
+    /// Id of the solid block the entry data is stored in and where it
+    /// starts in the decompressed block, see [`crate::solid`]
+    pub fn solid_block(&self) -> Option<(u64, u64)> {
+        Some((
+            self.get_u64(keys::BLOCK)?,
+            self.get_u64(keys::BLOCK_OFFSET)?,
+        ))
+    }
+
+    /// Value of the user attribute `key`, stored under
+    /// [`keys::USER_PREFIX`] so it never clashes with the well known keys
+    pub fn user(&self, key: &str) -> Option<&[u8]> {
+        self.get(&format!("{}{}", keys::USER_PREFIX, key))
+    }
+
+    pub fn set_user(&mut self, key: &str, value: &[u8]) {
+        self.set(&format!("{}{}", keys::USER_PREFIX, key), value);
+    }
+
+    pub fn remove_user(&mut self, key: &str) -> Option<Vec<u8>> {
+        self.remove(&format!("{}{}", keys::USER_PREFIX, key))
+    }
+
+    /// The user attributes in key order, keys without their prefix
+    pub fn user_attrs(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.attrs.iter().filter_map(|(key, value)| {
+            let key = key.strip_prefix(keys::USER_PREFIX)?;
+            Some((key, value.as_slice()))
+        })
+    }
+
+    /// Target of a symlink entry
+    pub fn link_target(&self) -> Option<PathBuf> {
+        let raw = self.get(keys::LINK)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            Some(PathBuf::from(std::ffi::OsStr::from_bytes(raw)))
+        }
+        #[cfg(not(unix))]
+        Some(PathBuf::from(String::from_utf8_lossy(raw).into_owned()))
+    }
+
+    pub fn set_link_target(&mut self, target: &Path) {
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            self.set(keys::LINK, target.as_os_str().as_bytes());
+        }
+        #[cfg(not(unix))]
+        self.set_str(keys::LINK, &target.to_string_lossy());
+    }
+
+    /// `(stream_size, size)` of the independent frames an entry was
+    /// compressed into, `None` for entries written as a single frame
+    pub fn frames(&self) -> Option<Vec<(u64, u64)>> {
+        let raw = self.get(keys::FRAMES)?;
+        if raw.len() % 16 != 0 {
+            return None;
+        }
+        Some(
+            raw.chunks_exact(16)
+                .map(|pair| {
+                    let (stream_size, size) = pair.split_at(8);
+                    (
+                        u64::from_be_bytes(stream_size.try_into().unwrap()),
+                        u64::from_be_bytes(size.try_into().unwrap()),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    pub fn set_frames(&mut self, frames: &[(u64, u64)]) {
+        let raw: Vec<u8> = frames
+            .iter()
+            .flat_map(|(stream_size, size)| {
+                stream_size
+                    .to_be_bytes()
+                    .into_iter()
+                    .chain(size.to_be_bytes())
+            })
+            .collect();
+        self.set(keys::FRAMES, &raw);
+    }
+
+    /// When the source file was created, as opposed to the entry's
+    /// `create_ts` recording when it was added to the depot
+    pub fn birth_time(&self) -> Option<SystemTime> {
+        let secs = self.get_u64(keys::BTIME)? as i64;
+        match secs {
+            0.. => UNIX_EPOCH.checked_add(Duration::from_secs(secs as u64)),
+            _ => UNIX_EPOCH.checked_sub(Duration::from_secs(secs.unsigned_abs())),
+        }
+    }
+
+    pub fn set_birth_time(&mut self, time: SystemTime) {
+        let secs = match time.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            Err(e) => -(e.duration().as_secs() as i64),
+        };
+        self.set_u64(keys::BTIME, secs as u64);
+    }
+}
+
+impl Ser for ExtMeta {
+    fn ser<S: SeekWrite>(&self, mut output: S) -> Result<u64, Error> {
+        output.write_u32::<BigEndian>(self.attrs.len() as u32)?;
+        let mut written = 4;
+        for (key, value) in self.attrs.iter() {
+            written += write_lpstr(&mut output, LPWidth::LP32, Endianness::BigEndian, key)?;
+            written += write_lpbuf(&mut output, LPWidth::LP32, Endianness::BigEndian, value)?;
+        }
+        Ok(written)
+    }
+}
+
+impl De for ExtMeta {
+    fn de<D: SeekRead>(stream: D) -> Result<Self, Error>
+    where
+        Self: Sized,
+    {
+        Self::de_within(stream, u64::MAX)
+    }
+}
+
+impl ExtMeta {
+    /// Reads metadata that has to end before `end`, so corrupted lengths
+    /// fail instead of allocating what the stream can not hold
+    pub(crate) fn de_within<D: SeekRead>(mut stream: D, end: u64) -> Result<Self, Error> {
+        let count = stream.read_u32::<BigEndian>()? as u64;
+        // every attribute takes at least its two lengths
+        if count > remaining(&mut stream, end)? / 8 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{} metadata attributes do not fit the toc", count),
+            ));
+        }
+        let mut meta = ExtMeta::default();
+        for _ in 0..count {
+            let max = remaining(&mut stream, end)?.min(MAX_NAME_LEN as u64);
+            let key = read_lpstr_max(&mut stream, max)?;
+            let max = remaining(&mut stream, end)?;
+            let value = read_lpbuf_max(&mut stream, max)?;
+            meta.attrs.insert(key, value);
+        }
+        Ok(meta)
+    }
+}
+
+/// Collects the extended metadata of a file on disk, of the link itself
+/// rather than its target unless `follow` is set
+pub(crate) fn source_meta(path: &Path, follow: bool) -> ExtMeta {
+    let mut meta = ExtMeta::default();
+    let stat = match follow {
+        true => std::fs::metadata(path),
+        false => std::fs::symlink_metadata(path),
+    };
+    let Ok(fs_meta) = stat else {
+        return meta;
+    };
+    if fs_meta.file_type().is_symlink() {
+        meta.set_entry_type(EntryType::Symlink);
+    } else if fs_meta.is_dir() {
+        meta.set_entry_type(EntryType::Dir);
+    }
+    // statx on Linux, the creation time on Windows and macOS
+    if let Ok(created) = fs_meta.created() {
+        meta.set_birth_time(created);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        // the permissions of a link are never used
+        if !fs_meta.file_type().is_symlink() {
+            meta.set_u64(keys::MODE, (fs_meta.mode() & 0o7777) as u64);
+        }
+        meta.set_u64(keys::UID, fs_meta.uid() as u64);
+        meta.set_u64(keys::GID, fs_meta.gid() as u64);
+        if let Some(name) = crate::helpers::user_name(fs_meta.uid()) {
+            meta.set_str(keys::UNAME, &name);
+        }
+        if let Some(name) = crate::helpers::group_name(fs_meta.gid()) {
+            meta.set_str(keys::GNAME, &name);
+        }
+    }
+    meta
+}
@@ -0,0 +1,231 @@
+//! Package payload mode, a depot carrying an install prefix, install scripts
+//! and a manifest in reserved streams can be installed like a lightweight
+//! system package
+
+use crate::depot_handle::{DepotHandle, StreamInfo};
+use crate::error::DepotError;
+use crate::extract::ExtractOptions;
+use std::fs;
+use std::io::{Cursor, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+
+/// Stream holding the package manifest
+pub const MANIFEST_STREAM: &str = ".depot/package/manifest";
+/// Stream holding the script run before files are installed
+pub const PRE_INSTALL_STREAM: &str = ".depot/package/pre-install";
+/// Stream holding the script run after files are installed
+pub const POST_INSTALL_STREAM: &str = ".depot/package/post-install";
+/// Directory under the install prefix where uninstall manifests are recorded
+pub const RECEIPT_DIR: &str = ".depot-installed";
+
+/// Package metadata stored in a depot
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PackageInfo {
+    pub name: String,
+    pub version: String,
+    /// Default install prefix, used when none is given at install time
+    pub prefix: PathBuf,
+    /// Shell script run before files are installed
+    pub pre_install: Option<String>,
+    /// Shell script run after files are installed
+    pub post_install: Option<String>,
+}
+
+impl PackageInfo {
+    fn manifest(&self) -> String {
+        format!(
+            "name={}\nversion={}\nprefix={}\n",
+            self.name,
+            self.version,
+            self.prefix.display()
+        )
+    }
+
+    fn parse_manifest(manifest: &str) -> Result<Self, DepotError> {
+        let corrupt = |reason: String| DepotError::CorruptEntry {
+            name: MANIFEST_STREAM.to_owned(),
+            reason,
+        };
+        let mut info = PackageInfo::default();
+        for line in manifest.lines().filter(|l| !l.trim().is_empty()) {
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| corrupt(format!("has an invalid line `{}`", line)))?;
+            match key {
+                "name" => info.name = value.to_owned(),
+                "version" => info.version = value.to_owned(),
+                "prefix" => info.prefix = PathBuf::from(value),
+                // unknown keys are ignored for forward compatibility
+                _ => {}
+            }
+        }
+        if info.name.is_empty() {
+            return Err(corrupt("has no name".to_owned()));
+        }
+        check_name(&info.name).map_err(|e| corrupt(e.to_string()))?;
+        Ok(info)
+    }
+}
+
+/// Package names name the uninstall manifest under [`RECEIPT_DIR`], so they
+/// have to be a single plain path component, without separators, `..` or a
+/// root that would place it elsewhere, and without control characters
+fn check_name(name: &str) -> Result<(), DepotError> {
+    let mut components = Path::new(name).components();
+    let plain = matches!(components.next(), Some(Component::Normal(_)))
+        && components.next().is_none()
+        && !name.contains(['/', '\\'])
+        && !name.contains(char::is_control);
+    if !plain {
+        return Err(DepotError::InvalidName {
+            name: name.to_owned(),
+            reason: "is not a plain file name for a package".to_owned(),
+        });
+    }
+    Ok(())
+}
+
+/// Manifest values end at the line break, one within a value, or any other
+/// control character, would forge the lines after it
+fn check_value(key: &str, value: &str) -> Result<(), DepotError> {
+    if value.contains(char::is_control) {
+        return Err(DepotError::InvalidInput(format!(
+            "package {} `{}` contains a control character",
+            key,
+            value.escape_debug()
+        )));
+    }
+    Ok(())
+}
+
+/// What [`install`] did
+#[derive(Debug, Clone)]
+pub struct InstallReceipt {
+    pub package: PackageInfo,
+    pub prefix: PathBuf,
+    /// Files written, absolute or relative to the working directory like `prefix`
+    pub files: Vec<PathBuf>,
+    /// Uninstall manifest listing every installed file
+    pub manifest: PathBuf,
+}
+
+/// Writes the package manifest and scripts into reserved streams
+pub fn write_package_info(depot: &mut DepotHandle, info: &PackageInfo) -> Result<(), DepotError> {
+    check_name(&info.name)?;
+    check_value("version", &info.version)?;
+    check_value("prefix", &info.prefix.to_string_lossy())?;
+    add_text(depot, MANIFEST_STREAM, &info.manifest())?;
+    if let Some(script) = &info.pre_install {
+        add_text(depot, PRE_INSTALL_STREAM, script)?;
+    }
+    if let Some(script) = &info.post_install {
+        add_text(depot, POST_INSTALL_STREAM, script)?;
+    }
+    Ok(())
+}
+
+/// Reads the package metadata, `None` when the depot is not a package
+pub fn read_package_info(depot: &mut DepotHandle) -> Result<Option<PackageInfo>, DepotError> {
+    let manifest = match read_text(depot, MANIFEST_STREAM)? {
+        Some(m) => m,
+        None => return Ok(None),
+    };
+    let mut info = PackageInfo::parse_manifest(&manifest)?;
+    info.pre_install = read_text(depot, PRE_INSTALL_STREAM)?;
+    info.post_install = read_text(depot, POST_INSTALL_STREAM)?;
+    Ok(Some(info))
+}
+
+/// Installs a package depot under `prefix` (or the recorded default),
+/// running its scripts when `run_scripts` is set and recording an uninstall
+/// manifest under [`RECEIPT_DIR`]
+pub fn install(
+    depot: &mut DepotHandle,
+    prefix: Option<&Path>,
+    options: &ExtractOptions,
+    run_scripts: bool,
+) -> Result<InstallReceipt, DepotError> {
+    let package = read_package_info(depot)?.ok_or_else(|| {
+        DepotError::InvalidInput("depot has no package manifest, not a package".to_owned())
+    })?;
+    let prefix = prefix.unwrap_or(&package.prefix).to_path_buf();
+    fs::create_dir_all(&prefix)?;
+
+    if run_scripts {
+        if let Some(script) = &package.pre_install {
+            run_script(&package, "pre-install", script, &prefix)?;
+        }
+    }
+
+    let streams: Vec<StreamInfo> = depot
+        .streams()
+        .map(|(name, einf)| (name.clone(), einf.clone()).into())
+        .collect();
+    let mut files = Vec::new();
+    let mut record = |_: &StreamInfo, path: &Path| files.push(path.to_path_buf());
+    depot.extract_to_dir(&streams, &prefix, options, Some(&mut record))?;
+
+    let receipt_dir = prefix.join(RECEIPT_DIR);
+    fs::create_dir_all(&receipt_dir)?;
+    let manifest = receipt_dir.join(format!("{}.files", package.name));
+    let mut fh = fs::File::create(&manifest)?;
+    writeln!(fh, "# {} {}", package.name, package.version)?;
+    for file in &files {
+        writeln!(fh, "{}", file.display())?;
+    }
+
+    if run_scripts {
+        if let Some(script) = &package.post_install {
+            run_script(&package, "post-install", script, &prefix)?;
+        }
+    }
+
+    Ok(InstallReceipt {
+        package,
+        prefix,
+        files,
+        manifest,
+    })
+}
+
+fn run_script(
+    package: &PackageInfo,
+    stage: &str,
+    script: &str,
+    prefix: &Path,
+) -> Result<(), DepotError> {
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(script)
+        .current_dir(prefix)
+        .env("DEPOT_PACKAGE", &package.name)
+        .env("DEPOT_VERSION", &package.version)
+        .env("DEPOT_PREFIX", prefix)
+        .status()?;
+    if !status.success() {
+        return Err(DepotError::InvalidInput(format!(
+            "{} script of {} failed: {}",
+            stage, package.name, status
+        )));
+    }
+    Ok(())
+}
+
+fn add_text(depot: &mut DepotHandle, name: &str, text: &str) -> Result<(), DepotError> {
+    depot.add_reserved_stream(name, Cursor::new(text.as_bytes()))
+}
+
+fn read_text(depot: &mut DepotHandle, name: &str) -> Result<Option<String>, DepotError> {
+    let stream = match depot.get_named_stream(name) {
+        Some(s) => s,
+        None => return Ok(None),
+    };
+    let bytes = depot.stream_to_memory(&stream)?;
+    String::from_utf8(bytes)
+        .map(Some)
+        .map_err(|e| DepotError::CorruptEntry {
+            name: name.to_owned(),
+            reason: format!("is not valid UTF-8: {}", e),
+        })
+}
@@ -0,0 +1,401 @@
+//! Minimal perfect hash index over entry names, baked into a reserved
+//! stream at finalize so runtimes can resolve names in constant time
+//! without building the toc map. Built with the hash and displace scheme,
+//! every name maps to exactly one slot of a table as large as the entry
+//! count. The index is the last stream before the toc and ends in a
+//! trailer, so [`PhfReader`] finds it from the header alone and reads
+//! entries without ever reading the toc
+
+use crate::crypt::EncryptionKey;
+use crate::depot_handle::{
+    block_member, check_extracted, decoder, entry_dict, entry_key, DepotHeader, EntryInfo,
+    StreamInfo,
+};
+use crate::dict::{Dictionary, DICT_PREFIX};
+use crate::error::DepotError;
+use crate::hash::EntryHasher;
+use crate::helpers::{De, Ser};
+use crate::meta::keys;
+use crate::{entry_id, normalize_id_name, FORMAT_VERSION, MAX_TOC_SIZE};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use neoncore::const_fn::ascii_to_u64_be;
+use neoncore::streams::{SeekRead, SeekWrite};
+use std::io::{BufReader, Cursor, Error, ErrorKind, Read, SeekFrom};
+
+/// Reserved stream holding the index, stored uncompressed
+pub const PHF_STREAM: &str = ".depot/index/phf";
+
+/// cbindgen:ignore
+pub const PHF_MAGIC: u64 = ascii_to_u64_be(b"DEPOTPHF");
+
+const PHF_VERSION: u32 = 2;
+/// the index length and magic closing the index
+const TRAILER_SIZE: u64 = 16;
+/// average number of keys per displacement bucket
+const BUCKET_LOAD: usize = 4;
+/// seeds tried before giving up, failing even once is very unlikely
+const MAX_SEEDS: u64 = 16;
+
+/// An entry as recorded in the index, with everything needed to read it
+#[derive(Debug, Clone)]
+pub struct PhfSlot {
+    /// [`entry_id`] of the name, used to reject names not in the index
+    pub id: u64,
+    pub entry: EntryInfo,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PhfIndex {
+    seed: u64,
+    displacements: Vec<(u32, u32)>,
+    slots: Vec<PhfSlot>,
+}
+
+struct Hashes {
+    bucket: u64,
+    f1: u32,
+    f2: u32,
+}
+
+fn hashes(id: u64, name: &str, seed: u64) -> Hashes {
+    let h = seahash::hash_seeded(name.as_bytes(), seed, !seed, id, PHF_VERSION as u64);
+    Hashes {
+        bucket: id ^ seed,
+        f1: h as u32,
+        f2: (h >> 32) as u32,
+    }
+}
+
+fn slot_of(h: &Hashes, (d1, d2): (u32, u32), len: usize) -> usize {
+    (d2.wrapping_add(h.f1.wrapping_mul(d1)).wrapping_add(h.f2) as usize) % len
+}
+
+impl PhfIndex {
+    /// Builds the index over `entries`
+    pub fn build<'a, I>(entries: I) -> Result<Self, DepotError>
+    where
+        I: IntoIterator<Item = (&'a String, &'a EntryInfo)>,
+    {
+        let keys: Vec<(String, &EntryInfo)> = entries
+            .into_iter()
+            .map(|(name, entry)| (normalize_id_name(name), entry))
+            .collect();
+        if keys.is_empty() {
+            return Ok(Self::default());
+        }
+        for seed in 0..MAX_SEEDS {
+            if let Some(index) = Self::try_build(&keys, seed) {
+                return Ok(index);
+            }
+        }
+        Err(DepotError::InvalidInput(
+            "no perfect hash found, entry names have colliding ids".to_owned(),
+        ))
+    }
+
+    fn try_build(keys: &[(String, &EntryInfo)], seed: u64) -> Option<Self> {
+        let len = keys.len();
+        let bucket_count = len.div_ceil(BUCKET_LOAD);
+        let hashes: Vec<Hashes> = keys
+            .iter()
+            .map(|(name, _)| hashes(entry_id(name), name, seed))
+            .collect();
+
+        let mut buckets: Vec<Vec<usize>> = vec![Vec::new(); bucket_count];
+        for (i, h) in hashes.iter().enumerate() {
+            buckets[(h.bucket % bucket_count as u64) as usize].push(i);
+        }
+        // place the largest buckets first while the table is still empty
+        let mut order: Vec<usize> = (0..bucket_count).collect();
+        order.sort_by_key(|&b| std::cmp::Reverse(buckets[b].len()));
+
+        let mut taken = vec![false; len];
+        let mut slot_key = vec![usize::MAX; len];
+        let mut displacements = vec![(0, 0); bucket_count];
+        let mut tried = Vec::new();
+        for b in order {
+            let bucket = &buckets[b];
+            if bucket.is_empty() {
+                continue;
+            }
+            let placed = (0..len as u32)
+                .flat_map(|d1| (0..len as u32).map(move |d2| (d1, d2)))
+                .find(|&d| {
+                    tried.clear();
+                    for &key in bucket {
+                        let slot = slot_of(&hashes[key], d, len);
+                        if taken[slot] || tried.contains(&slot) {
+                            return false;
+                        }
+                        tried.push(slot);
+                    }
+                    true
+                })?;
+            displacements[b] = placed;
+            for &key in bucket {
+                let slot = slot_of(&hashes[key], placed, len);
+                taken[slot] = true;
+                slot_key[slot] = key;
+            }
+        }
+
+        let slots = slot_key
+            .into_iter()
+            .map(|key| {
+                let (name, entry) = &keys[key];
+                PhfSlot {
+                    id: entry_id(name),
+                    entry: (*entry).clone(),
+                }
+            })
+            .collect();
+        Some(Self {
+            seed,
+            displacements,
+            slots,
+        })
+    }
+
+    /// Resolves `name` in constant time, `None` if it is not in the index
+    pub fn lookup(&self, name: &str) -> Option<&PhfSlot> {
+        if self.slots.is_empty() {
+            return None;
+        }
+        let name = normalize_id_name(name);
+        let id = entry_id(&name);
+        let h = hashes(id, &name, self.seed);
+        let bucket = (h.bucket % self.displacements.len() as u64) as usize;
+        let slot = &self.slots[slot_of(&h, self.displacements[bucket], self.slots.len())];
+        (slot.id == id).then_some(slot)
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Parses an index read from [`PHF_STREAM`]
+    pub fn from_bytes(data: &[u8]) -> Result<Self, DepotError> {
+        Ok(Self::de(Cursor::new(data))?)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Cursor::new(Vec::new());
+        // writing to memory can not fail
+        self.ser(&mut data).unwrap();
+        data.into_inner()
+    }
+}
+
+impl Ser for PhfIndex {
+    fn ser<S: SeekWrite>(&self, mut output: S) -> Result<u64, Error> {
+        let start = output.stream_position()?;
+        output.write_u32::<BigEndian>(PHF_VERSION)?;
+        output.write_u64::<BigEndian>(self.seed)?;
+        output.write_u32::<BigEndian>(self.displacements.len() as u32)?;
+        output.write_u32::<BigEndian>(self.slots.len() as u32)?;
+        for (d1, d2) in &self.displacements {
+            output.write_u32::<BigEndian>(*d1)?;
+            output.write_u32::<BigEndian>(*d2)?;
+        }
+        for slot in &self.slots {
+            output.write_u64::<BigEndian>(slot.id)?;
+            slot.entry.ser(&mut output)?;
+            slot.entry.ext.ser(&mut output)?;
+        }
+        let len = output.stream_position()? - start + TRAILER_SIZE;
+        output.write_u64::<BigEndian>(len)?;
+        output.write_u64::<BigEndian>(PHF_MAGIC)?;
+        Ok(len)
+    }
+}
+
+impl De for PhfIndex {
+    fn de<D: SeekRead>(mut stream: D) -> Result<Self, Error>
+    where
+        Self: Sized,
+    {
+        let invalid = |reason: &str| Error::new(ErrorKind::InvalidData, reason.to_owned());
+        let start = stream.stream_position()?;
+        let end = stream.seek(SeekFrom::End(0))?;
+        stream.seek(SeekFrom::Start(start))?;
+        let version = stream.read_u32::<BigEndian>()?;
+        if version != PHF_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported perfect hash index version {}", version),
+            ));
+        }
+        let seed = stream.read_u64::<BigEndian>()?;
+        let bucket_count = stream.read_u32::<BigEndian>()?;
+        let len = stream.read_u32::<BigEndian>()?;
+        if (bucket_count == 0) != (len == 0) {
+            return Err(invalid("perfect hash index has slots without buckets"));
+        }
+        // every bucket takes 8 bytes and every slot more than 64, checked
+        // before anything is allocated for them
+        let left = end.saturating_sub(stream.stream_position()?);
+        if bucket_count as u64 * 8 + len as u64 * 64 > left {
+            return Err(invalid("perfect hash index is larger than its stream"));
+        }
+        let displacements = (0..bucket_count)
+            .map(|_| {
+                Ok((
+                    stream.read_u32::<BigEndian>()?,
+                    stream.read_u32::<BigEndian>()?,
+                ))
+            })
+            .collect::<Result<_, Error>>()?;
+        let slots = (0..len)
+            .map(|_| {
+                let id = stream.read_u64::<BigEndian>()?;
+                let entry = EntryInfo::de_with_ext(&mut stream, end)?;
+                Ok(PhfSlot { id, entry })
+            })
+            .collect::<Result<_, Error>>()?;
+        let written = stream.stream_position()? - start + TRAILER_SIZE;
+        if stream.read_u64::<BigEndian>()? != written
+            || stream.read_u64::<BigEndian>()? != PHF_MAGIC
+        {
+            return Err(invalid("perfect hash index has a damaged trailer"));
+        }
+        Ok(Self {
+            seed,
+            displacements,
+            slots,
+        })
+    }
+}
+
+/// Reads entries of a depot through its perfect hash index alone, for
+/// runtimes that can not afford building the toc map when they start. The
+/// index holds no names, so entries can be resolved but not listed
+pub struct PhfReader<R> {
+    reader: R,
+    index: PhfIndex,
+    dictionaries: Vec<Dictionary>,
+    encryption_key: Option<EncryptionKey>,
+}
+
+impl<R: SeekRead> PhfReader<R> {
+    /// Opens the depot starting at the current position of `reader` by the
+    /// index in front of its toc, a depot baked without an index fails with
+    /// [`DepotError::EntryNotFound`] for [`PHF_STREAM`]
+    pub fn open(mut reader: R) -> Result<Self, DepotError> {
+        let data_start = reader.stream_position()? + DepotHeader::SIZE;
+        let header = DepotHeader::de(&mut reader)?;
+        if !(1..=FORMAT_VERSION).contains(&header.version) {
+            return Err(DepotError::UnsupportedVersion {
+                found: header.version,
+                supported: FORMAT_VERSION,
+            });
+        }
+        let missing = || DepotError::EntryNotFound(PHF_STREAM.to_owned());
+        if header.toc_offset < data_start + TRAILER_SIZE {
+            return Err(missing());
+        }
+        reader.seek(SeekFrom::Start(header.toc_offset - TRAILER_SIZE))?;
+        let len = reader.read_u64::<BigEndian>()?;
+        if reader.read_u64::<BigEndian>()? != PHF_MAGIC {
+            return Err(missing());
+        }
+        if len < TRAILER_SIZE || len > header.toc_offset - data_start || len > MAX_TOC_SIZE {
+            return Err(DepotError::CorruptEntry {
+                name: PHF_STREAM.to_owned(),
+                reason: format!("claims {} bytes before the toc", len),
+            });
+        }
+        reader.seek(SeekFrom::Start(header.toc_offset - len))?;
+        let mut data = vec![0; len as usize];
+        reader.read_exact(&mut data)?;
+        let index = PhfIndex::from_bytes(&data).map_err(|e| DepotError::CorruptEntry {
+            name: PHF_STREAM.to_owned(),
+            reason: e.to_string(),
+        })?;
+        Ok(Self {
+            reader,
+            index,
+            dictionaries: Vec::new(),
+            encryption_key: None,
+        })
+    }
+
+    pub fn index(&self) -> &PhfIndex {
+        &self.index
+    }
+
+    /// Key to decrypt encrypted entries with, see
+    /// [`crate::depot_handle::DepotHandle::set_encryption_key`]
+    pub fn set_encryption_key(&mut self, key: Option<EncryptionKey>) {
+        self.encryption_key = key;
+    }
+
+    /// Size, flags and location of the entry named `name`
+    pub fn stat(&self, name: &str) -> Option<StreamInfo> {
+        let slot = self.index.lookup(name)?;
+        Some((name.to_owned(), slot.entry.clone()).into())
+    }
+
+    /// Reads the whole entry `name` into memory, its size and hash are
+    /// checked
+    pub fn read_all(&mut self, name: &str) -> Result<Vec<u8>, DepotError> {
+        let stream = self
+            .stat(name)
+            .ok_or_else(|| DepotError::EntryNotFound(name.to_owned()))?;
+        let entry = &stream.einf;
+        if let Some(tag) = entry.ext.get_str(keys::DICT) {
+            self.load_dictionary(tag)?;
+        }
+        let dict = entry_dict(&self.dictionaries, name, entry)?;
+        let key = entry_key(self.encryption_key.as_ref(), name, entry)?;
+        let solid = entry.flags & EntryInfo::FLAG_SOLID != 0;
+        // a solid member is decoded from the start of its block
+        let (at, end) = match entry.ext.solid_block() {
+            Some((_, at)) if solid => (at, at.saturating_add(entry.size)),
+            None if solid => {
+                return Err(DepotError::CorruptEntry {
+                    name: name.to_owned(),
+                    reason: "is missing the solid block it is stored in".to_owned(),
+                })
+            }
+            _ => (0, entry.size),
+        };
+        self.reader.seek(SeekFrom::Start(entry.offset))?;
+        let mut data = Vec::new();
+        decoder(
+            name,
+            entry,
+            dict.as_deref(),
+            key.as_ref(),
+            BufReader::new(&mut self.reader),
+        )?
+        // one byte more shows an entry longer than recorded
+        .take(end.saturating_add(1))
+        .read_to_end(&mut data)?;
+        if solid {
+            data = block_member(name, &data, at, entry.size)?.to_vec();
+        }
+        let mut hasher = EntryHasher::new();
+        hasher.update(&data);
+        check_extracted(name, entry, data.len() as u64, hasher.finish())?;
+        Ok(data)
+    }
+
+    /// Reads the dictionary `tag` through the index unless it was read before
+    fn load_dictionary(&mut self, tag: &str) -> Result<(), DepotError> {
+        if self.dictionaries.iter().any(|dict| dict.tag() == tag) {
+            return Ok(());
+        }
+        let name = format!("{}{}", DICT_PREFIX, tag);
+        // dictionaries are stored without one, so this does not recurse
+        if self.index.lookup(&name).is_none() {
+            return Ok(());
+        }
+        let data = self.read_all(&name)?;
+        self.dictionaries.push(Dictionary::from_bytes(tag, &data)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,214 @@
+//! Content policies checked while entries are added, so a bake fails or
+//! warns before debug symbols, `.env` files or keys end up in a shipped
+//! depot, see [`crate::depot_handle::DepotHandle::set_content_policy`].
+//! Names are checked before any data is written, sizes and secrets once the
+//! entry was written, an entry refused then is dropped again
+
+use crate::error::DepotError;
+use regex::bytes::Regex;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::Path;
+
+/// Bytes of an entry scanned for secrets at once
+const SCAN_CHUNK: usize = 256 * 1024;
+/// Bytes kept from the previous chunk, secrets longer than this may be
+/// missed where they cross chunks
+const SCAN_OVERLAP: usize = 4 * 1024;
+
+/// Built in secret rules, see [`ContentPolicy::default_secrets`]
+const DEFAULT_SECRETS: &[(&str, &str)] = &[
+    ("private-key", r"-----BEGIN (?:[A-Z0-9]+ )*PRIVATE KEY-----"),
+    ("aws-access-key", r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b"),
+    ("github-token", r"\bgh[pousr]_[A-Za-z0-9]{36,}"),
+    ("slack-token", r"\bxox[abposr]-[A-Za-z0-9-]{10,}"),
+    ("google-api-key", r"\bAIza[0-9A-Za-z_-]{35}"),
+];
+
+/// What to do with an entry breaking the policy
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PolicyAction {
+    /// Keep the entry and record a warning
+    Warn,
+    /// Fail the add with [`DepotError::PolicyViolation`]
+    #[default]
+    Error,
+}
+
+/// Rules entries are held against as they are added
+#[derive(Debug, Clone, Default)]
+pub struct ContentPolicy {
+    deny: Vec<glob::Pattern>,
+    /// largest size by lower case extension, `*` for every entry
+    max_size: BTreeMap<String, u64>,
+    secrets: Vec<(String, Regex)>,
+    pub action: PolicyAction,
+}
+
+impl ContentPolicy {
+    pub fn new(action: PolicyAction) -> Self {
+        Self {
+            action,
+            ..Self::default()
+        }
+    }
+
+    /// Refuses the entries matching `pattern`. Patterns with a `/` match
+    /// the whole name like [`crate::depot_handle::DepotHandle::find_streams`],
+    /// others only its last component, so `*.pdb` denies debug symbols in
+    /// every directory
+    pub fn deny(&mut self, pattern: &str) -> Result<(), DepotError> {
+        let pattern = match pattern.ends_with('/') {
+            true => format!("{}**", pattern),
+            false => pattern.to_owned(),
+        };
+        let matcher = glob::Pattern::new(&pattern).map_err(|e| DepotError::InvalidPattern {
+            pattern: pattern.clone(),
+            reason: e.to_string(),
+        })?;
+        self.deny.push(matcher);
+        Ok(())
+    }
+
+    /// Caps the size of entries with the extension `ext`, compared without
+    /// regard to case, `*` caps every entry without a cap of its own
+    pub fn max_size(&mut self, ext: &str, max: u64) {
+        let ext = ext.trim_start_matches('.').to_ascii_lowercase();
+        self.max_size.insert(ext, max);
+    }
+
+    /// Flags entries whose data matches `regex` as holding a secret, `label`
+    /// names the rule in messages
+    pub fn secret(&mut self, label: &str, regex: &str) -> Result<(), DepotError> {
+        let regex = Regex::new(regex).map_err(|e| DepotError::InvalidPattern {
+            pattern: regex.to_owned(),
+            reason: format!("secret rule {}: {}", label, e),
+        })?;
+        self.secrets.push((label.to_owned(), regex));
+        Ok(())
+    }
+
+    /// Adds rules for private key blocks and AWS, GitHub, Slack and Google
+    /// API credentials
+    pub fn default_secrets(&mut self) {
+        for (label, regex) in DEFAULT_SECRETS {
+            // the built in rules are known to compile
+            self.secret(label, regex).unwrap();
+        }
+    }
+
+    /// true when entry data has to be scanned
+    pub(crate) fn scans(&self) -> bool {
+        !self.secrets.is_empty()
+    }
+
+    /// Why the name `name` is refused, if it is
+    pub(crate) fn check_name(&self, name: &str) -> Option<String> {
+        let options = glob::MatchOptions {
+            case_sensitive: true,
+            require_literal_separator: true,
+            require_literal_leading_dot: false,
+        };
+        let file_name = name.rsplit('/').next().unwrap_or(name);
+        self.deny
+            .iter()
+            .find(|pattern| match pattern.as_str().contains('/') {
+                true => pattern.matches_with(name, options),
+                false => pattern.matches_with(file_name, options),
+            })
+            .map(|pattern| format!("it matches the denied pattern {}", pattern))
+    }
+
+    /// Why an entry `name` of `size` bytes is refused, if it is
+    pub(crate) fn check_size(&self, name: &str, size: u64) -> Option<String> {
+        let ext = Path::new(name)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+        let (ext, max) = ext
+            .and_then(|ext| self.max_size.get_key_value(&ext))
+            .or_else(|| self.max_size.get_key_value("*"))?;
+        (size > *max).then(|| match ext.as_str() {
+            "*" => format!("it is {} bytes, entries may be {} at most", size, max),
+            ext => format!(
+                "it is {} bytes, .{} entries may be {} at most",
+                size, ext, max
+            ),
+        })
+    }
+}
+
+/// Scans the data of an entry for secrets as it is read
+pub(crate) struct SecretScan<'p> {
+    policy: &'p ContentPolicy,
+    window: Vec<u8>,
+    /// offset of the first byte of `window` in the entry
+    base: u64,
+    found: Option<String>,
+}
+
+impl<'p> SecretScan<'p> {
+    pub(crate) fn new(policy: &'p ContentPolicy) -> Self {
+        Self {
+            policy,
+            window: Vec::new(),
+            base: 0,
+            found: None,
+        }
+    }
+
+    fn feed(&mut self, data: &[u8]) {
+        if self.found.is_some() {
+            return;
+        }
+        self.window.extend_from_slice(data);
+        if self.window.len() >= SCAN_CHUNK + SCAN_OVERLAP {
+            self.scan();
+            let drop = self.window.len() - SCAN_OVERLAP;
+            self.window.drain(..drop);
+            self.base += drop as u64;
+        }
+    }
+
+    fn scan(&mut self) {
+        for (label, regex) in &self.policy.secrets {
+            if let Some(found) = regex.find(&self.window) {
+                let at = self.base + found.start() as u64;
+                self.found = Some(format!(
+                    "it matches the secret rule {} at byte {}",
+                    label, at
+                ));
+                return;
+            }
+        }
+    }
+
+    /// Scans what is left and returns why the entry is refused, if it is
+    pub(crate) fn finish(mut self) -> Option<String> {
+        if self.found.is_none() {
+            self.scan();
+        }
+        self.found
+    }
+}
+
+/// Feeds everything read through it to a [`SecretScan`]
+pub(crate) struct Scanned<'s, 'p, R> {
+    inner: R,
+    scan: Option<&'s mut SecretScan<'p>>,
+}
+
+impl<'s, 'p, R> Scanned<'s, 'p, R> {
+    pub(crate) fn new(inner: R, scan: Option<&'s mut SecretScan<'p>>) -> Self {
+        Self { inner, scan }
+    }
+}
+
+impl<R: Read> Read for Scanned<'_, '_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some(scan) = &mut self.scan {
+            scan.feed(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
@@ -0,0 +1,276 @@
+//! Extraction in priority order for launchers that start before a depot is
+//! fully extracted, the entries needed next can be queued or moved to the
+//! front at any time while the rest keeps extracting in the background
+
+use crate::depot_handle::{DepotHandle, OpenMode, StreamInfo};
+use crate::error::DepotError;
+use crate::extract::ExtractOptions;
+use crate::meta::EntryType;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// Outcome of a queued entry
+#[derive(Debug)]
+pub enum PriorityEvent {
+    /// The entry was extracted to `path`
+    Extracted { name: String, path: PathBuf },
+    /// The entry could not be extracted, it is not queued again
+    Failed { name: String, error: DepotError },
+    /// The depot could not be opened, nothing is extracted
+    Unreadable(DepotError),
+}
+
+#[derive(Default)]
+struct Queue {
+    /// highest priority first, entries of equal priority in queueing order
+    order: BTreeSet<(Reverse<i64>, u64, String)>,
+    queued: HashMap<String, (i64, u64)>,
+    /// the entry being extracted
+    active: Option<String>,
+    /// symlinks held back until the extractor stops
+    links: HashSet<String>,
+    done: HashSet<String>,
+    next_seq: u64,
+    /// extract what is queued, then stop
+    finishing: bool,
+    stopped: bool,
+}
+
+impl Queue {
+    fn pop(&mut self) -> Option<String> {
+        let (_, _, name) = self.order.pop_first()?;
+        self.queued.remove(&name);
+        self.active = Some(name.clone());
+        Some(name)
+    }
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    wake: Condvar,
+}
+
+/// Extracts queued entries of a depot on a background thread, highest
+/// priority first
+pub struct PriorityExtractor {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PriorityExtractor {
+    /// Starts extracting entries of the depot at `path` into `output` as they
+    /// are queued. `on_event` runs on the extracting thread once per entry.
+    /// Symlinks are only created once the extractor stops, after every file
+    pub fn start<P, Q, F>(path: P, output: Q, options: ExtractOptions, mut on_event: F) -> Self
+    where
+        P: Into<PathBuf>,
+        Q: Into<PathBuf>,
+        F: FnMut(PriorityEvent) + Send + 'static,
+    {
+        let path = path.into();
+        let output = output.into();
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue::default()),
+            wake: Condvar::new(),
+        });
+        let worker = shared.clone();
+
+        let thread = thread::spawn(move || {
+            let mut dh = match DepotHandle::open_file(&path, OpenMode::Read) {
+                Ok(dh) => dh,
+                Err(e) => return on_event(PriorityEvent::Unreadable(e)),
+            };
+            // links are created in one batch after every file, so no
+            // later entry is written through one of them
+            let mut links = Vec::new();
+            while let Some(name) = worker.next() {
+                let event = match dh.get_named_stream(&name) {
+                    Some(stream) if stream.einf.entry_type() == EntryType::Symlink => {
+                        links.push(stream);
+                        let mut queue = worker.queue.lock().unwrap();
+                        queue.active = None;
+                        queue.links.insert(name);
+                        continue;
+                    }
+                    Some(stream) => extract_batch(&mut dh, &[stream], &output, &options),
+                    None => vec![PriorityEvent::Failed {
+                        name: name.clone(),
+                        error: DepotError::EntryNotFound(name.clone()),
+                    }],
+                };
+                {
+                    let mut queue = worker.queue.lock().unwrap();
+                    queue.active = None;
+                    queue.done.insert(name);
+                }
+                event.into_iter().for_each(&mut on_event);
+            }
+            if !links.is_empty() {
+                let events = extract_batch(&mut dh, &links, &output, &options);
+                {
+                    let mut queue = worker.queue.lock().unwrap();
+                    let links = std::mem::take(&mut queue.links);
+                    queue.done.extend(links);
+                }
+                events.into_iter().for_each(&mut on_event);
+            }
+        });
+
+        Self {
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    /// Queues `name` with `priority`, higher is extracted first. An entry
+    /// that is already queued is moved to the new priority, entries being
+    /// or already extracted are not queued again
+    pub fn push(&self, name: &str, priority: i64) {
+        let mut queue = self.shared.queue.lock().unwrap();
+        if queue.done.contains(name)
+            || queue.links.contains(name)
+            || queue.active.as_deref() == Some(name)
+        {
+            return;
+        }
+        let seq = match queue.queued.remove(name) {
+            Some((old, seq)) => {
+                queue.order.remove(&(Reverse(old), seq, name.to_owned()));
+                seq
+            }
+            None => {
+                queue.next_seq += 1;
+                queue.next_seq
+            }
+        };
+        queue
+            .order
+            .insert((Reverse(priority), seq, name.to_owned()));
+        queue.queued.insert(name.to_owned(), (priority, seq));
+        self.shared.wake.notify_one();
+    }
+
+    /// Queues every name with the same priority, in the given order
+    pub fn push_all<S: AsRef<str>>(&self, names: &[S], priority: i64) {
+        for name in names {
+            self.push(name.as_ref(), priority);
+        }
+    }
+
+    /// Removes `name` from the queue, false if it was not queued
+    pub fn cancel(&self, name: &str) -> bool {
+        let mut queue = self.shared.queue.lock().unwrap();
+        match queue.queued.remove(name) {
+            Some((priority, seq)) => {
+                queue
+                    .order
+                    .remove(&(Reverse(priority), seq, name.to_owned()));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Entries queued and not extracted yet, the one being extracted excluded
+    pub fn pending(&self) -> usize {
+        self.shared.queue.lock().unwrap().order.len()
+    }
+
+    /// true once `name` was extracted or failed
+    pub fn is_done(&self, name: &str) -> bool {
+        self.shared.queue.lock().unwrap().done.contains(name)
+    }
+
+    /// Extracts everything still queued, then stops
+    pub fn finish(mut self) {
+        self.shutdown(true);
+    }
+
+    /// Stops once the entry being extracted is done, the rest of the queue
+    /// is dropped
+    pub fn stop(mut self) {
+        self.shutdown(false);
+    }
+
+    fn shutdown(&mut self, drain: bool) {
+        {
+            let mut queue = self.shared.queue.lock().unwrap();
+            queue.finishing = true;
+            queue.stopped = !drain;
+        }
+        self.shared.wake.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for PriorityExtractor {
+    fn drop(&mut self) {
+        self.shutdown(false);
+    }
+}
+
+impl Shared {
+    /// Waits for the next entry to extract, `None` when stopping
+    fn next(&self) -> Option<String> {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if queue.stopped {
+                return None;
+            }
+            if let Some(name) = queue.pop() {
+                return Some(name);
+            }
+            if queue.finishing {
+                return None;
+            }
+            queue = self.wake.wait(queue).unwrap();
+        }
+    }
+}
+
+/// Extracts `streams` in one call, one event per stream
+fn extract_batch(
+    dh: &mut DepotHandle,
+    streams: &[StreamInfo],
+    output: &Path,
+    options: &ExtractOptions,
+) -> Vec<PriorityEvent> {
+    let mut paths = HashMap::new();
+    let mut extracted = |stream: &StreamInfo, out: &Path| {
+        paths.insert(stream.name.clone(), out.to_owned());
+    };
+    let result = dh.extract_to_dir(streams, output, options, Some(&mut extracted));
+    let aborted = result.is_err();
+    let mut error = result.err();
+    let mut events = Vec::with_capacity(streams.len());
+    for stream in streams {
+        let name = stream.name.clone();
+        let event = match paths.remove(&name) {
+            Some(path) => PriorityEvent::Extracted { name, path },
+            None => match error.take() {
+                Some(error) => PriorityEvent::Failed { name, error },
+                None if aborted => PriorityEvent::Failed {
+                    error: DepotError::InvalidInput(format!(
+                        "{} was not extracted, an earlier entry failed",
+                        name
+                    )),
+                    name,
+                },
+                None => PriorityEvent::Failed {
+                    error: DepotError::InvalidInput(format!(
+                        "{} is excluded by the path mapper",
+                        name
+                    )),
+                    name,
+                },
+            },
+        };
+        events.push(event);
+    }
+    events
+}
@@ -0,0 +1,60 @@
+//! Object safe read access to depots, so engines can pick a backend at
+//! runtime and keep it behind a single `Box<dyn DepotRead>`
+
+use crate::depot_handle::{DepotHandle, StreamInfo};
+use crate::error::DepotError;
+use crate::scope::ScopedDepot;
+use std::io::Read;
+
+/// Read side of a depot backend
+pub trait DepotRead {
+    /// Size, flags and location of the entry named `name`
+    fn stat(&self, name: &str) -> Option<StreamInfo>;
+
+    /// Names of all user entries
+    fn list(&self) -> Vec<String>;
+
+    /// A reader over the uncompressed contents of `name`
+    fn open_stream(&mut self, name: &str) -> Result<Box<dyn Read + '_>, DepotError>;
+
+    /// Reads the whole entry into memory
+    fn read_all(&mut self, name: &str) -> Result<Vec<u8>, DepotError> {
+        let mut data = Vec::new();
+        self.open_stream(name)?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+}
+
+impl DepotRead for DepotHandle<'_> {
+    fn stat(&self, name: &str) -> Option<StreamInfo> {
+        self.get_named_stream(name)
+    }
+
+    fn list(&self) -> Vec<String> {
+        self.streams().map(|(name, _)| name.clone()).collect()
+    }
+
+    fn open_stream(&mut self, name: &str) -> Result<Box<dyn Read + '_>, DepotError> {
+        let stream = self
+            .get_named_stream(name)
+            .ok_or_else(|| DepotError::EntryNotFound(name.to_owned()))?;
+        Ok(Box::new(DepotHandle::open_stream(self, &stream)?))
+    }
+}
+
+impl DepotRead for ScopedDepot<'_, '_> {
+    fn stat(&self, name: &str) -> Option<StreamInfo> {
+        self.get_named_stream(name)
+    }
+
+    fn list(&self) -> Vec<String> {
+        self.streams().map(|(name, _)| name.to_owned()).collect()
+    }
+
+    fn open_stream(&mut self, name: &str) -> Result<Box<dyn Read + '_>, DepotError> {
+        let stream = self
+            .get_named_stream(name)
+            .ok_or_else(|| DepotError::EntryNotFound(name.to_owned()))?;
+        Ok(Box::new(ScopedDepot::open_stream(self, &stream)?))
+    }
+}
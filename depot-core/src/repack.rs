@@ -0,0 +1,29 @@
+//! Writing the entries of one depot into another, e.g. to bake old depots
+//! again at a higher level or with another frame size without extracting
+//! them first, see [`crate::depot_handle::DepotHandle::repack_into`]
+
+/// Order the entries are written to the new depot in
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum RepackOrder {
+    /// the order they are stored in
+    #[default]
+    Stored,
+    /// name order
+    Name,
+    /// the listed names first in the order given, e.g. the hottest entries
+    /// of an [`crate::trace::AccessTrace`], then the rest as stored
+    Listed(Vec<String>),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RepackOptions {
+    /// decompress every entry and compress it again with the compression,
+    /// level, frame settings, dictionaries and key of the new depot,
+    /// otherwise the stored data is copied as is
+    pub recompress: bool,
+    /// only the entries matching one of these patterns, see
+    /// [`crate::depot_handle::DepotHandle::find_streams`], every entry when
+    /// empty
+    pub include: Vec<String>,
+    pub order: RepackOrder,
+}
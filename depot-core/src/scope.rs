@@ -0,0 +1,182 @@
+//! Namespaced views of a depot, several logical packages such as a base
+//! game and its DLCs can share one physical archive with every view only
+//! seeing and adding entries below its own prefix
+
+use crate::depot_handle::{DepotHandle, DepotStreamReader, EntryInfo, StreamInfo};
+use crate::error::DepotError;
+use crate::ingest::IngestSource;
+use crate::is_reserved_name;
+use neoncore::streams::{SeekRead, SeekWrite};
+use std::io::{Error, Read};
+
+/// A view of the entries below a prefix, see [`DepotHandle::scoped`].
+/// Names passed to and returned from the view are relative to the prefix,
+/// [`StreamInfo`]s keep the full name so they can be used with the depot
+pub struct ScopedDepot<'d, 'io> {
+    depot: &'d mut DepotHandle<'io>,
+    prefix: String,
+}
+
+impl<'io> DepotHandle<'io> {
+    /// A view restricted to the entries below `prefix`, a `/` is appended
+    /// when missing. The reserved namespace can not be scoped into
+    pub fn scoped(&mut self, prefix: &str) -> Result<ScopedDepot<'_, 'io>, DepotError> {
+        let prefix = scope_prefix("", prefix)?;
+        Ok(ScopedDepot {
+            depot: self,
+            prefix,
+        })
+    }
+}
+
+fn scope_prefix(parent: &str, prefix: &str) -> Result<String, DepotError> {
+    let prefix = prefix.trim_start_matches('/');
+    if prefix.is_empty() {
+        return Err(DepotError::InvalidInput("empty scope prefix".to_owned()));
+    }
+    let mut full = format!("{}{}", parent, prefix);
+    if !full.ends_with('/') {
+        full.push('/');
+    }
+    if is_reserved_name(&full) {
+        return Err(DepotError::ReservedName(full));
+    }
+    Ok(full)
+}
+
+impl<'io> ScopedDepot<'_, 'io> {
+    /// The prefix of the view, always ending in `/`
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// A view nested below this one
+    pub fn scoped(&mut self, prefix: &str) -> Result<ScopedDepot<'_, 'io>, DepotError> {
+        let prefix = scope_prefix(&self.prefix, prefix)?;
+        Ok(ScopedDepot {
+            depot: self.depot,
+            prefix,
+        })
+    }
+
+    fn full_name(&self, name: &str) -> String {
+        format!("{}{}", self.prefix, name.trim_start_matches('/'))
+    }
+
+    /// Entries of the view with their names relative to the prefix
+    pub fn streams(&self) -> impl Iterator<Item = (&str, &EntryInfo)> {
+        let prefix = self.prefix.as_str();
+        self.depot
+            .streams()
+            .filter_map(move |(name, einf)| Some((name.strip_prefix(prefix)?, einf)))
+    }
+
+    pub fn stream_count(&self) -> u64 {
+        self.streams().count() as u64
+    }
+
+    pub fn get_named_stream(&self, name: &str) -> Option<StreamInfo> {
+        self.depot.get_named_stream(&self.full_name(name))
+    }
+
+    pub fn add_bytes(&mut self, name: &str, data: &[u8]) -> Result<(), DepotError> {
+        let name = self.full_name(name);
+        self.depot.add_bytes(&name, data)
+    }
+
+    pub fn add_named_sized_stream<R: SeekRead>(
+        &mut self,
+        name: &str,
+        reader: R,
+        size: u64,
+        progress: Option<&mut dyn FnMut(u64, u64)>,
+    ) -> Result<(), DepotError> {
+        let name = self.full_name(name);
+        self.depot
+            .add_named_sized_stream(&name, reader, size, progress)
+    }
+
+    pub fn add_named_stream<R: Read>(
+        &mut self,
+        name: &str,
+        reader: R,
+        progress: Option<&mut dyn FnMut(u64, u64)>,
+    ) -> Result<(), DepotError> {
+        let name = self.full_name(name);
+        self.depot.add_named_stream(&name, reader, progress)
+    }
+
+    /// Adds `source` below the prefix
+    pub fn add_source<S: IngestSource>(
+        &mut self,
+        source: S,
+        progress: Option<&mut dyn FnMut(u64, u64)>,
+    ) -> Result<(), DepotError> {
+        let name = self.full_name(source.name());
+        self.depot.add_source(Renamed { name, source }, progress)
+    }
+
+    fn check_scope(&self, stream: &StreamInfo) -> Result<(), DepotError> {
+        if !stream.name.starts_with(&self.prefix) {
+            return Err(DepotError::OutOfScope {
+                name: stream.name.clone(),
+                scope: self.prefix.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Extracts a stream of this view, streams of other views are refused
+    pub fn extract_stream<W: SeekWrite>(
+        &mut self,
+        stream: &StreamInfo,
+        writer: W,
+    ) -> Result<(), DepotError> {
+        self.check_scope(stream)?;
+        self.depot.extract_stream(stream, writer)
+    }
+
+    /// Opens a stream of this view for lazy reading, see
+    /// [`DepotHandle::open_stream`]
+    pub fn open_stream(
+        &mut self,
+        stream: &StreamInfo,
+    ) -> Result<DepotStreamReader<'_>, DepotError> {
+        self.check_scope(stream)?;
+        self.depot.open_stream(stream)
+    }
+
+    pub fn read_bytes(&mut self, name: &str) -> Result<Vec<u8>, DepotError> {
+        let name = self.full_name(name);
+        self.depot.read_bytes(&name)
+    }
+
+    pub fn read_string(&mut self, name: &str) -> Result<String, DepotError> {
+        let name = self.full_name(name);
+        self.depot.read_string(&name)
+    }
+}
+
+/// Stores a source under another name
+struct Renamed<S> {
+    name: String,
+    source: S,
+}
+
+impl<S: IngestSource> IngestSource for Renamed<S> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn size_hint(&self) -> Option<u64> {
+        self.source.size_hint()
+    }
+
+    fn reader(&mut self) -> Result<Box<dyn Read + '_>, Error> {
+        self.source.reader()
+    }
+
+    fn metadata(&self) -> crate::meta::ExtMeta {
+        self.source.metadata()
+    }
+}
@@ -0,0 +1,162 @@
+//! Background scrubbing of long lived depots, entries are verified at a
+//! bounded rate on a dedicated thread so corruption of content that stays
+//! resident for weeks is reported before it is served
+
+use crate::crypt::EncryptionKey;
+use crate::depot_handle::{DepotHandle, OpenMode, StreamInfo};
+use crate::error::DepotError;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct ScrubConfig {
+    /// Entries verified per second, at least one
+    pub entries_per_sec: u32,
+    /// Also verify streams in the reserved namespace
+    pub include_reserved: bool,
+    /// Pause between two full passes over the depot
+    pub pass_interval: Duration,
+    /// Key to decrypt encrypted entries with, without it they are reported
+    /// as [`ScrubIssue::Failed`]
+    pub key: Option<EncryptionKey>,
+}
+
+impl Default for ScrubConfig {
+    fn default() -> Self {
+        Self {
+            entries_per_sec: 10,
+            include_reserved: true,
+            pass_interval: Duration::from_secs(60 * 60),
+            key: None,
+        }
+    }
+}
+
+/// A problem found by the scrubber
+#[derive(Debug)]
+pub enum ScrubIssue {
+    /// The entry failed its size or hash check
+    Corrupt { stream: String, error: DepotError },
+    /// The entry could not be checked for a reason other than damage, e.g.
+    /// a missing key or a read error
+    Failed { stream: String, error: DepotError },
+    /// The depot could not be opened for a pass, the next pass retries
+    Unreadable(DepotError),
+}
+
+/// Counters shared with the scrubbing thread
+#[derive(Debug, Default)]
+pub struct ScrubStats {
+    pub checked: AtomicU64,
+    pub corrupt: AtomicU64,
+    pub failed: AtomicU64,
+    pub passes: AtomicU64,
+}
+
+/// Verifies the depot at a path in the background until stopped or dropped
+pub struct Scrubber {
+    stop: Option<Sender<()>>,
+    stats: Arc<ScrubStats>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Scrubber {
+    /// Starts scrubbing `path`, the depot is reopened for every pass so a
+    /// replaced file is picked up. `on_issue` runs on the scrubbing thread
+    pub fn start<P, F>(path: P, config: ScrubConfig, mut on_issue: F) -> Self
+    where
+        P: Into<PathBuf>,
+        F: FnMut(ScrubIssue) + Send + 'static,
+    {
+        let path = path.into();
+        let (stop, stopped) = mpsc::channel::<()>();
+        let stats = Arc::new(ScrubStats::default());
+        let counters = stats.clone();
+        let delay = Duration::from_secs(1) / config.entries_per_sec.max(1);
+        // waits for `timeout`, true when the scrubber was stopped meanwhile
+        let wait = move |timeout: Duration| {
+            !matches!(
+                stopped.recv_timeout(timeout),
+                Err(RecvTimeoutError::Timeout)
+            )
+        };
+
+        let thread = thread::spawn(move || loop {
+            match DepotHandle::open_file(&path, OpenMode::Read) {
+                Ok(mut dh) => {
+                    dh.set_encryption_key(config.key.clone());
+                    let streams: Vec<StreamInfo> = if config.include_reserved {
+                        dh.all_streams()
+                            .map(|(name, einf)| (name.clone(), einf.clone()).into())
+                            .collect()
+                    } else {
+                        dh.streams()
+                            .map(|(name, einf)| (name.clone(), einf.clone()).into())
+                            .collect()
+                    };
+                    for stream in &streams {
+                        if wait(delay) {
+                            return;
+                        }
+                        match dh.verify_stream(stream) {
+                            Ok(()) => {}
+                            Err(error) if error.is_corruption() => {
+                                counters.corrupt.fetch_add(1, Ordering::Relaxed);
+                                on_issue(ScrubIssue::Corrupt {
+                                    stream: stream.name.clone(),
+                                    error,
+                                });
+                            }
+                            Err(error) => {
+                                counters.failed.fetch_add(1, Ordering::Relaxed);
+                                on_issue(ScrubIssue::Failed {
+                                    stream: stream.name.clone(),
+                                    error,
+                                });
+                            }
+                        }
+                        counters.checked.fetch_add(1, Ordering::Relaxed);
+                    }
+                    counters.passes.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(e) => on_issue(ScrubIssue::Unreadable(e)),
+            }
+            if wait(config.pass_interval) {
+                return;
+            }
+        });
+
+        Self {
+            stop: Some(stop),
+            stats,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn stats(&self) -> &ScrubStats {
+        &self.stats
+    }
+
+    /// Stops scrubbing and waits for the entry being verified to finish
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        // dropping the sender wakes the thread up
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Scrubber {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
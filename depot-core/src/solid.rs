@@ -0,0 +1,45 @@
+//! Solid blocks, small entries compressed together as one stream rather
+//! than each on its own, which gives far better ratios on thousands of small
+//! text and config files. Members of a block share its stored data and
+//! record the block id and where their data starts in the decompressed
+//! block, see [`crate::depot_handle::DepotHandle::set_solid_blocks`].
+//! Reading a member decompresses its whole block, handles keep the last
+//! block read so its other members are served from memory
+
+use crate::depot_handle::Compression;
+
+/// When entries are grouped into solid blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SolidOptions {
+    /// entries of at most this many bytes go into blocks, larger ones are
+    /// compressed on their own
+    pub threshold: u64,
+    /// a block is written once adding the next member would take it past
+    /// this many uncompressed bytes
+    pub block_size: u64,
+}
+
+impl Default for SolidOptions {
+    fn default() -> Self {
+        Self {
+            threshold: 64 * 1024,
+            block_size: 4 * 1024 * 1024,
+        }
+    }
+}
+
+impl SolidOptions {
+    /// true if an entry of `size` bytes compressed with `compression` goes
+    /// into a block, stored entries gain nothing from it
+    pub(crate) fn takes(&self, size: u64, compression: Compression) -> bool {
+        compression != Compression::None && (1..=self.threshold).contains(&size)
+    }
+}
+
+/// The block members are currently added to, its uncompressed data is held
+/// until it is full or the depot is closed
+pub(crate) struct PendingBlock {
+    pub(crate) id: u64,
+    pub(crate) compression: Compression,
+    pub(crate) data: Vec<u8>,
+}
@@ -0,0 +1,104 @@
+//! Where the library may write intermediates, e.g. source snapshots and
+//! compacted copies, so sandboxed or read-only-root environments can keep
+//! it from writing anywhere unexpected
+
+use crate::error::DepotError;
+use std::fs::{File, OpenOptions};
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TempPolicy {
+    /// Never write intermediates, operations that cannot work without them
+    /// fail and compaction moves entries in place instead
+    Never,
+    /// Write intermediates only to this directory
+    Dir(PathBuf),
+    /// The system temp directory, and next to the file being worked on where
+    /// that saves a copy, e.g. reflinked snapshots or compacted depots that
+    /// are renamed into place
+    #[default]
+    System,
+}
+
+/// An intermediate file, removed when dropped unless kept
+pub(crate) struct TempFile {
+    file: Option<File>,
+    path: PathBuf,
+}
+
+impl TempPolicy {
+    /// Directory for intermediates, `None` when they are not allowed
+    pub fn dir(&self) -> Option<PathBuf> {
+        match self {
+            TempPolicy::Never => None,
+            TempPolicy::Dir(dir) => Some(dir.clone()),
+            TempPolicy::System => Some(std::env::temp_dir()),
+        }
+    }
+
+    /// true if intermediates may be written next to `path`
+    pub(crate) fn allows_sibling(&self) -> bool {
+        *self == TempPolicy::System
+    }
+
+    /// Creates an intermediate file in the policy's directory, `what`
+    /// describes the operation in the error when none is allowed
+    pub(crate) fn create(&self, what: &str) -> Result<TempFile, Error> {
+        let dir = self
+            .dir()
+            .ok_or_else(|| Error::from(DepotError::TempForbidden(what.to_owned())))?;
+        TempFile::create(&dir, "")
+    }
+}
+
+impl TempFile {
+    /// Creates a uniquely named file in `dir`, `prefix` is prepended to its
+    /// name, e.g. `.` to hide it
+    pub(crate) fn create(dir: &Path, prefix: &str) -> Result<Self, Error> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let path = dir.join(format!(
+            "{}depot-tmp-{}-{}",
+            prefix,
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(Self {
+            file: Some(file),
+            path,
+        })
+    }
+
+    pub(crate) fn file(&mut self) -> &mut File {
+        self.file.as_mut().unwrap()
+    }
+
+    /// Moves the file over `target`, by renaming it when both are on the
+    /// same file system and copying it otherwise
+    pub(crate) fn persist(mut self, target: &Path) -> Result<(), Error> {
+        self.file.take();
+        if std::fs::rename(&self.path, target).is_ok() {
+            // nothing left to remove
+            self.path = PathBuf::new();
+            return Ok(());
+        }
+        std::fs::copy(&self.path, target)?;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        // closed first, open files cannot be removed everywhere
+        self.file.take();
+        if !self.path.as_os_str().is_empty() {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! Access traces record which entries a process read and how much, so the
+//! hot set can be prefetched the next time the depot is opened, see
+//! [`crate::depot_handle::DepotHandle::warm_from_trace`]
+
+use crate::depot_handle::AccessStats;
+use crate::error::DepotError;
+use crate::helpers::{De, Ser};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use neoncore::streams::read::read_lpstr;
+use neoncore::streams::write::write_lpstr;
+use neoncore::streams::{Endianness, LPWidth, SeekRead, SeekWrite};
+use std::collections::BTreeMap;
+use std::io::{Cursor, Error, ErrorKind};
+
+const TRACE_VERSION: u32 = 1;
+
+/// Entries read by a process, hottest first
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccessTrace {
+    entries: Vec<(String, AccessStats)>,
+}
+
+impl AccessTrace {
+    /// A trace of the stats collected with
+    /// [`crate::depot_handle::DepotHandle::set_access_stats`]
+    pub fn from_stats(stats: &BTreeMap<String, AccessStats>) -> Self {
+        let mut entries: Vec<_> = stats
+            .iter()
+            .map(|(name, stats)| (name.clone(), *stats))
+            .collect();
+        entries.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes).then(b.1.reads.cmp(&a.1.reads)));
+        Self { entries }
+    }
+
+    /// Entries from the most to the least read bytes
+    pub fn entries(&self) -> &[(String, AccessStats)] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Adds the reads of `other`, e.g. to combine the traces of several days
+    pub fn merge(&mut self, other: &AccessTrace) {
+        let mut stats: BTreeMap<String, AccessStats> = self.entries.drain(..).collect();
+        for (name, other) in &other.entries {
+            let stats = stats.entry(name.clone()).or_default();
+            stats.reads += other.reads;
+            stats.bytes += other.bytes;
+        }
+        *self = Self::from_stats(&stats);
+    }
+
+    /// Parses a trace written by [`AccessTrace::to_bytes`]
+    pub fn from_bytes(data: &[u8]) -> Result<Self, DepotError> {
+        Ok(Self::de(Cursor::new(data))?)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Cursor::new(Vec::new());
+        // writing to memory can not fail
+        self.ser(&mut data).unwrap();
+        data.into_inner()
+    }
+}
+
+impl Ser for AccessTrace {
+    fn ser<S: SeekWrite>(&self, mut output: S) -> Result<u64, Error> {
+        output.write_u32::<BigEndian>(TRACE_VERSION)?;
+        output.write_u32::<BigEndian>(self.entries.len() as u32)?;
+        let mut written = 8;
+        for (name, stats) in &self.entries {
+            written += write_lpstr(&mut output, LPWidth::LP32, Endianness::BigEndian, name)?;
+            output.write_u64::<BigEndian>(stats.reads)?;
+            output.write_u64::<BigEndian>(stats.bytes)?;
+            written += 16;
+        }
+        Ok(written)
+    }
+}
+
+impl De for AccessTrace {
+    fn de<D: SeekRead>(mut stream: D) -> Result<Self, Error>
+    where
+        Self: Sized,
+    {
+        let version = stream.read_u32::<BigEndian>()?;
+        if version != TRACE_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported access trace version {}", version),
+            ));
+        }
+        let count = stream.read_u32::<BigEndian>()?;
+        let entries = (0..count)
+            .map(|_| {
+                let name = read_lpstr(&mut stream, LPWidth::LP32, Endianness::BigEndian)?;
+                let reads = stream.read_u64::<BigEndian>()?;
+                let bytes = stream.read_u64::<BigEndian>()?;
+                Ok((name, AccessStats { reads, bytes }))
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(Self { entries })
+    }
+}
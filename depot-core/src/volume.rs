@@ -0,0 +1,265 @@
+//! Depots split over several files of a fixed size, for distribution
+//! platforms capping file sizes. The parts of `game.dpt` are `game.dpt.001`,
+//! `game.dpt.002` and so on, each starts with a small header holding its
+//! index followed by its share of the depot. [`Volumes`] chains them into
+//! one stream a [`crate::depot_handle::DepotHandle`] reads and writes like
+//! any other, see [`crate::depot_handle::DepotHandle::create_split`]
+
+use crate::error::DepotError;
+use crate::helpers::FileId;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use neoncore::const_fn::ascii_to_u64_be;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// cbindgen:ignore
+pub const VOLUME_MAGIC: u64 = ascii_to_u64_be(b"DEPOTVOL");
+
+/// Size of the header starting every part, magic, index and part size
+const PART_HEADER_SIZE: u64 = 20;
+
+/// Path of the part `index` of the depot at `base`, counted from 0 and
+/// numbered from `.001`
+pub fn part_path(base: &Path, index: usize) -> PathBuf {
+    let mut path = OsString::from(base.as_os_str());
+    path.push(format!(".{:03}", index + 1));
+    PathBuf::from(path)
+}
+
+/// The depot `path` is a part of, when it names a split depot by its base
+/// path that does not exist itself or by its first part
+pub fn split_base(path: &Path) -> Option<PathBuf> {
+    if !path.exists() {
+        return part_path(path, 0).is_file().then(|| path.to_owned());
+    }
+    let base = path.to_str()?.strip_suffix(".001")?;
+    let magic = File::open(path).and_then(|mut fh| fh.read_u64::<BigEndian>());
+    (magic.ok()? == VOLUME_MAGIC).then(|| PathBuf::from(base))
+}
+
+struct Parts {
+    base: PathBuf,
+    part_size: u64,
+    files: Vec<(File, Option<FileId>)>,
+    writable: bool,
+}
+
+impl Parts {
+    fn create_part(&mut self, index: usize) -> std::io::Result<()> {
+        // the part before is full once data goes past it
+        if let Some((last, _)) = self.files.last() {
+            if last.metadata()?.len() < PART_HEADER_SIZE + self.part_size {
+                last.set_len(PART_HEADER_SIZE + self.part_size)?;
+            }
+        }
+        let mut fh = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(part_path(&self.base, index))?;
+        fh.write_u64::<BigEndian>(VOLUME_MAGIC)?;
+        fh.write_u32::<BigEndian>(index as u32)?;
+        fh.write_u64::<BigEndian>(self.part_size)?;
+        let id = FileId::of(&fh.metadata()?);
+        self.files.push((fh, id));
+        Ok(())
+    }
+
+    fn len(&self) -> std::io::Result<u64> {
+        let Some((last, _)) = self.files.last() else {
+            return Ok(0);
+        };
+        let last_len = last.metadata()?.len().saturating_sub(PART_HEADER_SIZE);
+        Ok((self.files.len() as u64 - 1) * self.part_size + last_len)
+    }
+}
+
+/// The parts of a split depot as one stream. Clones share the parts but
+/// keep their own position
+#[derive(Clone)]
+pub struct Volumes {
+    parts: Arc<Mutex<Parts>>,
+    pos: u64,
+}
+
+impl Volumes {
+    /// Creates a depot split into parts of `part_size` bytes each, not
+    /// counting their headers. Parts left over from an earlier depot at
+    /// `base` are removed
+    pub fn create<P: AsRef<Path>>(base: P, part_size: u64) -> Result<Self, DepotError> {
+        if part_size == 0 {
+            return Err(DepotError::InvalidInput(
+                "parts have to hold at least one byte".to_owned(),
+            ));
+        }
+        let base = base.as_ref().to_owned();
+        let mut stale = 0;
+        while part_path(&base, stale).is_file() {
+            fs::remove_file(part_path(&base, stale))?;
+            stale += 1;
+        }
+        let mut parts = Parts {
+            base,
+            part_size,
+            files: Vec::new(),
+            writable: true,
+        };
+        parts.create_part(0)?;
+        Ok(Self {
+            parts: Arc::new(Mutex::new(parts)),
+            pos: 0,
+        })
+    }
+
+    /// Opens the parts of the depot at `base`, checking every part follows
+    /// the one before
+    pub fn open<P: AsRef<Path>>(base: P, writable: bool) -> Result<Self, DepotError> {
+        let base = base.as_ref().to_owned();
+        let mut files = Vec::new();
+        let mut part_size = 0;
+        let mut index = 0;
+        loop {
+            let path = part_path(&base, index);
+            if index > 0 && !path.is_file() {
+                break;
+            }
+            let mut fh = OpenOptions::new().read(true).write(writable).open(&path)?;
+            let magic = fh.read_u64::<BigEndian>()?;
+            let found = fh.read_u32::<BigEndian>()?;
+            let size = fh.read_u64::<BigEndian>()?;
+            if index == 0 {
+                part_size = size;
+            }
+            if magic != VOLUME_MAGIC || found as usize != index || size != part_size || size == 0 {
+                return Err(DepotError::BadPart { path, index });
+            }
+            let id = FileId::of(&fh.metadata()?);
+            files.push((fh, id));
+            index += 1;
+        }
+        let parts = Parts {
+            base,
+            part_size,
+            files,
+            writable,
+        };
+        Ok(Self {
+            parts: Arc::new(Mutex::new(parts)),
+            pos: 0,
+        })
+    }
+
+    pub fn part_size(&self) -> u64 {
+        self.parts.lock().unwrap().part_size
+    }
+
+    pub fn part_count(&self) -> usize {
+        self.parts.lock().unwrap().files.len()
+    }
+
+    /// Size of the depot across all parts, without the part headers
+    pub fn size(&self) -> Result<u64, DepotError> {
+        Ok(self.parts.lock().unwrap().len()?)
+    }
+
+    /// Cuts the depot to `len` bytes, dropping the parts past it
+    pub fn truncate(&self, len: u64) -> Result<(), DepotError> {
+        let mut parts = self.parts.lock().unwrap();
+        if len >= parts.len()? {
+            return Ok(());
+        }
+        let keep = len.div_ceil(parts.part_size).max(1) as usize;
+        while parts.files.len() > keep {
+            let index = parts.files.len() - 1;
+            parts.files.pop();
+            fs::remove_file(part_path(&parts.base, index))?;
+        }
+        let last_len = len - (keep as u64 - 1) * parts.part_size;
+        parts.files[keep - 1]
+            .0
+            .set_len(PART_HEADER_SIZE + last_len)?;
+        Ok(())
+    }
+
+    /// true if `id` is one of the parts
+    pub(crate) fn holds(&self, id: Option<FileId>) -> bool {
+        id.is_some()
+            && self
+                .parts
+                .lock()
+                .unwrap()
+                .files
+                .iter()
+                .any(|(_, part)| *part == id)
+    }
+}
+
+impl Read for Volumes {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut parts = self.parts.lock().unwrap();
+        let part_size = parts.part_size;
+        let Some((fh, _)) = parts.files.get_mut((self.pos / part_size) as usize) else {
+            return Ok(0);
+        };
+        let within = self.pos % part_size;
+        let len = buf.len().min((part_size - within) as usize);
+        fh.seek(SeekFrom::Start(PART_HEADER_SIZE + within))?;
+        let n = fh.read(&mut buf[..len])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Write for Volumes {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut parts = self.parts.lock().unwrap();
+        if !parts.writable {
+            return Err(DepotError::ReadOnly.into());
+        }
+        let part_size = parts.part_size;
+        let index = (self.pos / part_size) as usize;
+        while parts.files.len() <= index {
+            let next = parts.files.len();
+            parts.create_part(next)?;
+        }
+        let within = self.pos % part_size;
+        let len = buf.len().min((part_size - within) as usize);
+        let (fh, _) = &mut parts.files[index];
+        fh.seek(SeekFrom::Start(PART_HEADER_SIZE + within))?;
+        let n = fh.write(&buf[..len])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let mut parts = self.parts.lock().unwrap();
+        for (fh, _) in &mut parts.files {
+            fh.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl Seek for Volumes {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let (from, offset) = match pos {
+            SeekFrom::Start(offset) => {
+                self.pos = offset;
+                return Ok(offset);
+            }
+            SeekFrom::Current(offset) => (self.pos, offset),
+            SeekFrom::End(offset) => (self.parts.lock().unwrap().len()?, offset),
+        };
+        self.pos = from.checked_add_signed(offset).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
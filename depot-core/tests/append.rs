@@ -0,0 +1,75 @@
+//! Appending writes after the old toc, a depot stays readable with its old
+//! entries until the appending handle is closed
+
+use depot_core::depot_handle::{DepotHandle, OpenMode};
+use depot_core::error::DepotError;
+use std::path::Path;
+
+mod common;
+use common::{read, TempPath};
+
+fn names(path: &Path) -> Vec<String> {
+    let dh = DepotHandle::open_file(path, OpenMode::Read).unwrap();
+    dh.streams()
+        .map(|(name, _)| name.clone())
+        .filter(|name| !depot_core::is_reserved_name(name))
+        .collect()
+}
+
+#[test]
+fn dropped_append_keeps_the_old_depot() {
+    let depot = TempPath::new("append");
+    let path = depot.path();
+    let mut dh = DepotHandle::create_file(path).unwrap();
+    dh.add_bytes("a.txt", b"first file").unwrap();
+    dh.close().unwrap();
+
+    // an append failing half way is never closed
+    let mut dh = DepotHandle::open_append(path).unwrap();
+    dh.add_bytes("b.txt", &[7; 64 * 1024]).unwrap();
+    drop(dh);
+    assert_eq!(names(path), ["a.txt"]);
+    assert_eq!(read(path, "a.txt"), b"first file");
+
+    let mut dh = DepotHandle::open_append(path).unwrap();
+    dh.add_bytes("c.txt", b"second file").unwrap();
+    dh.close().unwrap();
+    assert_eq!(names(path), ["a.txt", "c.txt"]);
+    assert_eq!(read(path, "a.txt"), b"first file");
+    assert_eq!(read(path, "c.txt"), b"second file");
+
+    // the old toc and the dropped entry are left for compaction
+    let mut dh = DepotHandle::open_file(path, OpenMode::Read).unwrap();
+    assert!(!dh.dead_regions().unwrap().is_empty());
+}
+
+#[test]
+fn unfreezing_append_keeps_the_freeze_until_written() {
+    let depot = TempPath::new("unfreeze");
+    let path = depot.path();
+    let mut dh = DepotHandle::create_file(path).unwrap();
+    dh.add_bytes("a.txt", b"first file").unwrap();
+    dh.freeze().unwrap();
+    assert!(matches!(
+        DepotHandle::open_append(path),
+        Err(DepotError::Frozen)
+    ));
+
+    // a change failing before anything was written keeps the freeze
+    let mut dh = DepotHandle::open_append_unfreezing(path).unwrap();
+    assert!(dh.add_file(path.with_extension("missing"), None).is_err());
+    drop(dh);
+    assert!(matches!(
+        DepotHandle::open_append(path),
+        Err(DepotError::Frozen)
+    ));
+    let mut dh = DepotHandle::open_file(path, OpenMode::Read).unwrap();
+    dh.verify_frozen().unwrap();
+
+    let mut dh = DepotHandle::open_append_unfreezing(path).unwrap();
+    dh.add_bytes("c.txt", b"second file").unwrap();
+    dh.close().unwrap();
+    let dh = DepotHandle::open_file(path, OpenMode::Read).unwrap();
+    assert!(dh.frozen().is_none());
+    assert_eq!(names(path), ["a.txt", "c.txt"]);
+}
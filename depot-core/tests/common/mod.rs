@@ -0,0 +1,52 @@
+//! Helpers shared by the integration tests, also pulled into the tests of
+//! depot-ops by path
+
+// every test file uses a different part of them
+#![allow(dead_code)]
+
+use depot_core::depot_handle::{DepotHandle, OpenMode};
+use depot_core::volume::part_path;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A path in the temp dir unique to the test and the process. Whatever is
+/// at it when the test ends, passed or not, is removed, as are the parts
+/// of a split depot based on it
+pub struct TempPath(PathBuf);
+
+impl TempPath {
+    pub fn new(test: &str) -> Self {
+        Self(std::env::temp_dir().join(format!("depot-{}-{}", test, std::process::id())))
+    }
+
+    /// A new empty directory, anything left at the path by an earlier run
+    /// is removed first
+    pub fn dir(test: &str) -> Self {
+        let dir = Self::new(test);
+        let _ = fs::remove_dir_all(&dir.0);
+        fs::create_dir_all(&dir.0).unwrap();
+        dir
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+        let _ = fs::remove_dir_all(&self.0);
+        let mut index = 0;
+        while fs::remove_file(part_path(&self.0, index)).is_ok() {
+            index += 1;
+        }
+    }
+}
+
+/// Reads the entry `name` of the depot at `path` whole
+pub fn read(path: &Path, name: &str) -> Vec<u8> {
+    let mut dh = DepotHandle::open_file(path, OpenMode::Read).unwrap();
+    let stream = dh.get_named_stream(name).unwrap();
+    dh.stream_to_memory(&stream).unwrap()
+}
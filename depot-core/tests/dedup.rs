@@ -0,0 +1,74 @@
+//! Entries with the same contents share their data when dedup is on, and
+//! keep it through removals, replacements and compaction
+
+use depot_core::creator::CreatorInfo;
+use depot_core::depot_handle::{DepotHandle, OpenMode};
+use depot_core::temp::TempPolicy;
+use std::path::Path;
+
+mod common;
+use common::{read, TempPath};
+
+/// Bakes `a.bin` and `b.bin` with the same contents and `c.bin` without
+fn bake(path: &Path) {
+    let mut dh = DepotHandle::create_file(path).unwrap();
+    dh.set_dedup(true);
+    dh.add_bytes("a.bin", &[1; 64 * 1024]).unwrap();
+    dh.add_bytes("b.bin", &[1; 64 * 1024]).unwrap();
+    dh.add_bytes("c.bin", &[2; 64 * 1024]).unwrap();
+    dh.close().unwrap();
+}
+
+fn offset(dh: &DepotHandle, name: &str) -> u64 {
+    dh.get_named_stream(name).unwrap().einf.offset
+}
+
+#[test]
+fn duplicate_shares_data() {
+    let depot = TempPath::new("dedup-shared");
+    let path = depot.path();
+    bake(path);
+
+    let dh = DepotHandle::open_file(path, OpenMode::Read).unwrap();
+    assert_eq!(offset(&dh, "a.bin"), offset(&dh, "b.bin"));
+    assert_ne!(offset(&dh, "a.bin"), offset(&dh, "c.bin"));
+    assert_eq!(read(path, "a.bin"), [1; 64 * 1024]);
+    assert_eq!(read(path, "b.bin"), [1; 64 * 1024]);
+    assert_eq!(read(path, "c.bin"), [2; 64 * 1024]);
+}
+
+#[test]
+fn compaction_keeps_shared_data() {
+    for temp in [TempPolicy::System, TempPolicy::Never] {
+        let depot = TempPath::new("dedup-compacted");
+        let path = depot.path();
+        bake(path);
+
+        // the first owner of the shared data goes, the other replaces it
+        let mut dh = DepotHandle::open_file(path, OpenMode::ReadWrite).unwrap();
+        dh.remove_stream("a.bin").unwrap();
+        dh.replace_stream("c.bin", &[3; 1024][..]).unwrap();
+        dh.close().unwrap();
+        DepotHandle::compact_file(path, CreatorInfo::default(), &temp).unwrap();
+
+        let mut dh = DepotHandle::open_file(path, OpenMode::Read).unwrap();
+        assert!(dh.dead_regions().unwrap().is_empty(), "{:?}", temp);
+        assert!(dh.get_named_stream("a.bin").is_none());
+        assert_eq!(read(path, "b.bin"), [1; 64 * 1024], "{:?}", temp);
+        assert_eq!(read(path, "c.bin"), [3; 1024], "{:?}", temp);
+    }
+}
+
+#[test]
+fn replacement_shares_data() {
+    let depot = TempPath::new("dedup-replaced");
+    let path = depot.path();
+    bake(path);
+
+    let mut dh = DepotHandle::open_file(path, OpenMode::ReadWrite).unwrap();
+    dh.set_dedup(true);
+    dh.replace_stream("c.bin", &[1; 64 * 1024][..]).unwrap();
+    assert_eq!(offset(&dh, "a.bin"), offset(&dh, "c.bin"));
+    dh.close().unwrap();
+    assert_eq!(read(path, "c.bin"), [1; 64 * 1024]);
+}
@@ -0,0 +1,122 @@
+//! Entries read through the perfect hash index alone, without the toc
+
+use depot_core::depot_handle::{Compression, DepotHandle, OpenMode};
+use depot_core::dict::Dictionary;
+use depot_core::error::DepotError;
+use depot_core::meta::keys;
+use depot_core::phf::{PhfIndex, PhfReader};
+use depot_core::solid::SolidOptions;
+use std::io::Cursor;
+
+fn text(i: usize) -> Vec<u8> {
+    format!("[entry {}]\nvalue = {}\n", i, i * 3)
+        .repeat(40 + i)
+        .into_bytes()
+}
+
+/// A depot with compressed, stored, solid and dictionary compressed
+/// entries, and the perfect hash index over them
+fn bake() -> Vec<u8> {
+    let mut depot = Cursor::new(Vec::new());
+    let mut dh = DepotHandle::create(&mut depot).unwrap();
+    dh.set_perfect_hash(true);
+    dh.add_bytes("big.bin", &vec![7; 256 * 1024]).unwrap();
+    dh.set_compression(Compression::None);
+    dh.add_bytes("stored.txt", b"kept as is").unwrap();
+    dh.set_compression(Compression::Zstd);
+    let dict = Dictionary::new(
+        "cfg",
+        vec!["cfg/".to_owned()],
+        b"[entry value = ]\n".repeat(64),
+    )
+    .unwrap();
+    dh.add_dictionary(dict).unwrap();
+    dh.add_bytes("cfg/a.ini", &text(100)).unwrap();
+    dh.set_solid_blocks(Some(SolidOptions {
+        threshold: 4 * 1024,
+        block_size: 2 * 1024,
+    }))
+    .unwrap();
+    for i in 0..8 {
+        dh.add_bytes(&format!("small/{}.ini", i), &text(i)).unwrap();
+    }
+    dh.close().unwrap();
+    depot.into_inner()
+}
+
+#[test]
+fn reads_entries_without_the_toc() {
+    let depot = bake();
+    let mut dh = DepotHandle::open_read(Cursor::new(&depot[..])).unwrap();
+    let names: Vec<String> = dh.streams().map(|(name, _)| name.clone()).collect();
+    let mut expected = Vec::new();
+    for name in &names {
+        let stream = dh.get_named_stream(name).unwrap();
+        expected.push(dh.stream_to_memory(&stream).unwrap());
+    }
+    let cfg = dh.get_named_stream("cfg/a.ini").unwrap();
+    assert!(cfg.einf.ext.get_str(keys::DICT).is_some());
+
+    let mut reader = PhfReader::open(Cursor::new(&depot[..])).unwrap();
+    for (name, data) in names.iter().zip(&expected) {
+        assert_eq!(&reader.read_all(name).unwrap(), data, "{}", name);
+        assert_eq!(reader.stat(name).unwrap().einf.size, data.len() as u64);
+    }
+    // names are normalized like entry ids
+    assert_eq!(reader.read_all("./stored.txt").unwrap(), b"kept as is");
+
+    assert!(reader.stat("missing.txt").is_none());
+    match reader.read_all("missing.txt") {
+        Err(DepotError::EntryNotFound(name)) => assert_eq!(name, "missing.txt"),
+        other => panic!("expected a missing entry, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn index_round_trip() {
+    let depot = bake();
+    let mut dh = DepotHandle::open_read(Cursor::new(&depot[..])).unwrap();
+    let index = dh.perfect_hash_index().unwrap().unwrap();
+    let parsed = PhfIndex::from_bytes(&index.to_bytes()).unwrap();
+    assert_eq!(parsed.len(), index.len());
+    for (name, entry) in dh.streams() {
+        let slot = parsed.lookup(name).unwrap();
+        assert_eq!(slot.entry.offset, entry.offset, "{}", name);
+        assert_eq!(slot.entry.hash, entry.hash, "{}", name);
+        assert_eq!(slot.entry.ext.frames(), entry.ext.frames(), "{}", name);
+        assert_eq!(
+            slot.entry.ext.solid_block(),
+            entry.ext.solid_block(),
+            "{}",
+            name
+        );
+    }
+    assert!(parsed.lookup("missing.txt").is_none());
+
+    // damaged or cut short
+    let bytes = index.to_bytes();
+    assert!(PhfIndex::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    let mut damaged = bytes.clone();
+    *damaged.last_mut().unwrap() ^= 1;
+    assert!(PhfIndex::from_bytes(&damaged).is_err());
+}
+
+#[test]
+fn depot_without_index() {
+    let mut depot = Cursor::new(Vec::new());
+    let mut dh = DepotHandle::create(&mut depot).unwrap();
+    dh.add_bytes("a.txt", b"no index").unwrap();
+    dh.close().unwrap();
+    let depot = depot.into_inner();
+    assert!(matches!(
+        PhfReader::open(Cursor::new(&depot[..])),
+        Err(DepotError::EntryNotFound(_))
+    ));
+
+    // and once it is dropped again by a handle writing to the depot
+    let mut depot = Cursor::new(bake());
+    let mut dh = DepotHandle::new(&mut depot, OpenMode::ReadWrite).unwrap();
+    dh.set_perfect_hash(false);
+    dh.close().unwrap();
+    assert!(PhfReader::open(Cursor::new(depot.into_inner())).is_err());
+}
@@ -0,0 +1,116 @@
+//! A depot opened read-only must never write to its stream, these open one
+//! through a wrapper that panics on any write
+
+use depot_core::depot_handle::{DepotHandle, OpenMode, StreamInfo};
+use depot_core::error::DepotError;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+
+/// Reads and seeks like the wrapped stream, panics on writes
+struct NoWrite<T>(T);
+
+impl<T: Read> Read for NoWrite<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<T: Seek> Seek for NoWrite<T> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+impl<T> Write for NoWrite<T> {
+    fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+        panic!("a read-only depot wrote to its stream");
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        panic!("a read-only depot flushed its stream");
+    }
+}
+
+const FILES: [(&str, &[u8]); 3] = [
+    ("a.txt", b"first file"),
+    ("dir/b.bin", &[7; 4096]),
+    ("empty", b""),
+];
+
+fn depot() -> Vec<u8> {
+    let mut data = Cursor::new(Vec::new());
+    let mut dh = DepotHandle::create(&mut data).unwrap();
+    for (name, contents) in FILES {
+        dh.add_bytes(name, contents).unwrap();
+    }
+    dh.close().unwrap();
+    data.into_inner()
+}
+
+fn stream(dh: &DepotHandle, name: &str) -> StreamInfo {
+    dh.get_named_stream(name).unwrap()
+}
+
+fn check_reads(mut dh: DepotHandle) {
+    let names: Vec<&str> = dh.streams().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["a.txt", "dir/b.bin", "empty"]);
+
+    for (name, contents) in FILES {
+        let mut out = Cursor::new(Vec::new());
+        dh.extract_stream(&stream(&dh, name), &mut out).unwrap();
+        assert_eq!(out.get_ref(), contents);
+    }
+
+    assert!(dh.verify_all(None).is_empty());
+    dh.verify_stream(&stream(&dh, "a.txt")).unwrap();
+
+    let path = std::env::temp_dir().join(format!("depot-read-only-{}", std::process::id()));
+    let out = std::fs::File::create(&path).unwrap();
+    let carved = dh.carve_stream(&stream(&dh, "dir/b.bin"), &out);
+    let size = std::fs::metadata(&path).map(|meta| meta.len());
+    std::fs::remove_file(&path).unwrap();
+    carved.unwrap();
+    assert_eq!(size.unwrap(), stream(&dh, "dir/b.bin").einf.stream_size);
+
+    dh.close().unwrap();
+}
+
+fn check_mutations(mut dh: DepotHandle) {
+    assert!(matches!(
+        dh.add_bytes("c.txt", b"new"),
+        Err(DepotError::ReadOnly)
+    ));
+    assert!(matches!(
+        dh.add_named_stream("d.txt", &b"new"[..], None),
+        Err(DepotError::ReadOnly)
+    ));
+    assert!(matches!(
+        dh.remove_stream("a.txt"),
+        Err(DepotError::ReadOnly)
+    ));
+    assert!(matches!(
+        dh.replace_stream("a.txt", &b"new"[..]),
+        Err(DepotError::ReadOnly)
+    ));
+    assert!(matches!(
+        dh.set_archive_meta("build", b"1"),
+        Err(DepotError::ReadOnly)
+    ));
+    // there is nothing to flush, the stream is not touched
+    dh.flush().unwrap();
+    assert!(matches!(dh.freeze(), Err(DepotError::ReadOnly)));
+}
+
+#[test]
+fn open_read_never_writes() {
+    let data = depot();
+    check_reads(DepotHandle::open_read(NoWrite(Cursor::new(&data))).unwrap());
+    check_mutations(DepotHandle::open_read(NoWrite(Cursor::new(&data))).unwrap());
+}
+
+#[test]
+fn read_mode_never_writes() {
+    let data = depot();
+    let open = || DepotHandle::new(NoWrite(Cursor::new(data.clone())), OpenMode::Read).unwrap();
+    check_reads(open());
+    check_mutations(open());
+}
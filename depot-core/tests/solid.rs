@@ -0,0 +1,57 @@
+//! Members of solid blocks read back whole and by range
+
+use depot_core::depot_handle::{DepotHandle, OpenMode};
+use depot_core::solid::SolidOptions;
+
+mod common;
+use common::TempPath;
+
+/// Small text like contents, different for every member
+fn member(i: usize) -> Vec<u8> {
+    format!("[member {}]\nvalue = {}\n", i, i * 7)
+        .repeat(20 + i)
+        .into_bytes()
+}
+
+#[test]
+fn reads_solid_members() {
+    let depot = TempPath::new("solid");
+    let path = depot.path();
+    let mut dh = DepotHandle::create_file(path).unwrap();
+    let options = SolidOptions {
+        threshold: 4 * 1024,
+        block_size: 2 * 1024,
+    };
+    dh.set_solid_blocks(Some(options)).unwrap();
+    for i in 0..8 {
+        dh.add_bytes(&format!("{}.ini", i), &member(i)).unwrap();
+    }
+    let large = vec![9; 64 * 1024];
+    dh.add_bytes("large.bin", &large).unwrap();
+    dh.close().unwrap();
+
+    let mut dh = DepotHandle::open_file(path, OpenMode::Read).unwrap();
+    let first = dh.get_named_stream("0.ini").unwrap();
+    let second = dh.get_named_stream("1.ini").unwrap();
+    let last = dh.get_named_stream("7.ini").unwrap();
+    assert_eq!(first.einf.offset, second.einf.offset);
+    assert_ne!(first.einf.offset, last.einf.offset);
+
+    // members of the last block, then of the first one again
+    for i in (0..8).rev().chain(0..2) {
+        let stream = dh.get_named_stream(&format!("{}.ini", i)).unwrap();
+        assert_eq!(dh.stream_to_memory(&stream).unwrap(), member(i), "{}", i);
+    }
+    for i in [0, 7] {
+        let stream = dh.get_named_stream(&format!("{}.ini", i)).unwrap();
+        let range = dh.read_range(&stream, 10, 50).unwrap();
+        assert_eq!(range, member(i)[10..60], "{}", i);
+        // past the end of the member, not into the next one
+        let size = stream.einf.size;
+        let tail = dh.read_range(&stream, size - 5, 50).unwrap();
+        assert_eq!(tail, member(i)[size as usize - 5..], "{}", i);
+    }
+
+    let stream = dh.get_named_stream("large.bin").unwrap();
+    assert_eq!(dh.stream_to_memory(&stream).unwrap(), large);
+}
@@ -0,0 +1,30 @@
+//! Depots split over many tiny parts read back like any other
+
+use depot_core::depot_handle::{DepotHandle, OpenMode};
+use depot_core::volume::part_path;
+
+mod common;
+use common::TempPath;
+
+#[test]
+fn tiny_parts_round_trip() {
+    let depot = TempPath::new("split");
+    let base = depot.path();
+    let data: Vec<u8> = (0..10_000u32).map(|i| (i * 31 % 251) as u8).collect();
+    let mut dh = DepotHandle::create_split(base, 100).unwrap();
+    dh.add_bytes("a.bin", &data).unwrap();
+    dh.add_bytes("b.txt", b"second file").unwrap();
+    dh.close().unwrap();
+    assert!(part_path(base, 2).is_file());
+    assert!(!base.exists());
+
+    // opened by the path it was created at and by its first part
+    for path in [base.to_owned(), part_path(base, 0)] {
+        let mut dh = DepotHandle::open_file(&path, OpenMode::Read).unwrap();
+        assert!(dh.volumes().unwrap().part_count() > 2);
+        let stream = dh.get_named_stream("a.bin").unwrap();
+        assert_eq!(dh.stream_to_memory(&stream).unwrap(), data);
+        let stream = dh.get_named_stream("b.txt").unwrap();
+        assert_eq!(dh.stream_to_memory(&stream).unwrap(), b"second file");
+    }
+}
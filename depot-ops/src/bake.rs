@@ -0,0 +1,104 @@
+use crate::{Event, Job, JobContext, OperationReport};
+use depot_core::depot_handle::{DepotHandle, RatioGuard};
+use depot_core::error::DepotError;
+use depot_core::policy::ContentPolicy;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Creates a depot from a list of files
+#[derive(Debug, Clone)]
+pub struct BakeJob {
+    pub output: PathBuf,
+    pub files: Vec<PathBuf>,
+    pub level: i32,
+    pub threads: usize,
+    pub frame_size: usize,
+    pub ratio_guard: Option<RatioGuard>,
+    pub content_policy: Option<Arc<ContentPolicy>>,
+}
+
+impl BakeJob {
+    pub fn new(output: impl Into<PathBuf>, files: Vec<PathBuf>) -> Self {
+        Self {
+            output: output.into(),
+            files,
+            level: 10,
+            threads: 4,
+            frame_size: 8 * 1024 * 1024,
+            ratio_guard: None,
+            content_policy: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BakeResult {
+    pub output: PathBuf,
+    pub files: u64,
+    pub warnings: Vec<String>,
+    pub report: OperationReport,
+}
+
+impl Job for BakeJob {
+    type Output = BakeResult;
+
+    /// Bakes the depot, a failed or cancelled bake removes the partial output
+    fn run(self, ctx: &JobContext) -> Result<BakeResult, DepotError> {
+        let result = self.bake(ctx);
+        if result.is_err() {
+            let _ = fs::remove_file(&self.output);
+        }
+        result
+    }
+}
+
+impl BakeJob {
+    fn bake(&self, ctx: &JobContext) -> Result<BakeResult, DepotError> {
+        let started = Instant::now();
+        let mut dh = DepotHandle::create_file(&self.output)?;
+        dh.set_comp_level(self.level);
+        dh.set_mt_threads(self.threads);
+        dh.set_comp_frame_size(self.frame_size);
+        dh.set_ratio_guard(self.ratio_guard);
+        dh.set_content_policy(self.content_policy.clone());
+
+        let mut result = BakeResult {
+            output: self.output.clone(),
+            ..Default::default()
+        };
+        ctx.emit(Event::Started {
+            total: self.files.len() as u64,
+        });
+        for (pos, path) in self.files.iter().enumerate() {
+            ctx.check_cancelled()?;
+            let size = fs::metadata(path)?.len();
+            ctx.emit(Event::Item {
+                name: path.to_string_lossy().into_owned(),
+                size,
+                pos: pos as u64 + 1,
+            });
+            let entry_started = Instant::now();
+            dh.add_file(path, None)?;
+            let name = path.to_string_lossy();
+            // the stat size is 0 for pipes and stale for files that changed
+            let (size, stream_size) = dh.get_named_stream(&name).map_or((size, 0), |stream| {
+                (stream.einf.size, stream.einf.stream_size)
+            });
+            result
+                .report
+                .record(&name, size, stream_size, entry_started.elapsed());
+            for warning in dh.take_warnings() {
+                ctx.emit(Event::Warning(warning.clone()));
+                result.warnings.push(warning);
+            }
+            result.files += 1;
+            result.report.bytes_read += size;
+        }
+        dh.close()?;
+        result.report.bytes_written = fs::metadata(&self.output)?.len();
+        result.report.elapsed = started.elapsed();
+        Ok(result)
+    }
+}
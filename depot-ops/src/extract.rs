@@ -0,0 +1,137 @@
+//! Extraction of a whole depot into a directory as a job, reporting every
+//! entry as it is written and honouring cancellation between entries
+
+use crate::{CancelToken, Event, Job, JobContext, OperationReport};
+use depot_core::depot_handle::{DepotHandle, OpenMode, StreamInfo};
+use depot_core::error::DepotError;
+use depot_core::extract::{ExtractHooks, ExtractOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Extracts streams of a depot into a directory
+pub struct ExtractJob {
+    pub depot: PathBuf,
+    pub output: PathBuf,
+    /// Streams to extract, every user stream when empty
+    pub streams: Vec<String>,
+    pub options: ExtractOptions,
+}
+
+impl ExtractJob {
+    pub fn new(depot: impl Into<PathBuf>, output: impl Into<PathBuf>) -> Self {
+        Self {
+            depot: depot.into(),
+            output: output.into(),
+            streams: Vec::new(),
+            options: ExtractOptions::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExtractResult {
+    /// Paths written, streams skipped by the path mapper are not listed
+    pub files: Vec<PathBuf>,
+    pub report: OperationReport,
+}
+
+impl Job for ExtractJob {
+    type Output = ExtractResult;
+
+    fn run(self, ctx: &JobContext) -> Result<ExtractResult, DepotError> {
+        let started = Instant::now();
+        let mut dh = DepotHandle::open_file(&self.depot, OpenMode::Read)?;
+        let streams: Vec<StreamInfo> = if self.streams.is_empty() {
+            dh.streams()
+                .map(|(name, einf)| (name.clone(), einf.clone()).into())
+                .collect()
+        } else {
+            self.streams
+                .iter()
+                .map(|name| {
+                    dh.get_named_stream(name)
+                        .ok_or_else(|| DepotError::EntryNotFound(name.clone()))
+                })
+                .collect::<Result<_, _>>()?
+        };
+
+        let mut result = ExtractResult::default();
+        ctx.emit(Event::Started {
+            total: streams.len() as u64,
+        });
+        ctx.check_cancelled()?;
+        // one batch, so links are only created once every file is out
+        let mut options = self.options;
+        options.hooks = Some(Box::new(CancelHooks {
+            cancel: ctx.cancel.clone(),
+            inner: options.hooks.take(),
+        }));
+        let mut pos = 0;
+        let mut entry_started = Instant::now();
+        let mut record = |stream: &StreamInfo, path: &Path| {
+            pos += 1;
+            ctx.emit(Event::Item {
+                name: stream.name.clone(),
+                size: stream.einf.size,
+                pos,
+            });
+            result.files.push(path.to_path_buf());
+            result.report.bytes_read += stream.einf.stream_size;
+            result.report.bytes_written += stream.einf.size;
+            result.report.record(
+                &stream.name,
+                stream.einf.size,
+                stream.einf.stream_size,
+                entry_started.elapsed(),
+            );
+            entry_started = Instant::now();
+        };
+        dh.extract_to_dir(&streams, &self.output, &options, Some(&mut record))?;
+        result.report.elapsed = started.elapsed();
+        Ok(result)
+    }
+}
+
+/// Fails the next file of an extraction once the job was cancelled, then
+/// hands over to the hooks set by the caller
+struct CancelHooks {
+    cancel: CancelToken,
+    inner: Option<Box<dyn ExtractHooks>>,
+}
+
+impl ExtractHooks for CancelHooks {
+    fn on_entry_start(&self, stream: &StreamInfo, path: &Path) -> Result<(), DepotError> {
+        if self.cancel.is_cancelled() {
+            return Err(DepotError::Cancelled);
+        }
+        match &self.inner {
+            Some(inner) => inner.on_entry_start(stream, path),
+            None => Ok(()),
+        }
+    }
+
+    fn transform_writer<'w>(
+        &self,
+        stream: &StreamInfo,
+        path: &Path,
+        writer: Box<dyn Write + 'w>,
+    ) -> Result<Box<dyn Write + 'w>, DepotError> {
+        match &self.inner {
+            Some(inner) => inner.transform_writer(stream, path, writer),
+            None => Ok(writer),
+        }
+    }
+
+    fn on_entry_done(
+        &self,
+        stream: &StreamInfo,
+        path: &Path,
+        error: Option<&DepotError>,
+    ) -> Result<(), DepotError> {
+        match &self.inner {
+            Some(inner) => inner.on_entry_done(stream, path, error),
+            None => Ok(()),
+        }
+    }
+}
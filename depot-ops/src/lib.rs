@@ -0,0 +1,188 @@
+//! Coarse, frontend agnostic depot operations. Each job reports its progress
+//! as a stream of [`Event`]s, can be cancelled through a [`CancelToken`] and
+//! returns a structured result, so GUIs and other frontends only need to
+//! render events instead of orchestrating the low level handle themselves
+
+use depot_core::error::DepotError;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+mod bake;
+mod extract;
+mod verify;
+
+pub use bake::{BakeJob, BakeResult};
+pub use extract::{ExtractJob, ExtractResult};
+pub use verify::{VerifyFailure, VerifyJob, VerifyResult};
+
+/// Progress of a running job
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// The job started working on `total` items
+    Started { total: u64 },
+    /// Work on item number `pos` started
+    Item { name: String, size: u64, pos: u64 },
+    /// Non fatal issue, the job carries on
+    Warning(String),
+    /// The job is done, successfully or not
+    Finished,
+}
+
+/// Cooperative cancellation flag shared between a job and its frontend,
+/// jobs check it between items
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// What a job reports to and is controlled by
+#[derive(Debug, Clone, Default)]
+pub struct JobContext {
+    events: Option<Sender<Event>>,
+    cancel: CancelToken,
+}
+
+impl JobContext {
+    pub fn new(events: Option<Sender<Event>>, cancel: CancelToken) -> Self {
+        Self { events, cancel }
+    }
+
+    /// Sends `event`, a frontend that went away is not an error
+    pub(crate) fn emit(&self, event: Event) {
+        if let Some(events) = &self.events {
+            let _ = events.send(event);
+        }
+    }
+
+    /// Fails with [`DepotError::Cancelled`] once the job was cancelled
+    pub(crate) fn check_cancelled(&self) -> Result<(), DepotError> {
+        if self.cancel.is_cancelled() {
+            return Err(DepotError::Cancelled);
+        }
+        Ok(())
+    }
+}
+
+/// A job that can be run on the current thread or spawned
+pub trait Job: Send + 'static {
+    type Output: Send + 'static;
+
+    /// Runs the job on the current thread, a cancelled job fails with
+    /// [`DepotError::Cancelled`]
+    fn run(self, ctx: &JobContext) -> Result<Self::Output, DepotError>;
+
+    /// Runs the job on its own thread
+    fn spawn(self) -> JobHandle<Self::Output>
+    where
+        Self: Sized,
+    {
+        let (tx, rx) = mpsc::channel();
+        let cancel = CancelToken::default();
+        let ctx = JobContext::new(Some(tx), cancel.clone());
+        let thread = thread::spawn(move || {
+            let result = self.run(&ctx);
+            ctx.emit(Event::Finished);
+            result
+        });
+        JobHandle {
+            events: rx,
+            cancel,
+            thread,
+        }
+    }
+}
+
+/// A job running on its own thread, see [`Job::spawn`]
+pub struct JobHandle<T> {
+    events: Receiver<Event>,
+    cancel: CancelToken,
+    thread: JoinHandle<Result<T, DepotError>>,
+}
+
+impl<T> JobHandle<T> {
+    /// Progress events, the channel closes when the job ends
+    pub fn events(&self) -> &Receiver<Event> {
+        &self.events
+    }
+
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Waits for the job and returns its result
+    pub fn join(self) -> Result<T, DepotError> {
+        self.thread
+            .join()
+            .unwrap_or_else(|_| Err(std::io::Error::other("job panicked").into()))
+    }
+}
+
+/// Bandwidth and timing of a finished job
+#[derive(Debug, Clone, Default)]
+pub struct OperationReport {
+    /// Bytes read from the source, files for a bake, the depot otherwise
+    pub bytes_read: u64,
+    /// Bytes written to the destination
+    pub bytes_written: u64,
+    /// Uncompressed size of the entries processed
+    pub uncompressed: u64,
+    /// Size of the entries as stored in the depot
+    pub compressed: u64,
+    /// Wall time of the whole job
+    pub elapsed: Duration,
+    pub entries: Vec<EntryReport>,
+}
+
+/// Sizes and time spent on a single entry
+#[derive(Debug, Clone)]
+pub struct EntryReport {
+    pub name: String,
+    pub size: u64,
+    pub stream_size: u64,
+    pub elapsed: Duration,
+}
+
+impl OperationReport {
+    pub(crate) fn record(&mut self, name: &str, size: u64, stream_size: u64, elapsed: Duration) {
+        self.uncompressed += size;
+        self.compressed += stream_size;
+        self.entries.push(EntryReport {
+            name: name.to_owned(),
+            size,
+            stream_size,
+            elapsed,
+        });
+    }
+
+    /// Uncompressed bytes processed per second of wall time
+    pub fn throughput(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        self.uncompressed as f64 / secs
+    }
+
+    /// `compressed / uncompressed`, 0.0 when nothing was processed
+    pub fn ratio(&self) -> f64 {
+        if self.uncompressed == 0 {
+            return 0.0;
+        }
+        self.compressed as f64 / self.uncompressed as f64
+    }
+}
@@ -0,0 +1,93 @@
+use crate::{Event, Job, JobContext, OperationReport};
+use depot_core::depot_handle::{DepotHandle, OpenMode, StreamInfo};
+use depot_core::error::DepotError;
+use std::path::PathBuf;
+use std::time::Instant;
+
+/// Decompresses every stream of a depot, checking sizes and hashes
+#[derive(Debug, Clone)]
+pub struct VerifyJob {
+    pub depot: PathBuf,
+    /// Also verify streams in the reserved namespace
+    pub include_reserved: bool,
+}
+
+impl VerifyJob {
+    pub fn new(depot: impl Into<PathBuf>) -> Self {
+        Self {
+            depot: depot.into(),
+            include_reserved: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VerifyFailure {
+    pub stream: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VerifyResult {
+    pub checked: u64,
+    pub failures: Vec<VerifyFailure>,
+    pub report: OperationReport,
+}
+
+impl VerifyResult {
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl Job for VerifyJob {
+    type Output = VerifyResult;
+
+    /// Corrupt streams are collected in the result, only failing to open the
+    /// depot or a cancellation fail the job
+    fn run(self, ctx: &JobContext) -> Result<VerifyResult, DepotError> {
+        let started = Instant::now();
+        let mut dh = DepotHandle::open_file(&self.depot, OpenMode::Read)?;
+        let streams: Vec<StreamInfo> = if self.include_reserved {
+            dh.all_streams()
+                .map(|(name, einf)| (name.clone(), einf.clone()).into())
+                .collect()
+        } else {
+            dh.streams()
+                .map(|(name, einf)| (name.clone(), einf.clone()).into())
+                .collect()
+        };
+
+        let mut result = VerifyResult::default();
+        ctx.emit(Event::Started {
+            total: streams.len() as u64,
+        });
+        for (pos, stream) in streams.iter().enumerate() {
+            ctx.check_cancelled()?;
+            ctx.emit(Event::Item {
+                name: stream.name.clone(),
+                size: stream.einf.size,
+                pos: pos as u64 + 1,
+            });
+            let entry_started = Instant::now();
+            let verified = dh.verify_stream(stream);
+            result.report.bytes_read += stream.einf.stream_size;
+            result.report.record(
+                &stream.name,
+                stream.einf.size,
+                stream.einf.stream_size,
+                entry_started.elapsed(),
+            );
+            if let Err(e) = verified {
+                ctx.emit(Event::Warning(format!("{}: {}", stream.name, e)));
+                result.failures.push(VerifyFailure {
+                    stream: stream.name.clone(),
+                    error: e.to_string(),
+                });
+            }
+            result.checked += 1;
+        }
+        result.report.elapsed = started.elapsed();
+        Ok(result)
+    }
+}
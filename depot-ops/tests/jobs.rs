@@ -0,0 +1,88 @@
+//! Jobs run on the current thread and spawned, against files in the temp dir
+
+use depot_core::error::DepotError;
+use depot_ops::{BakeJob, CancelToken, Event, ExtractJob, Job, JobContext, VerifyJob};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+#[path = "../../depot-core/tests/common/mod.rs"]
+mod common;
+use common::TempPath;
+
+fn write_sources(dir: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+    let sources = vec![
+        (dir.join("a.txt"), b"first file".to_vec()),
+        (dir.join("b.bin"), (0..64 * 1024).map(|i| i as u8).collect()),
+        (dir.join("empty"), Vec::new()),
+    ];
+    for (path, data) in &sources {
+        fs::write(path, data).unwrap();
+    }
+    sources
+}
+
+#[test]
+fn bake_verify_extract_round_trip() {
+    let tmp = TempPath::dir("ops-round-trip");
+    let sources = write_sources(tmp.path());
+    let depot = tmp.path().join("out.dpt");
+    let files = sources.iter().map(|(path, _)| path.clone()).collect();
+
+    let (tx, rx) = mpsc::channel();
+    let ctx = JobContext::new(Some(tx), CancelToken::default());
+    let baked = BakeJob::new(&depot, files).run(&ctx).unwrap();
+    assert_eq!(baked.files, 3);
+    assert_eq!(baked.report.bytes_read, 10 + 64 * 1024);
+    let events: Vec<Event> = rx.try_iter().collect();
+    assert_eq!(events[0], Event::Started { total: 3 });
+
+    let verified = VerifyJob::new(&depot).spawn().join().unwrap();
+    assert!(verified.is_ok());
+    assert_eq!(verified.checked, 3);
+
+    // the sources were added by their absolute paths
+    let output = tmp.path().join("extracted");
+    let mut job = ExtractJob::new(&depot, &output);
+    job.options
+        .set_path_mapper(|name| Path::new(name).file_name().map(PathBuf::from));
+    let extracted = job.run(&JobContext::default()).unwrap();
+    assert_eq!(extracted.files.len(), 3);
+    for (path, data) in &sources {
+        let name = path.file_name().unwrap();
+        assert_eq!(&fs::read(output.join(name)).unwrap(), data);
+    }
+}
+
+#[test]
+fn cancelled_bake_removes_its_output() {
+    let tmp = TempPath::dir("ops-cancelled");
+    let sources = write_sources(tmp.path());
+    let depot = tmp.path().join("out.dpt");
+    let files = sources.iter().map(|(path, _)| path.clone()).collect();
+
+    let cancel = CancelToken::default();
+    cancel.cancel();
+    let err = BakeJob::new(&depot, files)
+        .run(&JobContext::new(None, cancel))
+        .unwrap_err();
+    assert!(matches!(err, DepotError::Cancelled));
+    assert!(!err.is_corruption());
+    assert!(!depot.exists());
+}
+
+#[test]
+fn missing_stream_fails_extraction() {
+    let tmp = TempPath::dir("ops-missing");
+    let sources = write_sources(tmp.path());
+    let depot = tmp.path().join("out.dpt");
+    let files = sources.iter().map(|(path, _)| path.clone()).collect();
+    BakeJob::new(&depot, files)
+        .run(&JobContext::default())
+        .unwrap();
+
+    let mut job = ExtractJob::new(&depot, tmp.path().join("extracted"));
+    job.streams.push("not-there".to_owned());
+    let err = job.run(&JobContext::default()).unwrap_err();
+    assert!(matches!(err, DepotError::EntryNotFound(name) if name == "not-there"));
+}